@@ -14,37 +14,233 @@
 
 use bindgen;
 
+#[path = "cf-util/build/cache.rs"]
+mod cache;
+#[path = "cf-util/build/sdk.rs"]
+mod sdk;
+
 use std::env;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Write a table of the names of the `OSStatus` codes in `bindings` to
+/// `path`.
+///
+/// Bindgen turns the anonymous enums of status codes into constants like
+/// `pub const errSecItemNotFound: _bindgen_ty_5 = -25300;`.  The table holds
+/// the first name of every code, sorted by code.
+fn write_status_names(bindings: &str, path: &Path) {
+    let mut names: Vec<(i32, &str)> = Vec::new();
+    // Split by statements rather than lines, in case rustfmt didn't run
+    for statement in bindings.split(';') {
+        let declaration = match statement.find("pub const ") {
+            Some(start) => &statement[start + "pub const ".len()..],
+            None => continue,
+        };
+        let name = declaration.split(':').next().unwrap_or_default().trim();
+        if !(name.starts_with("errSec") || name.starts_with("errAuthorization")) {
+            continue;
+        }
+        let value = declaration.rsplit('=').next().map(str::trim);
+        if let Some(code) = value.and_then(|value| value.parse().ok()) {
+            names.push((code, name));
+        }
+    }
+    // Stable, so the first name of a code stays first
+    names.sort_by_key(|&(code, _)| code);
+    names.dedup_by_key(|&mut (code, _)| code);
+    let mut table = String::from("/// Names of `OSStatus` codes, sorted by code.\n");
+    table.push_str(&format!(
+        "static STATUS_NAMES: [(OSStatus, &str); {}] = [\n",
+        names.len()
+    ));
+    for (code, name) in names {
+        table.push_str(&format!("    ({}, \"{}\"),\n", code, name));
+    }
+    table.push_str("];\n");
+    fs::write(path, table).expect("Couldn't write status names!");
+}
+
+/// The symbol name of a foreign item with `ident` and `attrs`.
+///
+/// Take the name from `link_name` if bindgen gave one, without the marker of
+/// raw symbol names and the leading underscore of Mach-O symbols.
+fn symbol_name(ident: &syn::Ident, attrs: &[syn::Attribute]) -> String {
+    for attr in attrs {
+        if let syn::Meta::NameValue(ref meta) = attr.meta {
+            if meta.path.is_ident("link_name") {
+                if let syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Str(ref name),
+                    ..
+                }) = meta.value
+                {
+                    let name = name.value();
+                    return match name.strip_prefix('\u{1}') {
+                        Some(raw) => raw.trim_start_matches('_').to_string(),
+                        None => name,
+                    };
+                }
+            }
+        }
+    }
+    ident.to_string()
+}
+
+/// The symbol name of a foreign item as NUL-terminated byte string literal.
+fn symbol_literal(ident: &syn::Ident, attrs: &[syn::Attribute]) -> syn::LitByteStr {
+    let symbol = symbol_name(ident, attrs);
+    syn::LitByteStr::new(
+        format!("{}\0", symbol).as_bytes(),
+        proc_macro2::Span::call_site(),
+    )
+}
+
+/// Turn the statics of `bindings` into accessor functions.
+///
+/// Every static `kSecFoo` becomes a safe function `kSecFoo()` which returns
+/// its value, and the static itself moves into a private module, so that
+/// nothing reads it directly.  With `dynamic`, for the `dlopen-security`
+/// feature, accessors find their static with `keychain::dynamic::symbol`,
+/// which loads the framework first, and every function becomes a wrapper
+/// which finds the function the same way on first call.  All other items
+/// stay as they are.
+fn accessor_bindings(bindings: &str, dynamic: bool) -> String {
+    use quote::quote;
+
+    let file = syn::parse_file(bindings).expect("Couldn't parse bindings!");
+    let mut items = Vec::new();
+    let mut statics = Vec::new();
+    for item in file.items {
+        let mut foreign = match item {
+            syn::Item::ForeignMod(foreign) => foreign,
+            item => {
+                items.push(quote!(#item));
+                continue;
+            }
+        };
+        let mut functions = Vec::new();
+        for foreign_item in foreign.items {
+            match foreign_item {
+                syn::ForeignItem::Fn(function) if !dynamic => {
+                    functions.push(syn::ForeignItem::Fn(function));
+                }
+                syn::ForeignItem::Fn(function) => {
+                    let symbol = symbol_literal(&function.sig.ident, &function.attrs);
+                    let docs = function.attrs.iter().filter(|a| a.path().is_ident("doc"));
+                    let name = &function.sig.ident;
+                    let inputs = &function.sig.inputs;
+                    let output = &function.sig.output;
+                    assert!(function.sig.variadic.is_none(), "Variadic {}", name);
+                    let (arguments, types): (Vec<_>, Vec<_>) = inputs
+                        .iter()
+                        .map(|input| match *input {
+                            syn::FnArg::Typed(ref typed) => (&typed.pat, &typed.ty),
+                            syn::FnArg::Receiver(_) => unreachable!(),
+                        })
+                        .unzip();
+                    items.push(quote! {
+                        #(#docs)*
+                        pub unsafe fn #name(#inputs) #output {
+                            static ADDRESS: ::std::sync::atomic::AtomicPtr<::std::os::raw::c_void> =
+                                ::std::sync::atomic::AtomicPtr::new(::std::ptr::null_mut());
+                            let function: unsafe extern "C" fn(#(#types),*) #output =
+                                ::std::mem::transmute(crate::keychain::dynamic::symbol(&ADDRESS, #symbol));
+                            function(#(#arguments),*)
+                        }
+                    });
+                }
+                syn::ForeignItem::Static(item) => {
+                    let docs = item.attrs.iter().filter(|a| a.path().is_ident("doc"));
+                    let name = &item.ident;
+                    let ty = &item.ty;
+                    if dynamic {
+                        let symbol = symbol_literal(&item.ident, &item.attrs);
+                        items.push(quote! {
+                            #(#docs)*
+                            pub fn #name() -> #ty {
+                                static ADDRESS: ::std::sync::atomic::AtomicPtr<::std::os::raw::c_void> =
+                                    ::std::sync::atomic::AtomicPtr::new(::std::ptr::null_mut());
+                                unsafe {
+                                    *(crate::keychain::dynamic::symbol(&ADDRESS, #symbol) as *const #ty)
+                                }
+                            }
+                        });
+                    } else {
+                        items.push(quote! {
+                            #(#docs)*
+                            pub fn #name() -> #ty {
+                                unsafe { statics::#name }
+                            }
+                        });
+                        statics.push(syn::ForeignItem::Static(item));
+                    }
+                }
+                item => panic!("Unexpected foreign item: {}", quote!(#item)),
+            }
+        }
+        if !functions.is_empty() {
+            foreign.items = functions;
+            items.push(quote!(#foreign));
+        }
+    }
+    let statics = quote! {
+        /// The statics of the framework, read only through their accessors.
+        mod statics {
+            use super::*;
+
+            extern "C" {
+                #(#statics)*
+            }
+        }
+    };
+    if dynamic {
+        quote!(#(#items)*).to_string()
+    } else {
+        quote!(#(#items)* #statics).to_string()
+    }
+}
 
 fn main() {
     if std::env::var("TARGET").unwrap().contains("-apple") {
-        println!("cargo:rustc-link-lib=framework=CoreFoundation");
-        println!("cargo:rustc-link-lib=framework=Security");
+        // With dlopen-security, keychain::dynamic loads both at runtime
+        if env::var_os("CARGO_FEATURE_DLOPEN_SECURITY").is_none() {
+            println!("cargo:rustc-link-lib=framework=Security");
+            println!("cargo:rustc-link-lib=framework=LocalAuthentication");
+        }
+        println!("cargo:rustc-link-lib=objc");
 
-        let bindings = bindgen::Builder::default()
+        let builder = bindgen::Builder::default()
+            .clang_args(sdk::clang_args())
             .header("src/keychain-wrapper.h")
             // Security Framework
             .whitelist_function("SecCopyErrorMessageString")
             .whitelist_function("^SecItem.*")
+            .whitelist_function("^SecKey.*")
+            .whitelist_function("SecRandomCopyBytes")
+            .whitelist_function("^SecKeychain.*")
+            .whitelist_function("^SecCode.*") // Code signing
+            .whitelist_function("^SecIdentity.*")
+            .whitelist_function("^SecCertificate.*")
+            .whitelist_function("^SecAccessControl.*")
+            .whitelist_function("^Authorization.*")
+            .whitelist_var("^kAuthorization.*")
+            .whitelist_var("^errAuthorization.*")
             .whitelist_var("^kSec.*")
             .whitelist_var("^errSec.*") // Error codes
-            // Core foundation
-            .whitelist_function("^CFString.*")
-            .whitelist_var("^kCF.*")
-            .whitelist_function("^CFData.*")
-            .whitelist_function("^CFDictionary.*")
-            .whitelist_function("CFRelease")
-            .whitelist_function("CFShow")
-            .whitelist_function("CFTypeRef")
+            // CommonCrypto
+            .whitelist_function("CCHmac")
+            .whitelist_function("CC_SHA256")
+            .whitelist_var("^kCCHmacAlg.*")
             // Base types
             .whitelist_type("OSStatus")
-            .generate()
-            .expect("Unable to generate bindings");
+            // CoreFoundation comes from native::cf
+            .blacklist_type("^(__)?CF.*");
 
         let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
-        bindings
-            .write_to_file(out_path.join("bindings.rs"))
-            .expect("Couldn't write bindings!");
+        let bindings = cache::generate("security", builder, &["src/keychain-wrapper.h"]);
+        write_status_names(&bindings, &out_path.join("status_names.rs"));
+        let dynamic = env::var_os("CARGO_FEATURE_DLOPEN_SECURITY").is_some();
+        let bindings = accessor_bindings(&bindings, dynamic);
+        fs::write(out_path.join("security.rs"), &bindings).expect("Couldn't write bindings!");
     }
 }
@@ -0,0 +1,89 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Building queries for `create_dictionary` in a `Vec` versus in
+//! `DictionaryItems`.
+//!
+//! Run with `cargo bench -p cf-util`.  Each way builds the query of
+//! `copy_first_generic_password`, ie, five fixed items and an optional
+//! account, creates the dictionary, and releases it again; the difference is
+//! the cost of allocating the `Vec`.
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn main() {
+    use std::hint::black_box;
+    use std::time::{Duration, Instant};
+
+    use cf_util::sys::*;
+    use cf_util::{create_cf_string, create_dictionary, DictionaryItems};
+
+    const ITERATIONS: u32 = 1_000_000;
+
+    /// Run `f` `ITERATIONS` times, and return the mean time of a run.
+    fn measure<F: FnMut()>(mut f: F) -> Duration {
+        // Warm up caches and the allocator first
+        for _ in 0..ITERATIONS / 10 {
+            f();
+        }
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            f();
+        }
+        start.elapsed() / ITERATIONS
+    }
+
+    unsafe {
+        let keys: Vec<CFTypeRef> = (0..6)
+            .map(|i| create_cf_string(&format!("key{}", i)) as CFTypeRef)
+            .collect();
+        let value = kCFBooleanTrue as CFTypeRef;
+        let account = black_box(Some(create_cf_string("account") as CFTypeRef));
+
+        let vec = measure(|| {
+            let mut items = vec![
+                (keys[0], value),
+                (keys[1], value),
+                (keys[2], value),
+                (keys[3], value),
+                (keys[4], value),
+            ];
+            if let Some(account) = account {
+                items.push((keys[5], account));
+            }
+            CFRelease(black_box(create_dictionary(&items)) as CFTypeRef);
+        });
+        let inline = measure(|| {
+            let mut items = DictionaryItems::new(&[
+                (keys[0], value),
+                (keys[1], value),
+                (keys[2], value),
+                (keys[3], value),
+                (keys[4], value),
+            ]);
+            if let Some(account) = account {
+                items.push((keys[5], account));
+            }
+            CFRelease(black_box(create_dictionary(&items)) as CFTypeRef);
+        });
+        println!("Vec:             {:?} per query", vec);
+        println!("DictionaryItems: {:?} per query", inline);
+
+        for key in keys.into_iter().chain(account) {
+            CFRelease(key);
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+fn main() {}
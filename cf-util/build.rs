@@ -0,0 +1,61 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+#[path = "build/cache.rs"]
+mod cache;
+#[path = "build/sdk.rs"]
+mod sdk;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+fn main() {
+    if std::env::var("TARGET").unwrap().contains("-apple") {
+        println!("cargo:rustc-link-lib=framework=CoreFoundation");
+
+        let builder = bindgen::Builder::default()
+            .clang_args(sdk::clang_args())
+            .header("src/cf-wrapper.h")
+            .whitelist_function("^CFString.*")
+            .whitelist_var("^kCF.*")
+            .whitelist_function("^CFData.*")
+            .whitelist_function("^CFDictionary.*")
+            .whitelist_function("^CFArray.*")
+            .whitelist_function("^CFDate.*")
+            .whitelist_function("^CFRunLoop.*")
+            .whitelist_function("^CFNumber.*")
+            .whitelist_function("^CFBoolean.*")
+            .whitelist_function("^CFError.*")
+            .whitelist_function("^CFPropertyList.*")
+            .whitelist_function("CFBundleGetBundleWithIdentifier")
+            .whitelist_function("CFBundleCopyResourceURLForLocalization")
+            .whitelist_function("CFURLGetFileSystemRepresentation")
+            .whitelist_function("CFEqual")
+            .whitelist_function("CFCopyDescription")
+            .whitelist_function("CFGetTypeID")
+            .whitelist_function("CFRetain")
+            .whitelist_function("CFRelease")
+            .whitelist_function("CFShow")
+            // All CF types, because the bindings of dependent crates use them
+            // instead of generating their own
+            .whitelist_type("^CF.*")
+            // Base types
+            .whitelist_type("OSStatus");
+
+        let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
+        let bindings = cache::generate("cf", builder, &["src/cf-wrapper.h"]);
+        fs::write(out_path.join("bindings.rs"), &bindings).expect("Couldn't write bindings!");
+    }
+}
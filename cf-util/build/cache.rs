@@ -0,0 +1,116 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! A cache of generated bindings, for the build scripts of the workspace.
+//!
+//! Generating bindings takes tens of seconds, and build scripts run afresh for
+//! every new `OUT_DIR`, eg, after changing features.  The cache keeps bindings
+//! in the `bindgen` directory of the profile, eg, `target/debug/bindgen`, or
+//! in `BINDGEN_CACHE_DIR`, under a hash of everything that goes into them.
+
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// The directory of the cache.
+fn directory() -> PathBuf {
+    if let Some(directory) = env::var_os("BINDGEN_CACHE_DIR") {
+        return directory.into();
+    }
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    // OUT_DIR is <profile>/build/<package>-<hash>/out
+    match out_dir.ancestors().nth(3) {
+        Some(profile) => profile.join("bindgen"),
+        None => out_dir,
+    }
+}
+
+/// Hash the configuration of `builder` and the contents of `inputs`.
+fn key(builder: &bindgen::Builder, inputs: &[&str]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    // The build script itself covers the configuration in code, and the
+    // version of bindgen
+    if let Ok(build_script) = env::current_exe().and_then(fs::read) {
+        build_script.hash(&mut hasher);
+    }
+    let flags = builder.command_line_flags();
+    flags.hash(&mut hasher);
+    // Xcode updates SDKs in place, so the path of the SDK isn't enough
+    let sdk_settings = flags
+        .windows(2)
+        .filter(|pair| pair[0] == "-isysroot")
+        .map(|pair| Path::new(&pair[1]).join("SDKSettings.json"));
+    for path in inputs.iter().map(PathBuf::from).chain(sdk_settings) {
+        fs::read(&path).ok().hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Remove cached bindings named `name`, except `keep`.
+fn prune(directory: &Path, name: &str, keep: &Path) {
+    let prefix = format!("{}-", name);
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        let file_name = entry.file_name();
+        let file_name = file_name.to_string_lossy();
+        if path != keep && file_name.starts_with(&prefix) && file_name.ends_with(".rs") {
+            // Another build may still read it; it's just stale otherwise
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Generate the bindings named `name` with `builder`, or take them from the
+/// cache.
+///
+/// `inputs` are the files of the crate which go into the bindings, ie, the
+/// headers; tell cargo to run the build script again when they change.
+pub fn generate(name: &str, builder: bindgen::Builder, inputs: &[&str]) -> String {
+    for input in inputs {
+        println!("cargo:rerun-if-changed={}", input);
+    }
+    println!("cargo:rerun-if-env-changed=BINDGEN_CACHE_DIR");
+
+    let directory = directory();
+    let path = directory.join(format!("{}-{:016x}.rs", name, key(&builder, inputs)));
+    if let Ok(bindings) = fs::read_to_string(&path) {
+        return bindings;
+    }
+    let bindings = builder
+        .generate()
+        .expect("Unable to generate bindings")
+        .to_string();
+    // Write to a temporary file first, so that concurrent builds never read
+    // partial bindings
+    let temporary = path.with_extension(format!("{}.tmp", process::id()));
+    let cached = fs::create_dir_all(&directory)
+        .and_then(|_| fs::write(&temporary, &bindings))
+        .and_then(|_| fs::rename(&temporary, &path));
+    match cached {
+        Ok(_) => prune(&directory, name, &path),
+        Err(error) => println!(
+            "cargo:warning=Couldn't cache bindings in {}: {}",
+            directory.display(),
+            error
+        ),
+    }
+    bindings
+}
@@ -0,0 +1,98 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! The SDK to generate bindings against, for the build scripts of the
+//! workspace.
+//!
+//! bindgen runs clang, which only finds the system headers if it knows the
+//! SDK.  `xcrun` knows the active SDK, whether it comes with Xcode, an Xcode
+//! beta selected with `xcode-select` or `DEVELOPER_DIR`, or the Command Line
+//! Tools alone.
+
+use std::env;
+use std::process::Command;
+
+/// Whether `target` is the iOS simulator.
+fn is_simulator(target: &str) -> bool {
+    target.ends_with("-ios-sim") || target == "x86_64-apple-ios"
+}
+
+/// The clang target for the Rust `target`.
+///
+/// Clang spells the simulator `simulator` rather than `sim`, and has no
+/// separate triple for the x86_64 simulator.
+fn clang_target(target: &str) -> String {
+    if target.ends_with("-ios-sim") {
+        format!("{}ulator", target)
+    } else if target == "x86_64-apple-ios" {
+        "x86_64-apple-ios-simulator".to_string()
+    } else {
+        target.to_string()
+    }
+}
+
+/// The path of the SDK for `target`.
+///
+/// Prefer `SDKROOT` if set, and ask `xcrun` otherwise.  Return `None` if
+/// `xcrun` is missing or doesn't know the SDK.
+fn sdk_path(target: &str) -> Option<String> {
+    if let Some(path) = env::var_os("SDKROOT").filter(|path| !path.is_empty()) {
+        return path.into_string().ok();
+    }
+    let sdk = if target.contains("-apple-darwin") || target.ends_with("-macabi") {
+        // Mac Catalyst builds against the macOS SDK
+        "macosx"
+    } else if is_simulator(target) {
+        "iphonesimulator"
+    } else if target.contains("-apple-ios") {
+        "iphoneos"
+    } else {
+        return None;
+    };
+    let output = Command::new("xcrun")
+        .args(["--sdk", sdk, "--show-sdk-path"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let path = String::from_utf8(output.stdout).ok()?;
+    let path = path.trim();
+    if path.is_empty() {
+        None
+    } else {
+        Some(path.to_string())
+    }
+}
+
+/// Clang arguments for bindings for the target of the build.
+///
+/// Pass the target to clang, so that bindings fit the target when
+/// cross-compiling between x86_64 and aarch64 or for iOS, and the sysroot of
+/// the SDK if `xcrun` finds one; otherwise leave it to clang to find the
+/// headers.
+pub fn clang_args() -> Vec<String> {
+    println!("cargo:rerun-if-env-changed=SDKROOT");
+    println!("cargo:rerun-if-env-changed=DEVELOPER_DIR");
+    let target = env::var("TARGET").unwrap();
+    let mut args = vec![format!("--target={}", clang_target(&target))];
+    match sdk_path(&target) {
+        Some(path) => {
+            args.push("-isysroot".to_string());
+            args.push(path);
+        }
+        None => println!("cargo:warning=xcrun found no SDK for {}", target),
+    }
+    args
+}
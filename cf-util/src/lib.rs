@@ -0,0 +1,822 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! CoreFoundation bindings and wrappers.
+//!
+//! `sys` has the raw bindings; the functions of this crate convert between
+//! CoreFoundation and Rust types, and build collections for CoreFoundation
+//! APIs.  They know nothing about the keychain, which is the business of the
+//! `rust-ffi-demo` crate.
+
+#![deny(warnings)]
+#![cfg(any(target_os = "macos", target_os = "ios"))]
+
+use std::error;
+use std::fmt;
+use std::ops::Deref;
+use std::os::raw::c_void;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[allow(non_upper_case_globals)]
+#[allow(non_camel_case_types)]
+#[allow(non_snake_case)]
+#[allow(dead_code)]
+pub mod sys;
+
+pub mod ownership;
+
+use crate::sys::*;
+
+pub use crate::ownership::{Borrowed, CfType, Owned};
+
+/// Converts a CoreFoundation String to a rust `String`.
+///
+/// Unpaired UTF-16 surrogates, which CoreFoundation strings may contain but
+/// Rust strings cannot, become U+FFFD; use `utf16_from_cf_string` to get the
+/// string losslessly.
+///
+/// # Safety
+///
+/// The caller must ensure that `cfstring` is not null.
+///
+/// # Errors
+///
+/// Return `UnexpectedCfType` if `cfstring` is no string, in debug builds or
+/// with the `strict-types` feature; see `cast_cf_string`.
+pub unsafe fn string_from_cf_string(cfstring: CFStringRef) -> Result<String, UnexpectedCfType> {
+    assert!(!cfstring.is_null());
+    check_type(cfstring as CFTypeRef, "CFString", CFStringGetTypeID())?;
+    let length = CFStringGetLength(cfstring);
+    let range = CFRange {
+        location: 0,
+        length,
+    };
+    // Measure first, then convert; a lossByte of 0 makes the conversion stop
+    // at the first character without UTF-8 representation
+    let mut size: CFIndex = 0;
+    let converted = CFStringGetBytes(
+        cfstring,
+        range,
+        kCFStringEncodingUTF8,
+        0,
+        false as u8,
+        std::ptr::null_mut(),
+        0,
+        &mut size,
+    );
+    if converted == length {
+        let mut bytes = vec![0; size as usize];
+        CFStringGetBytes(
+            cfstring,
+            range,
+            kCFStringEncodingUTF8,
+            0,
+            false as u8,
+            bytes.as_mut_ptr(),
+            size,
+            &mut size,
+        );
+        if let Ok(string) = String::from_utf8(bytes) {
+            return Ok(string);
+        }
+    }
+    Ok(String::from_utf16_lossy(&utf16_from_cf_string(cfstring)?))
+}
+
+/// Get the UTF-16 code units of a CoreFoundation String.
+///
+/// Unlike `string_from_cf_string` this is lossless for all strings.
+///
+/// # Safety
+///
+/// The caller must ensure that `cfstring` is not null.
+///
+/// # Errors
+///
+/// Return `UnexpectedCfType` if `cfstring` is no string, like
+/// `string_from_cf_string`.
+pub unsafe fn utf16_from_cf_string(cfstring: CFStringRef) -> Result<Vec<u16>, UnexpectedCfType> {
+    assert!(!cfstring.is_null());
+    check_type(cfstring as CFTypeRef, "CFString", CFStringGetTypeID())?;
+    let length = CFStringGetLength(cfstring);
+    let mut units = vec![0; length as usize];
+    CFStringGetCharacters(
+        cfstring,
+        CFRange {
+            location: 0,
+            length,
+        },
+        units.as_mut_ptr(),
+    );
+    Ok(units)
+}
+
+/// The encoding of text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// A CoreFoundation string, which has no encoding of its own.
+    Unicode,
+    /// UTF-8 bytes.
+    Utf8,
+    /// UTF-16 bytes with a byte order mark.
+    Utf16,
+    /// Windows Latin 1, aka Windows-1252, a superset of ISO Latin 1.
+    WindowsLatin1,
+    /// Mac OS Roman, the encoding of classic Mac OS.
+    MacRoman,
+}
+
+impl Encoding {
+    /// The name of this encoding.
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Unicode => "unicode",
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf16 => "utf-16",
+            Encoding::WindowsLatin1 => "windows-1252",
+            Encoding::MacRoman => "macintosh",
+        }
+    }
+}
+
+/// The encodings `decode_bytes` tries after UTF-8, in order.
+///
+/// Mac OS Roman maps every byte, so it always succeeds.
+const FALLBACK_ENCODINGS: [(Encoding, CFStringEncoding); 3] = [
+    (Encoding::Utf16, kCFStringEncodingUTF16),
+    (Encoding::WindowsLatin1, kCFStringEncodingWindowsLatin1),
+    (Encoding::MacRoman, kCFStringEncodingMacRoman),
+];
+
+/// Decode `bytes` of unknown encoding, and return the text with the encoding
+/// which decoded it.
+///
+/// Try UTF-8 first, then UTF-16 if `bytes` start with a byte order mark, and
+/// then the legacy encodings of Windows and Mac OS.
+pub fn decode_bytes(bytes: &[u8]) -> (String, Encoding) {
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return (text.to_string(), Encoding::Utf8);
+    }
+    let has_bom = bytes.starts_with(&[0xFF, 0xFE]) || bytes.starts_with(&[0xFE, 0xFF]);
+    for &(encoding, cf_encoding) in &FALLBACK_ENCODINGS {
+        if encoding == Encoding::Utf16 && !has_bom {
+            continue;
+        }
+        unsafe {
+            let cfstring = CFStringCreateWithBytes(
+                std::ptr::null_mut(),
+                bytes.as_ptr(),
+                bytes.len() as i64,
+                cf_encoding,
+                (encoding == Encoding::Utf16) as u8,
+            );
+            if !cfstring.is_null() {
+                let text = string_from_cf_string(cfstring);
+                CFRelease(cfstring as CFTypeRef);
+                if let Ok(text) = text {
+                    return (text, encoding);
+                }
+            }
+        }
+    }
+    (String::from_utf8_lossy(bytes).into_owned(), Encoding::Utf8)
+}
+
+/// Creates a `CFString` which borrows the bytes of `s`.
+///
+/// # Safety
+///
+/// The returned string must not outlive `s`.  The caller must call `CFRelease`
+/// on the returned string.
+pub unsafe fn create_cf_string_no_copy(s: &str) -> CFStringRef {
+    let cfstring = CFStringCreateWithBytesNoCopy(
+        std::ptr::null_mut(),
+        s.as_ptr(),
+        s.len() as i64,
+        kCFStringEncodingUTF8,
+        false as u8,
+        kCFAllocatorNull,
+    );
+    assert!(!cfstring.is_null());
+    cfstring
+}
+
+/// Creates a `CFString` with a copy of `s`.
+///
+/// Use this instead of `create_cf_string_no_copy` for strings which may
+/// outlive `s`.
+///
+/// # Safety
+///
+/// The caller must call `CFRelease` on the returned string.
+pub unsafe fn create_cf_string(s: &str) -> CFStringRef {
+    let cfstring = CFStringCreateWithBytes(
+        std::ptr::null_mut(),
+        s.as_ptr(),
+        s.len() as i64,
+        kCFStringEncodingUTF8,
+        false as u8,
+    );
+    assert!(!cfstring.is_null());
+    cfstring
+}
+
+/// Creates a `CFData` which borrows `bytes`.
+///
+/// # Safety
+///
+/// The returned data must not outlive `bytes`.  The caller must call
+/// `CFRelease` on the returned data.
+pub unsafe fn create_cf_data_no_copy(bytes: &[u8]) -> CFDataRef {
+    let cfdata = CFDataCreateWithBytesNoCopy(
+        std::ptr::null_mut(),
+        bytes.as_ptr(),
+        bytes.len() as i64,
+        kCFAllocatorNull,
+    );
+    assert!(!cfdata.is_null());
+    cfdata
+}
+
+/// Creates a `CFNumber` for `value`.
+///
+/// # Safety
+///
+/// The caller must call `CFRelease` on the returned number.
+pub unsafe fn create_cf_number(value: i64) -> CFNumberRef {
+    let cfnumber = CFNumberCreate(
+        std::ptr::null_mut(),
+        kCFNumberSInt64Type as CFNumberType,
+        &value as *const i64 as *const c_void,
+    );
+    assert!(!cfnumber.is_null());
+    cfnumber
+}
+
+/// Get the value of `key` in `dictionary`, which belongs to `dictionary`.
+///
+/// Return `None` if `key` is absent.
+///
+/// # Safety
+///
+/// `key` must be a valid string.
+pub unsafe fn dict_get<'a>(
+    dictionary: Borrowed<'a, CFDictionaryRef>,
+    key: CFStringRef,
+) -> Option<Borrowed<'a, CFTypeRef>> {
+    let value = CFDictionaryGetValue(dictionary.get(), key as *const c_void);
+    if value.is_null() {
+        None
+    } else {
+        Some(Borrowed::from_get(value))
+    }
+}
+
+/// Get the string of `key` in `dictionary`, which belongs to `dictionary`.
+///
+/// Return `None` if `key` is absent or no string.
+///
+/// # Safety
+///
+/// `key` must be a valid string.
+pub unsafe fn dict_get_string<'a>(
+    dictionary: Borrowed<'a, CFDictionaryRef>,
+    key: CFStringRef,
+) -> Option<Borrowed<'a, CFStringRef>> {
+    dict_get(dictionary, key)
+        .filter(|value| CFGetTypeID(value.get()) == CFStringGetTypeID())
+        .map(|value| Borrowed::from_get(value.get() as CFStringRef))
+}
+
+/// Get the data of `key` in `dictionary`, which belongs to `dictionary`.
+///
+/// Return `None` if `key` is absent or no data.
+///
+/// # Safety
+///
+/// `key` must be a valid string.
+pub unsafe fn dict_get_data<'a>(
+    dictionary: Borrowed<'a, CFDictionaryRef>,
+    key: CFStringRef,
+) -> Option<Borrowed<'a, CFDataRef>> {
+    dict_get(dictionary, key)
+        .filter(|value| CFGetTypeID(value.get()) == CFDataGetTypeID())
+        .map(|value| Borrowed::from_get(value.get() as CFDataRef))
+}
+
+/// Get the string value of `key` in `dictionary`.
+///
+/// Return `None` if `key` is absent or no string.
+///
+/// # Safety
+///
+/// `dictionary` must not be null.
+pub unsafe fn string_value(dictionary: CFDictionaryRef, key: CFStringRef) -> Option<String> {
+    let value = CFDictionaryGetValue(dictionary, key as *const c_void);
+    if !value.is_null() && CFGetTypeID(value) == CFStringGetTypeID() {
+        string_from_cf_string(value as CFStringRef).ok()
+    } else {
+        None
+    }
+}
+
+/// Get the text value of `key` in `dictionary`, with its encoding.
+///
+/// Accept strings, and data which legacy apps store for text attributes, see
+/// `decode_bytes`.  Return `None` if `key` is absent or neither.
+///
+/// # Safety
+///
+/// `dictionary` must not be null.
+pub unsafe fn text_value(
+    dictionary: CFDictionaryRef,
+    key: CFStringRef,
+) -> Option<(String, Encoding)> {
+    let value = CFDictionaryGetValue(dictionary, key as *const c_void);
+    if value.is_null() {
+        None
+    } else if CFGetTypeID(value) == CFStringGetTypeID() {
+        string_from_cf_string(value as CFStringRef)
+            .ok()
+            .map(|text| (text, Encoding::Unicode))
+    } else if CFGetTypeID(value) == CFDataGetTypeID() {
+        vec_from_cfdata(value as CFDataRef)
+            .ok()
+            .map(|bytes| decode_bytes(&bytes))
+    } else {
+        None
+    }
+}
+
+/// Get the data value of `key` in `dictionary`.
+///
+/// Return `None` if `key` is absent or no data.
+///
+/// # Safety
+///
+/// `dictionary` must not be null.
+pub unsafe fn data_value(dictionary: CFDictionaryRef, key: CFStringRef) -> Option<Vec<u8>> {
+    let value = CFDictionaryGetValue(dictionary, key as *const c_void);
+    if !value.is_null() && CFGetTypeID(value) == CFDataGetTypeID() {
+        vec_from_cfdata(value as CFDataRef).ok()
+    } else {
+        None
+    }
+}
+
+/// Get the integer value of `key` in `dictionary`.
+///
+/// Return `None` if `key` is absent or no number.
+///
+/// # Safety
+///
+/// `dictionary` must not be null.
+pub unsafe fn number_value(dictionary: CFDictionaryRef, key: CFStringRef) -> Option<i64> {
+    let value = CFDictionaryGetValue(dictionary, key as *const c_void);
+    if value.is_null() || CFGetTypeID(value) != CFNumberGetTypeID() {
+        return None;
+    }
+    let mut number: i64 = 0;
+    let converted = CFNumberGetValue(
+        value as CFNumberRef,
+        kCFNumberSInt64Type as CFNumberType,
+        &mut number as *mut i64 as *mut c_void,
+    );
+    if converted == 0 {
+        None
+    } else {
+        Some(number)
+    }
+}
+
+/// Get the boolean value of `key` in `dictionary`.
+///
+/// Accept booleans as well as numbers, which the keychain uses for flags of
+/// some items.  Return `None` if `key` is absent or neither.
+///
+/// # Safety
+///
+/// `dictionary` must not be null.
+pub unsafe fn bool_value(dictionary: CFDictionaryRef, key: CFStringRef) -> Option<bool> {
+    let value = CFDictionaryGetValue(dictionary, key as *const c_void);
+    if value.is_null() {
+        None
+    } else if CFGetTypeID(value) == CFBooleanGetTypeID() {
+        Some(CFBooleanGetValue(value as CFBooleanRef) != 0)
+    } else {
+        number_value(dictionary, key).map(|number| number != 0)
+    }
+}
+
+/// A CoreFoundation value of another type than expected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnexpectedCfType {
+    /// The name of the expected type, eg, `CFString`.
+    pub expected: &'static str,
+    /// The type ID of the actual value.
+    pub actual: CFTypeID,
+}
+
+impl fmt::Display for UnexpectedCfType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Expected {}, got CoreFoundation type {}",
+            self.expected, self.actual
+        )
+    }
+}
+
+impl error::Error for UnexpectedCfType {}
+
+/// Whether casts and conversions check the types of CoreFoundation values.
+///
+/// Debug builds always check, release builds only with the `strict-types`
+/// feature.
+const CHECK_TYPES: bool = cfg!(any(debug_assertions, feature = "strict-types"));
+
+/// Check that `value` has `type_id`, named `expected`.
+unsafe fn has_type(
+    value: CFTypeRef,
+    expected: &'static str,
+    type_id: CFTypeID,
+) -> Result<(), UnexpectedCfType> {
+    let actual = CFGetTypeID(value);
+    if actual == type_id {
+        Ok(())
+    } else {
+        Err(UnexpectedCfType { expected, actual })
+    }
+}
+
+/// Check that `value` has `type_id`, named `expected`.
+///
+/// Debug builds, and release builds with the `strict-types` feature, check
+/// the type of `value`; other release builds trust it blindly.  Use this for
+/// types of other frameworks, and the `cast_cf_*` functions for
+/// CoreFoundation types.
+///
+/// # Errors
+///
+/// Return `UnexpectedCfType` if the check finds that `value` has another
+/// type.
+///
+/// # Safety
+///
+/// `value` must be a valid CoreFoundation object.
+pub unsafe fn check_type(
+    value: CFTypeRef,
+    expected: &'static str,
+    type_id: CFTypeID,
+) -> Result<(), UnexpectedCfType> {
+    if CHECK_TYPES {
+        has_type(value, expected, type_id)
+    } else {
+        Ok(())
+    }
+}
+
+/// Cast `value` to a `CFString`.
+///
+/// Debug builds, and release builds with the `strict-types` feature, check
+/// the type of `value`; other release builds trust it blindly.
+///
+/// # Errors
+///
+/// Return `UnexpectedCfType` if the check finds that `value` is no string.
+///
+/// # Safety
+///
+/// `value` must be a valid CoreFoundation object.
+///
+/// # Panics
+///
+/// Panic if `value` is null.
+pub unsafe fn cast_cf_string(value: CFTypeRef) -> Result<CFStringRef, UnexpectedCfType> {
+    assert!(!value.is_null());
+    check_type(value, "CFString", CFStringGetTypeID())?;
+    Ok(value as CFStringRef)
+}
+
+/// Cast `value` to a `CFData`.
+///
+/// Type checks as for `cast_cf_string`.
+///
+/// # Errors
+///
+/// Return `UnexpectedCfType` if the check finds that `value` is no data.
+///
+/// # Safety
+///
+/// `value` must be a valid CoreFoundation object.
+///
+/// # Panics
+///
+/// Panic if `value` is null.
+pub unsafe fn cast_cf_data(value: CFTypeRef) -> Result<CFDataRef, UnexpectedCfType> {
+    assert!(!value.is_null());
+    check_type(value, "CFData", CFDataGetTypeID())?;
+    Ok(value as CFDataRef)
+}
+
+/// Cast `value` to a `CFDictionary`.
+///
+/// Type checks as for `cast_cf_string`.
+///
+/// # Errors
+///
+/// Return `UnexpectedCfType` if the check finds that `value` is no
+/// dictionary.
+///
+/// # Safety
+///
+/// `value` must be a valid CoreFoundation object.
+///
+/// # Panics
+///
+/// Panic if `value` is null.
+pub unsafe fn cast_cf_dictionary(value: CFTypeRef) -> Result<CFDictionaryRef, UnexpectedCfType> {
+    assert!(!value.is_null());
+    check_type(value, "CFDictionary", CFDictionaryGetTypeID())?;
+    Ok(value as CFDictionaryRef)
+}
+
+/// Cast `value` to a `CFArray`.
+///
+/// Type checks as for `cast_cf_string`.
+///
+/// # Errors
+///
+/// Return `UnexpectedCfType` if the check finds that `value` is no array.
+///
+/// # Safety
+///
+/// `value` must be a valid CoreFoundation object.
+///
+/// # Panics
+///
+/// Panic if `value` is null.
+pub unsafe fn cast_cf_array(value: CFTypeRef) -> Result<CFArrayRef, UnexpectedCfType> {
+    assert!(!value.is_null());
+    check_type(value, "CFArray", CFArrayGetTypeID())?;
+    Ok(value as CFArrayRef)
+}
+
+/// Get the value of `key` in `dictionary` with `type_id`, named `expected`.
+///
+/// Return `Ok(None)` if `key` is absent, and `UnexpectedCfType` if the value
+/// has another type.
+unsafe fn typed_value(
+    dictionary: CFDictionaryRef,
+    key: CFStringRef,
+    expected: &'static str,
+    type_id: CFTypeID,
+) -> Result<Option<CFTypeRef>, UnexpectedCfType> {
+    assert!(!dictionary.is_null());
+    let value = CFDictionaryGetValue(dictionary, key as *const c_void);
+    if value.is_null() {
+        return Ok(None);
+    }
+    has_type(value, expected, type_id)?;
+    Ok(Some(value))
+}
+
+/// Get the string of `key` in `dictionary`.
+///
+/// Unlike `string_value` tell a missing key from a value of another type:
+/// return `Ok(None)` if `key` is absent, and `UnexpectedCfType` if its value
+/// is no string.
+///
+/// # Safety
+///
+/// `dictionary` must not be null, and `key` must be a valid string.
+pub unsafe fn cf_dict_get_string(
+    dictionary: CFDictionaryRef,
+    key: CFStringRef,
+) -> Result<Option<String>, UnexpectedCfType> {
+    let value = typed_value(dictionary, key, "CFString", CFStringGetTypeID())?;
+    value
+        .map(|value| string_from_cf_string(value as CFStringRef))
+        .transpose()
+}
+
+/// Get the data of `key` in `dictionary`.
+///
+/// Return `Ok(None)` if `key` is absent, and `UnexpectedCfType` if its value
+/// is no data.
+///
+/// # Safety
+///
+/// `dictionary` must not be null, and `key` must be a valid string.
+pub unsafe fn cf_dict_get_data(
+    dictionary: CFDictionaryRef,
+    key: CFStringRef,
+) -> Result<Option<Vec<u8>>, UnexpectedCfType> {
+    let value = typed_value(dictionary, key, "CFData", CFDataGetTypeID())?;
+    value
+        .map(|value| vec_from_cfdata(value as CFDataRef))
+        .transpose()
+}
+
+/// Get the date of `key` in `dictionary`.
+///
+/// Return `Ok(None)` if `key` is absent, and `UnexpectedCfType` if its value
+/// is no date.
+///
+/// # Safety
+///
+/// `dictionary` must not be null, and `key` must be a valid string.
+pub unsafe fn cf_dict_get_date(
+    dictionary: CFDictionaryRef,
+    key: CFStringRef,
+) -> Result<Option<SystemTime>, UnexpectedCfType> {
+    let value = typed_value(dictionary, key, "CFDate", CFDateGetTypeID())?;
+    Ok(value.map(|value| {
+        // CoreFoundation counts seconds since 2001
+        let seconds = CFDateGetAbsoluteTime(value as CFDateRef) + kCFAbsoluteTimeIntervalSince1970;
+        if seconds < 0.0 {
+            UNIX_EPOCH - Duration::from_secs_f64(-seconds)
+        } else {
+            UNIX_EPOCH + Duration::from_secs_f64(seconds)
+        }
+    }))
+}
+
+/// Get the boolean of `key` in `dictionary`.
+///
+/// Accept booleans as well as numbers, like `bool_value`.  Return `Ok(None)`
+/// if `key` is absent, and `UnexpectedCfType` if its value is neither.
+///
+/// # Safety
+///
+/// `dictionary` must not be null, and `key` must be a valid string.
+pub unsafe fn cf_dict_get_bool(
+    dictionary: CFDictionaryRef,
+    key: CFStringRef,
+) -> Result<Option<bool>, UnexpectedCfType> {
+    match typed_value(dictionary, key, "CFBoolean", CFBooleanGetTypeID()) {
+        Ok(value) => Ok(value.map(|value| CFBooleanGetValue(value as CFBooleanRef) != 0)),
+        Err(error) if error.actual == CFNumberGetTypeID() => {
+            Ok(number_value(dictionary, key).map(|number| number != 0))
+        }
+        Err(error) => Err(error),
+    }
+}
+
+/// Converts a `CFData` to a vector.
+///
+/// # Safety
+///
+/// The caller must ensure that `cfdata` is not null.
+///
+/// # Errors
+///
+/// Return `UnexpectedCfType` if `cfdata` is no data, in debug builds or with
+/// the `strict-types` feature; see `cast_cf_data`.
+pub unsafe fn vec_from_cfdata(cfdata: CFDataRef) -> Result<Vec<u8>, UnexpectedCfType> {
+    assert!(!cfdata.is_null());
+    check_type(cfdata as CFTypeRef, "CFData", CFDataGetTypeID())?;
+    let length = CFDataGetLength(cfdata) as usize;
+    // Empty data need not have any bytes to point to
+    if length == 0 {
+        return Ok(Vec::new());
+    }
+    Ok(std::slice::from_raw_parts(CFDataGetBytePtr(cfdata), length).into())
+}
+
+/// How many dictionary entries `create_dictionary` keeps on the stack.
+///
+/// Nearly all keychain queries have fewer entries.
+pub const INLINE_ENTRIES: usize = 8;
+
+/// Create a `CFDictionary` from `keys` and `values` of equal length.
+unsafe fn create_dictionary_from(
+    keys: &mut [CFTypeRef],
+    values: &mut [CFTypeRef],
+) -> CFDictionaryRef {
+    CFDictionaryCreate(
+        std::ptr::null_mut(),
+        keys.as_mut_ptr(),
+        values.as_mut_ptr(),
+        keys.len() as i64,
+        &kCFTypeDictionaryKeyCallBacks,
+        &kCFTypeDictionaryValueCallBacks,
+    )
+}
+
+/// Items for `create_dictionary`, kept on the stack.
+///
+/// Queries mostly have a fixed set of items and a few optional ones; collect
+/// them here rather than in a `Vec`, so that building a query allocates
+/// nothing besides the dictionary itself.  Holds up to `INLINE_ENTRIES` items,
+/// and dereferences to a slice of them:
+///
+/// ```no_run
+/// use cf_util::{create_cf_string, create_dictionary, DictionaryItems};
+///
+/// unsafe {
+///     let key = create_cf_string("key") as _;
+///     let value = create_cf_string("value") as _;
+///     let mut items = DictionaryItems::new(&[]);
+///     items.push((key, value));
+///     let dictionary = create_dictionary(&items);
+/// }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct DictionaryItems {
+    items: [(CFTypeRef, CFTypeRef); INLINE_ENTRIES],
+    length: usize,
+}
+
+impl DictionaryItems {
+    /// Start with `items`.
+    ///
+    /// # Panics
+    ///
+    /// Panic if there are more than `INLINE_ENTRIES` items.
+    pub fn new(items: &[(CFTypeRef, CFTypeRef)]) -> DictionaryItems {
+        let mut inline = DictionaryItems {
+            items: [(std::ptr::null(), std::ptr::null()); INLINE_ENTRIES],
+            length: 0,
+        };
+        for &item in items {
+            inline.push(item);
+        }
+        inline
+    }
+
+    /// Add `item`.
+    ///
+    /// # Panics
+    ///
+    /// Panic if there are `INLINE_ENTRIES` items already.
+    pub fn push(&mut self, item: (CFTypeRef, CFTypeRef)) {
+        assert!(self.length < INLINE_ENTRIES, "Too many dictionary items");
+        self.items[self.length] = item;
+        self.length += 1;
+    }
+}
+
+impl Deref for DictionaryItems {
+    type Target = [(CFTypeRef, CFTypeRef)];
+
+    fn deref(&self) -> &[(CFTypeRef, CFTypeRef)] {
+        &self.items[..self.length]
+    }
+}
+
+/// Create a `CFDictionary` from items.
+///
+/// Dictionaries of up to `INLINE_ENTRIES` items need no allocation besides the
+/// dictionary itself; see `DictionaryItems` to build the items without
+/// allocating either.
+///
+/// # Safety
+///
+/// `items` must have types as expected by whoever uses the dictionary; they
+/// must also only contain CoreFoundation types!
+///
+/// The caller must call `CFRelease` on the returned dictionary.
+pub unsafe fn create_dictionary(items: &[(CFTypeRef, CFTypeRef)]) -> CFDictionaryRef {
+    if items.len() <= INLINE_ENTRIES {
+        let mut keys = [std::ptr::null(); INLINE_ENTRIES];
+        let mut values = [std::ptr::null(); INLINE_ENTRIES];
+        for (index, &(key, value)) in items.iter().enumerate() {
+            keys[index] = key;
+            values[index] = value;
+        }
+        create_dictionary_from(&mut keys[..items.len()], &mut values[..items.len()])
+    } else {
+        let mut keys: Vec<CFTypeRef> = items.iter().map(|i| i.0).collect();
+        let mut values: Vec<CFTypeRef> = items.iter().map(|i| i.1).collect();
+        create_dictionary_from(&mut keys, &mut values)
+    }
+}
+
+/// Create a `CFArray` from `values`.
+///
+/// # Safety
+///
+/// `values` must only contain CoreFoundation types.
+///
+/// The caller must call `CFRelease` on the returned array.
+pub unsafe fn create_array(values: &[CFTypeRef]) -> CFArrayRef {
+    let mut values = values.to_vec();
+    CFArrayCreate(
+        std::ptr::null_mut(),
+        values.as_mut_ptr(),
+        values.len() as i64,
+        &kCFTypeArrayCallBacks,
+    )
+}
@@ -0,0 +1,238 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Ownership of CoreFoundation objects, after the Create and Get rules.
+//!
+//! CoreFoundation functions with `Create` or `Copy` in their name return an
+//! object which the caller owns and must release; functions with `Get` in
+//! their name return an object which somebody else owns, typically the
+//! collection it came from, and which the caller must not release.  `Owned`
+//! and `Borrowed` put these rules into types:
+//!
+//! ```no_run
+//! use cf_util::{create_cf_string, create_dictionary, dict_get_string, Owned};
+//!
+//! unsafe {
+//!     let key = Owned::from_create(create_cf_string("key"));
+//!     let value = Owned::from_create(create_cf_string("value"));
+//!     let dictionary = Owned::from_create(create_dictionary(&[(
+//!         key.as_type_ref(),
+//!         value.as_type_ref(),
+//!     )]));
+//!     // The string belongs to the dictionary, and cannot outlive it
+//!     let borrowed = dict_get_string(dictionary.borrow(), key.get()).unwrap();
+//!     assert_eq!(borrowed.into_string().unwrap(), "value");
+//! }
+//! // Dropping `Owned` released the dictionary and both strings
+//! ```
+//!
+//! An `Owned` releases its object when dropped, and a `Borrowed` has no way
+//! to release its object, and cannot outlive the `Owned` it came from, so
+//! releasing an object too often or too early does not compile.  `retain`
+//! turns a borrowed object into an owned one, for objects which must outlive
+//! their collection.
+
+use std::marker::PhantomData;
+use std::mem;
+
+use crate::sys::*;
+use crate::UnexpectedCfType;
+
+/// A pointer to a CoreFoundation object.
+///
+/// # Safety
+///
+/// Implementors must be pointers to CoreFoundation objects, which
+/// `CFRetain` and `CFRelease` accept.
+pub unsafe trait CfType: Copy {
+    /// This object as `CFTypeRef`.
+    fn as_type_ref(self) -> CFTypeRef;
+
+    /// Whether this pointer is null.
+    fn is_null(self) -> bool {
+        self.as_type_ref().is_null()
+    }
+}
+
+/// Implement `CfType` for pointers to CoreFoundation objects.
+macro_rules! cf_types {
+    ($($name:ty),*) => {
+        $(
+            unsafe impl CfType for $name {
+                fn as_type_ref(self) -> CFTypeRef {
+                    self as CFTypeRef
+                }
+            }
+        )*
+    };
+}
+
+unsafe impl CfType for CFTypeRef {
+    fn as_type_ref(self) -> CFTypeRef {
+        self
+    }
+}
+
+cf_types!(
+    CFStringRef,
+    CFDataRef,
+    CFDictionaryRef,
+    CFArrayRef,
+    CFNumberRef,
+    CFBooleanRef,
+    CFDateRef
+);
+
+/// A CoreFoundation object which this code owns, and releases when dropped.
+#[derive(Debug)]
+pub struct Owned<T: CfType> {
+    object: T,
+}
+
+impl<T: CfType> Owned<T> {
+    /// Take ownership of `object` from a function under the Create rule,
+    /// ie, with `Create` or `Copy` in its name.
+    ///
+    /// # Safety
+    ///
+    /// `object` must be a valid CoreFoundation object which the caller owns
+    /// and does not release otherwise.
+    ///
+    /// # Panics
+    ///
+    /// Panic if `object` is null.
+    pub unsafe fn from_create(object: T) -> Owned<T> {
+        assert!(!object.is_null());
+        Owned { object }
+    }
+
+    /// Retain `object` from a function under the Get rule, ie, with `Get` in
+    /// its name, to own it.
+    ///
+    /// # Safety
+    ///
+    /// `object` must be a valid CoreFoundation object.
+    ///
+    /// # Panics
+    ///
+    /// Panic if `object` is null.
+    pub unsafe fn from_get(object: T) -> Owned<T> {
+        assert!(!object.is_null());
+        CFRetain(object.as_type_ref());
+        Owned { object }
+    }
+
+    /// The object, for CoreFoundation functions.
+    ///
+    /// The object remains owned by this `Owned`; do not release it.
+    pub fn get(&self) -> T {
+        self.object
+    }
+
+    /// The object as `CFTypeRef`, for CoreFoundation functions.
+    pub fn as_type_ref(&self) -> CFTypeRef {
+        self.object.as_type_ref()
+    }
+
+    /// Borrow the object.
+    pub fn borrow(&self) -> Borrowed<'_, T> {
+        Borrowed {
+            object: self.object,
+            owner: PhantomData,
+        }
+    }
+
+    /// Give up ownership of the object without releasing it.
+    ///
+    /// The caller must call `CFRelease` on the returned object.
+    pub fn into_raw(self) -> T {
+        let object = self.object;
+        mem::forget(self);
+        object
+    }
+}
+
+impl<T: CfType> Drop for Owned<T> {
+    fn drop(&mut self) {
+        unsafe { CFRelease(self.object.as_type_ref()) }
+    }
+}
+
+/// A CoreFoundation object which belongs to something else that lives for
+/// `'a`, typically the collection it came from.
+#[derive(Debug, Clone, Copy)]
+pub struct Borrowed<'a, T: CfType> {
+    object: T,
+    owner: PhantomData<&'a ()>,
+}
+
+impl<'a, T: CfType> Borrowed<'a, T> {
+    /// Borrow `object` from a function under the Get rule, ie, with `Get` in
+    /// its name.
+    ///
+    /// # Safety
+    ///
+    /// `object` must be a valid CoreFoundation object which remains valid for
+    /// `'a`.
+    ///
+    /// # Panics
+    ///
+    /// Panic if `object` is null.
+    pub unsafe fn from_get(object: T) -> Borrowed<'a, T> {
+        assert!(!object.is_null());
+        Borrowed {
+            object,
+            owner: PhantomData,
+        }
+    }
+
+    /// The object, for CoreFoundation functions.
+    ///
+    /// The object belongs to its owner; do not release it.
+    pub fn get(self) -> T {
+        self.object
+    }
+
+    /// The object as `CFTypeRef`, for CoreFoundation functions.
+    pub fn as_type_ref(self) -> CFTypeRef {
+        self.object.as_type_ref()
+    }
+
+    /// Retain the object, to own it beyond `'a`.
+    pub fn retain(self) -> Owned<T> {
+        unsafe { Owned::from_get(self.object) }
+    }
+}
+
+impl Borrowed<'_, CFStringRef> {
+    /// Convert this string to a Rust string, see `string_from_cf_string`.
+    ///
+    /// # Errors
+    ///
+    /// Return `UnexpectedCfType` if the object is no string.
+    pub fn into_string(self) -> Result<String, UnexpectedCfType> {
+        unsafe { crate::string_from_cf_string(self.object) }
+    }
+}
+
+impl Borrowed<'_, CFDataRef> {
+    /// Copy the bytes of this data.
+    ///
+    /// # Errors
+    ///
+    /// Return `UnexpectedCfType` if the object is no data.
+    pub fn into_vec(self) -> Result<Vec<u8>, UnexpectedCfType> {
+        unsafe { crate::vec_from_cfdata(self.object) }
+    }
+}
@@ -0,0 +1,45 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! The age plugin for keychain identities.
+
+#![deny(warnings)]
+
+use rust_ffi_demo::age_plugin;
+use std::env;
+use std::io;
+use std::process;
+
+fn main() {
+    let state_machine = env::args().find_map(|arg| {
+        arg.strip_prefix("--age-plugin=")
+            .map(ToString::to_string)
+    });
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let stdout = io::stdout();
+    let mut output = stdout.lock();
+    let result = match state_machine.as_deref() {
+        Some("identity-v1") => age_plugin::run_identity_v1(&mut input, &mut output),
+        Some("recipient-v1") => age_plugin::run_recipient_v1(&mut input, &mut output),
+        _ => {
+            eprintln!("This binary is an age plugin; use age-identity to create identities.");
+            process::exit(2);
+        }
+    };
+    if let Err(error) = result {
+        eprintln!("age-plugin-keychain: {}", error);
+        process::exit(1);
+    }
+}
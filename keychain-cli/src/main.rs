@@ -0,0 +1,2957 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+#![deny(warnings)]
+
+use clap::{
+    crate_description, crate_version, value_t, App, AppSettings, Arg, ArgMatches, SubCommand,
+};
+use indicatif::{ProgressBar, ProgressStyle};
+use rust_ffi_demo::audit_log::{self, AuditLog};
+use rust_ffi_demo::bulk::{self, BulkReport};
+use rust_ffi_demo::credentials::Credential;
+use rust_ffi_demo::dump;
+use rust_ffi_demo::formatter::{self, OutputFormatter, Value};
+#[cfg(feature = "age")]
+use rust_ffi_demo::keychain::age;
+use rust_ffi_demo::keychain::attachments;
+#[cfg(feature = "dlopen-security")]
+use rust_ffi_demo::keychain::dynamic;
+use rust_ffi_demo::keychain::events::{self, Event};
+use rust_ffi_demo::keychain::items::{self, Filter, Item, ListOptions};
+use rust_ffi_demo::keychain::keychains::{self, Keychain, SystemKeychain};
+use rust_ffi_demo::keychain::labels::{self, DefaultLabel};
+use rust_ffi_demo::keychain::local_auth;
+use rust_ffi_demo::keychain::metadata::{self, Migrated};
+use rust_ffi_demo::keychain::notes;
+use rust_ffi_demo::keychain::protection::{Accessibility, Flag};
+use rust_ffi_demo::keychain::query::{self, FilterExpr};
+use rust_ffi_demo::keychain::read_only;
+use rust_ffi_demo::keychain::sensitive::{self, Redaction, Sensitive};
+use rust_ffi_demo::keychain::totp::{self, Totp};
+use rust_ffi_demo::keychain::wifi;
+use rust_ffi_demo::keychain::{self, KeychainError};
+use rust_ffi_demo::launchd::Agent;
+use rust_ffi_demo::lint::{Finding, Rules};
+use rust_ffi_demo::manifest::{self, Manifest};
+use rust_ffi_demo::migrate::{self, Outcome};
+use rust_ffi_demo::output::{self, format_time, json_optional_string, json_string};
+use rust_ffi_demo::pattern::glob_match;
+use rust_ffi_demo::policy::{Access, Decision, Policy};
+use rust_ffi_demo::profile::{self, Format, Profile, Profiles};
+use rust_ffi_demo::progress::{self, Progress};
+use rust_ffi_demo::reveal::{self, Revealed};
+use rust_ffi_demo::runtime;
+use rust_ffi_demo::search::{self, Matcher};
+use rust_ffi_demo::shell::{self, Dialect, Shell};
+use rust_ffi_demo::store::{FileStore, KeychainStore, SecretStore};
+use rust_ffi_demo::sync::{self, Conflict, Side};
+#[cfg(feature = "tui")]
+use rust_ffi_demo::tui;
+use rust_ffi_demo::{
+    batch, canary, credentials, diff, doctor, export, harden, hook, metrics_endpoint, mount,
+    pinentry, repl, ssh_agent,
+};
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufWriter, IsTerminal, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The name of the command.
+///
+/// The name of this crate is `keychain-cli`, but the command keeps its
+/// original name.
+const NAME: &str = "rust-ffi-demo";
+
+/// The result of a subcommand.
+type CommandResult = Result<(), Box<dyn Error>>;
+
+/// The environment variable with a JSON export to use as store in place of
+/// keychains.
+///
+/// The snapshot tests set it, so that they never touch a keychain: the
+/// subcommands in `STORE_COMMANDS` read and write the export, and all others
+/// refuse to run.
+const STORE_VAR: &str = "RUST_FFI_DEMO_STORE";
+
+/// The subcommands which work with the store of `STORE_VAR`.
+///
+/// Besides the subcommands on generic passwords these are the subcommands
+/// which need no keychain, or take stores by name.
+const STORE_COMMANDS: [&str; 13] = [
+    "list", "get", "delete", "export", "import", "env", "search", "lint", "diff", "sync", "hook",
+    "allow", "deny",
+];
+
+/// An access which the policy denied, or the user declined to confirm.
+#[derive(Debug)]
+struct PolicyDenied(String);
+
+impl fmt::Display for PolicyDenied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for PolicyDenied {}
+
+/// A bulk operation in which some items failed.
+#[derive(Debug)]
+struct ItemsFailed {
+    /// How many items failed.
+    message: String,
+    /// The error of the first failed item, if any item failed rather than
+    /// being skipped.
+    first: Option<KeychainError>,
+}
+
+impl fmt::Display for ItemsFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for ItemsFailed {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.first
+            .as_ref()
+            .map(|error| error as &(dyn Error + 'static))
+    }
+}
+
+/// The policy for services, loaded at startup.
+static POLICY: OnceLock<Policy> = OnceLock::new();
+
+/// The profile of the environment, selected at startup.
+static PROFILE: OnceLock<Profile> = OnceLock::new();
+
+/// The profile of the environment, or the empty profile if none is selected.
+fn profile() -> &'static Profile {
+    PROFILE.get_or_init(Profile::default)
+}
+
+/// Get the `service` argument, in the environment of the profile.
+fn service_arg(matches: &ArgMatches<'_>) -> Option<String> {
+    matches
+        .value_of("service")
+        .map(|service| profile().service(service))
+}
+
+/// The filter of the `--tag` options in `matches`.
+fn tag_filter(matches: &ArgMatches<'_>) -> FilterExpr {
+    FilterExpr::all_tags(matches.values_of("tag").into_iter().flatten())
+}
+
+/// The policy.
+fn policy() -> &'static Policy {
+    POLICY.get().expect("Policy not loaded")
+}
+
+/// Check `access` to `services` against the policy.
+///
+/// Ask the user to confirm accesses which the policy wants confirmed, and
+/// fail if stdin is no terminal to ask on.
+fn enforce_policy<'a, I: IntoIterator<Item = &'a str>>(
+    services: I,
+    access: Access,
+) -> CommandResult {
+    let policy = policy();
+    let mut confirm = Vec::new();
+    for service in services {
+        match policy.check(service, access) {
+            Decision::Allowed => (),
+            Decision::Confirm => confirm.push(service),
+            Decision::Denied(reason) => {
+                let message = format!("Policy forbids {} {}: {}", access, service, reason);
+                return Err(PolicyDenied(message).into());
+            }
+        }
+    }
+    if confirm.is_empty() {
+        return Ok(());
+    }
+    confirm.sort_unstable();
+    confirm.dedup();
+    let stdin = io::stdin();
+    if !stdin.is_terminal() {
+        return Err(PolicyDenied(format!(
+            "Policy requires confirmation for {} {}",
+            access,
+            confirm.join(", ")
+        ))
+        .into());
+    }
+    eprint!(
+        "{}",
+        policy
+            .prompts
+            .confirm(&access.to_string(), &confirm.join(", "))
+    );
+    let answer = read_line(&mut stdin.lock(), "confirmation")?;
+    if answer.trim().eq_ignore_ascii_case("y") {
+        Ok(())
+    } else {
+        Err(PolicyDenied("Not confirmed".to_string()).into())
+    }
+}
+
+/// Add, get and delete a demo account.
+fn demo() -> CommandResult {
+    let account = keychain::Account {
+        name: "foo".to_string(),
+        password: Sensitive::new("very safe password".to_string()),
+    };
+    let service = "fancy-service";
+    println!(
+        "Delete: {:?}",
+        keychain::delete_generic_passwords_by_service(service)
+    );
+    println!(
+        "Add: {:?}",
+        keychain::add_generic_password(service, &account)
+    );
+    println!("Exists: {:?}", keychain::exists(service, &account.name));
+    println!(
+        "Get: {:?}",
+        keychain::find_generic_password_by_service(service)
+    );
+    println!(
+        "Get account: {:?}",
+        keychain::find_generic_password(service, &account.name)
+    );
+    println!(
+        "Cleanup: {:?}",
+        keychain::delete_generic_passwords_by_service(service)
+    );
+    Ok(())
+}
+
+/// Print the current TOTP code for a service.
+fn totp(matches: &ArgMatches<'_>) -> CommandResult {
+    let service = service_arg(matches).unwrap();
+    enforce_policy(Some(service.as_str()), Access::Read)?;
+    println!("{}", totp::current_code(&service)?);
+    Ok(())
+}
+
+/// Store a TOTP secret for a service.
+fn add_totp(matches: &ArgMatches<'_>) -> CommandResult {
+    let service = service_arg(matches).unwrap();
+    enforce_policy(Some(service.as_str()), Access::Write)?;
+    let account = matches.value_of("account").unwrap();
+    let totp = Totp {
+        issuer: matches.value_of("issuer").map(ToString::to_string),
+        digits: value_t!(matches, "digits", u32).unwrap_or_else(|e| e.exit()),
+        period: value_t!(matches, "period", u64).unwrap_or_else(|e| e.exit()),
+        algorithm: matches.value_of("algorithm").unwrap().parse()?,
+        ..Totp::from_base32(matches.value_of("secret").unwrap())?
+    };
+    totp::add_totp(&service, account, &totp)?;
+    Ok(())
+}
+
+/// Print, or generate, an age identity in the keychain.
+#[cfg(feature = "age")]
+fn age_identity(matches: &ArgMatches<'_>) -> CommandResult {
+    let name = matches.value_of("name").unwrap();
+    let identity = if matches.is_present("generate") {
+        enforce_policy(Some(age::SERVICE), Access::Write)?;
+        age::generate_identity(name)?
+    } else {
+        enforce_policy(Some(age::SERVICE), Access::Read)?;
+        age::find_identity(name)?
+    };
+    println!("# name: {}", identity.name);
+    println!("# recipient: {}", identity.recipient());
+    println!("{}", identity.plugin_identity());
+    Ok(())
+}
+
+/// Get the body of a note from the arguments, or from standard input.
+fn note_body(matches: &ArgMatches<'_>) -> io::Result<String> {
+    match matches.value_of("body") {
+        Some(body) => Ok(body.to_string()),
+        None => {
+            let mut body = String::new();
+            io::stdin().read_to_string(&mut body)?;
+            Ok(body)
+        }
+    }
+}
+
+/// Manage secure notes.
+fn note(matches: &ArgMatches<'_>) -> CommandResult {
+    // The title of a note is its service
+    let (name, m) = matches.subcommand();
+    let title = m.unwrap().value_of("title").unwrap();
+    let access = match name {
+        "show" => Access::Read,
+        "delete" => Access::Delete,
+        _ => Access::Write,
+    };
+    enforce_policy(Some(title), access)?;
+    match name {
+        "add" => notes::add_note(title, &note_body(m.unwrap())?)?,
+        "show" => print!("{}", notes::find_note(title)?.body),
+        "update" => notes::update_note(title, &note_body(m.unwrap())?)?,
+        "delete" => notes::delete_note(title)?,
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+/// Manage files stored as secrets.
+fn attachment(matches: &ArgMatches<'_>) -> CommandResult {
+    match matches.subcommand() {
+        ("add", Some(m)) => {
+            let service = service_arg(m).unwrap();
+            enforce_policy(Some(service.as_str()), Access::Write)?;
+            let attachment = attachments::store_file(
+                &service,
+                m.value_of("name").unwrap(),
+                m.value_of_os("file").unwrap(),
+            )?;
+            println!("Stored {} ({})", attachment.file_name, attachment.sha256);
+        }
+        ("get", Some(m)) => {
+            let service = service_arg(m).unwrap();
+            enforce_policy(Some(service.as_str()), Access::Read)?;
+            let attachment = attachments::retrieve_file(&service, m.value_of("name").unwrap())?;
+            match m.value_of_os("output") {
+                Some(path) => {
+                    // The file holds a secret, so keep it private
+                    let mut file = OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .mode(0o600)
+                        .open(path)?;
+                    file.write_all(&attachment.contents)?;
+                }
+                None => io::stdout().write_all(&attachment.contents)?,
+            }
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+/// Manage the tags of items.
+fn tag(matches: &ArgMatches<'_>) -> CommandResult {
+    let (name, m) = matches.subcommand();
+    let m = m.unwrap();
+    let keychain = keychain_arg(m)?;
+    let service = service_arg(m).unwrap();
+    let account = m.value_of("account").unwrap();
+    let tags: Vec<&str> = m
+        .values_of("tag")
+        .map(Iterator::collect)
+        .unwrap_or_default();
+    for tag in &tags {
+        metadata::check_tag(tag)?;
+    }
+    let access = if name == "list" {
+        Access::Read
+    } else {
+        Access::Write
+    };
+    enforce_policy(Some(service.as_str()), access)?;
+    let item = items::find_item(keychain.as_ref(), &service, account)?;
+    let mut metadata = item.metadata()?;
+    match name {
+        "add" => {
+            for tag in &tags {
+                metadata.add_tag(tag)?;
+            }
+        }
+        "remove" => {
+            for tag in &tags {
+                metadata.remove_tag(tag);
+            }
+        }
+        "list" => {
+            for tag in metadata.tags() {
+                println!("{}", tag);
+            }
+            return Ok(());
+        }
+        _ => unreachable!(),
+    }
+    metadata::set_metadata(keychain.as_ref(), &item, &metadata)?;
+    Ok(())
+}
+
+/// Read a line from `lines`, without the trailing newline.
+fn read_line<B: BufRead>(lines: &mut B, what: &str) -> io::Result<String> {
+    let mut line = String::new();
+    if lines.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("Missing {} on stdin", what),
+        ));
+    }
+    if line.ends_with('\n') {
+        line.pop();
+    }
+    Ok(line)
+}
+
+/// Manage keychain files.
+fn manage_keychain(matches: &ArgMatches<'_>) -> CommandResult {
+    match matches.subcommand() {
+        ("change-password", Some(m)) => {
+            let keychain = Keychain::named(m.value_of("keychain").unwrap())?;
+            let stdin = io::stdin();
+            let mut stdin = stdin.lock();
+            let old = read_line(&mut stdin, "old password")?;
+            let new = read_line(&mut stdin, "new password")?;
+            keychain.change_password(&old, &new)?;
+        }
+        ("unlock", Some(m)) => {
+            let keychain = Keychain::named(m.value_of("keychain").unwrap())?;
+            keychain.unlock_with_key_file(m.value_of_os("key-file").unwrap())?;
+        }
+        ("default", Some(m)) => match m.value_of("keychain") {
+            Some(name) => keychains::set_default_keychain(&Keychain::named(name)?)?,
+            None => println!("{}", keychains::default_keychain()?.path().display()),
+        },
+        ("delete-keychain", Some(m)) => {
+            let keychain = Keychain::named(m.value_of("keychain").unwrap())?;
+            let path = keychain.resolved_path()?;
+            if !m.is_present("force") {
+                return Err(format!("Pass --force to delete {}", path.display()).into());
+            }
+            keychain.delete(&path)?;
+            println!("Deleted {}", path.display());
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+/// Run a pinentry for gpg-agent.
+fn pinentry(matches: &ArgMatches<'_>) -> CommandResult {
+    let config = pinentry::Config {
+        service: service_arg(matches).unwrap(),
+        touch_id: matches.is_present("touch-id"),
+    };
+    enforce_policy(Some(config.service.as_str()), Access::Read)?;
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    pinentry::run(&mut stdin.lock(), &mut stdout.lock(), &config)?;
+    Ok(())
+}
+
+/// Browse keychain items interactively.
+#[cfg(feature = "tui")]
+fn tui() -> CommandResult {
+    tui::run(&mut |service: &str, access| policy().check(service, access))?;
+    Ok(())
+}
+
+/// Run an interactive shell for keychain maintenance.
+fn repl() -> CommandResult {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let interactive = stdin.is_terminal();
+    let mut guard = |service: &str, access| policy().check(service, access);
+    repl::run(
+        &mut stdin.lock(),
+        &mut stdout.lock(),
+        interactive,
+        &mut guard,
+    )?;
+    Ok(())
+}
+
+/// Run newline-delimited JSON commands from stdin.
+///
+/// Stdin carries the commands, so batch mode cannot ask to confirm accesses;
+/// the policy must allow them outright.
+fn batch() -> CommandResult {
+    let mut guard = |service: &str, access: Access| {
+        let service = profile().service(service);
+        match policy().check(&service, access) {
+            Decision::Allowed => Ok(service),
+            Decision::Confirm => Err(format!(
+                "Policy requires confirmation for {} {}, which batch mode cannot ask for",
+                access, service
+            )),
+            Decision::Denied(reason) => {
+                Err(format!("Policy forbids {} {}: {}", access, service, reason))
+            }
+        }
+    };
+    batch::run(io::stdin().lock(), &mut io::stdout().lock(), &mut guard)?;
+    Ok(())
+}
+
+/// Get the `keychain` argument, or else the keychain of the profile.
+fn keychain_name<'a>(matches: &'a ArgMatches<'_>) -> Option<&'a str> {
+    matches
+        .value_of("keychain")
+        .or_else(|| profile().keychain.as_deref())
+}
+
+/// Open the keychain named by the `keychain` argument or the profile, if
+/// any.
+fn keychain_arg(matches: &ArgMatches<'_>) -> keychain::Result<Option<Keychain>> {
+    keychain_name(matches).map(Keychain::named).transpose()
+}
+
+/// Authorize writes to the System keychain, if the `keychain` argument selects
+/// it.
+///
+/// Writes are authorized as long as the returned keychain lives.
+fn authorize_keychain_arg(matches: &ArgMatches<'_>) -> keychain::Result<Option<SystemKeychain>> {
+    if keychain_name(matches) == Some("system") {
+        let mut system = SystemKeychain::open()?;
+        system.authorize()?;
+        Ok(Some(system))
+    } else {
+        Ok(None)
+    }
+}
+
+/// The error for keychains while `STORE_VAR` replaces them.
+fn store_replaces_keychains(what: &str) -> Box<dyn Error> {
+    format!("Cannot use {} while {} selects a store", what, STORE_VAR).into()
+}
+
+/// The store of `STORE_VAR`, if set.
+///
+/// Fail if `matches` select a keychain as well.
+fn mock_store(matches: &ArgMatches<'_>) -> Result<Option<FileStore>, Box<dyn Error>> {
+    match env::var_os(STORE_VAR) {
+        None => Ok(None),
+        Some(_) if keychain_name(matches).is_some() => Err(store_replaces_keychains("keychains")),
+        Some(path) => Ok(Some(FileStore::open(path)?)),
+    }
+}
+
+/// Open the keychain `name`, unless `STORE_VAR` replaces keychains.
+fn keychain_named(name: &str) -> Result<Keychain, Box<dyn Error>> {
+    if env::var_os(STORE_VAR).is_some() {
+        Err(store_replaces_keychains(&format!("keychain {}", name)))
+    } else {
+        Ok(Keychain::named(name)?)
+    }
+}
+
+/// The generic passwords in `store`, or else in `keychain`.
+fn listed_items(
+    store: Option<&FileStore>,
+    keychain: Option<&Keychain>,
+) -> keychain::Result<Vec<Item>> {
+    match store {
+        Some(store) => Ok(store.entries()?.into_iter().map(Item::from).collect()),
+        None => items::list_items(keychain),
+    }
+}
+
+/// The password of `service` and `account` in `store`, or else in
+/// `keychain`.
+fn find_password(
+    store: Option<&FileStore>,
+    keychain: Option<&Keychain>,
+    service: &str,
+    account: &str,
+) -> Result<String, Box<dyn Error>> {
+    match store {
+        Some(store) => Ok(String::from_utf8(store.get(service, account)?)?),
+        None => Ok(items::find_password(keychain, service, account)?.into_exposed()),
+    }
+}
+
+/// Whether to print reports and errors as JSON.
+fn json_format(matches: &ArgMatches<'_>) -> bool {
+    match profile().format {
+        Some(format) if matches.occurrences_of("format") == 0 => format == Format::Json,
+        _ => matches.value_of("format") == Some("json"),
+    }
+}
+
+/// The formatter for rows in the format of `matches`.
+///
+/// Return `None` for text, and for dumps like `security(1)`.
+fn row_formatter(matches: &ArgMatches<'_>) -> Option<Arc<dyn OutputFormatter>> {
+    if json_format(matches) {
+        formatter::get("json")
+    } else {
+        matches.value_of("format").and_then(formatter::get)
+    }
+}
+
+/// Whether to dump items in the layout of `security(1)`.
+fn security_format(matches: &ArgMatches<'_>) -> bool {
+    matches.value_of("format") == Some("security")
+}
+
+/// The path of `keychain`, or of the default keychain, for dumps.
+fn dump_keychain_path(keychain: Option<&Keychain>) -> Result<PathBuf, KeychainError> {
+    match keychain {
+        Some(keychain) => Ok(keychain.path().to_path_buf()),
+        None => Ok(Keychain::default_keychain()?.path().to_path_buf()),
+    }
+}
+
+/// Format `error` of the subcommand `operation` as JSON object.
+fn json_error(operation: &str, error: &(dyn Error + 'static)) -> String {
+    let (code, status, message, correlation_id) = match error.downcast_ref::<KeychainError>() {
+        Some(error) => (
+            error.status.name(),
+            error.status.os_status(),
+            error.message.clone(),
+            error.correlation_id.map(|id| id.to_string()),
+        ),
+        None => match error.downcast_ref::<ItemsFailed>() {
+            Some(ItemsFailed {
+                message,
+                first: Some(first),
+            }) => (
+                first.status.name(),
+                first.status.os_status(),
+                message.clone(),
+                first.correlation_id.map(|id| id.to_string()),
+            ),
+            _ if error.is::<PolicyDenied>() => ("PolicyDenied", None, error.to_string(), None),
+            _ => match error.downcast_ref::<io::Error>() {
+                Some(io_error) if io_error.kind() == io::ErrorKind::InvalidData => {
+                    ("InvalidData", None, error.to_string(), None)
+                }
+                Some(_) => ("Io", None, error.to_string(), None),
+                None => ("Error", None, error.to_string(), None),
+            },
+        },
+    };
+    format!(
+        r#"{{"error":{{"code":{},"status":{},"message":{},"operation":{},"correlation-id":{}}}}}"#,
+        json_string(code),
+        status.map_or_else(|| "null".to_string(), |status| status.to_string()),
+        json_string(&message),
+        json_string(operation),
+        json_optional_string(correlation_id.as_deref())
+    )
+}
+
+/// Store or check the canary item.
+fn canary(matches: &ArgMatches<'_>) -> CommandResult {
+    let state_path = canary::default_state_path();
+    match matches.subcommand() {
+        ("init", Some(m)) => {
+            enforce_policy(Some(canary::SERVICE), Access::Write)?;
+            let keychain = keychain_arg(m)?;
+            canary::init(keychain.as_ref(), &state_path)?;
+            println!("Stored canary; state in {}", state_path.display());
+        }
+        ("check", Some(m)) => {
+            enforce_policy(Some(canary::SERVICE), Access::Read)?;
+            let keychain = keychain_arg(m)?;
+            let check = canary::check(keychain.as_ref(), &state_path)?;
+            let previously_verified = check
+                .previously_verified
+                .map(|seconds| format_time(UNIX_EPOCH + Duration::from_secs(seconds)));
+            if json_format(m) {
+                println!(
+                    r#"{{"outcome":{},"previously-verified":{},"rotations":{}}}"#,
+                    json_string(check.outcome.name()),
+                    json_optional_string(previously_verified.as_deref()),
+                    check.state.rotations
+                );
+            } else {
+                println!(
+                    "{}\tlast verified {}",
+                    check.outcome.name(),
+                    previously_verified.as_deref().unwrap_or("never")
+                );
+            }
+            if check.outcome != canary::Outcome::Intact {
+                return Err(format!(
+                    "Canary {}; the keychain may have been reset, restored or tampered with",
+                    check.outcome.name()
+                )
+                .into());
+            }
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+/// Check keychain access and print a report.
+fn doctor(matches: &ArgMatches<'_>) -> CommandResult {
+    let report = match matches.subcommand_name() {
+        Some("entitlements") => doctor::entitlements(),
+        _ => doctor::run(),
+    };
+    if matches.is_present("json") || json_format(matches) {
+        let checks: Vec<String> = report
+            .checks
+            .iter()
+            .map(|check| {
+                let (status, details) = match check.outcome {
+                    doctor::Outcome::Passed(ref details) => ("passed", Some(details.as_str())),
+                    doctor::Outcome::Failed(ref reason) => ("failed", Some(reason.as_str())),
+                    doctor::Outcome::Skipped => ("skipped", None),
+                };
+                format!(
+                    r#"{{"name":{},"status":{},"details":{}}}"#,
+                    json_string(check.name),
+                    json_string(status),
+                    json_optional_string(details)
+                )
+            })
+            .collect();
+        println!(
+            r#"{{"healthy":{},"checks":[{}]}}"#,
+            report.healthy(),
+            checks.join(",")
+        );
+    } else {
+        for check in &report.checks {
+            let outcome = check.outcome.to_string();
+            let outcome = match check.outcome {
+                doctor::Outcome::Failed(_) => output::failure(&outcome),
+                doctor::Outcome::Skipped => output::dimmed(&outcome),
+                doctor::Outcome::Passed(_) => outcome,
+            };
+            println!("{}\t{}", check.name, outcome);
+        }
+    }
+    if report.healthy() {
+        Ok(())
+    } else {
+        Err("Some checks failed".into())
+    }
+}
+
+/// The format of the export in `matches`.
+///
+/// Take the format from flags, or else from the extension of the file.
+fn export_format(matches: &ArgMatches<'_>) -> export::Format {
+    if matches.is_present("lines") {
+        export::Format::JsonLines
+    } else if matches.is_present("yaml") {
+        export::Format::Yaml
+    } else if matches.is_present("toml") {
+        export::Format::Toml
+    } else {
+        matches
+            .value_of_os("file")
+            .map_or(export::Format::Json, |path| {
+                export::Format::for_path(Path::new(path))
+            })
+    }
+}
+
+/// Export generic passwords to a JSON, YAML or TOML file.
+fn export(matches: &ArgMatches<'_>) -> CommandResult {
+    let store = mock_store(matches)?;
+    let keychain = keychain_arg(matches)?;
+    let format = export_format(matches);
+    let bar = progress_bar();
+    let mut progress = |progress: &Progress<'_>| advance(&bar, progress);
+    let service = service_arg(matches);
+    let service = service.as_deref();
+    let filter = tag_filter(matches);
+    let listed = listed_items(store.as_ref(), keychain.as_ref())?;
+    enforce_policy(
+        listed
+            .iter()
+            .filter(|item| service.is_none_or(|service| service == item.service))
+            .filter(|item| filter.matches(item))
+            .map(|item| item.service.as_str()),
+        Access::Read,
+    )?;
+    match matches.value_of_os("file") {
+        Some(path) => {
+            // The export holds plain text secrets, so keep it private
+            let file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(path)?;
+            let writer = BufWriter::new(file);
+            match store {
+                Some(ref store) => {
+                    export::export_store(store, service, &filter, format, writer, &mut progress)?
+                }
+                None => export::export_where(
+                    keychain.as_ref(),
+                    service,
+                    &filter,
+                    format,
+                    writer,
+                    &mut progress,
+                )?,
+            }
+        }
+        None => {
+            let writer = io::stdout().lock();
+            match store {
+                Some(ref store) => {
+                    export::export_store(store, service, &filter, format, writer, &mut progress)?
+                }
+                None => export::export_where(
+                    keychain.as_ref(),
+                    service,
+                    &filter,
+                    format,
+                    writer,
+                    &mut progress,
+                )?,
+            }
+        }
+    };
+    bar.finish_and_clear();
+    Ok(())
+}
+
+/// Create a progress bar on stderr, hidden if stderr is no terminal.
+fn progress_bar() -> ProgressBar {
+    let bar = ProgressBar::new(0);
+    bar.set_style(ProgressStyle::with_template("{bar:40} {pos}/{len} {msg}").unwrap());
+    bar
+}
+
+/// Advance `bar` to `progress`.
+fn advance(bar: &ProgressBar, progress: &Progress<'_>) {
+    bar.set_length(progress.total as u64);
+    bar.set_position(progress.processed as u64);
+    bar.set_message(format!("{} {}", progress.service, progress.account));
+}
+
+/// Print the outcome of every item in `report`.
+///
+/// Return an error if any item failed or was skipped.
+fn print_bulk_report(report: &BulkReport) -> CommandResult {
+    for id in &report.succeeded {
+        println!("{}\tok", id);
+    }
+    for failure in &report.failed {
+        println!(
+            "{}\t{}",
+            failure.item,
+            output::failure(&format!("failed: {}", failure.error))
+        );
+    }
+    for id in &report.skipped {
+        println!("{}\t{}", id, output::dimmed("skipped"));
+    }
+    if report.is_success() {
+        Ok(())
+    } else {
+        Err(ItemsFailed {
+            message: format!(
+                "{} of {} items failed, {} skipped",
+                report.failed.len(),
+                report.len(),
+                report.skipped.len()
+            ),
+            first: report.failed.first().map(|failure| failure.error.clone()),
+        }
+        .into())
+    }
+}
+
+/// Print shell code which exports the passwords of services with a prefix.
+///
+/// Read all passwords before printing anything, so that a failure never
+/// leaves a shell with half of its variables.
+fn shell_env(matches: &ArgMatches<'_>) -> CommandResult {
+    let prefix = profile().service(matches.value_of("service-prefix").unwrap());
+    let dialect = if matches.is_present("fish") {
+        Dialect::Fish
+    } else {
+        Dialect::Posix
+    };
+    let store = mock_store(matches)?;
+    let keychain = keychain_arg(matches)?;
+    let listed = listed_items(store.as_ref(), keychain.as_ref())?;
+    let selected: Vec<&Item> = listed
+        .iter()
+        .filter(|item| item.service.starts_with(&prefix))
+        .collect();
+    enforce_policy(
+        selected.iter().map(|item| item.service.as_str()),
+        Access::Read,
+    )?;
+    let mut variables = BTreeMap::new();
+    for item in selected {
+        let name = shell::variable_name(&item.service[prefix.len()..])
+            .ok_or_else(|| format!("No variable name for service {}", item.service))?;
+        if variables.contains_key(&name) {
+            return Err(format!("Several items for variable {}", name).into());
+        }
+        let password = find_password(
+            store.as_ref(),
+            keychain.as_ref(),
+            &item.service,
+            &item.account,
+        )?;
+        variables.insert(name, password);
+    }
+    for (name, value) in &variables {
+        println!("{}", shell::export(name, value, dialect));
+    }
+    Ok(())
+}
+
+/// Print the shell hook which loads the secrets of projects.
+fn shell_hook(matches: &ArgMatches<'_>) -> CommandResult {
+    let shell: Shell = matches.value_of("shell").unwrap().parse()?;
+    let program = env::current_exe()
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|_| NAME.to_string());
+    print!("{}", shell::hook(shell, &program));
+    Ok(())
+}
+
+/// Print shell code which loads the secrets of the project of the working
+/// directory, and unloads those of the project the shell left.
+fn hook_env(matches: &ArgMatches<'_>) -> CommandResult {
+    let shell: Shell = matches.value_of("shell").unwrap().parse()?;
+    let directory = env::current_dir()?;
+    let allowed = hook::default_allowed_path();
+    if let Some(project) = hook::find_project(&directory) {
+        if hook::is_allowed(&allowed, &project)? {
+            let bindings = hook::read_project(&project)?;
+            enforce_policy(
+                bindings.iter().map(|binding| binding.service.as_str()),
+                Access::Read,
+            )?;
+        }
+    }
+    let scope = hook::Scope::from_env();
+    if let Some(transition) = hook::transition(None, &scope, &directory, &allowed)? {
+        if let Some(ref notice) = transition.notice {
+            eprintln!("{}: {}", NAME, notice);
+        }
+        print!("{}", transition.code(shell.dialect()));
+    }
+    Ok(())
+}
+
+/// The project file for the `directory` argument in `matches`, or the
+/// working directory.
+fn project_arg(matches: &ArgMatches<'_>) -> Result<PathBuf, Box<dyn Error>> {
+    let directory = match matches.value_of_os("directory") {
+        Some(directory) => PathBuf::from(directory),
+        None => env::current_dir()?,
+    };
+    hook::find_project(&directory).ok_or_else(|| {
+        format!(
+            "No {} in {} or above",
+            hook::PROJECT_FILE,
+            directory.display()
+        )
+        .into()
+    })
+}
+
+/// Allow or deny the project file of a directory.
+fn allow_project(matches: &ArgMatches<'_>, allow: bool) -> CommandResult {
+    let project = project_arg(matches)?;
+    let allowed = hook::default_allowed_path();
+    if allow {
+        hook::allow(&allowed, &project)?;
+        println!(
+            "Allowed {}; enter its directory again to load it",
+            project.display()
+        );
+    } else {
+        hook::deny(&allowed, &project)?;
+        println!("Denied {}", project.display());
+    }
+    Ok(())
+}
+
+/// Import generic passwords from a JSON export.
+fn import(matches: &ArgMatches<'_>) -> CommandResult {
+    let mut store = mock_store(matches)?;
+    let _authorization = authorize_keychain_arg(matches)?;
+    let keychain = keychain_arg(matches)?;
+    let records = match matches.value_of_os("file") {
+        Some(path) => export::read(File::open(path)?, export_format(matches))?,
+        None => export::read(io::stdin().lock(), export_format(matches))?,
+    };
+    enforce_policy(
+        records.iter().map(|record| record.service.as_str()),
+        Access::Write,
+    )?;
+    let bar = progress_bar();
+    let continue_on_error = matches.is_present("continue-on-error");
+    let mut progress = |progress: &Progress<'_>| advance(&bar, progress);
+    let report = match store {
+        Some(ref mut store) => bulk::import_into(store, &records, continue_on_error, &mut progress),
+        None => bulk::import(
+            keychain.as_ref(),
+            &records,
+            continue_on_error,
+            &mut progress,
+        ),
+    };
+    bar.finish_and_clear();
+    print_bulk_report(&report)
+}
+
+/// Delete generic passwords matching wildcard patterns.
+fn delete(matches: &ArgMatches<'_>) -> CommandResult {
+    let mut store = mock_store(matches)?;
+    let _authorization = authorize_keychain_arg(matches)?;
+    let keychain = keychain_arg(matches)?;
+    let service = service_arg(matches).unwrap();
+    let account = matches.value_of("account").unwrap_or("*");
+    let filter = tag_filter(matches);
+    let selected = |item: &Item| {
+        glob_match(&service, &item.service)
+            && glob_match(account, &item.account)
+            && filter.matches(item)
+    };
+    let listed = listed_items(store.as_ref(), keychain.as_ref())?;
+    enforce_policy(
+        listed
+            .iter()
+            .filter(|item| selected(item))
+            .map(|item| item.service.as_str()),
+        Access::Delete,
+    )?;
+    let continue_on_error = matches.is_present("continue-on-error");
+    let report = match store {
+        Some(ref mut store) => bulk::delete_from(
+            store,
+            |entry| selected(&Item::from(entry.clone())),
+            continue_on_error,
+            &mut progress::ignore,
+        )?,
+        None => bulk::delete_where(
+            keychain.as_ref(),
+            selected,
+            continue_on_error,
+            &mut progress::ignore,
+        )?,
+    };
+    print_bulk_report(&report)
+}
+
+/// The extensions of export files.
+const EXPORT_EXTENSIONS: [&str; 5] = ["json", "jsonl", "yaml", "yml", "toml"];
+
+/// Load records from an export, or from a keychain by name.
+fn diff_side(name: &str) -> Result<Vec<export::Record>, Box<dyn Error>> {
+    let path = Path::new(name);
+    if path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| EXPORT_EXTENSIONS.contains(&extension))
+        && path.is_file()
+    {
+        Ok(export::read(
+            File::open(path)?,
+            export::Format::for_path(path),
+        )?)
+    } else {
+        let keychain = keychain_named(name)?;
+        let listed = items::list_items(Some(&keychain))?;
+        enforce_policy(
+            listed.iter().map(|item| item.service.as_str()),
+            Access::Read,
+        )?;
+        Ok(export::collect(
+            Some(&keychain),
+            None,
+            &mut progress::ignore,
+        )?)
+    }
+}
+
+/// Compare two keychains, or a keychain and an export.
+fn diff(matches: &ArgMatches<'_>) -> CommandResult {
+    let left = diff_side(matches.value_of("left").unwrap())?;
+    let right = diff_side(matches.value_of("right").unwrap())?;
+    for change in diff::diff(&left, &right) {
+        println!("{}", change);
+    }
+    Ok(())
+}
+
+/// A change of `attribute` as JSON object.
+fn attribute_json(attribute: &str, from: Option<&str>, to: &str) -> String {
+    format!(
+        r#"{{"attribute":{},"from":{},"to":{}}}"#,
+        json_string(attribute),
+        json_optional_string(from),
+        json_string(to)
+    )
+}
+
+/// The attributes an action sets, as JSON objects.
+fn action_changes_json(action: &manifest::Action) -> Vec<String> {
+    let digest = |digest: &str| format!("sha256:{}", digest);
+    match *action {
+        manifest::Action::Create(ref entry) => {
+            let mut changes = Vec::new();
+            if let Some(ref label) = entry.label {
+                changes.push(attribute_json("label", None, label));
+            }
+            match entry.digest() {
+                Some(_) if entry.generated => {
+                    changes.push(attribute_json("value", None, "generated"))
+                }
+                Some(value) => changes.push(attribute_json("value", None, &digest(&value))),
+                None => {}
+            }
+            if let Some(preset) = entry.protection {
+                changes.push(attribute_json("protection", None, preset.name()));
+            }
+            changes
+        }
+        manifest::Action::Update(_, ref differences) => differences
+            .iter()
+            .map(|difference| match *difference {
+                manifest::Difference::Label(ref from, ref to) => {
+                    attribute_json("label", from.as_deref(), to)
+                }
+                manifest::Difference::Value => attribute_json("value", None, "differs"),
+                manifest::Difference::Protection(ref from, to) => attribute_json(
+                    "protection",
+                    from.as_ref().map(ToString::to_string).as_deref(),
+                    to.name(),
+                ),
+            })
+            .collect(),
+        manifest::Action::Delete { .. } => Vec::new(),
+    }
+}
+
+/// A plan as JSON object.
+fn plan_json(plan: &manifest::Plan) -> String {
+    let actions: Vec<String> = plan
+        .actions
+        .iter()
+        .map(|action| {
+            let kind = match *action {
+                manifest::Action::Create(_) => "create",
+                manifest::Action::Update(..) => "update",
+                manifest::Action::Delete { .. } => "delete",
+            };
+            let (service, account) = action.target();
+            format!(
+                r#"{{"action":{},"service":{},"account":{},"changes":[{}]}}"#,
+                json_string(kind),
+                json_string(service),
+                json_string(account),
+                action_changes_json(action).join(",")
+            )
+        })
+        .collect();
+    let (create, update, delete) = plan.counts();
+    format!(
+        r#"{{"create":{},"update":{},"delete":{},"actions":[{}]}}"#,
+        create,
+        update,
+        delete,
+        actions.join(",")
+    )
+}
+
+/// Show what applying a manifest would change, without changing anything.
+fn plan(matches: &ArgMatches<'_>) -> CommandResult {
+    let keychain = keychain_arg(matches)?;
+    let manifest = Manifest::load(Path::new(matches.value_of_os("file").unwrap()))?;
+    enforce_policy(manifest.read_services(), Access::Read)?;
+    let plan = manifest::plan(keychain.as_ref(), &manifest, matches.is_present("prune"))?;
+    if json_format(matches) {
+        println!("{}", plan_json(&plan));
+    } else {
+        print!("{}", plan);
+    }
+    Ok(())
+}
+
+/// Converge the keychain to a manifest, after showing the plan.
+fn apply(matches: &ArgMatches<'_>) -> CommandResult {
+    let _authorization = authorize_keychain_arg(matches)?;
+    let keychain = keychain_arg(matches)?;
+    let manifest = Manifest::load(Path::new(matches.value_of_os("file").unwrap()))?;
+    enforce_policy(manifest.read_services(), Access::Read)?;
+    let plan = manifest::plan(keychain.as_ref(), &manifest, matches.is_present("prune"))?;
+    print!("{}", plan);
+    if plan.is_empty() {
+        return Ok(());
+    }
+    let targets = |delete: bool| {
+        plan.actions
+            .iter()
+            .filter(move |action| matches!(action, manifest::Action::Delete { .. }) == delete)
+            .map(|action| action.target().0)
+    };
+    enforce_policy(targets(false), Access::Write)?;
+    enforce_policy(targets(true), Access::Delete)?;
+    if !matches.is_present("yes") {
+        let stdin = io::stdin();
+        if !stdin.is_terminal() {
+            return Err("Not applying without --yes, stdin is no terminal".into());
+        }
+        eprint!("Apply this plan? [y/N] ");
+        let answer = read_line(&mut stdin.lock(), "confirmation")?;
+        if !answer.trim().eq_ignore_ascii_case("y") {
+            return Err("Not applied".into());
+        }
+    }
+    let report = manifest::apply(keychain.as_ref(), &plan)?;
+    println!("Applied {} changes", report.applied);
+    Ok(())
+}
+
+/// Copy or move items between keychains.
+fn migrate(matches: &ArgMatches<'_>) -> CommandResult {
+    let from = Keychain::named(matches.value_of("from").unwrap())?;
+    let to = Keychain::named(matches.value_of("to").unwrap())?;
+    let pattern = service_arg(matches);
+    let pattern = pattern.as_deref();
+    let listed = items::list_items(Some(&from))?;
+    let services: Vec<&str> = listed
+        .iter()
+        .map(|item| item.service.as_str())
+        .filter(|service| glob_match(pattern.unwrap_or("*"), service))
+        .collect();
+    enforce_policy(services.iter().copied(), Access::Write)?;
+    if matches.is_present("move") {
+        enforce_policy(services.iter().copied(), Access::Delete)?;
+    }
+    let bar = progress_bar();
+    let outcomes = migrate::migrate(
+        &from,
+        &to,
+        pattern,
+        matches.is_present("move"),
+        &mut |progress| advance(&bar, progress),
+    )?;
+    bar.finish_and_clear();
+    let mut failed = 0;
+    let mut first = None;
+    for outcome in &outcomes {
+        if let Outcome::Failed(ref error) = outcome.outcome {
+            failed += 1;
+            first = first.or_else(|| Some(error.clone()));
+        }
+        let text = outcome.outcome.to_string();
+        let text = match outcome.outcome {
+            Outcome::Failed(_) => output::failure(&text),
+            Outcome::SkippedDuplicate => output::dimmed(&text),
+            _ => text,
+        };
+        println!(
+            "{}\t{}\t{}",
+            output::service(&outcome.item.service),
+            outcome.item.account,
+            text
+        );
+    }
+    if 0 < failed {
+        Err(ItemsFailed {
+            message: format!("{} of {} items failed", failed, outcomes.len()),
+            first,
+        }
+        .into())
+    } else {
+        Ok(())
+    }
+}
+
+/// Upgrade the metadata of items to the current schema.
+fn migrate_schema(matches: &ArgMatches<'_>) -> CommandResult {
+    let keychain = keychain_arg(matches)?;
+    let migrations = metadata::migrate_all(keychain.as_ref(), matches.is_present("dry-run"))?;
+    let mut failed = 0;
+    let mut first = None;
+    for migration in &migrations {
+        let text = migration.outcome.to_string();
+        let text = match migration.outcome {
+            Migrated::Failed(ref error) => {
+                failed += 1;
+                first = first.or_else(|| Some(error.clone()));
+                output::failure(&text)
+            }
+            Migrated::Current => output::dimmed(&text),
+            _ => text,
+        };
+        println!(
+            "{}\t{}\t{}",
+            output::service(&migration.item.service),
+            migration.item.account,
+            text
+        );
+    }
+    if 0 < failed {
+        Err(ItemsFailed {
+            message: format!("{} of {} items failed", failed, migrations.len()),
+            first,
+        }
+        .into())
+    } else {
+        Ok(())
+    }
+}
+
+/// Open a store by `name`.
+///
+/// Names of JSON files refer to exports, `default` to all keychains in the
+/// search list, and other names to keychains.
+fn open_store(name: &str) -> Result<Box<dyn SecretStore>, Box<dyn Error>> {
+    if Path::new(name)
+        .extension()
+        .is_some_and(|extension| extension == "json")
+    {
+        Ok(Box::new(FileStore::open(name)?))
+    } else if env::var_os(STORE_VAR).is_some() {
+        Err(store_replaces_keychains(&format!("keychain {}", name)))
+    } else if name == "default" {
+        Ok(Box::new(KeychainStore::new(None)))
+    } else {
+        Ok(Box::new(KeychainStore::new(Some(Keychain::named(name)?))))
+    }
+}
+
+/// Ask on the terminal which side of a sync `conflict` to keep.
+fn ask_conflict(conflict: &Conflict<'_>) -> Side {
+    let stdin = io::stdin();
+    loop {
+        eprint!(
+            "{}",
+            policy()
+                .prompts
+                .conflict(&conflict.a.service, &conflict.a.account)
+        );
+        io::stderr().flush().ok();
+        let mut answer = String::new();
+        if stdin.lock().read_line(&mut answer).unwrap_or(0) == 0 {
+            // Keep the first store on end of input, like prefer-a
+            return Side::A;
+        }
+        match answer.trim() {
+            "a" => return Side::A,
+            "b" => return Side::B,
+            _ => (),
+        }
+    }
+}
+
+/// Synchronize two stores.
+fn sync(matches: &ArgMatches<'_>) -> CommandResult {
+    let mut a = open_store(matches.value_of("a").unwrap())?;
+    let mut b = open_store(matches.value_of("b").unwrap())?;
+    let policy = matches.value_of("policy").unwrap().parse()?;
+    let mut guard = |service: &str, access| enforce_policy(Some(service), access);
+    for copied in sync::sync(
+        a.as_mut(),
+        b.as_mut(),
+        policy,
+        &mut ask_conflict,
+        &mut guard,
+    )? {
+        println!("{}", copied);
+    }
+    Ok(())
+}
+
+/// The columns of listings.
+const COLUMNS: &[&str] = &[
+    "service",
+    "account",
+    "label",
+    "kind",
+    "comment",
+    "creator",
+    "type",
+    "flags",
+    "protection",
+    "created",
+    "modified",
+    "encoding",
+];
+
+/// Get the value of `column` for `item`.
+fn column(item: &Item, column: &str) -> String {
+    let time = |time: Option<SystemTime>| time.map(format_time).unwrap_or_default();
+    match column {
+        "service" => item.service.clone(),
+        "account" => item.account.clone(),
+        "label" => item.label.clone().unwrap_or_default(),
+        "kind" => item.description.clone().unwrap_or_default(),
+        "comment" => item.comment.clone().unwrap_or_default(),
+        "creator" => item.creator.map(|c| c.to_string()).unwrap_or_default(),
+        "type" => item.item_type.map(|t| t.to_string()).unwrap_or_default(),
+        "flags" => {
+            let flags = [("invisible", item.invisible), ("negative", item.negative)];
+            let set: Vec<&str> = flags
+                .iter()
+                .filter(|&&(_, set)| set)
+                .map(|&(flag, _)| flag)
+                .collect();
+            set.join(",")
+        }
+        "protection" => item
+            .protection
+            .as_ref()
+            .map(|p| p.to_string())
+            .unwrap_or_default(),
+        "created" => time(item.created),
+        "modified" => time(item.modified),
+        "encoding" => item
+            .encoding
+            .map(|encoding| encoding.name().to_string())
+            .unwrap_or_default(),
+        _ => unreachable!("Unknown column {}", column),
+    }
+}
+
+/// List items, with the selected columns and sorting.
+fn list(matches: &ArgMatches<'_>) -> CommandResult {
+    let options = ListOptions {
+        sort_by: matches.value_of("sort").unwrap().parse()?,
+        descending: matches.is_present("descending"),
+        filter: Filter {
+            creator: matches.value_of("creator").map(str::parse).transpose()?,
+            item_type: matches.value_of("type").map(str::parse).transpose()?,
+            include_invisible: matches.is_present("include-invisible"),
+        },
+    };
+    let columns: Vec<&str> = matches.values_of("columns").unwrap().collect();
+    let filter = tag_filter(matches);
+    let store = mock_store(matches)?;
+    let keychain = keychain_arg(matches)?;
+    let items = match store {
+        Some(ref store) => {
+            // Entries of stores have neither codes nor flags
+            let mut items = listed_items(Some(store), None)?;
+            items.retain(|item| {
+                options.filter.creator.is_none()
+                    && options.filter.item_type.is_none()
+                    && filter.matches(item)
+            });
+            options.sort(&mut items);
+            items
+        }
+        None => query::list_matching(keychain.as_ref(), &options, &filter)?,
+    };
+    if security_format(matches) {
+        let path = match store {
+            Some(ref store) => store.path().to_path_buf(),
+            None => dump_keychain_path(keychain.as_ref())?,
+        };
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        for item in &items {
+            dump::write_item(&mut stdout, &path, item)?;
+        }
+        return Ok(());
+    }
+    if let Some(formatter) = row_formatter(matches) {
+        let rows: Vec<Vec<Value>> = items
+            .iter()
+            .map(|item| {
+                columns
+                    .iter()
+                    .map(|name| Value::from(column(item, name)))
+                    .collect()
+            })
+            .collect();
+        formatter.write(&mut io::stdout().lock(), &columns, &rows)?;
+        return Ok(());
+    }
+    for item in items {
+        let values: Vec<String> = columns
+            .iter()
+            .map(|name| {
+                let value = column(&item, name);
+                match *name {
+                    "service" => output::service(&value),
+                    "account" | "label" => value,
+                    _ => output::dimmed(&value),
+                }
+            })
+            .collect();
+        println!("{}", values.join("\t"));
+    }
+    Ok(())
+}
+
+/// Print the password of an item.
+fn get(matches: &ArgMatches<'_>) -> CommandResult {
+    let service = service_arg(matches).unwrap();
+    let service = service.as_str();
+    enforce_policy(Some(service), Access::Read)?;
+    let account = matches.value_of("account").unwrap();
+    let require_biometrics =
+        matches.is_present("require-biometrics") || profile().require_biometrics;
+    let store = mock_store(matches)?;
+    let keychain = keychain_arg(matches)?;
+    // Check existence first, so that we don't ask for a finger in vain
+    let item = match store {
+        Some(ref store) => Item::from(store.entry(service, account)?),
+        None => items::find_item(keychain.as_ref(), service, account)?,
+    };
+    if require_biometrics {
+        let reason = policy().prompts.authenticate(service, account);
+        local_auth::authenticate(local_auth::Policy::Biometrics, &reason)?;
+    }
+    if security_format(matches) {
+        // Like security find-generic-password -g, with the password on stderr
+        let (password, path) = match store {
+            Some(ref store) => (store.get(service, account)?, store.path().to_path_buf()),
+            None => (
+                items::find_data(keychain.as_ref(), service, account)?.into_exposed(),
+                dump_keychain_path(keychain.as_ref())?,
+            ),
+        };
+        dump::write_item(&mut io::stdout().lock(), &path, &item)?;
+        eprintln!("{}", dump::password_line(&password));
+        return Ok(());
+    }
+    println!(
+        "{}",
+        find_password(store.as_ref(), keychain.as_ref(), service, account)?
+    );
+    Ok(())
+}
+
+/// Reveal an item in Keychain Access.
+fn open_item(matches: &ArgMatches<'_>) -> CommandResult {
+    let service = service_arg(matches).unwrap();
+    enforce_policy(Some(service.as_str()), Access::Read)?;
+    let keychain = keychain_arg(matches)?;
+    let revealed = reveal::reveal(keychain.as_ref(), &service, matches.value_of("account"))?;
+    if let Revealed::Opened(item) = revealed {
+        println!(
+            "Search for {} in Keychain Access",
+            item.label.as_deref().unwrap_or(&item.service)
+        );
+    }
+    Ok(())
+}
+
+/// Move an item under access control.
+fn harden(matches: &ArgMatches<'_>) -> CommandResult {
+    let service = service_arg(matches).unwrap();
+    enforce_policy(Some(service.as_str()), Access::Write)?;
+    let _authorization = authorize_keychain_arg(matches)?;
+    let keychain = keychain_arg(matches)?;
+    let accessibility: Accessibility = matches.value_of("accessibility").unwrap().parse()?;
+    let policy: Flag = matches.value_of("policy").unwrap().parse()?;
+    let item = harden::harden(
+        keychain.as_ref(),
+        &service,
+        matches.value_of("account").unwrap(),
+        accessibility,
+        policy,
+    )?;
+    println!(
+        "{}\t{}\t{}",
+        output::service(&item.service),
+        item.account,
+        item.protection
+            .map(|protection| protection.to_string())
+            .unwrap_or_default()
+    );
+    Ok(())
+}
+
+/// Check a password from stdin against an item.
+fn verify(matches: &ArgMatches<'_>) -> CommandResult {
+    let service = service_arg(matches).unwrap();
+    enforce_policy(Some(service.as_str()), Access::Read)?;
+    let keychain = keychain_arg(matches)?;
+    let candidate = read_line(&mut io::stdin().lock(), "password")?;
+    let matched = items::verify_password(
+        keychain.as_ref(),
+        &service,
+        matches.value_of("account").unwrap(),
+        candidate.as_bytes(),
+    )?;
+    if matched {
+        Ok(())
+    } else {
+        Err("Password does not match".into())
+    }
+}
+
+/// Search items by service, account and label.
+fn search(matches: &ArgMatches<'_>) -> CommandResult {
+    let pattern = matches.value_of("pattern").unwrap();
+    let matcher = if matches.is_present("regex") {
+        Matcher::regex(pattern)?
+    } else {
+        Matcher::substring(pattern)
+    };
+    let store = mock_store(matches)?;
+    let keychain = keychain_arg(matches)?;
+    let found = match store {
+        Some(ref store) => {
+            let mut found = listed_items(Some(store), None)?;
+            found.retain(|item| matcher.matches(item));
+            ListOptions::default().sort(&mut found);
+            found
+        }
+        None => search::search(keychain.as_ref(), &matcher)?,
+    };
+    for item in found {
+        println!(
+            "{}\t{}\t{}",
+            output::service(&item.service),
+            item.account,
+            output::dimmed(item.label.as_deref().unwrap_or(""))
+        );
+    }
+    Ok(())
+}
+
+/// The columns of lint findings in formatted rows.
+const FINDING_COLUMNS: [&str; 4] = ["service", "account", "rule", "message"];
+
+/// Check the names of items against naming rules.
+fn lint(matches: &ArgMatches<'_>) -> CommandResult {
+    let rules = match matches.value_of_os("rules") {
+        Some(path) => Rules::load(Path::new(path)),
+        None => Rules::load_default(),
+    }
+    .map_err(|error| format!("Cannot load lint rules: {}", error))?;
+    let store = mock_store(matches)?;
+    let keychain = keychain_arg(matches)?;
+    let service = service_arg(matches);
+    let findings: Vec<Finding> = listed_items(store.as_ref(), keychain.as_ref())?
+        .iter()
+        .filter(|item| {
+            service
+                .as_ref()
+                .is_none_or(|s| glob_match(s, &item.service))
+        })
+        .flat_map(|item| rules.check(&item.service, &item.account, item.label.as_deref()))
+        .collect();
+    if let Some(formatter) = row_formatter(matches) {
+        let rows: Vec<Vec<Value>> = findings
+            .iter()
+            .map(|finding| {
+                vec![
+                    Value::from(finding.service.as_str()),
+                    Value::from(finding.account.as_str()),
+                    Value::from(finding.rule.name()),
+                    Value::from(finding.message.as_str()),
+                ]
+            })
+            .collect();
+        formatter.write(&mut io::stdout().lock(), &FINDING_COLUMNS, &rows)?;
+    } else {
+        for finding in &findings {
+            println!(
+                "{}\t{}\t{}\t{}",
+                output::service(&finding.service),
+                finding.account,
+                output::dimmed(finding.rule.name()),
+                finding.message
+            );
+        }
+    }
+    if findings.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("{} naming findings", findings.len()).into())
+    }
+}
+
+/// The launchd label of the SSH agent.
+const AGENT_LABEL: &str = "de.swsnr.rust-ffi-demo.ssh-agent";
+
+/// Manage the SSH agent as launchd agent.
+fn agent(matches: &ArgMatches<'_>) -> CommandResult {
+    match matches.subcommand() {
+        ("install", Some(m)) => {
+            // Resolve the socket now, because launchd runs agents with
+            // another temporary directory
+            let socket = socket_arg(m);
+            let mut arguments = vec![
+                env::current_exe()?.to_string_lossy().into_owned(),
+                "ssh-agent".to_string(),
+                "--socket".to_string(),
+                socket.to_string_lossy().into_owned(),
+            ];
+            if let Some(address) = m.value_of("metrics") {
+                arguments.push("--metrics".to_string());
+                arguments.push(address.to_string());
+            }
+            let agent = Agent::new(AGENT_LABEL, arguments);
+            agent.install()?;
+            println!("Installed {}", agent.plist_path().display());
+            println!("Logs go to {}", agent.log_path().display());
+            println!("SSH_AUTH_SOCK={}; export SSH_AUTH_SOCK;", socket.display());
+        }
+        ("uninstall", Some(_)) => {
+            let agent = Agent::new(AGENT_LABEL, Vec::new());
+            agent.uninstall()?;
+            println!("Uninstalled {}", agent.plist_path().display());
+        }
+        ("status", Some(_)) => {
+            let status = Agent::new(AGENT_LABEL, Vec::new()).status()?;
+            println!("{}: {}", AGENT_LABEL, status);
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+/// Print keychain events as they happen.
+fn watch(matches: &ArgMatches<'_>) -> CommandResult {
+    let service = service_arg(matches);
+    let formatter = if matches.is_present("json") {
+        formatter::get("json")
+    } else {
+        row_formatter(matches)
+    };
+    events::watch(move |event: Event| {
+        if service.is_some() && event.service != service {
+            return;
+        }
+        let keychain = event
+            .keychain
+            .as_ref()
+            .map(|path| path.display().to_string());
+        if let Some(ref formatter) = formatter {
+            let row = vec![
+                Value::from(event.kind.to_string()),
+                Value::from(event.service.clone()),
+                Value::from(event.account.clone()),
+                Value::from(keychain),
+                Value::from(i64::from(event.pid)),
+            ];
+            let columns = ["event", "service", "account", "keychain", "pid"];
+            // Like println!, give up on a closed stdout
+            formatter
+                .write(&mut io::stdout().lock(), &columns, &[row])
+                .expect("Failed to write event");
+        } else {
+            println!(
+                "{}\t{}\t{}\t{}\tpid {}",
+                event.kind,
+                event.service.as_deref().unwrap_or("-"),
+                event.account.as_deref().unwrap_or("-"),
+                keychain.as_deref().unwrap_or("-"),
+                event.pid
+            );
+        }
+    })?;
+    Ok(())
+}
+
+/// Print the password of a Wi-Fi network.
+fn wifi_password(matches: &ArgMatches<'_>) -> CommandResult {
+    println!(
+        "{}",
+        wifi::find_wifi_password(matches.value_of("ssid").unwrap())?
+    );
+    Ok(())
+}
+
+/// Get the `socket` argument, or else the default SSH agent socket.
+fn socket_arg(matches: &ArgMatches<'_>) -> PathBuf {
+    matches
+        .value_of_os("socket")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| env::temp_dir().join(format!("{}-ssh-agent.sock", NAME)))
+}
+
+/// Run a command with secrets in credential files.
+///
+/// Exit with the status of the command, or like a shell if a signal killed
+/// it.
+fn run(matches: &ArgMatches<'_>) -> CommandResult {
+    let keychain = keychain_arg(matches)?;
+    let credentials = matches
+        .values_of("cred-file")
+        .into_iter()
+        .flatten()
+        .map(|value| {
+            value.parse().map(|credential: Credential| Credential {
+                service: profile().service(&credential.service),
+                ..credential
+            })
+        })
+        .collect::<keychain::Result<Vec<_>>>()?;
+    enforce_policy(
+        credentials
+            .iter()
+            .map(|credential| credential.service.as_str()),
+        Access::Read,
+    )?;
+    let mut arguments = matches.values_of_os("command").unwrap();
+    let mut command = process::Command::new(arguments.next().unwrap());
+    command.args(arguments);
+    // Outlive Ctrl-C, which also goes to the child, to remove the credentials
+    runtime::install();
+    let status = credentials::run(keychain.as_ref(), &credentials, &mut command)?;
+    process::exit(
+        status
+            .code()
+            .or_else(|| status.signal().map(runtime::exit_status))
+            .unwrap_or(1),
+    )
+}
+
+/// Keep a read-only directory of items up to date until shutdown.
+fn mount_export(matches: &ArgMatches<'_>) -> CommandResult {
+    let keychain = keychain_arg(matches)?;
+    let mut filter = tag_filter(matches);
+    if let Some(service) = service_arg(matches) {
+        filter = filter.and(FilterExpr::service(service));
+    }
+    let directory = Path::new(matches.value_of_os("directory").unwrap());
+    runtime::install();
+    mount::serve(
+        keychain.as_ref(),
+        directory,
+        &filter,
+        &|service| policy().check(service, Access::Read) == Decision::Allowed,
+        matches.is_present("secrets"),
+    )?;
+    runtime::exit()
+}
+
+/// Run an SSH agent for keychain keys.
+fn ssh_agent(matches: &ArgMatches<'_>) -> CommandResult {
+    let socket = socket_arg(matches);
+    if let Some(address) = matches.value_of("metrics") {
+        metrics_endpoint::spawn(address)?;
+    }
+    println!("SSH_AUTH_SOCK={}; export SSH_AUTH_SOCK;", socket.display());
+    runtime::install();
+    ssh_agent::serve(&socket)?;
+    runtime::exit()
+}
+
+fn main() {
+    let app = App::new(NAME)
+        .version(crate_version!())
+        .about(crate_description!())
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .global(true)
+                .possible_values(&["text", "json", "security", "table", "yaml", "tsv"])
+                .default_value("text")
+                .help(
+                    "The format of reports and errors; security dumps items of get and list \
+                     like security(1), and table, yaml and tsv format the rows of list and \
+                     watch",
+                ),
+        )
+        .arg(
+            Arg::with_name("color")
+                .long("color")
+                .takes_value(true)
+                .global(true)
+                .possible_values(&["auto", "always", "never"])
+                .default_value("auto")
+                .help("When to color output; NO_COLOR turns off automatic colors"),
+        )
+        .arg(
+            Arg::with_name("read-only")
+                .long("read-only")
+                .global(true)
+                .help("Reject all changes to items and keychains, like KEYCHAIN_READONLY=1"),
+        )
+        .arg(
+            Arg::with_name("default-label")
+                .long("default-label")
+                .takes_value(true)
+                .global(true)
+                .possible_values(&["service", "service-and-account", "none"])
+                .help("How to label new items without label [default: service]"),
+        )
+        .arg(
+            Arg::with_name("env")
+                .long("env")
+                .takes_value(true)
+                .global(true)
+                .help("Use the profile of this environment, like KEYCHAIN_ENV"),
+        )
+        .arg(
+            Arg::with_name("audit-log")
+                .long("audit-log")
+                .takes_value(true)
+                .global(true)
+                .value_name("FILE")
+                .help("Append keychain operations to this file, as JSON lines with redacted secrets"),
+        )
+        .arg(
+            Arg::with_name("redaction")
+                .long("redaction")
+                .takes_value(true)
+                .global(true)
+                .possible_values(&["full", "length", "digest"])
+                .help(
+                    "What redacted secrets tell: nothing, their length, or a digest; like \
+                     RUST_FFI_DEMO_REDACTION",
+                ),
+        )
+        .subcommand(SubCommand::with_name("demo").about("Add, get and delete a demo account"))
+        .subcommand(
+            SubCommand::with_name("sync")
+                .about("Synchronize items between two keychains or JSON exports")
+                .arg(
+                    Arg::with_name("a")
+                        .required(true)
+                        .help("A keychain name or path, default, or a JSON export"),
+                )
+                .arg(
+                    Arg::with_name("b")
+                        .required(true)
+                        .help("A keychain name or path, default, or a JSON export"),
+                )
+                .arg(
+                    Arg::with_name("policy")
+                        .long("policy")
+                        .takes_value(true)
+                        .possible_values(&["newest-wins", "prefer-a", "interactive"])
+                        .default_value("newest-wins")
+                        .help("How to resolve items which differ between both sides"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("totp")
+                .about("Print the current TOTP code for a service")
+                .arg(Arg::with_name("service").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("add-totp")
+                .about("Store a base32 TOTP secret for a service")
+                .arg(Arg::with_name("service").required(true))
+                .arg(Arg::with_name("account").required(true))
+                .arg(Arg::with_name("secret").required(true))
+                .arg(
+                    Arg::with_name("issuer")
+                        .long("issuer")
+                        .takes_value(true)
+                        .help("The issuer of the TOTP"),
+                )
+                .arg(
+                    Arg::with_name("digits")
+                        .long("digits")
+                        .takes_value(true)
+                        .default_value("6")
+                        .help("The number of digits per code"),
+                )
+                .arg(
+                    Arg::with_name("period")
+                        .long("period")
+                        .takes_value(true)
+                        .default_value("30")
+                        .help("The time step in seconds"),
+                )
+                .arg(
+                    Arg::with_name("algorithm")
+                        .long("algorithm")
+                        .takes_value(true)
+                        .possible_values(&["SHA1", "SHA256", "SHA512"])
+                        .default_value("SHA1")
+                        .help("The HMAC algorithm"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("canary")
+                .about("Detect reset, restored or tampered keychains with a canary item")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("init")
+                        .about("Store a new canary, and record it outside the keychain")
+                        .arg(
+                            Arg::with_name("keychain")
+                                .long("keychain")
+                                .takes_value(true)
+                                .help("Store the canary in this keychain, by name or path"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("check")
+                        .about("Verify the canary, and replace its value if it's intact")
+                        .arg(
+                            Arg::with_name("keychain")
+                                .long("keychain")
+                                .takes_value(true)
+                                .help("Only look for the canary in this keychain, by name or path"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("doctor")
+                .about("Check that the keychain works, with a canary item")
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .global(true)
+                        .help("Print the report as JSON object"),
+                )
+                .subcommand(
+                    SubCommand::with_name("entitlements").about(
+                        "Check the code signature and keychain access groups of this binary",
+                    ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Export generic passwords with plain text secrets as JSON, YAML or TOML")
+                .arg(
+                    Arg::with_name("keychain")
+                        .long("keychain")
+                        .takes_value(true)
+                        .help("Only export items of this keychain, by name or path"),
+                )
+                .arg(
+                    Arg::with_name("service")
+                        .long("service")
+                        .takes_value(true)
+                        .help("Only export items of this service"),
+                )
+                .arg(
+                    Arg::with_name("tag")
+                        .long("tag")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("TAG")
+                        .help("Only export items with this tag; repeat to require several tags"),
+                )
+                .arg(
+                    Arg::with_name("lines")
+                        .long("lines")
+                        .help("Write one JSON record per line instead of a JSON array"),
+                )
+                .arg(
+                    Arg::with_name("yaml")
+                        .long("yaml")
+                        .conflicts_with_all(&["lines", "toml"])
+                        .help("Write YAML instead of JSON"),
+                )
+                .arg(
+                    Arg::with_name("toml")
+                        .long("toml")
+                        .conflicts_with_all(&["lines", "yaml"])
+                        .help("Write TOML instead of JSON"),
+                )
+                .arg(Arg::with_name("file").help("The export file to create; stdout if omitted")),
+        )
+        .subcommand(
+            SubCommand::with_name("env")
+                .about("Print shell code which exports the passwords of services with a prefix")
+                .arg(
+                    Arg::with_name("keychain")
+                        .long("keychain")
+                        .takes_value(true)
+                        .help("Only read items of this keychain, by name or path"),
+                )
+                .arg(
+                    Arg::with_name("service-prefix")
+                        .long("service-prefix")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Export services with this prefix, named after the rest"),
+                )
+                .arg(
+                    Arg::with_name("fish")
+                        .long("fish")
+                        .help("Print fish code instead of POSIX shell code"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Import generic passwords from a JSON, YAML or TOML export")
+                .arg(
+                    Arg::with_name("keychain")
+                        .long("keychain")
+                        .takes_value(true)
+                        .help("Import into this keychain, by name or path"),
+                )
+                .arg(
+                    Arg::with_name("continue-on-error")
+                        .long("continue-on-error")
+                        .help("Import remaining items after an item failed"),
+                )
+                .arg(
+                    Arg::with_name("yaml")
+                        .long("yaml")
+                        .conflicts_with_all(&["toml"])
+                        .help("Read YAML instead of JSON"),
+                )
+                .arg(
+                    Arg::with_name("toml")
+                        .long("toml")
+                        .conflicts_with_all(&["yaml"])
+                        .help("Read TOML instead of JSON"),
+                )
+                .arg(Arg::with_name("file").help("The export file to read; stdin if omitted")),
+        )
+        .subcommand(
+            SubCommand::with_name("apply")
+                .about("Create, update and prune items to match a manifest")
+                .arg(
+                    Arg::with_name("file")
+                        .short("f")
+                        .long("file")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The manifest, in JSON, YAML or TOML by its extension"),
+                )
+                .arg(
+                    Arg::with_name("keychain")
+                        .long("keychain")
+                        .takes_value(true)
+                        .help("Apply to this keychain, by name or path"),
+                )
+                .arg(
+                    Arg::with_name("prune")
+                        .long("prune")
+                        .help("Delete items of the services in the manifest which it does not list"),
+                )
+                .arg(
+                    Arg::with_name("yes")
+                        .long("yes")
+                        .short("y")
+                        .help("Apply the plan without asking"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("hook")
+                .about("Print a shell hook which loads the secrets of .keychain.toml projects")
+                .arg(
+                    Arg::with_name("shell")
+                        .required(true)
+                        .possible_values(&["zsh", "bash", "fish"]),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("hook-env")
+                .setting(AppSettings::Hidden)
+                .about("Print shell code which enters the project of the working directory")
+                .arg(
+                    Arg::with_name("shell")
+                        .required(true)
+                        .possible_values(&["zsh", "bash", "fish"]),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("allow")
+                .about("Allow the shell hook to load the secrets of a .keychain.toml")
+                .arg(
+                    Arg::with_name("directory")
+                        .help("The project directory; the working directory if omitted"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("deny")
+                .about("Stop the shell hook from loading the secrets of a .keychain.toml")
+                .arg(
+                    Arg::with_name("directory")
+                        .help("The project directory; the working directory if omitted"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("Run a command with secrets in files in $CREDENTIALS_DIRECTORY")
+                .setting(AppSettings::TrailingVarArg)
+                .arg(
+                    Arg::with_name("keychain")
+                        .long("keychain")
+                        .takes_value(true)
+                        .help("Read secrets from this keychain, by name or path"),
+                )
+                .arg(
+                    Arg::with_name("cred-file")
+                        .long("cred-file")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .required(true)
+                        .value_name("NAME=SERVICE/ACCOUNT")
+                        .help("Write the secret of an item to the file NAME; repeat for more"),
+                )
+                .arg(
+                    Arg::with_name("command")
+                        .multiple(true)
+                        .required(true)
+                        .help("The command to run, with its arguments"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("mount-export")
+                .about("Keep a read-only directory with one file per item until interrupted")
+                .arg(
+                    Arg::with_name("keychain")
+                        .long("keychain")
+                        .takes_value(true)
+                        .help("Export items of this keychain, by name or path"),
+                )
+                .arg(
+                    Arg::with_name("service")
+                        .long("service")
+                        .takes_value(true)
+                        .help("Only export items of services matching this wildcard pattern"),
+                )
+                .arg(
+                    Arg::with_name("tag")
+                        .long("tag")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("TAG")
+                        .help("Only export items with this tag; repeat to require several tags"),
+                )
+                .arg(
+                    Arg::with_name("secrets")
+                        .long("secrets")
+                        .help("Write the secrets of items instead of their attributes"),
+                )
+                .arg(
+                    Arg::with_name("directory")
+                        .required(true)
+                        .help("The directory to create, and to remove on exit"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("plan")
+                .about("Show what apply would change, without changing anything")
+                .arg(
+                    Arg::with_name("file")
+                        .short("f")
+                        .long("file")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The manifest, in JSON, YAML or TOML by its extension"),
+                )
+                .arg(
+                    Arg::with_name("keychain")
+                        .long("keychain")
+                        .takes_value(true)
+                        .help("Compare to this keychain, by name or path"),
+                )
+                .arg(
+                    Arg::with_name("prune")
+                        .long("prune")
+                        .help("Plan to delete items of the services in the manifest which it does not list"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("Compare items of two keychains or exports, without showing secrets")
+                .arg(
+                    Arg::with_name("left")
+                        .required(true)
+                        .help("A keychain name or path, or a JSON export"),
+                )
+                .arg(
+                    Arg::with_name("right")
+                        .required(true)
+                        .help("A keychain name or path, or a JSON export"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("delete")
+                .about("Delete generic passwords matching wildcard patterns")
+                .arg(
+                    Arg::with_name("keychain")
+                        .long("keychain")
+                        .takes_value(true)
+                        .help("Only delete items of this keychain, by name or path"),
+                )
+                .arg(
+                    Arg::with_name("service")
+                        .long("service")
+                        .takes_value(true)
+                        .value_name("PATTERN")
+                        .required(true)
+                        .help("Delete items whose service matches this wildcard pattern"),
+                )
+                .arg(
+                    Arg::with_name("account")
+                        .long("account")
+                        .takes_value(true)
+                        .value_name("PATTERN")
+                        .help("Only delete items whose account matches this wildcard pattern"),
+                )
+                .arg(
+                    Arg::with_name("tag")
+                        .long("tag")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("TAG")
+                        .help("Only delete items with this tag; repeat to require several tags"),
+                )
+                .arg(
+                    Arg::with_name("continue-on-error")
+                        .long("continue-on-error")
+                        .help("Delete remaining items after an item failed"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("keychain")
+                .about("Manage keychain files")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("change-password")
+                        .about("Change the password of a keychain, reading old and new password as lines from stdin")
+                        .arg(
+                            Arg::with_name("keychain")
+                                .required(true)
+                                .help("The keychain, by name or path"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("unlock")
+                        .about("Unlock a keychain with the password in a private key file")
+                        .arg(
+                            Arg::with_name("key-file")
+                                .long("key-file")
+                                .takes_value(true)
+                                .required(true)
+                                .help("The file with the password, only accessible by its owner"),
+                        )
+                        .arg(
+                            Arg::with_name("keychain")
+                                .required(true)
+                                .help("The keychain, by name or path"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("default")
+                        .about("Print the default keychain, or make a keychain the default")
+                        .arg(
+                            Arg::with_name("keychain")
+                                .help("The new default keychain, by name or path"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("delete-keychain")
+                        .about("Delete a keychain file and remove it from the search list")
+                        .arg(
+                            Arg::with_name("force")
+                                .long("force")
+                                .help("Really delete the keychain"),
+                        )
+                        .arg(
+                            Arg::with_name("keychain")
+                                .required(true)
+                                .help("The keychain, by name or path"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("list")
+                .about("List items, without reading passwords")
+                .arg(
+                    Arg::with_name("keychain")
+                        .long("keychain")
+                        .takes_value(true)
+                        .help("Only list items of this keychain, by name or path"),
+                )
+                .arg(
+                    Arg::with_name("sort")
+                        .long("sort")
+                        .takes_value(true)
+                        .possible_values(&["service", "account", "created", "modified"])
+                        .default_value("service")
+                        .help("The attribute to sort by"),
+                )
+                .arg(
+                    Arg::with_name("descending")
+                        .long("descending")
+                        .help("Sort in descending order, eg, most recently modified first"),
+                )
+                .arg(
+                    Arg::with_name("creator")
+                        .long("creator")
+                        .takes_value(true)
+                        .value_name("CODE")
+                        .help("Only list items with this four character creator code"),
+                )
+                .arg(
+                    Arg::with_name("type")
+                        .long("type")
+                        .takes_value(true)
+                        .value_name("CODE")
+                        .help("Only list items with this four character type code"),
+                )
+                .arg(
+                    Arg::with_name("tag")
+                        .long("tag")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("TAG")
+                        .help("Only list items with this tag; repeat to require several tags"),
+                )
+                .arg(
+                    Arg::with_name("include-invisible")
+                        .long("include-invisible")
+                        .help("Also list invisible items, as created by the system"),
+                )
+                .arg(
+                    Arg::with_name("columns")
+                        .long("columns")
+                        .takes_value(true)
+                        .use_delimiter(true)
+                        .possible_values(COLUMNS)
+                        .default_value("service,account,label")
+                        .help("The columns to print, separated by commas"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .about("Copy items between keychains, skipping items already in the target")
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The source keychain, by name or path"),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The target keychain, by name or path"),
+                )
+                .arg(
+                    Arg::with_name("service")
+                        .long("service")
+                        .takes_value(true)
+                        .value_name("PATTERN")
+                        .help("Only migrate items whose service matches this wildcard pattern"),
+                )
+                .arg(
+                    Arg::with_name("move")
+                        .long("move")
+                        .help("Delete migrated items from the source keychain"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("migrate-schema")
+                .about("Upgrade the metadata of items to the current schema")
+                .arg(
+                    Arg::with_name("keychain")
+                        .long("keychain")
+                        .takes_value(true)
+                        .help("Only upgrade items of this keychain, by name or path"),
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("Only list outdated items"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("attachment")
+                .about("Manage files stored as secrets")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .about("Store a file with its name and SHA-256 digest")
+                        .arg(Arg::with_name("service").required(true))
+                        .arg(Arg::with_name("name").required(true))
+                        .arg(Arg::with_name("file").required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("get")
+                        .about("Print a stored file after verifying its digest")
+                        .arg(
+                            Arg::with_name("output")
+                                .long("output")
+                                .short("o")
+                                .takes_value(true)
+                                .help("Write the file to this new file instead"),
+                        )
+                        .arg(Arg::with_name("service").required(true))
+                        .arg(Arg::with_name("name").required(true)),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("tag")
+                .about("Manage the tags of items")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .about("Add tags to an item")
+                        .arg(
+                            Arg::with_name("keychain")
+                                .long("keychain")
+                                .takes_value(true)
+                                .help("Only look in this keychain, by name or path"),
+                        )
+                        .arg(Arg::with_name("service").required(true))
+                        .arg(Arg::with_name("account").required(true))
+                        .arg(Arg::with_name("tag").required(true).multiple(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about("Remove tags from an item")
+                        .arg(
+                            Arg::with_name("keychain")
+                                .long("keychain")
+                                .takes_value(true)
+                                .help("Only look in this keychain, by name or path"),
+                        )
+                        .arg(Arg::with_name("service").required(true))
+                        .arg(Arg::with_name("account").required(true))
+                        .arg(Arg::with_name("tag").required(true).multiple(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("Print the tags of an item")
+                        .arg(
+                            Arg::with_name("keychain")
+                                .long("keychain")
+                                .takes_value(true)
+                                .help("Only look in this keychain, by name or path"),
+                        )
+                        .arg(Arg::with_name("service").required(true))
+                        .arg(Arg::with_name("account").required(true)),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("note")
+                .about("Manage secure notes")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .about("Add a secure note, reading the body from stdin if omitted")
+                        .arg(Arg::with_name("title").required(true))
+                        .arg(Arg::with_name("body")),
+                )
+                .subcommand(
+                    SubCommand::with_name("show")
+                        .about("Print the body of a secure note")
+                        .arg(Arg::with_name("title").required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("update")
+                        .about("Replace the body of a secure note, reading stdin if omitted")
+                        .arg(Arg::with_name("title").required(true))
+                        .arg(Arg::with_name("body")),
+                )
+                .subcommand(
+                    SubCommand::with_name("delete")
+                        .about("Delete a secure note")
+                        .arg(Arg::with_name("title").required(true)),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("get")
+                .about("Print the password of an item")
+                .arg(
+                    Arg::with_name("keychain")
+                        .long("keychain")
+                        .takes_value(true)
+                        .help("Only look in this keychain, by name or path"),
+                )
+                .arg(
+                    Arg::with_name("require-biometrics")
+                        .long("require-biometrics")
+                        .help(
+                            "Require Touch ID before printing the password, even if the item \
+                             allows access; on by default if the profile requires biometrics",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("service")
+                        .required(true)
+                        .help("The service of the item"),
+                )
+                .arg(
+                    Arg::with_name("account")
+                        .required(true)
+                        .help("The account of the item"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("harden")
+                .about("Move an item into the data protection keychain under access control")
+                .arg(
+                    Arg::with_name("keychain")
+                        .long("keychain")
+                        .takes_value(true)
+                        .help("Only look in this keychain, by name or path"),
+                )
+                .arg(
+                    Arg::with_name("policy")
+                        .long("policy")
+                        .takes_value(true)
+                        .possible_values(&[
+                            "user-presence",
+                            "biometry",
+                            "biometry-current-set",
+                            "passcode",
+                        ])
+                        .default_value("biometry")
+                        .help("The authentication to demand for the item"),
+                )
+                .arg(
+                    Arg::with_name("accessibility")
+                        .long("accessibility")
+                        .takes_value(true)
+                        .possible_values(&[
+                            "when-unlocked",
+                            "when-unlocked-this-device-only",
+                            "after-first-unlock",
+                            "after-first-unlock-this-device-only",
+                            "when-passcode-set-this-device-only",
+                        ])
+                        .default_value("when-unlocked-this-device-only")
+                        .help("When the item is accessible"),
+                )
+                .arg(
+                    Arg::with_name("service")
+                        .required(true)
+                        .help("The service of the item"),
+                )
+                .arg(
+                    Arg::with_name("account")
+                        .required(true)
+                        .help("The account of the item"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Check a password from stdin against an item")
+                .arg(
+                    Arg::with_name("keychain")
+                        .long("keychain")
+                        .takes_value(true)
+                        .help("Only look in this keychain, by name or path"),
+                )
+                .arg(
+                    Arg::with_name("service")
+                        .required(true)
+                        .help("The service of the item"),
+                )
+                .arg(
+                    Arg::with_name("account")
+                        .required(true)
+                        .help("The account of the item"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("open")
+                .about("Reveal an item in Keychain Access, eg, to edit its access control")
+                .arg(
+                    Arg::with_name("keychain")
+                        .long("keychain")
+                        .takes_value(true)
+                        .help("Only look in this keychain, by name or path"),
+                )
+                .arg(
+                    Arg::with_name("service")
+                        .required(true)
+                        .help("The service of the item"),
+                )
+                .arg(
+                    Arg::with_name("account")
+                        .help("The account of the item; the first item of the service if omitted"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("pinentry")
+                .about("Answer gpg-agent passphrase requests from the keychain")
+                .arg(
+                    Arg::with_name("service")
+                        .long("service")
+                        .takes_value(true)
+                        .default_value("GnuPG")
+                        .help("The service of passphrase items, with keygrips as accounts"),
+                )
+                .arg(
+                    Arg::with_name("touch-id")
+                        .long("touch-id")
+                        .help("Require Touch ID before returning a passphrase"),
+                ),
+        )
+        .subcommand(SubCommand::with_name("batch").about(
+            "Run newline-delimited JSON commands from stdin, and print a JSON result for each",
+        ))
+        .subcommand(
+            SubCommand::with_name("repl")
+                .about("Run an interactive shell for keychain maintenance"),
+        )
+        .subcommand(
+            SubCommand::with_name("lint")
+                .about("Check the names of items against naming rules")
+                .arg(
+                    Arg::with_name("rules")
+                        .long("rules")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("The naming rules, in JSON; defaults to the rules of the organization"),
+                )
+                .arg(
+                    Arg::with_name("keychain")
+                        .long("keychain")
+                        .takes_value(true)
+                        .help("Only check this keychain, by name or path"),
+                )
+                .arg(
+                    Arg::with_name("service")
+                        .long("service")
+                        .takes_value(true)
+                        .help("Only check services matching this wildcard pattern"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("search")
+                .about("Find items whose service, account or label contains a pattern")
+                .arg(Arg::with_name("pattern").required(true))
+                .arg(
+                    Arg::with_name("regex")
+                        .long("regex")
+                        .help("Match the pattern as regular expression, not as substring"),
+                )
+                .arg(
+                    Arg::with_name("keychain")
+                        .long("keychain")
+                        .takes_value(true)
+                        .help("Only search this keychain, by name or path"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("ssh-agent")
+                .about("Run an SSH agent serving private keys from the keychain")
+                .arg(
+                    Arg::with_name("socket")
+                        .long("socket")
+                        .takes_value(true)
+                        .help("The path of the agent socket"),
+                )
+                .arg(
+                    Arg::with_name("metrics")
+                        .long("metrics")
+                        .takes_value(true)
+                        .value_name("ADDRESS")
+                        .help("Serve Prometheus metrics at /metrics on this address, eg, 127.0.0.1:9120"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("agent")
+                .about("Run the SSH agent as launchd agent, at login")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("install")
+                        .about("Install and start the launchd agent")
+                        .arg(
+                            Arg::with_name("socket")
+                                .long("socket")
+                                .takes_value(true)
+                                .help("The path of the agent socket"),
+                        )
+                        .arg(
+                            Arg::with_name("metrics")
+                                .long("metrics")
+                                .takes_value(true)
+                                .value_name("ADDRESS")
+                                .help("Serve Prometheus metrics at /metrics on this address"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("uninstall")
+                        .about("Stop and remove the launchd agent"),
+                )
+                .subcommand(
+                    SubCommand::with_name("status").about("Print the state of the launchd agent"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("watch")
+                .about("Print keychain changes of all processes as they happen")
+                .arg(
+                    Arg::with_name("service")
+                        .long("service")
+                        .takes_value(true)
+                        .help("Only print changes of items of this service"),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print one JSON object per event"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("wifi-password")
+                .about("Print the password of a known Wi-Fi network")
+                .arg(Arg::with_name("ssid").required(true)),
+        );
+    #[cfg(feature = "age")]
+    let app = app.subcommand(
+        SubCommand::with_name("age-identity")
+            .about("Print the age recipient and plugin identity of a keychain identity")
+            .arg(Arg::with_name("name").required(true))
+            .arg(
+                Arg::with_name("generate")
+                    .long("generate")
+                    .help("Generate a new identity with the given name"),
+            ),
+    );
+    #[cfg(feature = "tui")]
+    let app = app.subcommand(
+        SubCommand::with_name("tui").about("Browse, copy, relabel and delete keychain items"),
+    );
+    let matches = app.get_matches();
+    output::set_color(matches.value_of("color").unwrap().parse().unwrap());
+    let env_name = matches
+        .value_of("env")
+        .map(ToString::to_string)
+        .or_else(|| env::var(profile::ENV_VAR).ok());
+    if let Some(name) = env_name {
+        match Profiles::load_default().and_then(|profiles| profiles.get(&name).cloned()) {
+            Ok(selected) => PROFILE.get_or_init(|| selected),
+            Err(error) => {
+                eprintln!(
+                    "{}",
+                    output::error(&format!("Cannot load environment {}: {}", name, error))
+                );
+                process::exit(1);
+            }
+        };
+    }
+    if matches.is_present("read-only") || profile().read_only {
+        read_only::enable();
+    }
+    if matches.is_present("default-label") {
+        let policy = value_t!(matches, "default-label", DefaultLabel).unwrap_or_else(|e| e.exit());
+        labels::set_default_label(policy);
+    }
+    match Policy::load_default() {
+        Ok(policy) => POLICY.get_or_init(|| policy),
+        Err(error) => {
+            eprintln!(
+                "{}",
+                output::error(&format!("Cannot load policy: {}", error))
+            );
+            process::exit(1);
+        }
+    };
+
+    let redaction = matches
+        .value_of("redaction")
+        .map(ToString::to_string)
+        .or_else(|| env::var(sensitive::REDACTION_VAR).ok());
+    if let Some(redaction) = redaction {
+        match redaction.parse::<Redaction>() {
+            Ok(redaction) => sensitive::set_redaction(redaction),
+            Err(error) => {
+                eprintln!("{}", output::error(&error));
+                process::exit(1);
+            }
+        }
+    }
+    if let Some(path) = matches.value_of_os("audit-log") {
+        match AuditLog::open(path) {
+            Ok(log) => audit_log::install(log),
+            Err(error) => {
+                eprintln!(
+                    "{}",
+                    output::error(&format!("Cannot open audit log: {}", error))
+                );
+                process::exit(1);
+            }
+        }
+        let command: BTreeMap<&str, &str> = matches
+            .subcommand_name()
+            .map(|name| ("command", name))
+            .into_iter()
+            .collect();
+        audit_log::record("command", &command);
+    }
+
+    #[cfg(feature = "dlopen-security")]
+    if let Err(error) = dynamic::load() {
+        eprintln!("{}", output::error(&error.to_string()));
+        process::exit(1);
+    }
+
+    let result = match matches.subcommand() {
+        (command, Some(_))
+            if env::var_os(STORE_VAR).is_some() && !STORE_COMMANDS.contains(&command) =>
+        {
+            Err(store_replaces_keychains(command))
+        }
+        ("demo", Some(_)) => demo(),
+        ("sync", Some(m)) => sync(m),
+        ("totp", Some(m)) => totp(m),
+        ("add-totp", Some(m)) => add_totp(m),
+        ("canary", Some(m)) => canary(m),
+        ("doctor", Some(m)) => doctor(m),
+        ("export", Some(m)) => export(m),
+        ("env", Some(m)) => shell_env(m),
+        ("import", Some(m)) => import(m),
+        ("diff", Some(m)) => diff(m),
+        ("apply", Some(m)) => apply(m),
+        ("mount-export", Some(m)) => mount_export(m),
+        ("run", Some(m)) => run(m),
+        ("hook", Some(m)) => shell_hook(m),
+        ("hook-env", Some(m)) => hook_env(m),
+        ("allow", Some(m)) => allow_project(m, true),
+        ("deny", Some(m)) => allow_project(m, false),
+        ("plan", Some(m)) => plan(m),
+        ("delete", Some(m)) => delete(m),
+        ("keychain", Some(m)) => manage_keychain(m),
+        ("list", Some(m)) => list(m),
+        ("migrate", Some(m)) => migrate(m),
+        ("migrate-schema", Some(m)) => migrate_schema(m),
+        ("attachment", Some(m)) => attachment(m),
+        ("tag", Some(m)) => tag(m),
+        ("note", Some(m)) => note(m),
+        ("get", Some(m)) => get(m),
+        ("harden", Some(m)) => harden(m),
+        ("verify", Some(m)) => verify(m),
+        ("open", Some(m)) => open_item(m),
+        ("pinentry", Some(m)) => pinentry(m),
+        ("batch", Some(_)) => batch(),
+        ("repl", Some(_)) => repl(),
+        ("search", Some(m)) => search(m),
+        ("lint", Some(m)) => lint(m),
+        ("ssh-agent", Some(m)) => ssh_agent(m),
+        ("agent", Some(m)) => agent(m),
+        ("watch", Some(m)) => watch(m),
+        ("wifi-password", Some(m)) => wifi_password(m),
+        #[cfg(feature = "age")]
+        ("age-identity", Some(m)) => age_identity(m),
+        #[cfg(feature = "tui")]
+        ("tui", Some(_)) => tui(),
+        _ => unreachable!(),
+    };
+    if let Err(error) = result {
+        if json_format(&matches) {
+            let operation = matches.subcommand_name().unwrap_or_default();
+            eprintln!("{}", json_error(operation, error.as_ref()));
+        } else {
+            eprintln!("{}", output::error(&error.to_string()));
+        }
+        process::exit(1);
+    }
+}
@@ -0,0 +1,39 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Snapshots of the output of the command line.
+//!
+//! Every `cmd/*.toml` case runs the command with JSON exports as stores in
+//! place of keychains, and compares its output with the `.stdout` and
+//! `.stderr` files next to the case.  The `cmd/store*.trycmd` cases cover
+//! every subcommand in text and JSON format, with `RUST_FFI_DEMO_STORE`
+//! selecting an export as store; subcommands which need a keychain refuse
+//! to run then, so no case ever touches a keychain.  Subcommands of features
+//! have their cases in a directory named after the feature.
+//!
+//! Scripts depend on this output, so changes to it should be deliberate: run
+//! the tests with `TRYCMD=overwrite` to update the snapshots, and review the
+//! diff.
+
+#![cfg(target_os = "macos")]
+
+#[test]
+fn cli() {
+    let cases = trycmd::TestCases::new();
+    cases.case("tests/cmd/*.toml").case("tests/cmd/*.trycmd");
+    #[cfg(feature = "age")]
+    cases.case("tests/cmd/age/*.trycmd");
+    #[cfg(feature = "tui")]
+    cases.case("tests/cmd/tui/*.trycmd");
+}
@@ -0,0 +1,133 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Secrets never leak into output or logs.
+//!
+//! Run a session of subcommands against a JSON export as store, with the
+//! policy denying the service of a marker secret, and check that neither
+//! stdout, stderr nor the audit log of any command contain the marker.
+//! Some of the commands fail on purpose, since error paths are the most
+//! likely to leak.
+
+#![cfg(target_os = "macos")]
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// The secret which must never show up.
+const MARKER: &str = "PLAINTEXT-MARKER-724";
+
+/// The store, with the marker as secret of a denied service.
+const STORE: &str = r#"[
+  {"service": "api.example.org", "account": "jane", "label": "API", "password": "hunter2"},
+  {"service": "vault.example.org", "account": "root", "password": "PLAINTEXT-MARKER-724"}
+]"#;
+
+/// Another store, whose secret differs from the marker.
+const OTHER: &str = r#"[
+  {"service": "vault.example.org", "account": "root", "password": "PLAINTEXT-MARKER-724 changed"}
+]"#;
+
+/// Batch requests, with the marker in requests which fail.
+const BATCH: &str = r#"{"op":"get","service":"vault.example.org","account":"root","id":1}
+{"op":"set","service":"api.example.org","account":"jane","password":"PLAINTEXT-MARKER-724","id":2}
+{"op":"wipe","service":"api.example.org","password":"PLAINTEXT-MARKER-724"}
+PLAINTEXT-MARKER-724
+"#;
+
+/// The command line with `args` in `directory`, with the policy and the
+/// audit log in `directory`.
+fn command(directory: &Path, args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_rust-ffi-demo"));
+    command
+        .current_dir(directory)
+        .env("RUST_FFI_DEMO_POLICY", "policy.json")
+        .args([
+            "--color",
+            "never",
+            "--read-only",
+            "--audit-log",
+            "audit.log",
+        ])
+        .args(args);
+    command
+}
+
+/// Run `command` with `stdin`, and check its output for the marker.
+fn assert_no_leak(mut command: Command, stdin: &str) {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stdout.contains(MARKER), "{:?} leaked: {}", command, stdout);
+    assert!(!stderr.contains(MARKER), "{:?} leaked: {}", command, stderr);
+}
+
+#[test]
+fn session_does_not_leak_secrets() {
+    let directory = env::temp_dir().join(format!("rust-ffi-demo-leaks-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir(&directory).unwrap();
+    fs::write(directory.join("store.json"), STORE).unwrap();
+    fs::write(directory.join("other.json"), OTHER).unwrap();
+    fs::write(
+        directory.join("policy.json"),
+        r#"{"deny": ["vault.example.org"]}"#,
+    )
+    .unwrap();
+
+    let session: [&[&str]; 10] = [
+        &["get", "vault.example.org", "root"],
+        &["--format", "json", "get", "vault.example.org", "root"],
+        &["get", "api.example.org", "nobody"],
+        &["--format", "security", "get", "api.example.org", "jane"],
+        &["export"],
+        &["export", "--yaml", "--service", "vault.example.org"],
+        &["export", "--service", "api.example.org"],
+        &["env", "--service-prefix", "vault."],
+        &["--format", "json", "env", "--service-prefix", "vault."],
+        &["diff", "store.json", "other.json"],
+    ];
+    for args in session.iter() {
+        let mut command = command(&directory, args);
+        command.env("RUST_FFI_DEMO_STORE", "store.json");
+        assert_no_leak(command, "");
+    }
+    // Batch mode has no store, but fails before it touches a keychain
+    assert_no_leak(command(&directory, &["batch"]), BATCH);
+    assert_no_leak(command(&directory, &["--format", "json", "batch"]), BATCH);
+
+    let log = fs::read_to_string(directory.join("audit.log")).unwrap();
+    assert!(
+        log.contains("batch-command"),
+        "Audit log misses batch: {}",
+        log
+    );
+    assert!(!log.contains(MARKER), "Audit log leaked: {}", log);
+    fs::remove_dir_all(&directory).unwrap();
+}
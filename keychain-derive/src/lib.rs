@@ -0,0 +1,179 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Derive `KeychainItem` for structs.
+//!
+//! Every field needs a `#[keychain(…)]` attribute: exactly one `service`,
+//! `account` and `secret` field, and any number of `generic` fields.  See
+//! `rust_ffi_demo::mapping`.
+
+#![deny(warnings)]
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, Ident, Result};
+
+/// The role of a field.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Role {
+    Service,
+    Account,
+    Secret,
+    Generic,
+}
+
+/// Find the role of a field from its `keychain` attribute.
+fn role(field: &syn::Field) -> Result<Role> {
+    let mut role = None;
+    for attribute in field.attrs.iter().filter(|a| a.path().is_ident("keychain")) {
+        attribute.parse_nested_meta(|meta| {
+            let name = if meta.path.is_ident("service") {
+                Role::Service
+            } else if meta.path.is_ident("account") {
+                Role::Account
+            } else if meta.path.is_ident("secret") {
+                Role::Secret
+            } else if meta.path.is_ident("generic") {
+                Role::Generic
+            } else {
+                return Err(meta.error("expected service, account, secret or generic"));
+            };
+            if role.replace(name).is_some() {
+                return Err(meta.error("field has more than one keychain role"));
+            }
+            Ok(())
+        })?;
+    }
+    role.ok_or_else(|| Error::new_spanned(field, "field lacks a #[keychain(…)] attribute"))
+}
+
+/// Find the single field with `role` among `fields`.
+fn single<'a>(fields: &'a [(Ident, Role)], role: Role, name: &str) -> Result<&'a Ident> {
+    let mut matching = fields.iter().filter(|(_, r)| *r == role);
+    match (matching.next(), matching.next()) {
+        (Some((ident, _)), None) => Ok(ident),
+        (None, _) => Err(Error::new(
+            Span::call_site(),
+            format!("struct needs a #[keychain({})] field", name),
+        )),
+        (Some(_), Some((ident, _))) => Err(Error::new_spanned(
+            ident,
+            format!("struct has more than one #[keychain({})] field", name),
+        )),
+    }
+}
+
+/// Expand the derive for `input`.
+fn expand(input: DeriveInput) -> Result<TokenStream2> {
+    let named = match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => &fields.named,
+            _ => {
+                return Err(Error::new_spanned(
+                    &input.ident,
+                    "KeychainItem needs named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "KeychainItem only supports structs",
+            ))
+        }
+    };
+    let fields = named
+        .iter()
+        .map(|field| Ok((field.ident.clone().unwrap(), role(field)?)))
+        .collect::<Result<Vec<_>>>()?;
+    let service = single(&fields, Role::Service, "service")?;
+    let account = single(&fields, Role::Account, "account")?;
+    let secret = single(&fields, Role::Secret, "secret")?;
+    let generic: Vec<&Ident> = fields
+        .iter()
+        .filter(|(_, role)| *role == Role::Generic)
+        .map(|(ident, _)| ident)
+        .collect();
+    let generic_names: Vec<String> = generic.iter().map(|ident| ident.to_string()).collect();
+
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    let private = quote!(::rust_ffi_demo::mapping::__private);
+    let encode_generic = if generic.is_empty() {
+        quote!(None)
+    } else {
+        quote! {{
+            let mut fields = #private::serde_json::Map::new();
+            #(
+                fields.insert(
+                    #generic_names.to_string(),
+                    #private::serde_json::to_value(&self.#generic)
+                        .map_err(#private::json_error)?,
+                );
+            )*
+            Some(#private::encode_generic(fields))
+        }}
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::rust_ffi_demo::mapping::KeychainItem for #name #type_generics #where_clause {
+            fn to_entry(&self) -> ::rust_ffi_demo::keychain::Result<::rust_ffi_demo::store::Entry> {
+                Ok(::rust_ffi_demo::store::Entry {
+                    service: self.#service.clone(),
+                    account: self.#account.clone(),
+                    label: None,
+                    generic: #encode_generic,
+                    modified: None,
+                })
+            }
+
+            fn secret(&self) -> Vec<u8> {
+                ::rust_ffi_demo::mapping::Secret::to_bytes(&self.#secret)
+            }
+
+            fn from_entry(
+                entry: ::rust_ffi_demo::store::Entry,
+                secret: Vec<u8>,
+            ) -> ::rust_ffi_demo::keychain::Result<Self> {
+                #[allow(unused_mut, unused_variables)]
+                let mut generic = #private::decode_generic(&entry)?;
+                Ok(#name {
+                    #service: entry.service,
+                    #account: entry.account,
+                    #secret: ::rust_ffi_demo::mapping::Secret::from_bytes(secret)?,
+                    #(
+                        #generic: #private::serde_json::from_value(
+                            generic
+                                .remove(#generic_names)
+                                .unwrap_or(#private::serde_json::Value::Null),
+                        )
+                        .map_err(#private::json_error)?,
+                    )*
+                })
+            }
+        }
+    })
+}
+
+/// Derive `rust_ffi_demo::mapping::KeychainItem`.
+#[proc_macro_derive(KeychainItem, attributes(keychain))]
+pub fn derive_keychain_item(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(|error| error.to_compile_error())
+        .into()
+}
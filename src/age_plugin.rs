@@ -0,0 +1,199 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! The age plugin stdio protocol for keychain identities.
+//!
+//! age runs `age-plugin-keychain --age-plugin=identity-v1` for identities of
+//! the form `AGE-PLUGIN-KEYCHAIN-1…` and talks to it over stdin and stdout.
+//! The plugin receives all X25519 stanzas of a file and unwraps the file key
+//! with the matching identity from the keychain.
+//!
+//! Files are encrypted to the native `age1…` recipients, so the plugin has no
+//! recipient phase of its own.
+
+use std::io::{self, BufRead, Write};
+
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use base64::Engine;
+
+use crate::keychain::age::{find_identity, identity_name, Identity};
+
+/// The length of body lines in stanzas.
+const BODY_LINE_LENGTH: usize = 64;
+
+/// A stanza of the plugin protocol.
+#[derive(Debug)]
+struct Stanza {
+    tag: String,
+    args: Vec<String>,
+    body: Vec<u8>,
+}
+
+/// Read a stanza from `input`.
+///
+/// Return `None` at the end of input.
+fn read_stanza<R: BufRead>(input: &mut R) -> io::Result<Option<Stanza>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line
+        .trim_end_matches('\n')
+        .strip_prefix("-> ")
+        .ok_or_else(|| invalid("Stanza without prefix"))?
+        .split(' ')
+        .map(ToString::to_string);
+    let tag = parts.next().ok_or_else(|| invalid("Stanza without tag"))?;
+    let args = parts.collect();
+
+    // The body ends with the first line shorter than a full line
+    let mut encoded = String::new();
+    loop {
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return Err(invalid("Stanza without end of body"));
+        }
+        let body_line = line.trim_end_matches('\n');
+        encoded.push_str(body_line);
+        if body_line.len() < BODY_LINE_LENGTH {
+            break;
+        }
+    }
+    let body = STANDARD_NO_PAD
+        .decode(&encoded)
+        .map_err(|_| invalid("Invalid base64 in stanza body"))?;
+
+    Ok(Some(Stanza { tag, args, body }))
+}
+
+/// Write a stanza to `output`.
+fn write_stanza<W: Write>(output: &mut W, tag: &str, args: &[&str], body: &[u8]) -> io::Result<()> {
+    write!(output, "-> {}", tag)?;
+    for arg in args {
+        write!(output, " {}", arg)?;
+    }
+    writeln!(output)?;
+    let encoded = STANDARD_NO_PAD.encode(body);
+    let mut rest = encoded.as_str();
+    while BODY_LINE_LENGTH <= rest.len() {
+        writeln!(output, "{}", &rest[..BODY_LINE_LENGTH])?;
+        rest = &rest[BODY_LINE_LENGTH..];
+    }
+    // Always terminate the body with a short, possibly empty, line
+    writeln!(output, "{}", rest)?;
+    output.flush()
+}
+
+/// Send a command stanza to age and wait for its response.
+fn send_command<R: BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    tag: &str,
+    args: &[&str],
+    body: &[u8],
+) -> io::Result<Stanza> {
+    write_stanza(output, tag, args, body)?;
+    read_stanza(input)?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "No response from age"))
+}
+
+/// Run the `identity-v1` state machine on `input` and `output`.
+///
+/// # Errors
+///
+/// Return an IO error if the protocol breaks.  Keychain errors are reported to
+/// age instead.
+pub fn run_identity_v1<R: BufRead, W: Write>(input: &mut R, output: &mut W) -> io::Result<()> {
+    // Phase 1: Collect identities and X25519 stanzas from age
+    let mut identities = Vec::new();
+    let mut stanzas = Vec::new();
+    while let Some(stanza) = read_stanza(input)? {
+        match stanza.tag.as_str() {
+            "add-identity" => identities.push(stanza.args.first().cloned().unwrap_or_default()),
+            "recipient-stanza" => {
+                let file_index = stanza.args.first().map(String::as_str);
+                let tag = stanza.args.get(1).map(String::as_str);
+                let share = stanza.args.get(2);
+                if let (Some(file_index), Some("X25519"), Some(share)) = (file_index, tag, share)
+                {
+                    if let Ok(share) = STANDARD_NO_PAD.decode(share) {
+                        stanzas.push((file_index.to_string(), share, stanza.body));
+                    }
+                }
+            }
+            "done" => break,
+            // Ignore unsupported commands, as required by the protocol
+            _ => (),
+        }
+    }
+
+    // Phase 2: Load identities from the keychain and unwrap file keys
+    let mut loaded: Vec<Identity> = Vec::new();
+    for (index, identity) in identities.iter().enumerate() {
+        let result = identity_name(identity)
+            .ok_or_else(|| format!("Invalid identity: {}", identity))
+            .and_then(|name| find_identity(&name).map_err(|error| error.to_string()));
+        match result {
+            Ok(identity) => loaded.push(identity),
+            Err(message) => {
+                let index = index.to_string();
+                send_command(
+                    input,
+                    output,
+                    "error",
+                    &["identity", &index],
+                    message.as_bytes(),
+                )?;
+            }
+        }
+    }
+
+    let mut unwrapped: Vec<&str> = Vec::new();
+    for (file_index, share, body) in &stanzas {
+        if unwrapped.contains(&file_index.as_str()) {
+            continue;
+        }
+        let file_key = loaded
+            .iter()
+            .find_map(|identity| identity.unwrap_x25519(share, body));
+        if let Some(file_key) = file_key {
+            send_command(input, output, "file-key", &[file_index], &file_key)?;
+            unwrapped.push(file_index);
+        }
+    }
+
+    write_stanza(output, "done", &[], &[])
+}
+
+/// Run the `recipient-v1` state machine on `input` and `output`.
+///
+/// Keychain identities use native recipients, so this only tells age to use
+/// the `age1…` recipient instead.
+pub fn run_recipient_v1<R: BufRead, W: Write>(input: &mut R, output: &mut W) -> io::Result<()> {
+    while let Some(stanza) = read_stanza(input)? {
+        if stanza.tag == "done" {
+            break;
+        }
+    }
+    send_command(
+        input,
+        output,
+        "error",
+        &["internal"],
+        b"age-plugin-keychain has no recipients; encrypt to the age1 recipient of the identity",
+    )?;
+    write_stanza(output, "done", &[], &[])
+}
@@ -0,0 +1,200 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! An audit log of keychain operations, as JSON lines.
+//!
+//! Once installed, the log gets a line for every finished keychain operation,
+//! through the audit hook of `metrics`, and for every event which callers
+//! `record`, eg, the commands of batch mode:
+//!
+//! ```json
+//! {"time":"2024-05-01 09:30:00 UTC","event":"batch-command","op":"set","service":"api.example.com","account":"jane","password":"[redacted]"}
+//! {"time":"2024-05-01 09:30:00 UTC","event":"operation","operation":"set_password","correlation-id":"…","duration-ms":3,"error":null}
+//! ```
+//!
+//! Events serialize with serde, so sensitive values in events redact by
+//! construction; see `keychain::sensitive`.  The log redacts them even within
+//! `sensitive::revealing`.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::keychain::metrics::{self, AuditEvent};
+use crate::keychain::sensitive;
+use crate::output::{format_time, json_string};
+use crate::runtime;
+
+/// The installed log, if any.
+static INSTALLED: RwLock<Option<Arc<AuditLog>>> = RwLock::new(None);
+
+/// An audit log file.
+#[derive(Debug)]
+pub struct AuditLog {
+    file: Mutex<File>,
+}
+
+/// The fields of an operation event.
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Operation<'a> {
+    operation: &'a str,
+    correlation_id: String,
+    duration_ms: u128,
+    error: Option<Failure<'a>>,
+}
+
+/// The error of a failed operation.
+#[derive(Serialize)]
+struct Failure<'a> {
+    code: &'a str,
+    message: &'a str,
+}
+
+impl AuditLog {
+    /// Open the log at `path`, appending to it.
+    ///
+    /// Create the log with permissions only for the current user.
+    ///
+    /// # Errors
+    ///
+    /// Return an IO error if the log cannot be opened.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<AuditLog> {
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .open(path)?;
+        Ok(AuditLog {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Write an `event` with the fields of `fields`.
+    ///
+    /// `fields` should serialize to a map, whose entries go into the line;
+    /// other values go into the line as `value`.  Sensitive values redact,
+    /// even within `sensitive::revealing`:
+    ///
+    /// ```
+    /// use rust_ffi_demo::audit_log::AuditLog;
+    /// use rust_ffi_demo::keychain::sensitive::{self, Sensitive};
+    /// use std::collections::BTreeMap;
+    ///
+    /// let path = std::env::temp_dir().join(format!("audit-{}.log", std::process::id()));
+    /// let log = AuditLog::open(&path).unwrap();
+    /// let mut fields = BTreeMap::new();
+    /// fields.insert("token", Sensitive::new("PLAINTEXT-MARKER".to_string()));
+    /// sensitive::revealing(|| log.write("example", &fields)).unwrap();
+    ///
+    /// let contents = std::fs::read_to_string(&path).unwrap();
+    /// std::fs::remove_file(&path).unwrap();
+    /// assert!(contents.ends_with("\"event\":\"example\",\"token\":\"[redacted]\"}\n"));
+    /// assert!(!contents.contains("PLAINTEXT-MARKER"));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Return an IO error if writing fails, or `fields` fail to serialize.
+    pub fn write<T: Serialize + ?Sized>(&self, event: &str, fields: &T) -> io::Result<()> {
+        let fields = match sensitive::redacting(|| serde_json::to_value(fields))? {
+            Value::Object(fields) => fields,
+            value => {
+                let mut fields = Map::new();
+                fields.insert("value".to_string(), value);
+                fields
+            }
+        };
+        // Start with time and event, for people reading the log
+        let mut text = format!(
+            "{{\"time\":{},\"event\":{}",
+            json_string(&format_time(SystemTime::now())),
+            json_string(event)
+        );
+        for (key, value) in fields {
+            text.push_str(&format!(",{}:{}", json_string(&key), value));
+        }
+        text.push_str("}\n");
+        // Write every line at once, so that lines of processes sharing the
+        // log do not interleave
+        self.file.lock().unwrap().write_all(text.as_bytes())
+    }
+
+    /// Flush the log to disk.
+    ///
+    /// # Errors
+    ///
+    /// Return an IO error if the log cannot be synced.
+    pub fn flush(&self) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        file.flush()?;
+        file.sync_data()
+    }
+
+    /// Write the audit `event` of a keychain operation.
+    fn operation(&self, event: &AuditEvent<'_>) -> io::Result<()> {
+        let operation = Operation {
+            operation: event.operation,
+            correlation_id: event.correlation_id.to_string(),
+            duration_ms: event.duration.as_millis(),
+            error: event.error.map(|error| Failure {
+                code: error.status.name(),
+                message: &error.message,
+            }),
+        };
+        self.write("operation", &operation)
+    }
+}
+
+/// Write to `log` from now on, replacing the audit hook of `metrics`.
+///
+/// Flush the log on `runtime::exit`.
+pub fn install(log: AuditLog) {
+    let log = Arc::new(log);
+    *INSTALLED.write().unwrap() = Some(log.clone());
+    runtime::on_shutdown(flush);
+    metrics::set_audit_hook(move |event| {
+        if let Err(error) = log.operation(event) {
+            eprintln!("Cannot write audit log: {}", error);
+        }
+    });
+}
+
+/// Record `event` with `fields` in the installed log, if any.
+///
+/// Report failures to write on standard error, but carry on.
+pub fn record<T: Serialize + ?Sized>(event: &str, fields: &T) {
+    if let Some(ref log) = *INSTALLED.read().unwrap() {
+        if let Err(error) = log.write(event, fields) {
+            eprintln!("Cannot write audit log: {}", error);
+        }
+    }
+}
+
+/// Flush the installed log, if any.
+///
+/// Report failures on standard error, but carry on.
+pub fn flush() {
+    if let Some(ref log) = *INSTALLED.read().unwrap() {
+        if let Err(error) = log.flush() {
+            eprintln!("Cannot flush audit log: {}", error);
+        }
+    }
+}
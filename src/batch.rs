@@ -0,0 +1,313 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Drive many keychain operations over a single process.
+//!
+//! Batch mode reads one JSON command per line, and writes one JSON result per
+//! line, in order, right after running each command:
+//!
+//! ```text
+//! > {"op":"set","service":"api.example.com","account":"jane","password":"s3cr3t","id":1}
+//! < {"id":1,"ok":true}
+//! > {"op":"get","service":"api.example.com","account":"jane","id":2}
+//! < {"id":2,"ok":true,"password":"s3cr3t"}
+//! > {"op":"get","service":"api.example.com","account":"joe","id":3}
+//! < {"id":3,"ok":false,"error":{"code":"ItemNotFound","message":"…","correlation-id":"…"}}
+//! ```
+//!
+//! Results echo the `id` of their command, if any.  Every command takes an
+//! optional `keychain` by name or path.  The ops are:
+//!
+//! - `get`: the `password` of `service` and `account`.
+//! - `set`: set the `password` of `service` and `account`, adding the item if
+//!   needed.
+//! - `delete`: delete the item of `service` and `account`.
+//! - `exists`: whether an item of `service` and `account` `exists`, without
+//!   reading its password.
+//! - `list`: the `accounts` of `service`.
+//!
+//! A failed or malformed command fails on its own; batch mode goes on with
+//! the next line.
+
+use std::io::{self, BufRead, Write};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::audit_log;
+use crate::keychain::items;
+use crate::keychain::keychains::Keychain;
+use crate::keychain::sensitive::Sensitive;
+use crate::keychain::{correlation, KeychainError, KeychainErrorCode};
+use crate::policy::Access;
+
+/// A command of batch mode.
+///
+/// Serializing a command redacts its password, so commands go into logs as
+/// they are.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "kebab-case")]
+pub enum Command {
+    /// Get the password of an item.
+    Get {
+        /// The service of the item.
+        service: String,
+        /// The account of the item.
+        account: String,
+    },
+    /// Set the password of an item, adding the item if needed.
+    Set {
+        /// The service of the item.
+        service: String,
+        /// The account of the item.
+        account: String,
+        /// The new password.
+        password: Sensitive<String>,
+    },
+    /// Delete an item.
+    Delete {
+        /// The service of the item.
+        service: String,
+        /// The account of the item.
+        account: String,
+    },
+    /// Check whether an item exists.
+    Exists {
+        /// The service of the item.
+        service: String,
+        /// The account of the item.
+        account: String,
+    },
+    /// List the accounts of a service.
+    List {
+        /// The service of the items.
+        service: String,
+    },
+}
+
+impl Command {
+    /// The service of this command.
+    pub fn service(&self) -> &str {
+        match *self {
+            Command::Get { ref service, .. }
+            | Command::Set { ref service, .. }
+            | Command::Delete { ref service, .. }
+            | Command::Exists { ref service, .. }
+            | Command::List { ref service } => service,
+        }
+    }
+
+    /// The access this command needs to its service.
+    pub fn access(&self) -> Access {
+        match *self {
+            Command::Get { .. } | Command::Exists { .. } | Command::List { .. } => Access::Read,
+            Command::Set { .. } => Access::Write,
+            Command::Delete { .. } => Access::Delete,
+        }
+    }
+
+    /// Run this command on `service` in `keychain`, and return the fields of
+    /// its result.
+    fn run(
+        &self,
+        keychain: Option<&Keychain>,
+        service: &str,
+    ) -> Result<Map<String, Value>, KeychainError> {
+        let mut fields = Map::new();
+        match *self {
+            Command::Get { ref account, .. } => {
+                let password = items::find_password(keychain, service, account)?;
+                fields.insert(
+                    "password".to_string(),
+                    Value::String(password.into_exposed()),
+                );
+            }
+            Command::Set {
+                ref account,
+                ref password,
+                ..
+            } => items::set_password(keychain, service, account, password.expose())?,
+            Command::Delete { ref account, .. } => items::delete_item(keychain, service, account)?,
+            Command::Exists { ref account, .. } => {
+                let exists = match items::find_item(keychain, service, account) {
+                    Ok(_) => true,
+                    Err(ref error) if error.status == KeychainErrorCode::ItemNotFound => false,
+                    Err(error) => return Err(error),
+                };
+                fields.insert("exists".to_string(), Value::Bool(exists));
+            }
+            Command::List { .. } => {
+                let accounts: Vec<Value> = items::list_items(keychain)?
+                    .into_iter()
+                    .filter(|item| item.service == service)
+                    .map(|item| Value::String(item.account))
+                    .collect();
+                fields.insert("accounts".to_string(), Value::Array(accounts));
+            }
+        }
+        Ok(fields)
+    }
+}
+
+/// A line of batch input.
+#[derive(Debug, Deserialize)]
+struct Request {
+    /// An ID to echo in the result.
+    #[serde(default)]
+    id: Option<Value>,
+    /// The keychain to run the command on, by name or path.
+    #[serde(default)]
+    keychain: Option<String>,
+    /// The command.
+    #[serde(flatten)]
+    command: Command,
+}
+
+/// Why a command failed.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Failure {
+    /// The name of the error.
+    code: String,
+    /// What went wrong.
+    message: String,
+    /// The correlation ID of a failed keychain operation.
+    correlation_id: Option<String>,
+}
+
+impl Failure {
+    /// A failure with `code` and `message`.
+    fn new(code: &str, message: String) -> Failure {
+        Failure {
+            code: code.to_string(),
+            message,
+            correlation_id: None,
+        }
+    }
+}
+
+impl From<KeychainError> for Failure {
+    fn from(error: KeychainError) -> Failure {
+        Failure {
+            code: error.status.name().to_string(),
+            correlation_id: error.correlation_id.map(|id| id.to_string()),
+            message: error.message,
+        }
+    }
+}
+
+/// The result of a line of batch input.
+#[derive(Debug, Serialize)]
+struct Response {
+    /// The ID of the command.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<Value>,
+    /// Whether the command succeeded.
+    ok: bool,
+    /// The fields of the result of a successful command.
+    #[serde(flatten)]
+    fields: Map<String, Value>,
+    /// Why the command failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<Failure>,
+}
+
+impl Response {
+    /// The response to the command with `id`, from its `result`.
+    fn new(id: Option<Value>, result: Result<Map<String, Value>, Failure>) -> Response {
+        match result {
+            Ok(fields) => Response {
+                id,
+                ok: true,
+                fields,
+                error: None,
+            },
+            Err(failure) => Response {
+                id,
+                ok: false,
+                fields: Map::new(),
+                error: Some(failure),
+            },
+        }
+    }
+}
+
+/// Run a single `line` of batch input.
+fn run_line(line: &str, guard: &mut dyn FnMut(&str, Access) -> Result<String, String>) -> Response {
+    let request: Request = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(error) => {
+            let failure = Failure::new("InvalidRequest", error.to_string());
+            return Response::new(None, Err(failure));
+        }
+    };
+    let Request {
+        id,
+        keychain,
+        command,
+    } = request;
+    audit_log::record("batch-command", &command);
+    let result = match guard(command.service(), command.access()) {
+        Err(message) => Err(Failure::new("PolicyDenied", message)),
+        Ok(service) => correlation::scope(|_| {
+            let keychain = keychain.as_deref().map(Keychain::named).transpose()?;
+            command.run(keychain.as_ref(), &service)
+        })
+        .map_err(Failure::from),
+    };
+    Response::new(id, result)
+}
+
+/// Run batch commands from `input`, and write their results to `output`.
+///
+/// Run the service and access of every command through `guard` first, which
+/// returns the service to use, eg, with the prefix of a profile, or refuses
+/// the command with a message.  Skip blank lines.  Flush `output` after
+/// every result, so that the driving program can wait for it.
+///
+/// ```
+/// use rust_ffi_demo::batch;
+///
+/// let input = "{\"op\":\"get\",\"service\":\"secret\",\"account\":\"jane\",\"id\":7}\n\nnonsense\n";
+/// let mut output = Vec::new();
+/// let mut guard = |service: &str, _| Err(format!("{} is off limits", service));
+/// batch::run(input.as_bytes(), &mut output, &mut guard).unwrap();
+/// let lines: Vec<&str> = std::str::from_utf8(&output).unwrap().lines().collect();
+/// assert_eq!(lines.len(), 2);
+/// assert!(lines[0].starts_with(r#"{"id":7,"ok":false,"error":"#));
+/// assert!(lines[0].contains("secret is off limits"));
+/// assert!(lines[1].contains(r#""code":"InvalidRequest""#));
+/// ```
+///
+/// # Errors
+///
+/// Return an IO error if reading or writing fails.  Failures of commands go
+/// to `output`.
+pub fn run<R: BufRead, W: Write>(
+    input: R,
+    output: &mut W,
+    guard: &mut dyn FnMut(&str, Access) -> Result<String, String>,
+) -> io::Result<()> {
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let result = run_line(&line, guard);
+        serde_json::to_writer(&mut *output, &result)?;
+        output.write_all(b"\n")?;
+        output.flush()?;
+    }
+    Ok(())
+}
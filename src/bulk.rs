@@ -0,0 +1,340 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Batch operations with per-item results.
+//!
+//! Batch operations report the outcome of every item in a `BulkReport`
+//! instead of failing as a whole.  By default a batch stops at the first
+//! failure and reports the remaining items as skipped; with
+//! `continue_on_error` it attempts every item.  All batch operations report
+//! progress after every attempted item, except `fetch_all`, which runs
+//! lookups in parallel and returns the result of every lookup instead.
+//!
+//! `import_into` and `delete_from` run batches against any `SecretStore`,
+//! like an export file in place of a keychain.
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::export::Record;
+use crate::keychain::items::{self, Item};
+use crate::keychain::keychains::Keychain;
+use crate::keychain::sensitive::Sensitive;
+use crate::keychain::{
+    correlation, with_autorelease_pool, KeychainError, KeychainErrorCode, Result, SecretBytes,
+};
+use crate::progress::{Progress, ProgressFn};
+use crate::store::{Entry, SecretStore};
+
+/// The identity of an item in a batch.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ItemId {
+    /// The service of the item.
+    pub service: String,
+    /// The account of the item.
+    pub account: String,
+}
+
+impl ItemId {
+    /// The identity of `item`.
+    pub fn of(item: &Item) -> ItemId {
+        ItemId {
+            service: item.service.clone(),
+            account: item.account.clone(),
+        }
+    }
+}
+
+impl fmt::Display for ItemId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\t{}", self.service, self.account)
+    }
+}
+
+/// An item for which a batch operation failed.
+#[derive(Debug)]
+pub struct Failure {
+    /// The item.
+    pub item: ItemId,
+    /// Why the operation failed for this item.
+    pub error: KeychainError,
+}
+
+/// The outcome of a batch operation.
+#[derive(Debug, Default)]
+pub struct BulkReport {
+    /// Items for which the operation succeeded.
+    pub succeeded: Vec<ItemId>,
+    /// Items for which the operation failed.
+    pub failed: Vec<Failure>,
+    /// Items which were not attempted, because an earlier item failed.
+    pub skipped: Vec<ItemId>,
+}
+
+impl BulkReport {
+    /// Whether the operation succeeded for all items.
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty() && self.skipped.is_empty()
+    }
+
+    /// The number of items in this report.
+    pub fn len(&self) -> usize {
+        self.succeeded.len() + self.failed.len() + self.skipped.len()
+    }
+
+    /// Whether this report has no items at all.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Run `f` for all `entries`, recording outcomes in a report.
+fn run<T, F>(
+    entries: Vec<(ItemId, T)>,
+    continue_on_error: bool,
+    progress: &mut ProgressFn<'_>,
+    mut f: F,
+) -> BulkReport
+where
+    F: FnMut(&ItemId, T) -> Result<()>,
+{
+    let total = entries.len();
+    let mut report = BulkReport::default();
+    for (index, (id, entry)) in entries.into_iter().enumerate() {
+        if !continue_on_error && !report.failed.is_empty() {
+            report.skipped.push(id);
+            continue;
+        }
+        let result = f(&id, entry);
+        progress(&Progress {
+            processed: index + 1,
+            total,
+            service: &id.service,
+            account: &id.account,
+        });
+        match result {
+            Ok(()) => report.succeeded.push(id),
+            Err(error) => report.failed.push(Failure { item: id, error }),
+        }
+    }
+    report
+}
+
+/// Add all `items` with their data.
+///
+/// Add items to `keychain` if given, or to the default keychain otherwise.
+/// Items which already exist fail with `KeychainErrorCode::DuplicateItem`.
+pub fn add_many(
+    keychain: Option<&Keychain>,
+    items: &[(Item, SecretBytes)],
+    continue_on_error: bool,
+    progress: &mut ProgressFn<'_>,
+) -> BulkReport {
+    let entries = items
+        .iter()
+        .map(|entry| (ItemId::of(&entry.0), entry))
+        .collect();
+    run(entries, continue_on_error, progress, |_, (item, data)| {
+        with_autorelease_pool(|| items::add_item(keychain, item, data))
+    })
+}
+
+/// Import `records` of a JSON export.
+///
+/// See `add_many`.
+pub fn import(
+    keychain: Option<&Keychain>,
+    records: &[Record],
+    continue_on_error: bool,
+    progress: &mut ProgressFn<'_>,
+) -> BulkReport {
+    let items: Vec<(Item, SecretBytes)> = records
+        .iter()
+        .map(|record| {
+            let item = Item {
+                label: record.label.clone(),
+                ..Item::new(record.service.clone(), record.account.clone())
+            };
+            (item, record.password.expose().clone().into_bytes())
+        })
+        .collect();
+    add_many(keychain, &items, continue_on_error, progress)
+}
+
+/// Delete all generic passwords for which `predicate` holds.
+///
+/// If `keychain` is given only delete items of this keychain.
+///
+/// # Errors
+///
+/// Return `KeychainError` if listing items fails; failures to delete single
+/// items end up in the report.
+pub fn delete_where<P>(
+    keychain: Option<&Keychain>,
+    predicate: P,
+    continue_on_error: bool,
+    progress: &mut ProgressFn<'_>,
+) -> Result<BulkReport>
+where
+    P: Fn(&Item) -> bool,
+{
+    let entries = items::list_items(keychain)?
+        .into_iter()
+        .filter(|item| predicate(item))
+        .map(|item| (ItemId::of(&item), ()))
+        .collect();
+    Ok(run(entries, continue_on_error, progress, |id, ()| {
+        with_autorelease_pool(|| items::delete_item(keychain, &id.service, &id.account))
+    }))
+}
+
+/// Import `records` of a JSON export into `store`.
+///
+/// Like `import`, records which already exist in `store` fail with
+/// `KeychainErrorCode::DuplicateItem`.
+pub fn import_into<S: SecretStore + ?Sized>(
+    store: &mut S,
+    records: &[Record],
+    continue_on_error: bool,
+    progress: &mut ProgressFn<'_>,
+) -> BulkReport {
+    let entries = records
+        .iter()
+        .map(|record| {
+            let id = ItemId {
+                service: record.service.clone(),
+                account: record.account.clone(),
+            };
+            (id, record)
+        })
+        .collect();
+    run(entries, continue_on_error, progress, |id, record| {
+        match store.entry(&id.service, &id.account) {
+            Ok(_) => {
+                return Err(KeychainError {
+                    status: KeychainErrorCode::DuplicateItem,
+                    message: format!(
+                        "An entry for {} and {} already exists",
+                        id.service, id.account
+                    ),
+                    correlation_id: correlation::current(),
+                })
+            }
+            Err(ref error) if error.status == KeychainErrorCode::ItemNotFound => (),
+            Err(error) => return Err(error),
+        }
+        let entry = Entry {
+            service: record.service.clone(),
+            account: record.account.clone(),
+            label: record.label.clone(),
+            generic: None,
+            modified: None,
+        };
+        store.set(&entry, record.password.expose().as_bytes())
+    })
+}
+
+/// Delete all entries of `store` for which `predicate` holds.
+///
+/// See `delete_where`.
+///
+/// # Errors
+///
+/// Return `KeychainError` if listing entries fails; failures to delete single
+/// entries end up in the report.
+pub fn delete_from<S, P>(
+    store: &mut S,
+    predicate: P,
+    continue_on_error: bool,
+    progress: &mut ProgressFn<'_>,
+) -> Result<BulkReport>
+where
+    S: SecretStore + ?Sized,
+    P: Fn(&Entry) -> bool,
+{
+    let entries = store
+        .entries()?
+        .into_iter()
+        .filter(|entry| predicate(entry))
+        .map(|entry| {
+            let id = ItemId {
+                service: entry.service,
+                account: entry.account,
+            };
+            (id, ())
+        })
+        .collect();
+    Ok(run(entries, continue_on_error, progress, |id, ()| {
+        store.delete(&id.service, &id.account)
+    }))
+}
+
+/// Find the data of all `queries`, with up to `parallelism` lookups at once.
+///
+/// Look in `keychain` if given, or in the search list otherwise.  Every
+/// worker thread opens its own handle to `keychain`, so no keychain object is
+/// shared between threads.  Lookups may prompt for access, and prompts of
+/// parallel lookups queue up.
+///
+/// Return the result of every query, in the order of `queries`.
+///
+/// # Errors
+///
+/// Return `KeychainError` if a worker cannot open `keychain`; failures of
+/// single lookups end up in the results.
+pub fn fetch_all(
+    keychain: Option<&Keychain>,
+    queries: &[ItemId],
+    parallelism: usize,
+) -> Result<Vec<(ItemId, Result<SecretBytes>)>> {
+    let path = keychain.map(Keychain::path);
+    let next = AtomicUsize::new(0);
+    let workers = parallelism.max(1).min(queries.len());
+    let mut found: Vec<(usize, Result<SecretBytes>)> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let keychain = path.map(Keychain::open).transpose()?;
+                    let mut found = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let query = match queries.get(index) {
+                            Some(query) => query,
+                            None => return Ok(found),
+                        };
+                        let data = with_autorelease_pool(|| {
+                            items::find_data(keychain.as_ref(), &query.service, &query.account)
+                                .map(Sensitive::into_exposed)
+                        });
+                        found.push((index, data));
+                    }
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Result<Vec<Vec<_>>>>()
+    })?
+    .into_iter()
+    .flatten()
+    .collect();
+    found.sort_by_key(|entry| entry.0);
+    Ok(queries
+        .iter()
+        .cloned()
+        .zip(found.into_iter().map(|entry| entry.1))
+        .collect())
+}
@@ -0,0 +1,261 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Detect reset, restored or tampered keychains with a canary item.
+//!
+//! `init` stores a random value in a canary item, and records its digest in
+//! a state file outside the keychain.  `check` compares the canary with the
+//! state file, and if it's intact replaces the value with a fresh one and
+//! records when it verified the canary:
+//!
+//! ```no_run
+//! use rust_ffi_demo::canary::{self, Outcome};
+//!
+//! let state = canary::default_state_path();
+//! canary::init(None, &state).unwrap();
+//! assert_eq!(canary::check(None, &state).unwrap().outcome, Outcome::Intact);
+//! ```
+//!
+//! A reset keychain has no canary, and a keychain restored from a backup has
+//! an older value, if any check ran since the backup.  In read-only mode
+//! `check` only compares and does not rotate the value, so it misses restored
+//! backups taken since the last rotation.
+//!
+//! The state file holds only the digest of the canary, which tells nothing
+//! about other items.  If the keychain changes but the state file can't be
+//! written in a rotation the next check reports a changed canary.
+
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::keychain::digest::{ct_eq, sha256_hex};
+use crate::keychain::items;
+use crate::keychain::keychains::Keychain;
+use crate::keychain::{self, correlation, read_only, KeychainError, KeychainErrorCode, Result};
+
+/// The service of the canary item.
+pub const SERVICE: &str = "rust-ffi-demo.canary";
+
+/// The account of the canary item.
+pub const ACCOUNT: &str = "canary";
+
+/// The path of the state file relative to the home directory, unless
+/// `STATE_VAR` says otherwise.
+pub const DEFAULT_PATH: &str = "Library/Application Support/rust-ffi-demo/canary.json";
+
+/// The environment variable with the path to the state file.
+pub const STATE_VAR: &str = "RUST_FFI_DEMO_CANARY";
+
+/// What a state file records about the canary.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct State {
+    /// The SHA-256 digest of the current value, in hex.
+    pub digest: String,
+    /// When `init` stored the canary, in seconds since the epoch.
+    pub initialized: u64,
+    /// When `check` last found the canary intact, in seconds since the
+    /// epoch, or `None` if it never did.
+    #[serde(default)]
+    pub verified: Option<u64>,
+    /// How often `check` replaced the value.
+    #[serde(default)]
+    pub rotations: u64,
+}
+
+/// What `check` found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The canary has the value of the state file.
+    Intact,
+    /// The canary is gone, eg, because the keychain was reset or replaced.
+    Missing,
+    /// The canary has another value, eg, because the keychain was restored
+    /// from a backup, or the canary was tampered with.
+    Changed,
+}
+
+impl Outcome {
+    /// The name of this outcome, as printed.
+    pub fn name(self) -> &'static str {
+        match self {
+            Outcome::Intact => "intact",
+            Outcome::Missing => "missing",
+            Outcome::Changed => "changed",
+        }
+    }
+}
+
+/// The result of `check`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    /// What `check` found.
+    pub outcome: Outcome,
+    /// When the canary was verified before this check, if ever.
+    pub previously_verified: Option<u64>,
+    /// The state after this check.
+    pub state: State,
+}
+
+/// The path of the state file, from `STATE_VAR` or else `DEFAULT_PATH` in
+/// the home directory.
+pub fn default_state_path() -> PathBuf {
+    match env::var_os(STATE_VAR) {
+        Some(path) => PathBuf::from(path),
+        None => env::var_os("HOME")
+            .map(PathBuf::from)
+            .unwrap_or_default()
+            .join(DEFAULT_PATH),
+    }
+}
+
+/// The current time in seconds since the epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// A fresh random value for the canary, with its digest.
+fn fresh_value() -> Result<(String, String)> {
+    let value: String = keychain::random_bytes(32)?
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let digest = sha256_hex(value.as_bytes());
+    Ok((value, digest))
+}
+
+/// Load the state file at `path`.
+fn load(path: &Path) -> Result<State> {
+    let file = match File::open(path) {
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => {
+            return Err(KeychainError {
+                status: KeychainErrorCode::Io(io::ErrorKind::NotFound),
+                message: format!(
+                    "No canary state at {}; run canary init first",
+                    path.display()
+                ),
+                correlation_id: correlation::current(),
+            })
+        }
+        result => result?,
+    };
+    serde_json::from_reader(file).map_err(|error| {
+        KeychainError::invalid_data(format!(
+            "Invalid canary state at {}: {}",
+            path.display(),
+            error
+        ))
+    })
+}
+
+/// Write `state` to the state file at `path`.
+fn save(path: &Path, state: &State) -> Result<()> {
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+    // Move a complete temporary file over the state file, to never leave a
+    // partial state behind
+    let mut temporary = path.to_path_buf().into_os_string();
+    temporary.push(".tmp");
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&temporary)?;
+    serde_json::to_writer_pretty(file, state)
+        .map_err(|error| KeychainError::from(io::Error::from(error)))?;
+    fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+/// Store a new canary in `keychain`, and record it in the state file at
+/// `state_path`.
+///
+/// Store the canary in the default keychain if `keychain` is `None`.
+/// Replace an existing canary and state.
+///
+/// # Errors
+///
+/// Return `KeychainError` if the canary or the state file cannot be written.
+pub fn init(keychain: Option<&Keychain>, state_path: &Path) -> Result<State> {
+    correlation::scope(|_| {
+        let (value, digest) = fresh_value()?;
+        items::set_password(keychain, SERVICE, ACCOUNT, &value)?;
+        let state = State {
+            digest,
+            initialized: now(),
+            verified: None,
+            rotations: 0,
+        };
+        save(state_path, &state)?;
+        Ok(state)
+    })
+}
+
+/// Check the canary in `keychain` against the state file at `state_path`.
+///
+/// Look in the search list if `keychain` is `None`.  If the canary is intact
+/// and read-only mode is off, replace its value and record the rotation.
+/// Record the time of verification in any case.
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::Io` if there is no state file,
+/// `KeychainErrorCode::InvalidData` if it's invalid, and `KeychainError` if
+/// the canary cannot be read, eg, because the keychain is locked, or the new
+/// value or state cannot be written.
+pub fn check(keychain: Option<&Keychain>, state_path: &Path) -> Result<Check> {
+    correlation::scope(|_| {
+        let mut state = load(state_path)?;
+        let previously_verified = state.verified;
+        let outcome = match items::find_data(keychain, SERVICE, ACCOUNT) {
+            Err(ref error) if error.status == KeychainErrorCode::ItemNotFound => Outcome::Missing,
+            Err(error) => return Err(error),
+            Ok(value)
+                if ct_eq(
+                    sha256_hex(value.expose()).as_bytes(),
+                    state.digest.as_bytes(),
+                ) =>
+            {
+                Outcome::Intact
+            }
+            Ok(_) => Outcome::Changed,
+        };
+        if outcome == Outcome::Intact {
+            if !read_only::is_enabled() {
+                let (value, digest) = fresh_value()?;
+                items::change_password(keychain, SERVICE, ACCOUNT, value.as_bytes())?;
+                state.digest = digest;
+                state.rotations += 1;
+            }
+            state.verified = Some(now());
+            save(state_path, &state)?;
+        }
+        Ok(Check {
+            outcome,
+            previously_verified,
+            state,
+        })
+    })
+}
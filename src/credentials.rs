@@ -0,0 +1,194 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Pass secrets to child processes in files, like systemd credentials.
+//!
+//! Some programs refuse to take secrets from environment variables, which
+//! leak into child processes and crash reports.  `run` writes every secret
+//! to a file named after the credential in a private directory, and passes
+//! the directory in `$CREDENTIALS_DIRECTORY`, as systemd does for
+//! `LoadCredential=`:
+//!
+//! ```no_run
+//! use rust_ffi_demo::credentials::{self, Credential};
+//! use std::process::Command;
+//!
+//! let credentials: Vec<Credential> = vec!["token=api.example.com/jane".parse().unwrap()];
+//! let mut command = Command::new("sh");
+//! command.args(&["-c", "curl -H @\"$CREDENTIALS_DIRECTORY/token\" https://api.example.com"]);
+//! let status = credentials::run(None, &credentials, &mut command).unwrap();
+//! assert!(status.success());
+//! ```
+//!
+//! The directory and its files are readable only by the user, and exist only
+//! as long as the child runs.
+
+use std::env;
+use std::fmt;
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::str::FromStr;
+
+use crate::keychain::items;
+use crate::keychain::keychains::Keychain;
+use crate::keychain::{self, KeychainError, Result, SecretBytes};
+
+/// The variable which holds the directory of credentials.
+pub const CREDENTIALS_DIRECTORY: &str = "CREDENTIALS_DIRECTORY";
+
+/// A credential for a child, from a generic password.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credential {
+    /// The name of the credential, and of its file.
+    pub name: String,
+    /// The service of the item.
+    pub service: String,
+    /// The account of the item.
+    pub account: String,
+}
+
+impl fmt::Display for Credential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}/{}", self.name, self.service, self.account)
+    }
+}
+
+impl FromStr for Credential {
+    type Err = KeychainError;
+
+    /// Parse `NAME=SERVICE/ACCOUNT`.
+    ///
+    /// The service may contain slashes, the account may not.
+    ///
+    /// ```
+    /// use rust_ffi_demo::credentials::Credential;
+    ///
+    /// let credential: Credential = "db.pass=https://db.example.com/app".parse().unwrap();
+    /// assert_eq!(credential.name, "db.pass");
+    /// assert_eq!(credential.service, "https://db.example.com");
+    /// assert_eq!(credential.account, "app");
+    /// assert!("../token=api.example.com/jane".parse::<Credential>().is_err());
+    /// ```
+    fn from_str(s: &str) -> Result<Credential> {
+        let invalid = |reason: &str| {
+            KeychainError::invalid_data(format!("Invalid credential {}: {}", s, reason))
+        };
+        let (name, item) = s
+            .split_once('=')
+            .ok_or_else(|| invalid("expected NAME=SERVICE/ACCOUNT"))?;
+        let (service, account) = item
+            .rsplit_once('/')
+            .filter(|(service, account)| !service.is_empty() && !account.is_empty())
+            .ok_or_else(|| invalid("expected SERVICE/ACCOUNT"))?;
+        let valid_name = !name.is_empty()
+            && !name.starts_with('.')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "._-".contains(c));
+        if !valid_name {
+            return Err(invalid(
+                "only letters, digits, dots, dashes and underscores, and no leading dot",
+            ));
+        }
+        Ok(Credential {
+            name: name.to_string(),
+            service: service.to_string(),
+            account: account.to_string(),
+        })
+    }
+}
+
+/// A private directory of credential files, removed on drop.
+#[derive(Debug)]
+pub struct CredentialsDirectory {
+    path: PathBuf,
+}
+
+impl CredentialsDirectory {
+    /// Create a directory in the temporary directory with a file for every
+    /// name and secret in `secrets`.
+    ///
+    /// # Errors
+    ///
+    /// Return an IO error if the directory or a file cannot be created.
+    pub fn create(secrets: &[(&str, SecretBytes)]) -> Result<CredentialsDirectory> {
+        let suffix: String = keychain::random_bytes(8)?
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let path = env::temp_dir().join(format!("rust-ffi-demo-credentials-{}", suffix));
+        DirBuilder::new().mode(0o700).create(&path)?;
+        // Remove the directory if writing a file fails
+        let directory = CredentialsDirectory { path };
+        for &(name, ref secret) in secrets {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o400)
+                .open(directory.path.join(name))?;
+            file.write_all(secret)?;
+        }
+        Ok(directory)
+    }
+
+    /// The path of this directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for CredentialsDirectory {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Run `command` with `credentials` from `keychain`, or the search list.
+///
+/// Read all secrets first, which may prompt for access, then write them to
+/// a `CredentialsDirectory`, run `command` with `$CREDENTIALS_DIRECTORY`,
+/// and remove the directory once `command` exits.
+///
+/// # Errors
+///
+/// Return `KeychainError` if a secret cannot be read, two credentials have
+/// the same name, or the directory cannot be created, and an IO error if
+/// `command` cannot be run.
+pub fn run(
+    keychain: Option<&Keychain>,
+    credentials: &[Credential],
+    command: &mut Command,
+) -> Result<ExitStatus> {
+    let mut secrets: Vec<(&str, SecretBytes)> = Vec::with_capacity(credentials.len());
+    for credential in credentials {
+        if secrets.iter().any(|&(name, _)| name == credential.name) {
+            return Err(KeychainError::invalid_data(format!(
+                "Several credentials named {}",
+                credential.name
+            )));
+        }
+        let secret =
+            items::find_data(keychain, &credential.service, &credential.account)?.into_exposed();
+        secrets.push((&credential.name, secret));
+    }
+    let directory = CredentialsDirectory::create(&secrets)?;
+    drop(secrets);
+    let status = command
+        .env(CREDENTIALS_DIRECTORY, directory.path())
+        .status()?;
+    Ok(status)
+}
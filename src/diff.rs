@@ -0,0 +1,152 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Compare sets of generic passwords.
+//!
+//! Items are matched by service and account.  Passwords are only compared by
+//! their SHA-256 digests, and changes carry these digests rather than the
+//! passwords, so a diff never reveals plain text secrets.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::export::Record;
+use crate::keychain::digest::sha256_hex;
+
+/// The number of hex digits of digests shown in diffs.
+const SHORT_DIGEST: usize = 12;
+
+/// A difference between two sets of items.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// The item only exists on the right side.
+    Added { service: String, account: String },
+    /// The item only exists on the left side.
+    Removed { service: String, account: String },
+    /// The item exists on both sides, but differs.
+    Changed {
+        service: String,
+        account: String,
+        /// The label on the left and right side, if it differs.
+        label: Option<(Option<String>, Option<String>)>,
+        /// The password digests on the left and right side, if they differ.
+        digest: Option<(String, String)>,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Change::Added {
+                ref service,
+                ref account,
+            } => write!(f, "+ {}\t{}", service, account),
+            Change::Removed {
+                ref service,
+                ref account,
+            } => write!(f, "- {}\t{}", service, account),
+            Change::Changed {
+                ref service,
+                ref account,
+                ref label,
+                ref digest,
+            } => {
+                write!(f, "~ {}\t{}", service, account)?;
+                if let Some((ref left, ref right)) = *label {
+                    write!(
+                        f,
+                        "\tlabel {:?} -> {:?}",
+                        left.as_deref().unwrap_or(""),
+                        right.as_deref().unwrap_or("")
+                    )?;
+                }
+                if let Some((ref left, ref right)) = *digest {
+                    write!(
+                        f,
+                        "\tpassword sha256:{} -> sha256:{}",
+                        &left[..SHORT_DIGEST],
+                        &right[..SHORT_DIGEST]
+                    )?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Compare the `left` and the `right` records.
+///
+/// Return changes ordered by service and account.
+pub fn diff(left: &[Record], right: &[Record]) -> Vec<Change> {
+    let index = |records: &[Record]| -> BTreeMap<(String, String), (Option<String>, String)> {
+        records
+            .iter()
+            .map(|record| {
+                (
+                    (record.service.clone(), record.account.clone()),
+                    (record.label.clone(), sha256_hex(record.password.expose().as_bytes())),
+                )
+            })
+            .collect()
+    };
+    let left = index(left);
+    let mut right = index(right);
+
+    let mut changes = Vec::new();
+    for ((service, account), (left_label, left_digest)) in left {
+        match right.remove(&(service.clone(), account.clone())) {
+            None => changes.push(Change::Removed { service, account }),
+            Some((right_label, right_digest)) => {
+                let label = Some((left_label.clone(), right_label.clone()))
+                    .filter(|_| left_label != right_label);
+                let digest = Some((left_digest.clone(), right_digest.clone()))
+                    .filter(|_| left_digest != right_digest);
+                if label.is_some() || digest.is_some() {
+                    changes.push(Change::Changed {
+                        service,
+                        account,
+                        label,
+                        digest,
+                    });
+                }
+            }
+        }
+    }
+    changes.extend(
+        right
+            .into_keys()
+            .map(|(service, account)| Change::Added { service, account }),
+    );
+    changes.sort_by(|a, b| key(a).cmp(&key(b)));
+    changes
+}
+
+/// The service and account of `change`.
+fn key(change: &Change) -> (&str, &str) {
+    match *change {
+        Change::Added {
+            ref service,
+            ref account,
+        }
+        | Change::Removed {
+            ref service,
+            ref account,
+        }
+        | Change::Changed {
+            ref service,
+            ref account,
+            ..
+        } => (service, account),
+    }
+}
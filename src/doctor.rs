@@ -0,0 +1,241 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Health checks for keychain access.
+//!
+//! Check that the default keychain exists and is unlocked, and take a canary
+//! item through a full add, read, delete cycle while listening for the
+//! notification of the addition.  Each check depends on the previous ones, so
+//! after the first failure all remaining checks are skipped.
+//!
+//! A separate report inspects the code signature and entitlements of the
+//! binary, which decide about access to the data protection keychain.
+
+use std::fmt;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::keychain::codesign;
+use crate::keychain::events::{EventKind, Subscription};
+use crate::keychain::items::{self, Item};
+use crate::keychain::keychains::Keychain;
+
+/// The service of canary items.
+const CANARY_SERVICE: &str = "rust-ffi-demo.doctor";
+
+/// How long to wait for the notification about the canary.
+const NOTIFICATION_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// The outcome of a single check.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// The check passed, with details.
+    Passed(String),
+    /// The check failed, with the reason.
+    Failed(String),
+    /// The check did not run because an earlier check failed.
+    Skipped,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Outcome::Passed(ref details) => write!(f, "ok\t{}", details),
+            Outcome::Failed(ref reason) => write!(f, "FAILED\t{}", reason),
+            Outcome::Skipped => write!(f, "skipped"),
+        }
+    }
+}
+
+/// A single check.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    /// The name of the check.
+    pub name: &'static str,
+    /// The outcome of the check.
+    pub outcome: Outcome,
+}
+
+/// The checks of a doctor run.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Report {
+    /// All checks, in the order they ran.
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    /// Whether all checks passed.
+    pub fn healthy(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| matches!(check.outcome, Outcome::Passed(_)))
+    }
+
+    /// Run the check `name` with `f`, unless an earlier check failed.
+    ///
+    /// Return the value of a passed check.
+    fn check<T, F>(&mut self, name: &'static str, f: F) -> Option<T>
+    where
+        F: FnOnce() -> Result<(T, String), String>,
+    {
+        if !self.healthy() {
+            self.checks.push(Check {
+                name,
+                outcome: Outcome::Skipped,
+            });
+            return None;
+        }
+        let (value, outcome) = match f() {
+            Ok((value, details)) => (Some(value), Outcome::Passed(details)),
+            Err(reason) => (None, Outcome::Failed(reason)),
+        };
+        self.checks.push(Check { name, outcome });
+        value
+    }
+}
+
+/// Run all health checks.
+pub fn run() -> Report {
+    let mut report = Report::default();
+
+    let keychain = report.check("default keychain", || {
+        let keychain = Keychain::default_keychain().map_err(|error| error.to_string())?;
+        let path = keychain.path().display().to_string();
+        Ok((keychain, path))
+    });
+    report.check("keychain unlocked", || {
+        let status = keychain
+            .as_ref()
+            .unwrap()
+            .status()
+            .map_err(|error| error.to_string())?;
+        if !status.unlocked {
+            Err("The default keychain is locked; unlock it with `security unlock-keychain`".into())
+        } else if !status.writable {
+            Err("The default keychain is read-only".into())
+        } else {
+            Ok(((), "unlocked, readable and writable".to_string()))
+        }
+    });
+
+    let canary_account = format!(
+        "canary-{}-{}",
+        process::id(),
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0)
+    );
+    let canary = Item {
+        label: Some("rust-ffi-demo doctor canary".to_string()),
+        ..Item::new(CANARY_SERVICE, canary_account.clone())
+    };
+    let secret = format!("secret of {}", canary_account);
+
+    let notified = Arc::new(Mutex::new(false));
+    let subscription = report.check("subscribe to notifications", || {
+        let notified = notified.clone();
+        let account = canary_account.clone();
+        let subscription = Subscription::new(move |event| {
+            if event.kind == EventKind::Add && event.account.as_deref() == Some(account.as_str()) {
+                *notified.lock().unwrap() = true;
+            }
+        })
+        .map_err(|error| error.to_string())?;
+        Ok((subscription, "subscribed".to_string()))
+    });
+
+    let keychain = keychain.as_ref();
+    report.check("add canary item", || {
+        items::add_item(keychain, &canary, secret.as_bytes())
+            .map(|()| ((), format!("{} {}", CANARY_SERVICE, canary_account)))
+            .map_err(|error| error.to_string())
+    });
+    report.check("read canary item", || {
+        let data = items::find_data(keychain, CANARY_SERVICE, &canary_account)
+            .map_err(|error| error.to_string())?;
+        if data.expose() == secret.as_bytes() {
+            Ok(((), "secret matches".to_string()))
+        } else {
+            Err("The secret read back differs from the secret written".to_string())
+        }
+    });
+    report.check("notification delivery", || {
+        let subscription = subscription.as_ref().unwrap();
+        let deadline = SystemTime::now() + NOTIFICATION_TIMEOUT;
+        while !*notified.lock().unwrap() && SystemTime::now() < deadline {
+            subscription.run_for(Duration::from_millis(100));
+        }
+        if *notified.lock().unwrap() {
+            Ok(((), "received notification for canary".to_string()))
+        } else {
+            Err(format!(
+                "No notification for canary within {} seconds",
+                NOTIFICATION_TIMEOUT.as_secs()
+            ))
+        }
+    });
+
+    // Always try to clean up, even if reading or notifications failed
+    let deleted = items::delete_item(keychain, CANARY_SERVICE, &canary_account);
+    let added = report.checks.iter().any(|check| {
+        check.name == "add canary item" && matches!(check.outcome, Outcome::Passed(_))
+    });
+    let outcome = match deleted {
+        _ if !added => Outcome::Skipped,
+        Ok(()) => Outcome::Passed("deleted".to_string()),
+        Err(error) => Outcome::Failed(error.to_string()),
+    };
+    report.checks.push(Check {
+        name: "delete canary item",
+        outcome,
+    });
+    report
+}
+
+/// Check the code signature and entitlements of this binary.
+pub fn entitlements() -> Report {
+    let mut report = Report::default();
+
+    let info = report.check("code signature", || {
+        let info = codesign::signing_info().map_err(|error| error.to_string())?;
+        match info.diagnosis() {
+            Some(diagnosis) if !info.signed || info.adhoc => Err(diagnosis.to_string()),
+            _ => {
+                let details = info.to_string();
+                Ok((info, details))
+            }
+        }
+    });
+    report.check("keychain access groups", || {
+        let info = info.as_ref().unwrap();
+        match info.diagnosis() {
+            Some(diagnosis) => Err(diagnosis.to_string()),
+            None => {
+                let mut groups = info.access_groups.clone();
+                if let Some(ref application_identifier) = info.application_identifier {
+                    groups.insert(0, application_identifier.clone());
+                }
+                Ok(((), groups.join(", ")))
+            }
+        }
+    });
+    report.check("data protection keychain", || {
+        codesign::probe_data_protection_keychain()
+            .map(|()| ((), "accessible".to_string()))
+            .map_err(|error| error.to_string())
+    });
+    report
+}
@@ -0,0 +1,185 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Attribute dumps in the layout of `security(1)`.
+//!
+//! `security find-generic-password -g` prints the attributes of an item to
+//! standard output and its password to standard error.  Scripts which parse
+//! this output keep working with the dumps of this module:
+//!
+//! ```text
+//! keychain: "/Users/jane/Library/Keychains/login.keychain-db"
+//! version: 512
+//! class: "genp"
+//! attributes:
+//!     0x00000007 <blob>="example.com"
+//!     0x00000008 <blob>=<NULL>
+//!     "acct"<blob>="jane"
+//!     ...
+//!     "svce"<blob>="example.com"
+//!     "type"<uint32>=<NULL>
+//! password: "topS3cr3t"
+//! ```
+//!
+//! Items don't know their keychain, so dumps show the keychain which the
+//! caller searched.  Attributes which this crate doesn't read, like the
+//! custom signature `cusi`, are always `<NULL>`.
+
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::keychain::items::{FourCC, Item};
+use crate::output::format_time;
+
+/// The record version which `security` shows for current keychains.
+const VERSION: u32 = 512;
+
+/// Format `bytes` like `security` formats blobs.
+///
+/// Show printable ASCII in quotes, and everything else in hex, followed by
+/// the bytes in quotes with octal escapes for unprintable bytes:
+///
+/// ```
+/// use rust_ffi_demo::dump::blob;
+///
+/// assert_eq!(blob(b"jane"), r#""jane""#);
+/// assert_eq!(blob(b"caf\xc3\xa9"), r#"0x636166C3A9  "caf\303\251""#);
+/// ```
+pub fn blob(bytes: &[u8]) -> String {
+    if bytes.iter().all(|b| (0x20..0x7f).contains(b)) {
+        return format!("\"{}\"", String::from_utf8_lossy(bytes));
+    }
+    let mut formatted = String::from("0x");
+    for byte in bytes {
+        write!(formatted, "{:02X}", byte).unwrap();
+    }
+    formatted.push_str("  \"");
+    for &byte in bytes {
+        if (0x20..0x7f).contains(&byte) {
+            formatted.push(byte as char);
+        } else {
+            write!(formatted, "\\{:03o}", byte).unwrap();
+        }
+    }
+    formatted.push('"');
+    formatted
+}
+
+/// Format an optional blob, or `<NULL>`.
+fn optional_blob(bytes: Option<&[u8]>) -> String {
+    bytes.map_or_else(|| "<NULL>".to_string(), blob)
+}
+
+/// Format `time` like `security` formats dates, ie, as zulu time string with
+/// a trailing NUL.
+fn timedate(time: Option<SystemTime>) -> String {
+    match time {
+        None => "<NULL>".to_string(),
+        Some(time) => {
+            let mut zulu: Vec<u8> = format_time(time)
+                .bytes()
+                .filter(u8::is_ascii_digit)
+                .collect();
+            zulu.extend_from_slice(b"Z\0");
+            blob(&zulu)
+        }
+    }
+}
+
+/// Format a four character code like `security` formats `uint32` values.
+fn uint32(code: Option<&FourCC>) -> String {
+    match code {
+        None => "<NULL>".to_string(),
+        Some(code) => {
+            let bytes = code.0.to_be_bytes();
+            if bytes.iter().all(|b| (0x20..0x7f).contains(b)) {
+                blob(&bytes)
+            } else {
+                format!("0x{:08X}", code.0)
+            }
+        }
+    }
+}
+
+/// Format a flag like `security` formats `sint32` values.
+fn sint32(flag: bool) -> String {
+    if flag {
+        "0x00000001".to_string()
+    } else {
+        "<NULL>".to_string()
+    }
+}
+
+/// Write the attributes of the generic password `item` from `keychain` to
+/// `out`.
+///
+/// ```
+/// use rust_ffi_demo::dump::write_item;
+/// use rust_ffi_demo::keychain::items::Item;
+/// use std::path::Path;
+///
+/// let mut out = Vec::new();
+/// let item = Item::new("example.com", "jane");
+/// write_item(&mut out, Path::new("/tmp/test.keychain"), &item).unwrap();
+/// let out = String::from_utf8(out).unwrap();
+/// assert!(out.starts_with("keychain: \"/tmp/test.keychain\"\nversion: 512\n"));
+/// assert!(out.contains("    \"svce\"<blob>=\"example.com\"\n"));
+/// ```
+///
+/// # Errors
+///
+/// Return the error of `out`.
+pub fn write_item<W: Write>(out: &mut W, keychain: &Path, item: &Item) -> io::Result<()> {
+    let text = |value: &Option<String>| optional_blob(value.as_deref().map(str::as_bytes));
+    let keychain = keychain.to_string_lossy();
+    writeln!(out, "keychain: {}", blob(keychain.as_bytes()))?;
+    writeln!(out, "version: {}", VERSION)?;
+    writeln!(out, "class: \"genp\"")?;
+    writeln!(out, "attributes:")?;
+    let attributes = [
+        ("0x00000007 ", "blob", text(&item.label)),
+        ("0x00000008 ", "blob", "<NULL>".to_string()),
+        ("\"acct\"", "blob", blob(item.account.as_bytes())),
+        ("\"cdat\"", "timedate", timedate(item.created)),
+        ("\"crtr\"", "uint32", uint32(item.creator.as_ref())),
+        ("\"cusi\"", "sint32", "<NULL>".to_string()),
+        ("\"desc\"", "blob", text(&item.description)),
+        ("\"gena\"", "blob", optional_blob(item.generic.as_deref())),
+        ("\"icmt\"", "blob", text(&item.comment)),
+        ("\"invi\"", "sint32", sint32(item.invisible)),
+        ("\"mdat\"", "timedate", timedate(item.modified)),
+        ("\"nega\"", "sint32", sint32(item.negative)),
+        ("\"prot\"", "blob", "<NULL>".to_string()),
+        ("\"scrp\"", "sint32", "<NULL>".to_string()),
+        ("\"svce\"", "blob", blob(item.service.as_bytes())),
+        ("\"type\"", "uint32", uint32(item.item_type.as_ref())),
+    ];
+    for (name, kind, value) in attributes.iter() {
+        writeln!(out, "    {}<{}>={}", name, kind, value)?;
+    }
+    Ok(())
+}
+
+/// Format the line of `password`, as printed by `security -g`.
+///
+/// ```
+/// use rust_ffi_demo::dump::password_line;
+///
+/// assert_eq!(password_line(b"topS3cr3t"), r#"password: "topS3cr3t""#);
+/// ```
+pub fn password_line(password: &[u8]) -> String {
+    format!("password: {}", blob(password))
+}
@@ -0,0 +1,679 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Export generic passwords to JSON, YAML or TOML files.
+//!
+//! An export is a JSON array of records with service, account, label and
+//! the plain text password of each item, or a sequence of such records, one
+//! per line, in JSON Lines format.  Exports hold secrets in plain text; keep
+//! them safe.
+//!
+//! For configuration management tools exports also come as a YAML sequence
+//! of mappings, or as TOML array of `item` tables:
+//!
+//! ```toml
+//! [[item]]
+//! service = "api.example.com"
+//! account = "jane"
+//! password = "topS3cr3t"
+//! ```
+//!
+//! Reading YAML and TOML only supports flat records like these, with
+//! strings and integers, and no nested structures, anchors, or multi-line
+//! strings.
+//!
+//! `export` writes every record as soon as it has read the password, so only
+//! one password is in memory at a time, however large the keychain.
+//! `export_store` exports the entries of any `SecretStore` likewise.
+
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::keychain::items::{self, find_password, Item};
+use crate::keychain::keychains::Keychain;
+use crate::keychain::query::FilterExpr;
+use crate::keychain::sensitive::{self, Sensitive};
+use crate::keychain::{with_autorelease_pool, KeychainError, Result};
+use crate::output::json_string;
+use crate::progress::{Progress, ProgressFn};
+use crate::store::SecretStore;
+
+/// An exported generic password.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    /// The service of the item.
+    pub service: String,
+    /// The account of the item.
+    pub account: String,
+    /// The label of the item.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// The password of the item.
+    ///
+    /// Serializing records redacts the password, except within
+    /// `sensitive::revealing`, as record writers do.
+    pub password: Sensitive<String>,
+    /// When the item was last modified, in seconds since the epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<u64>,
+}
+
+/// The format of an export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// A JSON array of records.
+    Json,
+    /// One JSON record per line.
+    JsonLines,
+    /// A YAML sequence of records.
+    Yaml,
+    /// A TOML array of `item` tables.
+    Toml,
+}
+
+impl Format {
+    /// The format of the export at `path`, by its extension.
+    ///
+    /// ```
+    /// use rust_ffi_demo::export::Format;
+    /// use std::path::Path;
+    ///
+    /// assert_eq!(Format::for_path(Path::new("secrets.yml")), Format::Yaml);
+    /// assert_eq!(Format::for_path(Path::new("secrets.toml")), Format::Toml);
+    /// assert_eq!(Format::for_path(Path::new("secrets.jsonl")), Format::JsonLines);
+    /// assert_eq!(Format::for_path(Path::new("secrets")), Format::Json);
+    /// ```
+    ///
+    /// Fall back to JSON for other extensions.
+    pub fn for_path(path: &Path) -> Format {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("yaml") | Some("yml") => Format::Yaml,
+            Some("toml") => Format::Toml,
+            Some("jsonl") => Format::JsonLines,
+            _ => Format::Json,
+        }
+    }
+}
+
+/// List the items to export, in order of service and account.
+///
+/// Only reads attributes, so this does not prompt.
+fn list_exported(
+    keychain: Option<&Keychain>,
+    service: Option<&str>,
+    filter: &FilterExpr,
+) -> Result<Vec<Item>> {
+    let mut listed = items::list_items(keychain)?;
+    listed.retain(|item| service.is_none_or(|service| service == item.service));
+    listed.retain(|item| filter.matches(item));
+    listed.sort_by(|a, b| (&a.service, &a.account).cmp(&(&b.service, &b.account)));
+    Ok(listed)
+}
+
+/// Read the password of `item` into a record.
+fn record(keychain: Option<&Keychain>, item: Item) -> Result<Record> {
+    let password = find_password(keychain, &item.service, &item.account)?;
+    Ok(Record {
+        service: item.service,
+        account: item.account,
+        label: item.label,
+        password,
+        modified: item
+            .modified
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|modified| modified.as_secs()),
+    })
+}
+
+/// Collect records for all generic passwords.
+///
+/// If `keychain` is given only collect items of this keychain, and if
+/// `service` is given only collect items of this service.  Reading passwords
+/// may prompt for access to every item.  Report `progress` after reading the
+/// password of every item.
+///
+/// This holds all passwords in memory; use `export` to write them out
+/// instead.
+///
+/// # Errors
+///
+/// Return `KeychainError` if listing items or reading a password fails.
+pub fn collect(
+    keychain: Option<&Keychain>,
+    service: Option<&str>,
+    progress: &mut ProgressFn<'_>,
+) -> Result<Vec<Record>> {
+    let listed = list_exported(keychain, service, &FilterExpr::All)?;
+    let total = listed.len();
+    let mut records = Vec::with_capacity(total);
+    for item in listed {
+        let record = with_autorelease_pool(|| record(keychain, item))?;
+        progress(&Progress {
+            processed: records.len() + 1,
+            total,
+            service: &record.service,
+            account: &record.account,
+        });
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// The name of the array of tables of TOML exports.
+const TOML_TABLE: &str = "item";
+
+/// The fields of `record`, in order, with values quoted for YAML and TOML.
+///
+/// Quote strings as JSON, which YAML and TOML both take as double-quoted
+/// strings.  Omit absent fields.
+fn fields(record: &Record) -> Vec<(&'static str, String)> {
+    let mut fields = vec![
+        ("service", json_string(&record.service)),
+        ("account", json_string(&record.account)),
+    ];
+    if let Some(ref label) = record.label {
+        fields.push(("label", json_string(label)));
+    }
+    fields.push(("password", json_string(record.password.expose())));
+    if let Some(modified) = record.modified {
+        fields.push(("modified", modified.to_string()));
+    }
+    fields
+}
+
+/// Writes records of an export one by one.
+pub struct RecordWriter<W: Write> {
+    writer: W,
+    format: Format,
+    written: usize,
+}
+
+impl<W: Write> RecordWriter<W> {
+    /// Write an export in `format` to `writer`.
+    pub fn new(writer: W, format: Format) -> RecordWriter<W> {
+        RecordWriter {
+            writer,
+            format,
+            written: 0,
+        }
+    }
+
+    /// Write `record`.
+    ///
+    /// # Errors
+    ///
+    /// Return an IO error if writing fails.
+    pub fn write(&mut self, record: &Record) -> io::Result<()> {
+        match self.format {
+            Format::Json => {
+                let separator: &[u8] = if self.written == 0 { b"[\n" } else { b",\n" };
+                self.writer.write_all(separator)?;
+                sensitive::revealing(|| serde_json::to_writer(&mut self.writer, record))?;
+            }
+            Format::JsonLines => {
+                sensitive::revealing(|| serde_json::to_writer(&mut self.writer, record))?;
+                self.writer.write_all(b"\n")?;
+            }
+            Format::Yaml => {
+                for (index, (key, value)) in fields(record).iter().enumerate() {
+                    let lead = if index == 0 { "- " } else { "  " };
+                    writeln!(self.writer, "{}{}: {}", lead, key, value)?;
+                }
+            }
+            Format::Toml => {
+                if 0 < self.written {
+                    self.writer.write_all(b"\n")?;
+                }
+                writeln!(self.writer, "[[{}]]", TOML_TABLE)?;
+                for (key, value) in fields(record) {
+                    writeln!(self.writer, "{} = {}", key, value)?;
+                }
+            }
+        }
+        self.written += 1;
+        Ok(())
+    }
+
+    /// Finish the export, and return the number of records written.
+    ///
+    /// # Errors
+    ///
+    /// Return an IO error if writing fails.
+    pub fn finish(mut self) -> io::Result<usize> {
+        match self.format {
+            Format::Json => {
+                let end: &[u8] = if self.written == 0 { b"[]\n" } else { b"\n]\n" };
+                self.writer.write_all(end)?;
+            }
+            Format::Yaml if self.written == 0 => self.writer.write_all(b"[]\n")?,
+            _ => {}
+        }
+        self.writer.flush()?;
+        Ok(self.written)
+    }
+}
+
+/// Export all generic passwords in `format` to `writer`.
+///
+/// Select items like `collect`, but write every record right after reading its
+/// password, so at most one password is in memory at a time.  Report
+/// `progress` after writing every record.
+///
+/// Return the number of exported items.
+///
+/// # Errors
+///
+/// Return `KeychainError` if listing items, reading a password, or writing
+/// fails.  The output is incomplete then.
+pub fn export<W: Write>(
+    keychain: Option<&Keychain>,
+    service: Option<&str>,
+    format: Format,
+    writer: W,
+    progress: &mut ProgressFn<'_>,
+) -> Result<usize> {
+    export_where(
+        keychain,
+        service,
+        &FilterExpr::All,
+        format,
+        writer,
+        progress,
+    )
+}
+
+/// Export the generic passwords matching `filter` in `format` to `writer`.
+///
+/// Like `export`, but only export items which also match `filter`.  Filter
+/// items before reading their passwords, so this only prompts for access to
+/// exported items.
+///
+/// # Errors
+///
+/// See `export`.
+pub fn export_where<W: Write>(
+    keychain: Option<&Keychain>,
+    service: Option<&str>,
+    filter: &FilterExpr,
+    format: Format,
+    writer: W,
+    progress: &mut ProgressFn<'_>,
+) -> Result<usize> {
+    let listed = list_exported(keychain, service, filter)?;
+    let total = listed.len();
+    let mut writer = RecordWriter::new(writer, format);
+    for (index, item) in listed.into_iter().enumerate() {
+        let record = record(keychain, item)?;
+        writer.write(&record)?;
+        progress(&Progress {
+            processed: index + 1,
+            total,
+            service: &record.service,
+            account: &record.account,
+        });
+    }
+    Ok(writer.finish()?)
+}
+
+/// Export the entries of `store` matching `filter` in `format` to `writer`.
+///
+/// Like `export_where`, but read entries and secrets from `store`.
+///
+/// # Errors
+///
+/// Return `KeychainError` if reading `store` or writing fails, and
+/// `KeychainErrorCode::InvalidData` for secrets which are not UTF-8.  The
+/// output is incomplete then.
+pub fn export_store<S: SecretStore + ?Sized, W: Write>(
+    store: &S,
+    service: Option<&str>,
+    filter: &FilterExpr,
+    format: Format,
+    writer: W,
+    progress: &mut ProgressFn<'_>,
+) -> Result<usize> {
+    let mut listed: Vec<Item> = store.entries()?.into_iter().map(Item::from).collect();
+    listed.retain(|item| service.is_none_or(|service| service == item.service));
+    listed.retain(|item| filter.matches(item));
+    listed.sort_by(|a, b| (&a.service, &a.account).cmp(&(&b.service, &b.account)));
+    let total = listed.len();
+    let mut writer = RecordWriter::new(writer, format);
+    for (index, item) in listed.into_iter().enumerate() {
+        let password = String::from_utf8(store.get(&item.service, &item.account)?)
+            .map_err(|_| KeychainError::invalid_data("Password is not UTF-8"))?;
+        let record = Record {
+            password: Sensitive::new(password),
+            modified: item
+                .modified
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|modified| modified.as_secs()),
+            service: item.service,
+            account: item.account,
+            label: item.label,
+        };
+        writer.write(&record)?;
+        progress(&Progress {
+            processed: index + 1,
+            total,
+            service: &record.service,
+            account: &record.account,
+        });
+    }
+    Ok(writer.finish()?)
+}
+
+/// Write `records` as JSON export to `writer`.
+///
+/// # Errors
+///
+/// Return an IO error if writing fails.
+pub fn write_json<W: Write>(writer: W, records: &[Record]) -> io::Result<()> {
+    sensitive::revealing(|| serde_json::to_writer_pretty(writer, records)).map_err(io::Error::from)
+}
+
+/// Read records from a JSON or JSON Lines export in `reader`.
+///
+/// # Errors
+///
+/// Return an IO error if reading fails, or `io::ErrorKind::InvalidData` if
+/// the export is malformed.
+pub fn read_json<R: Read>(reader: R) -> io::Result<Vec<Record>> {
+    json_as(reader)
+}
+
+/// Read values of `T` from a JSON array or JSON Lines in `reader`.
+fn json_as<T: DeserializeOwned, R: Read>(mut reader: R) -> io::Result<Vec<T>> {
+    let mut contents = Vec::new();
+    reader.read_to_end(&mut contents)?;
+    let is_array = contents
+        .iter()
+        .find(|byte| !byte.is_ascii_whitespace())
+        .is_some_and(|&byte| byte == b'[');
+    if is_array {
+        serde_json::from_slice(&contents).map_err(io::Error::from)
+    } else {
+        serde_json::Deserializer::from_slice(&contents)
+            .into_iter()
+            .collect::<serde_json::Result<_>>()
+            .map_err(io::Error::from)
+    }
+}
+
+/// Read records from an export in `format` in `reader`.
+///
+/// ```
+/// use rust_ffi_demo::export::{self, Format};
+/// use rust_ffi_demo::keychain::sensitive::Sensitive;
+///
+/// let yaml = "- service: api.example.com\n  account: 'jane'\n  password: \"s3cr3t\"\n";
+/// let records = export::read(yaml.as_bytes(), Format::Yaml).unwrap();
+/// assert_eq!(records[0].service, "api.example.com");
+/// assert_eq!(records[0].password.expose(), "s3cr3t");
+///
+/// let toml = "[[item]]\nservice = \"api.example.com\"\naccount = \"jane\"\npassword = 's3cr3t'\n";
+/// assert_eq!(export::read(toml.as_bytes(), Format::Toml).unwrap(), records);
+///
+/// // Exports in all formats read back
+/// let mut records = records;
+/// records[0].password = Sensitive::new("a 'quoted' \"password\" # with\ttabs".to_string());
+/// for &format in &[Format::Json, Format::JsonLines, Format::Yaml, Format::Toml] {
+///     let mut bytes = Vec::new();
+///     let mut writer = export::RecordWriter::new(&mut bytes, format);
+///     writer.write(&records[0]).unwrap();
+///     writer.finish().unwrap();
+///     assert_eq!(export::read(bytes.as_slice(), format).unwrap(), records);
+/// }
+/// ```
+///
+/// # Errors
+///
+/// Return an IO error if reading fails, or `io::ErrorKind::InvalidData` if
+/// the export is malformed, or uses YAML or TOML features beyond flat
+/// records.
+pub fn read<R: Read>(reader: R, format: Format) -> io::Result<Vec<Record>> {
+    read_as(reader, format)
+}
+
+/// Read flat records of `T` in `format` from `reader`.
+///
+/// Like `read`, for other record types than `Record`.
+pub(crate) fn read_as<T: DeserializeOwned, R: Read>(
+    mut reader: R,
+    format: Format,
+) -> io::Result<Vec<T>> {
+    let parse_line = match format {
+        Format::Json | Format::JsonLines => return json_as(reader),
+        Format::Yaml => yaml_line,
+        Format::Toml => toml_line,
+    };
+    let mut contents = String::new();
+    reader.read_to_string(&mut contents)?;
+    let mut tables: Vec<Map<String, Value>> = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let invalid = |message: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: {}", index + 1, message),
+            )
+        };
+        match parse_line(line).map_err(invalid)? {
+            Line::Skip => {}
+            Line::Start(field) => {
+                tables.push(Map::new());
+                if let Some((key, value)) = field {
+                    tables.last_mut().unwrap().insert(key, value);
+                }
+            }
+            Line::Field(key, value) => match tables.last_mut() {
+                Some(table) => {
+                    if table.insert(key.clone(), value).is_some() {
+                        return Err(invalid(format!("duplicate key {}", key)));
+                    }
+                }
+                None => return Err(invalid(format!("key {} outside of a record", key))),
+            },
+        }
+    }
+    tables
+        .into_iter()
+        .map(|table| serde_json::from_value(Value::Object(table)).map_err(io::Error::from))
+        .collect()
+}
+
+/// A line of a YAML or TOML export.
+enum Line {
+    /// A blank line, comment, or other line without fields.
+    Skip,
+    /// The start of a record, with its first field, if on the same line.
+    Start(Option<(String, Value)>),
+    /// A field of the current record.
+    Field(String, Value),
+}
+
+/// Parse a `line` of a YAML export.
+fn yaml_line(line: &str) -> std::result::Result<Line, String> {
+    let trimmed = line.trim_end();
+    if trimmed.trim_start().is_empty()
+        || trimmed.trim_start().starts_with('#')
+        || trimmed == "---"
+        || trimmed == "..."
+        || trimmed == "[]"
+    {
+        return Ok(Line::Skip);
+    }
+    if let Some(rest) = trimmed.strip_prefix('-') {
+        if rest.trim().is_empty() {
+            return Ok(Line::Start(None));
+        }
+        if let Some(field) = rest.strip_prefix(' ') {
+            return yaml_field(field).map(|field| Line::Start(Some(field)));
+        }
+    }
+    if trimmed.starts_with(' ') {
+        let (key, value) = yaml_field(trimmed)?;
+        return Ok(Line::Field(key, value));
+    }
+    Err(format!("expected a sequence of records: {}", trimmed))
+}
+
+/// Parse a `key: value` field of a YAML export.
+fn yaml_field(field: &str) -> std::result::Result<(String, Value), String> {
+    let field = field.trim();
+    let (key, value) = match field.find(": ") {
+        Some(position) => (&field[..position], field[position + 2..].trim()),
+        None => match field.strip_suffix(':') {
+            Some(key) => (key, ""),
+            None => return Err(format!("expected key: value, got {}", field)),
+        },
+    };
+    let value = match value {
+        "" | "~" | "null" => Value::Null,
+        value if value.starts_with('"') => end_of_value(quoted(value)?)?,
+        value if value.starts_with('\'') => end_of_value(single_quoted(value, true)?)?,
+        value if value.starts_with(|c| "[{&*!|>%@`".contains(c)) => {
+            return Err(format!("unsupported value of {}: {}", key, value))
+        }
+        value => {
+            // A plain scalar, up to a comment
+            let value = value.split(" #").next().unwrap_or_default().trim_end();
+            integer(value).unwrap_or_else(|| Value::String(value.to_string()))
+        }
+    };
+    Ok((key.trim().to_string(), value))
+}
+
+/// Parse a `line` of a TOML export.
+fn toml_line(line: &str) -> std::result::Result<Line, String> {
+    let trimmed = line.trim();
+    if trimmed.is_empty() || trimmed.starts_with('#') {
+        return Ok(Line::Skip);
+    }
+    if trimmed.starts_with('[') {
+        let header = trimmed.split('#').next().unwrap_or_default().trim_end();
+        return if header == format!("[[{}]]", TOML_TABLE) {
+            Ok(Line::Start(None))
+        } else {
+            Err(format!("expected [[{}]], got {}", TOML_TABLE, header))
+        };
+    }
+    let position = trimmed
+        .find('=')
+        .ok_or_else(|| format!("expected key = value, got {}", trimmed))?;
+    let key = trimmed[..position].trim();
+    if key.is_empty()
+        || !key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!("unsupported key {}", key));
+    }
+    let value = trimmed[position + 1..].trim_start();
+    let value = if value.starts_with('"') {
+        end_of_value(quoted(value)?)?
+    } else if value.starts_with('\'') {
+        end_of_value(single_quoted(value, false)?)?
+    } else {
+        let value = value.split('#').next().unwrap_or_default().trim_end();
+        integer(value).ok_or_else(|| format!("unsupported value of {}: {}", key, value))?
+    };
+    Ok(Line::Field(key.to_string(), value))
+}
+
+/// Parse `text` as a non-negative integer.
+fn integer(text: &str) -> Option<Value> {
+    let digits = text.strip_prefix('+').unwrap_or(text).replace('_', "");
+    if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse::<u64>().ok().map(Value::from)
+}
+
+/// Check that only a comment follows a quoted `value`.
+fn end_of_value((value, rest): (String, &str)) -> std::result::Result<Value, String> {
+    let rest = rest.trim_start();
+    if rest.is_empty() || rest.starts_with('#') {
+        Ok(Value::String(value))
+    } else {
+        Err(format!("unexpected {} after string", rest))
+    }
+}
+
+/// Parse the double-quoted string at the start of `text`.
+///
+/// Unescape the escapes of JSON, and the `\U` escapes of TOML.  Return the
+/// string and the rest of `text`.
+fn quoted(text: &str) -> std::result::Result<(String, &str), String> {
+    let mut chars = text.char_indices().skip(1);
+    let mut unquoted = String::new();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Ok((unquoted, &text[index + 1..])),
+            '\\' => {
+                let escape = chars.next().map(|(_, c)| c);
+                let c = match escape {
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some('/') => '/',
+                    Some('b') => '\u{8}',
+                    Some('f') => '\u{c}',
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some(kind @ 'u') | Some(kind @ 'U') => {
+                        let length = if kind == 'u' { 4 } else { 8 };
+                        let digits: String = chars.by_ref().take(length).map(|(_, c)| c).collect();
+                        u32::from_str_radix(&digits, 16)
+                            .ok()
+                            .filter(|_| digits.len() == length)
+                            .and_then(std::char::from_u32)
+                            .ok_or_else(|| format!("invalid escape \\{}{}", kind, digits))?
+                    }
+                    Some(other) => return Err(format!("unsupported escape \\{}", other)),
+                    None => break,
+                };
+                unquoted.push(c);
+            }
+            c => unquoted.push(c),
+        }
+    }
+    Err(format!("unterminated string {}", text))
+}
+
+/// Parse the single-quoted string at the start of `text`.
+///
+/// Single-quoted strings have no escapes, except for `''` for a single quote
+/// in YAML, if `doubled_quotes` is set.  Return the string and the rest of
+/// `text`.
+fn single_quoted(text: &str, doubled_quotes: bool) -> std::result::Result<(String, &str), String> {
+    let mut unquoted = String::new();
+    let mut rest = &text[1..];
+    loop {
+        let end = rest
+            .find('\'')
+            .ok_or_else(|| format!("unterminated string {}", text))?;
+        unquoted.push_str(&rest[..end]);
+        rest = &rest[end + 1..];
+        if doubled_quotes && rest.starts_with('\'') {
+            unquoted.push('\'');
+            rest = &rest[1..];
+        } else {
+            return Ok((unquoted, rest));
+        }
+    }
+}
@@ -0,0 +1,290 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Pluggable formatters for tabular output.
+//!
+//! Commands which print rows, like listing items or watching events, hand
+//! their columns and rows to an `OutputFormatter`, and `get` looks up the
+//! formatter of a format by name.  There are formatters for aligned tables,
+//! JSON lines, YAML and tab-separated values, and `register` adds formatters
+//! for further formats:
+//!
+//! ```
+//! use std::io::{self, Write};
+//! use std::sync::Arc;
+//! use rust_ffi_demo::formatter::{self, OutputFormatter, Value};
+//!
+//! /// Comma-separated values, without any quoting.
+//! struct Csv;
+//!
+//! impl OutputFormatter for Csv {
+//!     fn write(&self, out: &mut dyn Write, columns: &[&str], rows: &[Vec<Value>]) -> io::Result<()> {
+//!         writeln!(out, "{}", columns.join(","))?;
+//!         for row in rows {
+//!             let cells: Vec<String> = row.iter().map(Value::to_string).collect();
+//!             writeln!(out, "{}", cells.join(","))?;
+//!         }
+//!         Ok(())
+//!     }
+//! }
+//!
+//! formatter::register("csv", Arc::new(Csv));
+//! let mut out = Vec::new();
+//! let rows = [vec![Value::from("github.com"), Value::from("jane")]];
+//! formatter::get("csv").unwrap().write(&mut out, &["service", "account"], &rows).unwrap();
+//! assert_eq!(out, b"service,account\ngithub.com,jane\n");
+//! ```
+
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::{Arc, RwLock};
+
+use crate::output::json_string;
+
+/// The value of a cell.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// No value.
+    Null,
+    /// A boolean.
+    Bool(bool),
+    /// An integer.
+    Number(i64),
+    /// Text.
+    Text(String),
+}
+
+impl fmt::Display for Value {
+    /// Format the value as plain text; `Null` is empty.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Value::Null => Ok(()),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Number(value) => write!(f, "{}", value),
+            Value::Text(ref text) => write!(f, "{}", text),
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(text: &str) -> Value {
+        Value::Text(text.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(text: String) -> Value {
+        Value::Text(text)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Value {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Value {
+        Value::Bool(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Value {
+        Value::Number(value)
+    }
+}
+
+impl Value {
+    /// The value as JSON, which is also a YAML flow scalar.
+    fn to_json(&self) -> String {
+        match *self {
+            Value::Null => "null".to_string(),
+            Value::Text(ref text) => json_string(text),
+            ref value => value.to_string(),
+        }
+    }
+}
+
+/// Formats rows of output.
+pub trait OutputFormatter: Send + Sync {
+    /// Write `rows` to `out`.
+    ///
+    /// Every row has one value for each of `columns`, in order.  Streaming
+    /// commands call this once for every row.
+    ///
+    /// # Errors
+    ///
+    /// Return an IO error if writing fails.
+    fn write(&self, out: &mut dyn Write, columns: &[&str], rows: &[Vec<Value>]) -> io::Result<()>;
+}
+
+/// Aligned columns under a header, with `-` for missing values.
+///
+/// ```
+/// use rust_ffi_demo::formatter::{OutputFormatter, Table, Value};
+///
+/// let rows = [
+///     vec![Value::from("github.com"), Value::from("jane")],
+///     vec![Value::from("npm"), Value::Null],
+/// ];
+/// let mut out = Vec::new();
+/// Table.write(&mut out, &["service", "account"], &rows).unwrap();
+/// assert_eq!(
+///     String::from_utf8(out).unwrap(),
+///     "SERVICE     ACCOUNT\ngithub.com  jane\nnpm         -\n"
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Table;
+
+impl OutputFormatter for Table {
+    fn write(&self, out: &mut dyn Write, columns: &[&str], rows: &[Vec<Value>]) -> io::Result<()> {
+        let cell = |value: &Value| match *value {
+            Value::Null => "-".to_string(),
+            ref value => value.to_string(),
+        };
+        let header: Vec<String> = columns.iter().map(|c| c.to_uppercase()).collect();
+        let rows: Vec<Vec<String>> = rows
+            .iter()
+            .map(|row| row.iter().map(cell).collect())
+            .collect();
+        let mut widths: Vec<usize> = header.iter().map(|h| h.chars().count()).collect();
+        for row in &rows {
+            for (width, value) in widths.iter_mut().zip(row) {
+                *width = (*width).max(value.chars().count());
+            }
+        }
+        for row in Some(&header).into_iter().chain(&rows) {
+            let mut line = String::new();
+            for (index, (value, width)) in row.iter().zip(&widths).enumerate() {
+                if index + 1 == row.len() {
+                    line.push_str(value);
+                } else {
+                    line.push_str(&format!("{:width$}  ", value, width = width));
+                }
+            }
+            writeln!(out, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// One JSON object per row and line, with columns as keys.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonLines;
+
+impl OutputFormatter for JsonLines {
+    fn write(&self, out: &mut dyn Write, columns: &[&str], rows: &[Vec<Value>]) -> io::Result<()> {
+        for row in rows {
+            let fields: Vec<String> = columns
+                .iter()
+                .zip(row)
+                .map(|(column, value)| format!("{}:{}", json_string(column), value.to_json()))
+                .collect();
+            writeln!(out, "{{{}}}", fields.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// A YAML sequence of mappings, one per row.
+///
+/// ```
+/// use rust_ffi_demo::formatter::{OutputFormatter, Value, Yaml};
+///
+/// let rows = [vec![Value::from("github.com"), Value::Number(42)]];
+/// let mut out = Vec::new();
+/// Yaml.write(&mut out, &["service", "pid"], &rows).unwrap();
+/// assert_eq!(out, b"- service: \"github.com\"\n  pid: 42\n");
+/// ```
+///
+/// Rows of streaming commands add up to a single sequence.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Yaml;
+
+impl OutputFormatter for Yaml {
+    fn write(&self, out: &mut dyn Write, columns: &[&str], rows: &[Vec<Value>]) -> io::Result<()> {
+        if rows.is_empty() {
+            return writeln!(out, "[]");
+        }
+        for row in rows {
+            for (index, (column, value)) in columns.iter().zip(row).enumerate() {
+                let lead = if index == 0 { "- " } else { "  " };
+                writeln!(out, "{}{}: {}", lead, column, value.to_json())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Tab-separated values under a header.
+///
+/// Escape tabs, line breaks and backslashes in values with backslashes, and
+/// leave missing values empty.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tsv;
+
+impl OutputFormatter for Tsv {
+    fn write(&self, out: &mut dyn Write, columns: &[&str], rows: &[Vec<Value>]) -> io::Result<()> {
+        let escape = |value: &Value| {
+            value
+                .to_string()
+                .replace('\\', "\\\\")
+                .replace('\t', "\\t")
+                .replace('\n', "\\n")
+                .replace('\r', "\\r")
+        };
+        writeln!(out, "{}", columns.join("\t"))?;
+        for row in rows {
+            let cells: Vec<String> = row.iter().map(escape).collect();
+            writeln!(out, "{}", cells.join("\t"))?;
+        }
+        Ok(())
+    }
+}
+
+/// The names of the built-in formats.
+pub const BUILT_IN: [&str; 4] = ["table", "json", "yaml", "tsv"];
+
+/// Formatters registered with `register`, by name.
+static REGISTERED: RwLock<Vec<(String, Arc<dyn OutputFormatter>)>> = RwLock::new(Vec::new());
+
+/// Register `formatter` for the format `name`.
+///
+/// Replace a formatter registered for `name` before, and take precedence
+/// over the built-in formatter of `name`.
+pub fn register(name: &str, formatter: Arc<dyn OutputFormatter>) {
+    let mut registered = REGISTERED.write().unwrap();
+    registered.retain(|(registered, _)| registered != name);
+    registered.push((name.to_string(), formatter));
+}
+
+/// Get the formatter for the format `name`.
+///
+/// Return `None` if there is no formatter for `name`.
+pub fn get(name: &str) -> Option<Arc<dyn OutputFormatter>> {
+    let registered = REGISTERED.read().unwrap();
+    if let Some((_, formatter)) = registered.iter().find(|(registered, _)| registered == name) {
+        return Some(formatter.clone());
+    }
+    match name {
+        "table" => Some(Arc::new(Table)),
+        "json" => Some(Arc::new(JsonLines)),
+        "yaml" => Some(Arc::new(Yaml)),
+        "tsv" => Some(Arc::new(Tsv)),
+        _ => None,
+    }
+}
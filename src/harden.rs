@@ -0,0 +1,88 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Move unprotected items under access control.
+//!
+//! Only the data protection keychain supports access control, so hardening
+//! an item copies it into the data protection keychain with the requested
+//! policy, checks that the copy exists and is protected, and only then deletes
+//! the original.  If any step fails the protected copy is removed again, and
+//! the original stays as it was.
+
+use crate::keychain::items::{self, Item};
+use crate::keychain::keychains::Keychain;
+use crate::keychain::protection::{self, Accessibility, Flag};
+use crate::keychain::{correlation, KeychainError, Result};
+
+/// Remove the protected copy of `item` after `error`, and explain both.
+fn roll_back(item: &Item, error: KeychainError) -> KeychainError {
+    let outcome = match protection::delete_protected_item(&item.service, &item.account) {
+        Ok(()) => "removed the protected copy".to_string(),
+        Err(undo) => format!("failed to remove the protected copy: {}", undo.message),
+    };
+    KeychainError {
+        message: format!(
+            "Failed to harden {} {}: {}; {}",
+            item.service, item.account, error.message, outcome
+        ),
+        ..error
+    }
+}
+
+/// Check that the protected copy of `item` exists with access control.
+fn verify(item: &Item) -> Result<Item> {
+    let copy = protection::find_protected_item(&item.service, &item.account)?;
+    if copy.protection.is_none() {
+        return Err(KeychainError::invalid_data(
+            "the protected copy has no access control",
+        ));
+    }
+    Ok(copy)
+}
+
+/// Protect the generic password of `service` and `account` with `flag`.
+///
+/// Look for the item in `keychain` if given, or in the search list otherwise.
+/// Return the attributes of the protected item.
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::InvalidData` if the item already has access
+/// control, `KeychainErrorCode::DuplicateItem` if the data protection keychain
+/// already has an item for `service` and `account`, and `KeychainError` if
+/// the item does not exist or keychain access fails otherwise.  On errors
+/// after the protected copy was added the error says whether removing the
+/// copy succeeded.
+pub fn harden(
+    keychain: Option<&Keychain>,
+    service: &str,
+    account: &str,
+    accessibility: Accessibility,
+    flag: Flag,
+) -> Result<Item> {
+    correlation::scope(|_| {
+        let item = items::find_item(keychain, service, account)?;
+        if item.protection.is_some() {
+            return Err(KeychainError::invalid_data(format!(
+                "{} {} already has access control",
+                service, account
+            )));
+        }
+        let data = items::find_data(keychain, service, account)?;
+        protection::add_protected_item(&item, data.expose(), accessibility, flag)?;
+        let hardened = verify(&item).map_err(|error| roll_back(&item, error))?;
+        items::delete_item(keychain, service, account).map_err(|error| roll_back(&item, error))?;
+        Ok(hardened)
+    })
+}
@@ -0,0 +1,360 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Secrets scoped to project directories.
+//!
+//! A project declares the secrets it needs in a `.keychain.toml` at its root,
+//! one `[[item]]` table per environment variable:
+//!
+//! ```toml
+//! [[item]]
+//! variable = "GITHUB_TOKEN"
+//! service = "github.com"
+//! account = "jane"
+//! ```
+//!
+//! The shell hook of `shell::hook` runs `hook-env` on every directory change,
+//! which finds the nearest project file, and prints shell code which unloads
+//! the variables of the project the shell left, and loads those of the
+//! project it entered.  The shell remembers the loaded project in
+//! `SCOPE_DIRECTORY_VAR` and `SCOPE_VARIABLES_VAR`.  Leaving a project unsets
+//! its variables, even if they were set before entering it.
+//!
+//! Like direnv, a project file only takes effect once the user allowed it
+//! with `allow`, and again after every change, so that cloning a repository
+//! never hands secrets to its scripts.
+
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::export::{self, Format};
+use crate::keychain::digest::sha256_hex;
+use crate::keychain::items;
+use crate::keychain::keychains::Keychain;
+use crate::keychain::{KeychainError, Result};
+use crate::shell::{self, Dialect};
+
+/// The name of project files.
+pub const PROJECT_FILE: &str = ".keychain.toml";
+
+/// The variable with the directory of the loaded project.
+pub const SCOPE_DIRECTORY_VAR: &str = "RUST_FFI_DEMO_SCOPE";
+
+/// The variable with the comma-separated variables of the loaded project.
+pub const SCOPE_VARIABLES_VAR: &str = "RUST_FFI_DEMO_SCOPE_VARIABLES";
+
+/// The path of the list of allowed project files relative to the home
+/// directory, unless `ALLOWED_VAR` says otherwise.
+pub const DEFAULT_ALLOWED_PATH: &str = "Library/Application Support/rust-ffi-demo/allowed-projects";
+
+/// The environment variable with the path to the list of allowed project
+/// files.
+pub const ALLOWED_VAR: &str = "RUST_FFI_DEMO_ALLOWED_PROJECTS";
+
+/// A secret a project loads into a variable.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Binding {
+    /// The environment variable.
+    pub variable: String,
+    /// The service of the item.
+    pub service: String,
+    /// The account of the item.
+    pub account: String,
+}
+
+/// Whether `name` works as variable name in all shells.
+fn is_variable_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Find the project file of `directory`, in it or its nearest ancestor.
+pub fn find_project(directory: &Path) -> Option<PathBuf> {
+    directory
+        .ancestors()
+        .map(|ancestor| ancestor.join(PROJECT_FILE))
+        .find(|path| path.is_file())
+}
+
+/// Read the bindings of the project file at `path`.
+///
+/// # Errors
+///
+/// Return an IO error if reading fails, and `io::ErrorKind::InvalidData` if
+/// the file is malformed, or a variable name is invalid or repeated.
+pub fn read_project(path: &Path) -> io::Result<Vec<Binding>> {
+    let bindings: Vec<Binding> = export::read_as(File::open(path)?, Format::Toml)?;
+    for (index, binding) in bindings.iter().enumerate() {
+        let invalid = |message: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}: {}", path.display(), binding.variable, message),
+            )
+        };
+        if !is_variable_name(&binding.variable) {
+            return Err(invalid("invalid variable name"));
+        }
+        if bindings[..index]
+            .iter()
+            .any(|other| other.variable == binding.variable)
+        {
+            return Err(invalid("bound twice"));
+        }
+    }
+    Ok(bindings)
+}
+
+/// The path of the list of allowed project files, from `ALLOWED_VAR` or else
+/// `DEFAULT_ALLOWED_PATH` in the home directory.
+pub fn default_allowed_path() -> PathBuf {
+    match env::var_os(ALLOWED_VAR) {
+        Some(path) => PathBuf::from(path),
+        None => env::var_os("HOME")
+            .map(PathBuf::from)
+            .unwrap_or_default()
+            .join(DEFAULT_ALLOWED_PATH),
+    }
+}
+
+/// The entry of the project file at `path` in the allowed list, with the
+/// digest of its contents and its absolute path.
+fn allowed_entry(path: &Path) -> io::Result<String> {
+    let contents = fs::read(path)?;
+    let path = fs::canonicalize(path)?;
+    Ok(format!("{}\t{}", sha256_hex(&contents), path.display()))
+}
+
+/// The allowed list at `allowed`, without the entries of `project`.
+fn other_entries(allowed: &Path, project: &Path) -> io::Result<Vec<String>> {
+    let project = fs::canonicalize(project)?.display().to_string();
+    match fs::read_to_string(allowed) {
+        Ok(contents) => Ok(contents
+            .lines()
+            .filter(|line| line.split_once('\t').map(|(_, path)| path) != Some(&project))
+            .map(str::to_string)
+            .collect()),
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(error) => Err(error),
+    }
+}
+
+/// Write the allowed list at `allowed`.
+fn write_allowed(allowed: &Path, entries: &[String]) -> io::Result<()> {
+    if let Some(directory) = allowed.parent() {
+        fs::create_dir_all(directory)?;
+    }
+    let mut temporary = allowed.to_path_buf().into_os_string();
+    temporary.push(".tmp");
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&temporary)?;
+    for entry in entries {
+        writeln!(file, "{}", entry)?;
+    }
+    fs::rename(&temporary, allowed)
+}
+
+/// Allow the project file at `project` with its current contents, in the
+/// allowed list at `allowed`.
+///
+/// # Errors
+///
+/// Return an IO error if reading the project file, or reading or writing the
+/// allowed list fails.
+pub fn allow(allowed: &Path, project: &Path) -> io::Result<()> {
+    let mut entries = other_entries(allowed, project)?;
+    entries.push(allowed_entry(project)?);
+    write_allowed(allowed, &entries)
+}
+
+/// Remove the project file at `project` from the allowed list at `allowed`.
+///
+/// # Errors
+///
+/// Return an IO error if reading or writing the allowed list fails.
+pub fn deny(allowed: &Path, project: &Path) -> io::Result<()> {
+    let entries = other_entries(allowed, project)?;
+    write_allowed(allowed, &entries)
+}
+
+/// Whether the project file at `project` is allowed with its current
+/// contents in the allowed list at `allowed`.
+///
+/// # Errors
+///
+/// Return an IO error if reading the project file or the allowed list fails.
+pub fn is_allowed(allowed: &Path, project: &Path) -> io::Result<bool> {
+    let entry = allowed_entry(project)?;
+    match fs::read_to_string(allowed) {
+        Ok(contents) => Ok(contents.lines().any(|line| line == entry)),
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(error) => Err(error),
+    }
+}
+
+/// The project a shell has loaded.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Scope {
+    /// The directory of the project, or `None` if no project is loaded.
+    pub directory: Option<PathBuf>,
+    /// The variables the project loaded.
+    pub variables: Vec<String>,
+}
+
+impl Scope {
+    /// The scope of this process, from `SCOPE_DIRECTORY_VAR` and
+    /// `SCOPE_VARIABLES_VAR`.
+    pub fn from_env() -> Scope {
+        Scope {
+            directory: env::var_os(SCOPE_DIRECTORY_VAR)
+                .filter(|directory| !directory.is_empty())
+                .map(PathBuf::from),
+            variables: env::var(SCOPE_VARIABLES_VAR)
+                .unwrap_or_default()
+                .split(',')
+                .filter(|name| is_variable_name(name))
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+}
+
+/// What a shell needs to change to enter a directory.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Transition {
+    /// Variables to unset.
+    pub unset: Vec<String>,
+    /// The directory of the project to load, if any.
+    pub directory: Option<PathBuf>,
+    /// Variables to set, with their values.
+    pub set: Vec<(String, String)>,
+    /// A message for the user, eg, that a project is not allowed.
+    pub notice: Option<String>,
+}
+
+impl Transition {
+    /// Shell code in `dialect` which makes this transition.
+    ///
+    /// ```
+    /// use rust_ffi_demo::hook::Transition;
+    /// use rust_ffi_demo::shell::Dialect;
+    ///
+    /// let transition = Transition {
+    ///     unset: vec!["OLD_TOKEN".to_string()],
+    ///     set: vec![("TOKEN".to_string(), "s3cr3t".to_string())],
+    ///     directory: Some("/src/app".into()),
+    ///     notice: None,
+    /// };
+    /// assert_eq!(
+    ///     transition.code(Dialect::Posix),
+    ///     "unset OLD_TOKEN\n\
+    ///      export TOKEN='s3cr3t'\n\
+    ///      export RUST_FFI_DEMO_SCOPE='/src/app'\n\
+    ///      export RUST_FFI_DEMO_SCOPE_VARIABLES='TOKEN'\n"
+    /// );
+    /// ```
+    pub fn code(&self, dialect: Dialect) -> String {
+        let mut lines: Vec<String> = self
+            .unset
+            .iter()
+            .map(|name| shell::unset(name, dialect))
+            .collect();
+        match self.directory {
+            Some(ref directory) => {
+                lines.extend(
+                    self.set
+                        .iter()
+                        .map(|(name, value)| shell::export(name, value, dialect)),
+                );
+                let names: Vec<&str> = self.set.iter().map(|(name, _)| name.as_str()).collect();
+                lines.push(shell::export(
+                    SCOPE_DIRECTORY_VAR,
+                    &directory.display().to_string(),
+                    dialect,
+                ));
+                lines.push(shell::export(
+                    SCOPE_VARIABLES_VAR,
+                    &names.join(","),
+                    dialect,
+                ));
+            }
+            None => {
+                lines.push(shell::unset(SCOPE_DIRECTORY_VAR, dialect));
+                lines.push(shell::unset(SCOPE_VARIABLES_VAR, dialect));
+            }
+        }
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+}
+
+/// Plan the transition from `scope` to the project of `directory`.
+///
+/// Return `None` if the shell stays in the loaded project, or outside of
+/// projects.  Load a project only if it's allowed in the allowed list at
+/// `allowed`, and read its secrets from `keychain`, or the search list, which
+/// may prompt for access.
+///
+/// # Errors
+///
+/// Return `KeychainError` if the project file is malformed or a secret
+/// cannot be read.
+pub fn transition(
+    keychain: Option<&Keychain>,
+    scope: &Scope,
+    directory: &Path,
+    allowed: &Path,
+) -> Result<Option<Transition>> {
+    let project = find_project(directory);
+    let project_directory = project
+        .as_ref()
+        .and_then(|path| path.parent())
+        .map(Path::to_path_buf);
+    if project_directory == scope.directory {
+        return Ok(None);
+    }
+    let mut transition = Transition {
+        unset: scope.variables.clone(),
+        ..Transition::default()
+    };
+    if let (Some(project), Some(project_directory)) = (project, project_directory) {
+        if !is_allowed(allowed, &project)? {
+            transition.notice = Some(format!(
+                "{} is not allowed; run allow to load its secrets",
+                project.display()
+            ));
+            return Ok(Some(transition));
+        }
+        for binding in read_project(&project)? {
+            let password = items::find_password(keychain, &binding.service, &binding.account)
+                .map_err(|error| KeychainError {
+                    message: format!("{}: {}", binding.variable, error.message),
+                    ..error
+                })?;
+            transition
+                .set
+                .push((binding.variable, password.into_exposed()));
+        }
+        transition.directory = Some(project_directory);
+    }
+    Ok(Some(transition))
+}
@@ -13,3 +13,5 @@
 // the License.
 
 #include <Security/Security.h>
+#include <CommonCrypto/CommonHMAC.h>
+#include <CommonCrypto/CommonDigest.h>
@@ -0,0 +1,301 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! age X25519 identities in the keychain.
+//!
+//! Identities are generic passwords of the `age-plugin-keychain` service, with
+//! the name of the identity as account and the `AGE-SECRET-KEY-1…` encoding of
+//! the secret key as data.  Files are encrypted to the ordinary `age1…`
+//! recipient of an identity, and decrypted through the plugin identity
+//! `AGE-PLUGIN-KEYCHAIN-1…` which refers to the keychain item by name.
+
+use std::ptr;
+
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use super::cfutil::*;
+use super::native::*;
+use super::read_only;
+use super::{copy_matching, random_bytes, status_to_result, KeychainError, Result};
+
+/// The service of age identity items.
+pub const SERVICE: &str = "age-plugin-keychain";
+
+const SECRET_KEY_HRP: &str = "age-secret-key-";
+const RECIPIENT_HRP: &str = "age";
+const PLUGIN_IDENTITY_HRP: &str = "age-plugin-keychain-";
+
+/// The HKDF info for X25519 stanzas, see the age specification.
+const X25519_INFO: &[u8] = b"age-encryption.org/v1/X25519";
+
+/// An age X25519 identity from the keychain.
+pub struct Identity {
+    /// The name of this identity in the keychain.
+    pub name: String,
+    secret: StaticSecret,
+}
+
+impl Identity {
+    /// The public recipient of this identity, ie, `age1…`.
+    ///
+    /// Encrypt files to this recipient with any age implementation.
+    pub fn recipient(&self) -> String {
+        let public = PublicKey::from(&self.secret);
+        bech32_encode(RECIPIENT_HRP, public.as_bytes())
+    }
+
+    /// The plugin identity for this identity, ie, `AGE-PLUGIN-KEYCHAIN-1…`.
+    ///
+    /// Pass this identity to age to decrypt files through the keychain.
+    pub fn plugin_identity(&self) -> String {
+        plugin_identity(&self.name)
+    }
+
+    /// Unwrap the file key from an X25519 stanza.
+    ///
+    /// `ephemeral_share` is the decoded argument of the stanza and `body` its
+    /// decoded body.  Return `None` if the stanza was not encrypted to this
+    /// identity.
+    pub fn unwrap_x25519(&self, ephemeral_share: &[u8], body: &[u8]) -> Option<Vec<u8>> {
+        if ephemeral_share.len() != 32 || body.len() != 32 {
+            return None;
+        }
+        let mut share = [0; 32];
+        share.copy_from_slice(ephemeral_share);
+        let share = PublicKey::from(share);
+        let shared_secret = self.secret.diffie_hellman(&share);
+        if !shared_secret.was_contributory() {
+            return None;
+        }
+
+        let mut salt = Vec::with_capacity(64);
+        salt.extend_from_slice(share.as_bytes());
+        salt.extend_from_slice(PublicKey::from(&self.secret).as_bytes());
+        let mut wrap_key = [0; 32];
+        Hkdf::<Sha256>::new(Some(&salt), shared_secret.as_bytes())
+            .expand(X25519_INFO, &mut wrap_key)
+            .ok()?;
+
+        ChaCha20Poly1305::new(Key::from_slice(&wrap_key))
+            .decrypt(Nonce::from_slice(&[0; 12]), body)
+            .ok()
+    }
+}
+
+/// The plugin identity referring to the keychain identity `name`.
+pub fn plugin_identity(name: &str) -> String {
+    bech32_encode(PLUGIN_IDENTITY_HRP, name.as_bytes()).to_uppercase()
+}
+
+/// Get the name of the keychain identity from a plugin `identity`.
+///
+/// Return `None` if `identity` is no valid `AGE-PLUGIN-KEYCHAIN-1…` identity.
+pub fn identity_name(identity: &str) -> Option<String> {
+    let data = bech32_decode(PLUGIN_IDENTITY_HRP, identity)?;
+    String::from_utf8(data).ok()
+}
+
+/// Generate a new identity and store it in the keychain under `name`.
+///
+/// # Errors
+///
+/// Return `KeychainError` if an identity with `name` already exists, or
+/// keychain access fails otherwise.
+pub fn generate_identity(name: &str) -> Result<Identity> {
+    read_only::check()?;
+    let mut bytes = [0; 32];
+    bytes.copy_from_slice(&random_bytes(32)?);
+    let identity = Identity {
+        name: name.to_string(),
+        secret: StaticSecret::from(bytes),
+    };
+    let encoded = bech32_encode(SECRET_KEY_HRP, identity.secret.as_bytes()).to_uppercase();
+    let label = format!("age identity: {}", name);
+    unsafe {
+        let cf_service = create_cf_string_no_copy(SERVICE) as CFTypeRef;
+        let cf_account = create_cf_string_no_copy(name) as CFTypeRef;
+        let cf_label = create_cf_string_no_copy(&label) as CFTypeRef;
+        let cf_secret = create_cf_data_no_copy(encoded.as_bytes()) as CFTypeRef;
+
+        let items = [
+            (
+                kSecClass() as CFTypeRef,
+                kSecClassGenericPassword() as CFTypeRef,
+            ),
+            (kSecAttrService() as CFTypeRef, cf_service),
+            (kSecAttrAccount() as CFTypeRef, cf_account),
+            (kSecAttrLabel() as CFTypeRef, cf_label),
+            (kSecValueData() as CFTypeRef, cf_secret),
+        ];
+        let attributes = create_dictionary(&items);
+        assert!(!attributes.is_null());
+
+        let status = SecItemAdd(attributes, ptr::null_mut());
+
+        CFRelease(attributes as CFTypeRef);
+        CFRelease(cf_service);
+        CFRelease(cf_account);
+        CFRelease(cf_label);
+        CFRelease(cf_secret);
+
+        status_to_result(status)?;
+    }
+    Ok(identity)
+}
+
+/// Find the identity with the given `name` in the keychain.
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::InvalidData` if the item holds no valid age
+/// secret key, and `KeychainError` if the identity does not exist, or keychain
+/// access fails otherwise.
+pub fn find_identity(name: &str) -> Result<Identity> {
+    let encoded = unsafe {
+        let cf_service = create_cf_string_no_copy(SERVICE) as CFTypeRef;
+        let cf_account = create_cf_string_no_copy(name) as CFTypeRef;
+        let items = [
+            (
+                kSecClass() as CFTypeRef,
+                kSecClassGenericPassword() as CFTypeRef,
+            ),
+            (kSecAttrService() as CFTypeRef, cf_service),
+            (kSecAttrAccount() as CFTypeRef, cf_account),
+            (kSecMatchLimit() as CFTypeRef, kSecMatchLimitOne() as CFTypeRef),
+            (kSecReturnData() as CFTypeRef, kCFBooleanTrue as CFTypeRef),
+        ];
+        let result = copy_matching(&items);
+        CFRelease(cf_service);
+        CFRelease(cf_account);
+        let data = result?;
+        let encoded = cast_cf_data(data).and_then(|data| vec_from_cfdata(data));
+        CFRelease(data);
+        encoded?
+    };
+
+    let secret = String::from_utf8(encoded)
+        .ok()
+        .and_then(|encoded| bech32_decode(SECRET_KEY_HRP, &encoded))
+        .filter(|secret| secret.len() == 32)
+        .ok_or_else(|| KeychainError::invalid_data("Invalid age secret key"))?;
+    let mut bytes = [0; 32];
+    bytes.copy_from_slice(&secret);
+    Ok(Identity {
+        name: name.to_string(),
+        secret: StaticSecret::from(bytes),
+    })
+}
+
+/// The bech32 alphabet.
+const BECH32_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// The bech32 checksum over `values`, see BIP 173.
+fn bech32_polymod(values: &[u8]) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a_57b2, 0x2650_8e6d, 0x1ea1_19fa, 0x3d42_33dd, 0x2a14_62b3];
+    let mut checksum: u32 = 1;
+    for &value in values {
+        let top = checksum >> 25;
+        checksum = ((checksum & 0x1ff_ffff) << 5) ^ u32::from(value);
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+/// Expand the human-readable part for checksum computation.
+fn bech32_hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut expanded: Vec<u8> = hrp.bytes().map(|b| b >> 5).collect();
+    expanded.push(0);
+    expanded.extend(hrp.bytes().map(|b| b & 0x1f));
+    expanded
+}
+
+/// Regroup `data` from `from` bits to `to` bits per value.
+///
+/// Return `None` if `pad` is false and leftover bits are not zero padding.
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Option<Vec<u8>> {
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    let mut converted = Vec::with_capacity(data.len() * from as usize / to as usize + 1);
+    for &value in data {
+        buffer = (buffer << from) | u32::from(value);
+        bits += from;
+        while to <= bits {
+            bits -= to;
+            converted.push(((buffer >> bits) & ((1 << to) - 1)) as u8);
+        }
+    }
+    if pad {
+        if 0 < bits {
+            converted.push(((buffer << (to - bits)) & ((1 << to) - 1)) as u8);
+        }
+    } else if from <= bits || (buffer & ((1 << bits) - 1)) != 0 {
+        return None;
+    }
+    Some(converted)
+}
+
+/// Encode `data` as lowercase bech32 with the human-readable part `hrp`.
+///
+/// Unlike BIP 173 there’s no length limit, just like in age.
+fn bech32_encode(hrp: &str, data: &[u8]) -> String {
+    let data = convert_bits(data, 8, 5, true).expect("Padding never fails");
+    let mut values = bech32_hrp_expand(hrp);
+    values.extend_from_slice(&data);
+    values.extend_from_slice(&[0; 6]);
+    let polymod = bech32_polymod(&values) ^ 1;
+
+    let mut encoded = String::with_capacity(hrp.len() + 1 + data.len() + 6);
+    encoded.push_str(hrp);
+    encoded.push('1');
+    for &value in &data {
+        encoded.push(BECH32_CHARSET[value as usize] as char);
+    }
+    for i in 0..6 {
+        encoded.push(BECH32_CHARSET[((polymod >> (5 * (5 - i))) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+/// Decode a bech32 string `s` with the human-readable part `hrp`.
+///
+/// Return `None` if `s` has mixed case, a different human-readable part, or
+/// an invalid checksum.
+fn bech32_decode(hrp: &str, s: &str) -> Option<Vec<u8>> {
+    if s.to_lowercase() != s && s.to_uppercase() != s {
+        return None;
+    }
+    let s = s.to_lowercase();
+    let separator = s.rfind('1')?;
+    if s[..separator] != *hrp || s.len() < separator + 7 {
+        return None;
+    }
+    let data = s[separator + 1..]
+        .bytes()
+        .map(|c| BECH32_CHARSET.iter().position(|&b| b == c).map(|p| p as u8))
+        .collect::<Option<Vec<u8>>>()?;
+    let mut values = bech32_hrp_expand(hrp);
+    values.extend_from_slice(&data);
+    if bech32_polymod(&values) != 1 {
+        return None;
+    }
+    convert_bits(&data[..data.len() - 6], 5, 8, false)
+}
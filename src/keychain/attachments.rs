@@ -0,0 +1,130 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Files as secrets.
+//!
+//! `store_file` stores the contents of a file, like a certificate, a
+//! kubeconfig or a license file, as the secret of a generic password, along
+//! with its original file name and its SHA-256 digest in the metadata of the
+//! item.  `retrieve_file` verifies the contents against the digest:
+//!
+//! ```no_run
+//! use rust_ffi_demo::keychain::attachments;
+//!
+//! attachments::store_file("kubernetes", "production", "/tmp/kubeconfig.yaml").unwrap();
+//! let attachment = attachments::retrieve_file("kubernetes", "production").unwrap();
+//! assert_eq!(attachment.file_name, "kubeconfig.yaml");
+//! std::fs::write(&attachment.file_name, &attachment.contents).unwrap();
+//! ```
+//!
+//! The name of an attachment is the account of its item.  Large files are
+//! compressed and stored in chunks, see `chunked`.
+
+use std::fs;
+use std::path::Path;
+
+use super::chunked;
+use super::digest::{ct_eq, sha256_hex};
+use super::items;
+use super::metadata::{self, Metadata};
+use super::{KeychainError, Result, SecretBytes};
+
+/// The metadata keys of attachments.
+const FILE_NAME_KEY: &str = "file-name";
+const SHA256_KEY: &str = "file-sha256";
+
+/// A file retrieved from the keychain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attachment {
+    /// The name of the original file, without directories.
+    pub file_name: String,
+    /// The SHA-256 digest of the contents, in hex.
+    pub sha256: String,
+    /// The contents of the file.
+    pub contents: SecretBytes,
+}
+
+/// Read the metadata of the attachment `name` of `service`.
+///
+/// Return empty metadata if the item has none.
+fn read_metadata(service: &str, name: &str) -> Result<Metadata> {
+    let item = items::find_item(None, service, name)?;
+    match item.generic {
+        Some(ref generic) if metadata::is_versioned(generic) => {
+            metadata::read_and_upgrade(None, service, name, generic)
+        }
+        _ => Ok(Metadata::new()),
+    }
+}
+
+/// Store the file at `path` as attachment `name` of `service`.
+///
+/// Replace the contents of an existing attachment, or add a new one to the
+/// default keychain.  Return the stored attachment.
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::Io` if the file cannot be read, and
+/// `KeychainError` if keychain access fails.
+pub fn store_file<P: AsRef<Path>>(service: &str, name: &str, path: P) -> Result<Attachment> {
+    let path = path.as_ref();
+    let file_name = path
+        .file_name()
+        .and_then(|file_name| file_name.to_str())
+        .ok_or_else(|| {
+            KeychainError::invalid_data(format!("No UTF-8 file name: {}", path.display()))
+        })?
+        .to_string();
+    let contents = fs::read(path)?;
+    let sha256 = sha256_hex(&contents);
+    chunked::store(None, service, name, &contents)?;
+    // Add to the metadata of the item, which may hold a chunk index already
+    let mut metadata = read_metadata(service, name)?;
+    metadata.set(FILE_NAME_KEY, file_name.as_str())?;
+    metadata.set(SHA256_KEY, sha256.as_str())?;
+    metadata::write(None, service, name, &metadata)?;
+    Ok(Attachment {
+        file_name,
+        sha256,
+        contents,
+    })
+}
+
+/// Retrieve the attachment `name` of `service`.
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::ItemNotFound` if there is no such attachment,
+/// `KeychainErrorCode::InvalidData` if the item is no attachment, or its
+/// contents do not match its digest, and `KeychainError` if keychain access
+/// fails otherwise.
+pub fn retrieve_file(service: &str, name: &str) -> Result<Attachment> {
+    let metadata = read_metadata(service, name)?;
+    let no_attachment =
+        || KeychainError::invalid_data(format!("{} {} is no attachment", service, name));
+    let file_name = metadata.get(FILE_NAME_KEY).ok_or_else(no_attachment)?;
+    let sha256 = metadata.get(SHA256_KEY).ok_or_else(no_attachment)?;
+    let contents = chunked::fetch(None, service, name)?;
+    if !ct_eq(sha256_hex(&contents).as_bytes(), sha256.as_bytes()) {
+        return Err(KeychainError::invalid_data(format!(
+            "Attachment {} {} does not match its digest",
+            service, name
+        )));
+    }
+    Ok(Attachment {
+        file_name: file_name.to_string(),
+        sha256: sha256.to_string(),
+        contents,
+    })
+}
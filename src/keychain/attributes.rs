@@ -0,0 +1,252 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Typed attribute keys.
+//!
+//! Every `Attr` knows the type of its values, so `Attributes` only accepts
+//! values of the right type:
+//!
+//! ```
+//! use rust_ffi_demo::keychain::attributes::{self, Attributes};
+//!
+//! let attributes = Attributes::generic_password()
+//!     .set(attributes::SERVICE, "example.com")
+//!     .set(attributes::ACCOUNT, "jane")
+//!     .set(attributes::INVISIBLE, &true);
+//! assert_eq!(attributes.len(), 4);
+//! ```
+//!
+//! A value of the wrong type does not compile:
+//!
+//! ```compile_fail
+//! use rust_ffi_demo::keychain::attributes::{self, Attributes};
+//!
+//! let attributes = Attributes::generic_password().set(attributes::SERVICE, &true);
+//! ```
+
+use std::marker::PhantomData;
+use std::ptr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::cfutil::*;
+use super::items::FourCC;
+use super::native::*;
+
+mod private {
+    /// Prevents value types outside of this module.
+    pub trait Sealed {}
+}
+
+/// A type of attribute values.
+pub trait AttrValue: private::Sealed {
+    /// Convert this value to a CoreFoundation object.
+    ///
+    /// # Safety
+    ///
+    /// The returned object may borrow from `self`, and must not outlive it.
+    /// The caller must call `CFRelease` on the returned object.
+    #[doc(hidden)]
+    unsafe fn to_cf_type(&self) -> CFTypeRef;
+}
+
+impl private::Sealed for str {}
+
+impl AttrValue for str {
+    unsafe fn to_cf_type(&self) -> CFTypeRef {
+        create_cf_string_no_copy(self) as CFTypeRef
+    }
+}
+
+impl private::Sealed for [u8] {}
+
+impl AttrValue for [u8] {
+    unsafe fn to_cf_type(&self) -> CFTypeRef {
+        create_cf_data_no_copy(self) as CFTypeRef
+    }
+}
+
+impl private::Sealed for bool {}
+
+impl AttrValue for bool {
+    unsafe fn to_cf_type(&self) -> CFTypeRef {
+        let value = if *self {
+            kCFBooleanTrue
+        } else {
+            kCFBooleanFalse
+        };
+        CFRetain(value as CFTypeRef)
+    }
+}
+
+impl private::Sealed for SystemTime {}
+
+impl AttrValue for SystemTime {
+    unsafe fn to_cf_type(&self) -> CFTypeRef {
+        let seconds = match self.duration_since(UNIX_EPOCH) {
+            Ok(duration) => duration.as_secs_f64(),
+            Err(error) => -error.duration().as_secs_f64(),
+        };
+        // CoreFoundation counts seconds since 2001
+        let date = CFDateCreate(ptr::null(), seconds - kCFAbsoluteTimeIntervalSince1970);
+        assert!(!date.is_null());
+        date as CFTypeRef
+    }
+}
+
+impl private::Sealed for FourCC {}
+
+impl AttrValue for FourCC {
+    unsafe fn to_cf_type(&self) -> CFTypeRef {
+        create_cf_number(i64::from(self.0)) as CFTypeRef
+    }
+}
+
+/// An attribute key with values of type `T`.
+pub struct Attr<T: ?Sized> {
+    key: fn() -> CFStringRef,
+    value: PhantomData<fn(&T)>,
+}
+
+impl<T: ?Sized> Clone for Attr<T> {
+    fn clone(&self) -> Attr<T> {
+        *self
+    }
+}
+
+impl<T: ?Sized> Copy for Attr<T> {}
+
+/// Declare typed attribute keys.
+macro_rules! attributes {
+    ($($(#[$meta:meta])* $name:ident: $value:ty = $key:ident;)*) => {
+        $(
+            $(#[$meta])*
+            pub const $name: Attr<$value> = Attr {
+                key: $key,
+                value: PhantomData,
+            };
+        )*
+    };
+}
+
+attributes! {
+    /// The service of a generic password.
+    SERVICE: str = kSecAttrService;
+    /// The account of a generic password.
+    ACCOUNT: str = kSecAttrAccount;
+    /// The label, as shown in Keychain Access.
+    LABEL: str = kSecAttrLabel;
+    /// The kind of an item, eg, `secure note`.
+    DESCRIPTION: str = kSecAttrDescription;
+    /// The comment of an item.
+    COMMENT: str = kSecAttrComment;
+    /// Application specific data of a generic password.
+    GENERIC: [u8] = kSecAttrGeneric;
+    /// The creator code of an item.
+    CREATOR: FourCC = kSecAttrCreator;
+    /// The type code of an item.
+    TYPE: FourCC = kSecAttrType;
+    /// Whether an item is hidden from Keychain Access.
+    INVISIBLE: bool = kSecAttrIsInvisible;
+    /// Whether an item is a negative entry.
+    NEGATIVE: bool = kSecAttrIsNegative;
+    /// When an item was created.
+    CREATION_DATE: SystemTime = kSecAttrCreationDate;
+    /// When an item was last modified.
+    MODIFICATION_DATE: SystemTime = kSecAttrModificationDate;
+    /// The data of an item.
+    VALUE_DATA: [u8] = kSecValueData;
+    /// Whether a query returns the attributes of items.
+    RETURN_ATTRIBUTES: bool = kSecReturnAttributes;
+    /// Whether a query returns the data of items.
+    RETURN_DATA: bool = kSecReturnData;
+    /// Whether a query uses the data protection keychain.
+    USE_DATA_PROTECTION_KEYCHAIN: bool = kSecUseDataProtectionKeychain;
+}
+
+/// Attributes of an item, or a query for items.
+///
+/// Values are borrowed for `'a`, and converted to CoreFoundation objects
+/// right away.
+pub struct Attributes<'a> {
+    items: Vec<(CFTypeRef, CFTypeRef)>,
+    owned: Vec<CFTypeRef>,
+    values: PhantomData<&'a ()>,
+}
+
+impl<'a> Attributes<'a> {
+    /// Attributes of a generic password.
+    pub fn generic_password() -> Attributes<'a> {
+        let c = constants();
+        Attributes {
+            items: vec![(c.class, c.class_generic_password)],
+            owned: Vec::new(),
+            values: PhantomData,
+        }
+    }
+
+    /// Set `attr` to `value`.
+    pub fn set<T: AttrValue + ?Sized>(mut self, attr: Attr<T>, value: &'a T) -> Attributes<'a> {
+        let cf_value = unsafe { value.to_cf_type() };
+        self.owned.push(cf_value);
+        self.items.push(((attr.key)() as CFTypeRef, cf_value));
+        self
+    }
+
+    /// Set `attr` to `value`, if any.
+    pub fn set_optional<T: AttrValue + ?Sized>(
+        self,
+        attr: Attr<T>,
+        value: Option<&'a T>,
+    ) -> Attributes<'a> {
+        match value {
+            Some(value) => self.set(attr, value),
+            None => self,
+        }
+    }
+
+    /// Add a raw `key` and `value`, for keys without typed attribute.
+    ///
+    /// # Safety
+    ///
+    /// `key` and `value` must be CoreFoundation objects which outlive these
+    /// attributes.
+    pub(crate) unsafe fn push(&mut self, key: CFTypeRef, value: CFTypeRef) {
+        self.items.push((key, value));
+    }
+
+    /// The number of attributes, including the class.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Whether there are no attributes at all.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// The keys and values of these attributes, for `create_dictionary`.
+    ///
+    /// The returned objects are only valid as long as these attributes.
+    pub(crate) fn items(&self) -> &[(CFTypeRef, CFTypeRef)] {
+        &self.items
+    }
+}
+
+impl<'a> Drop for Attributes<'a> {
+    fn drop(&mut self) {
+        for &cf_value in &self.owned {
+            unsafe { CFRelease(cf_value) };
+        }
+    }
+}
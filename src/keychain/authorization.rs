@@ -0,0 +1,125 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Administrator rights through Authorization Services.
+//!
+//! Some keychain operations, like writing to the System keychain, need an
+//! administrator.  Obtaining a right with `Authorization::obtain` asks the user
+//! for administrator credentials, and records the right in the security
+//! session, where the security daemon finds it when it checks the operation.
+//! Dropping the `Authorization` revokes the right again.
+
+use std::ffi::CString;
+use std::ptr;
+
+use super::native::*;
+use super::{correlation, KeychainError, KeychainErrorCode, Result};
+
+/// The right to modify the System keychain.
+pub const SYSTEM_KEYCHAIN_MODIFY: &str = "system.keychain.modify";
+
+/// An obtained authorization right.
+#[derive(Debug)]
+pub struct Authorization {
+    authorization: AuthorizationRef,
+    right: String,
+}
+
+impl Authorization {
+    /// Obtain the authorization `right`.
+    ///
+    /// Ask the user for credentials if the right requires any.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::AuthFailed` if the user canceled or the
+    /// credentials do not grant the right, `KeychainErrorCode::InvalidData` if
+    /// `right` contains a NUL byte, and `KeychainError` if Authorization
+    /// Services fail otherwise.
+    pub fn obtain(right: &str) -> Result<Authorization> {
+        let c_right = CString::new(right)
+            .map_err(|_| KeychainError::invalid_data("Authorization right contains NUL byte"))?;
+        let mut item = AuthorizationItem {
+            name: c_right.as_ptr(),
+            valueLength: 0,
+            value: ptr::null_mut(),
+            flags: 0,
+        };
+        let rights = AuthorizationRights {
+            count: 1,
+            items: &mut item,
+        };
+        let flags = kAuthorizationFlagInteractionAllowed
+            | kAuthorizationFlagExtendRights
+            | kAuthorizationFlagPreAuthorize;
+        unsafe {
+            let mut authorization: AuthorizationRef = ptr::null();
+            let status = AuthorizationCreate(
+                ptr::null(),
+                ptr::null(),
+                kAuthorizationFlagDefaults,
+                &mut authorization,
+            );
+            if status != errSecSuccess {
+                return Err(KeychainError::from(status));
+            }
+            assert!(!authorization.is_null());
+            let status = AuthorizationCopyRights(
+                authorization,
+                &rights,
+                ptr::null(),
+                flags,
+                ptr::null_mut(),
+            );
+            if status != errSecSuccess {
+                AuthorizationFree(authorization, kAuthorizationFlagDefaults);
+                return Err(authorization_error(status, right));
+            }
+            Ok(Authorization {
+                authorization,
+                right: right.to_string(),
+            })
+        }
+    }
+
+    /// The right this authorization holds.
+    pub fn right(&self) -> &str {
+        &self.right
+    }
+}
+
+impl Drop for Authorization {
+    fn drop(&mut self) {
+        unsafe { AuthorizationFree(self.authorization, kAuthorizationFlagDestroyRights) };
+    }
+}
+
+/// Convert a failure to obtain `right` with `status` into an error.
+fn authorization_error(status: OSStatus, right: &str) -> KeychainError {
+    if status == errAuthorizationCanceled {
+        KeychainError {
+            status: KeychainErrorCode::AuthFailed,
+            message: format!("Authorization for {} canceled", right),
+            correlation_id: correlation::current(),
+        }
+    } else if status == errAuthorizationDenied {
+        KeychainError {
+            status: KeychainErrorCode::AuthFailed,
+            message: format!("Authorization for {} denied", right),
+            correlation_id: correlation::current(),
+        }
+    } else {
+        KeychainError::from(status)
+    }
+}
@@ -0,0 +1,139 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! What the keychain API offers on the current platform.
+//!
+//! iOS and Mac Catalyst only have the data protection keychain, which holds
+//! items, keys and identities.  Keychain files, keychain events, Authorization
+//! Services and code signing information exist on macOS only, and so do the
+//! modules for them:
+//!
+//! ```
+//! use rust_ffi_demo::keychain::capabilities::Capabilities;
+//!
+//! let capabilities = Capabilities::current();
+//! assert!(capabilities.items);
+//! println!("{}", capabilities);
+//! ```
+
+use std::fmt;
+
+/// The platform this crate was built for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    /// macOS.
+    MacOs,
+    /// iOS, on devices or in the simulator.
+    Ios,
+    /// iOS apps on macOS.
+    MacCatalyst,
+}
+
+impl Platform {
+    /// The platform of this build.
+    pub const fn current() -> Platform {
+        if cfg!(target_os = "macos") {
+            Platform::MacOs
+        } else if cfg!(target_abi = "macabi") {
+            Platform::MacCatalyst
+        } else {
+            Platform::Ios
+        }
+    }
+
+    /// The name of this platform.
+    pub fn name(self) -> &'static str {
+        match self {
+            Platform::MacOs => "macOS",
+            Platform::Ios => "iOS",
+            Platform::MacCatalyst => "Mac Catalyst",
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// What the keychain API offers on a platform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// The platform.
+    pub platform: Platform,
+    /// Items, keys and identities; see `items`, `keys` and `protection`.
+    pub items: bool,
+    /// User authentication with LocalAuthentication; see `local_auth`.
+    pub local_authentication: bool,
+    /// Keychain files and the System keychain; see `keychains` and
+    /// `session`.
+    pub keychain_files: bool,
+    /// Notifications about changes to keychains; see `events`.
+    pub events: bool,
+    /// Authorization Services; see `authorization`.
+    pub authorization: bool,
+    /// Code signing information of the current process; see `codesign`.
+    pub code_signing: bool,
+    /// Wi-Fi passwords in the System keychain; see `wifi`.
+    pub wifi: bool,
+}
+
+impl Capabilities {
+    /// The capabilities of `platform`.
+    pub const fn of(platform: Platform) -> Capabilities {
+        let macos = matches!(platform, Platform::MacOs);
+        Capabilities {
+            platform,
+            items: true,
+            local_authentication: true,
+            keychain_files: macos,
+            events: macos,
+            authorization: macos,
+            code_signing: macos,
+            wifi: macos,
+        }
+    }
+
+    /// The capabilities of the platform of this build.
+    pub const fn current() -> Capabilities {
+        Capabilities::of(Platform::current())
+    }
+
+    /// The names of all capabilities with whether they are available, in
+    /// order.
+    pub fn entries(&self) -> [(&'static str, bool); 7] {
+        [
+            ("items", self.items),
+            ("local-authentication", self.local_authentication),
+            ("keychain-files", self.keychain_files),
+            ("events", self.events),
+            ("authorization", self.authorization),
+            ("code-signing", self.code_signing),
+            ("wifi", self.wifi),
+        ]
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "platform: {}", self.platform)?;
+        for (name, available) in self.entries().iter() {
+            let available = if *available { "yes" } else { "no" };
+            writeln!(f, "{}: {}", name, available)?;
+        }
+        Ok(())
+    }
+}
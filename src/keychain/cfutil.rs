@@ -13,55 +13,106 @@
 // the License.
 
 //! Utilities for CoreFoundation.
+//!
+//! The wrappers live in the `cf-util` crate; this module adds the constants
+//! of keychain queries, and autorelease pools.
+
+use std::os::raw::c_void;
+use std::sync::OnceLock;
+
+pub use cf_util::{
+    bool_value, cast_cf_array, cast_cf_data, cast_cf_dictionary, cf_dict_get_data,
+    cf_dict_get_date, cf_dict_get_string, check_type, create_cf_data_no_copy, create_cf_number,
+    create_cf_string, create_cf_string_no_copy, create_dictionary, data_value, number_value,
+    string_from_cf_string, string_value, text_value, vec_from_cfdata, Borrowed, DictionaryItems,
+    Owned, UnexpectedCfType,
+};
+// Only search lists of keychain files need arrays
+#[cfg(target_os = "macos")]
+pub use cf_util::create_array;
 
-use std;
 use super::native::*;
 
-/// Converts a CoreFoundation String to a rust `String`.
-///
-/// # Safety
+/// CoreFoundation and Security constants used in most queries.
 ///
-/// The caller must ensure that `cfstring` is not null.
-pub unsafe fn string_from_cf_string(cfstring: CFStringRef) -> String {
-    assert!(!cfstring.is_null());
-    let cf_utf8 = CFStringCreateExternalRepresentation(
-        std::ptr::null_mut(),
-        cfstring,
-        kCFStringEncodingUTF8,
-        0,
-    );
-    let string = String::from_utf8_unchecked(vec_from_cfdata(cf_utf8));
-    CFRelease(cf_utf8 as CFTypeRef);
-    string
+/// Reading a foreign static is unsafe, so instead of reading these statics
+/// all over the place, code reads them from `constants`, which reads every
+/// static exactly once.
+pub struct Constants {
+    /// `kSecClass`.
+    pub class: CFTypeRef,
+    /// `kSecClassGenericPassword`.
+    pub class_generic_password: CFTypeRef,
+    /// `kSecAttrService`.
+    pub attr_service: CFTypeRef,
+    /// `kSecAttrAccount`.
+    pub attr_account: CFTypeRef,
+    /// `kSecValueData`.
+    pub value_data: CFTypeRef,
+    /// `kSecMatchLimit`.
+    pub match_limit: CFTypeRef,
+    /// `kSecMatchLimitOne`.
+    pub match_limit_one: CFTypeRef,
+    /// `kSecMatchLimitAll`.
+    pub match_limit_all: CFTypeRef,
+    /// `kSecReturnAttributes`.
+    pub return_attributes: CFTypeRef,
+    /// `kSecReturnData`.
+    pub return_data: CFTypeRef,
+    /// `kCFBooleanTrue`.
+    pub boolean_true: CFTypeRef,
 }
 
-/// Converts a `CFData` to a vector.
-///
-/// # Safety
-///
-/// The caller must ensure that `cfdata` is not null.
-pub unsafe fn vec_from_cfdata(cfdata: CFDataRef) -> Vec<u8> {
-    assert!(!cfdata.is_null());
-    std::slice::from_raw_parts(CFDataGetBytePtr(cfdata), CFDataGetLength(cfdata) as usize).into()
+// The constants are immutable, and CoreFoundation constants may be used from
+// any thread.
+unsafe impl Send for Constants {}
+unsafe impl Sync for Constants {}
+
+/// Get the common CoreFoundation and Security constants.
+pub fn constants() -> &'static Constants {
+    static CONSTANTS: OnceLock<Constants> = OnceLock::new();
+    CONSTANTS.get_or_init(|| unsafe {
+        Constants {
+            class: kSecClass() as CFTypeRef,
+            class_generic_password: kSecClassGenericPassword() as CFTypeRef,
+            attr_service: kSecAttrService() as CFTypeRef,
+            attr_account: kSecAttrAccount() as CFTypeRef,
+            value_data: kSecValueData() as CFTypeRef,
+            match_limit: kSecMatchLimit() as CFTypeRef,
+            match_limit_one: kSecMatchLimitOne() as CFTypeRef,
+            match_limit_all: kSecMatchLimitAll() as CFTypeRef,
+            return_attributes: kSecReturnAttributes() as CFTypeRef,
+            return_data: kSecReturnData() as CFTypeRef,
+            boolean_true: kCFBooleanTrue as CFTypeRef,
+        }
+    })
 }
 
-/// Create a `CFDictionary` from items.
-///
-/// # Safety
+extern "C" {
+    fn objc_autoreleasePoolPush() -> *mut c_void;
+    fn objc_autoreleasePoolPop(pool: *mut c_void);
+}
+
+/// Pops an autorelease pool when dropped.
+struct AutoreleasePool(*mut c_void);
+
+impl Drop for AutoreleasePool {
+    fn drop(&mut self) {
+        unsafe { objc_autoreleasePoolPop(self.0) };
+    }
+}
+
+/// Run `f` in an autorelease pool of its own.
 ///
-/// `items` must have types as expected by whoever uses the dictionary; they
-/// must also only contain CoreFoundation types!
+/// Security.framework autoreleases some objects it creates for queries.
+/// Threads without a pool of their own, like the main thread of a program
+/// without an Objective-C run loop, or worker threads, leak these objects,
+/// and threads with an outer pool accumulate them until the pool drains.
+/// Run every step of a loop over many items in a pool of its own, to free
+/// these objects after every step.
 ///
-/// The caller must call `CFRelease` on the returned dictionary.
-pub unsafe fn create_dictionary(items: &[(CFTypeRef, CFTypeRef)]) -> CFDictionaryRef {
-    let mut keys: Vec<CFTypeRef> = items.iter().map(|i| i.0).collect();
-    let mut values: Vec<CFTypeRef> = items.iter().map(|i| i.1).collect();
-    CFDictionaryCreate(
-        std::ptr::null_mut(),
-        (&mut keys).as_mut_ptr(),
-        (&mut values).as_mut_ptr(),
-        keys.len() as i64,
-        &kCFTypeDictionaryKeyCallBacks,
-        &kCFTypeDictionaryValueCallBacks,
-    )
+/// Pop the pool even if `f` panics.
+pub fn with_autorelease_pool<R, F: FnOnce() -> R>(f: F) -> R {
+    let _pool = AutoreleasePool(unsafe { objc_autoreleasePoolPush() });
+    f()
 }
@@ -0,0 +1,320 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Chunked storage for large secrets.
+//!
+//! Keychain items are meant for small secrets; large blobs like service
+//! account keys or kubeconfigs are slow to store and to read.  `store` splits
+//! secrets larger than `CHUNK_SIZE` across generic passwords with the accounts
+//! `account#chunk-0`, `account#chunk-1`, and so on.  The item of `account`
+//! itself becomes the index: its metadata holds the number of chunks, and the
+//! length and the SHA-256 digest of the whole secret.  `fetch` reassembles the
+//! chunks, and verifies the secret against the index:
+//!
+//! ```no_run
+//! use rust_ffi_demo::keychain::chunked;
+//!
+//! let kubeconfig = std::fs::read("kubeconfig.yaml").unwrap();
+//! chunked::store(None, "kubernetes", "production", &kubeconfig).unwrap();
+//! assert_eq!(chunked::fetch(None, "kubernetes", "production").unwrap(), kubeconfig);
+//! ```
+//!
+//! Small secrets go into a single item as usual, and `fetch` reads any
+//! generic password, chunked or not.  `store` compresses large secrets
+//! before it splits them, see `compression`; the index then describes the
+//! compressed secret.
+//!
+//! `store` writes the index last, so an interrupted `store` leaves a secret
+//! which fails verification, but never a corrupt secret which verifies.
+
+use std::ops::Range;
+
+use super::compression::{self, COMPRESSION_KEY};
+use super::digest::{ct_eq, sha256_hex};
+use super::items::{self, Item};
+use super::keychains::Keychain;
+use super::metadata::{self, Metadata};
+use super::{KeychainError, KeychainErrorCode, Result, SecretBytes};
+
+/// The size of chunks of large secrets.
+///
+/// `store` splits secrets larger than this size.
+pub const CHUNK_SIZE: usize = 32 * 1024;
+
+/// The description of index items, as shown in Keychain Access.
+const INDEX_DESCRIPTION: &str = "chunked secret";
+
+/// The metadata keys of the index.
+const CHUNKS_KEY: &str = "chunks";
+const LENGTH_KEY: &str = "length";
+const SHA256_KEY: &str = "sha256";
+
+/// The account of the chunk at `index` of the secret of `account`.
+fn chunk_account(account: &str, index: usize) -> String {
+    format!("{}#chunk-{}", account, index)
+}
+
+/// The index of a chunked secret.
+#[derive(Debug, Clone, PartialEq)]
+struct Index {
+    chunks: usize,
+    length: usize,
+    sha256: String,
+}
+
+impl Index {
+    /// The index of `secret`.
+    fn of(secret: &[u8]) -> Index {
+        Index {
+            chunks: secret.len().div_ceil(CHUNK_SIZE),
+            length: secret.len(),
+            sha256: sha256_hex(secret),
+        }
+    }
+
+    /// The index as metadata.
+    fn metadata(&self) -> Result<Metadata> {
+        let mut metadata = Metadata::new();
+        metadata.set(CHUNKS_KEY, self.chunks.to_string())?;
+        metadata.set(LENGTH_KEY, self.length.to_string())?;
+        metadata.set(SHA256_KEY, self.sha256.as_str())?;
+        Ok(metadata)
+    }
+
+    /// Read the index of `service` and `account` from its `metadata`.
+    ///
+    /// Return `None` if the item is no index.
+    fn from_metadata(service: &str, account: &str, metadata: &Metadata) -> Result<Option<Index>> {
+        let chunks = match metadata.get(CHUNKS_KEY) {
+            Some(chunks) => chunks,
+            None => return Ok(None),
+        };
+        let invalid = || {
+            KeychainError::invalid_data(format!("Invalid chunk index of {} {}", service, account))
+        };
+        Ok(Some(Index {
+            chunks: chunks.parse().map_err(|_| invalid())?,
+            length: metadata
+                .get(LENGTH_KEY)
+                .and_then(|length| length.parse().ok())
+                .ok_or_else(invalid)?,
+            sha256: metadata.get(SHA256_KEY).ok_or_else(invalid)?.to_string(),
+        }))
+    }
+}
+
+/// Read the metadata of `item`.
+///
+/// Return `None` if `item` has no metadata.
+fn item_metadata(keychain: Option<&Keychain>, item: &Item) -> Result<Option<Metadata>> {
+    match item.generic {
+        Some(ref generic) if metadata::is_versioned(generic) => {
+            metadata::read_and_upgrade(keychain, &item.service, &item.account, generic).map(Some)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Find the metadata of `service` and `account`.
+///
+/// Return `None` if the item does not exist or has no metadata.
+fn find_metadata(
+    keychain: Option<&Keychain>,
+    service: &str,
+    account: &str,
+) -> Result<Option<Metadata>> {
+    match items::find_item(keychain, service, account) {
+        Ok(item) => item_metadata(keychain, &item),
+        Err(ref error) if error.status == KeychainErrorCode::ItemNotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Find the number of chunks of `service` and `account`.
+///
+/// Return 0 if the item does not exist or is no index.
+fn find_chunks(keychain: Option<&Keychain>, service: &str, account: &str) -> Result<usize> {
+    match find_metadata(keychain, service, account)? {
+        Some(metadata) => {
+            Ok(Index::from_metadata(service, account, &metadata)?.map_or(0, |index| index.chunks))
+        }
+        None => Ok(0),
+    }
+}
+
+/// Delete the chunks in `range` of the secret of `service` and `account`.
+///
+/// Ignore chunks which do not exist.
+fn delete_chunks(
+    keychain: Option<&Keychain>,
+    service: &str,
+    account: &str,
+    range: Range<usize>,
+) -> Result<()> {
+    for index in range {
+        match items::delete_item(keychain, service, &chunk_account(account, index)) {
+            Err(ref error) if error.status == KeychainErrorCode::ItemNotFound => (),
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+/// Store `secret` for `service` and `account`, in chunks if it's large.
+///
+/// Replace the secret of an existing item, chunked or not, or add new items.
+/// If `keychain` is given only change items in this keychain, and add new
+/// items to this keychain.
+///
+/// # Errors
+///
+/// Return `KeychainError` if keychain access fails.
+pub fn store(
+    keychain: Option<&Keychain>,
+    service: &str,
+    account: &str,
+    secret: &[u8],
+) -> Result<()> {
+    let compressed = compression::compress(secret)?;
+    let payload = compressed
+        .as_ref()
+        .map_or(secret, |(compressed, _)| compressed.as_slice());
+    let compression = compressed.as_ref().map(|&(_, compression)| compression);
+    let old_metadata = find_metadata(keychain, service, account)?;
+    let old_chunks = match old_metadata {
+        Some(ref metadata) => {
+            Index::from_metadata(service, account, metadata)?.map_or(0, |index| index.chunks)
+        }
+        None => 0,
+    };
+    let new_chunks = if payload.len() <= CHUNK_SIZE {
+        if 0 < old_chunks {
+            // Drop the index metadata, which a plain secret must not have
+            items::delete_item(keychain, service, account)?;
+        }
+        items::set_data(keychain, service, account, payload)?;
+        let mut metadata = match old_metadata {
+            Some(metadata) if old_chunks == 0 => metadata,
+            _ => Metadata::new(),
+        };
+        let changed = match compression {
+            Some(compression) => {
+                metadata.set(COMPRESSION_KEY, compression)?;
+                true
+            }
+            None => metadata.remove(COMPRESSION_KEY).is_some(),
+        };
+        if changed {
+            metadata::write(keychain, service, account, &metadata)?;
+        }
+        0
+    } else {
+        for (index, chunk) in payload.chunks(CHUNK_SIZE).enumerate() {
+            items::set_data(keychain, service, &chunk_account(account, index), chunk)?;
+        }
+        let index = Index::of(payload);
+        let mut metadata = index.metadata()?;
+        if let Some(compression) = compression {
+            metadata.set(COMPRESSION_KEY, compression)?;
+        }
+        let item = Item {
+            description: Some(INDEX_DESCRIPTION.to_string()),
+            generic: Some(metadata.to_bytes()),
+            ..Item::new(service, account)
+        };
+        match items::add_item(keychain, &item, &[]) {
+            Err(ref error) if error.status == KeychainErrorCode::DuplicateItem => {
+                items::update_item(keychain, &item, &[])?
+            }
+            result => result?,
+        }
+        index.chunks
+    };
+    delete_chunks(
+        keychain,
+        service,
+        account,
+        new_chunks..old_chunks.max(new_chunks),
+    )
+}
+
+/// Fetch the secret of `service` and `account`, reassemble its chunks, and
+/// decompress it.
+///
+/// If `keychain` is given only read items of this keychain.
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::ItemNotFound` if the item does not exist,
+/// `KeychainErrorCode::InvalidData` if a chunk is missing, the secret does
+/// not match its index or does not decompress, and `KeychainError` if
+/// keychain access fails otherwise.
+pub fn fetch(keychain: Option<&Keychain>, service: &str, account: &str) -> Result<SecretBytes> {
+    let item = items::find_item(keychain, service, account)?;
+    let metadata = match item_metadata(keychain, &item)? {
+        Some(metadata) => metadata,
+        None => return Ok(items::find_data(keychain, service, account)?.into_exposed()),
+    };
+    let payload = match Index::from_metadata(service, account, &metadata)? {
+        Some(index) => fetch_chunks(keychain, service, account, &index)?,
+        None => items::find_data(keychain, service, account)?.into_exposed(),
+    };
+    compression::decompress(&metadata, payload)
+}
+
+/// Fetch and reassemble the chunks of `service` and `account` with `index`.
+fn fetch_chunks(
+    keychain: Option<&Keychain>,
+    service: &str,
+    account: &str,
+    index: &Index,
+) -> Result<SecretBytes> {
+    let mut secret = Vec::with_capacity(index.length);
+    for chunk in 0..index.chunks {
+        match items::find_data(keychain, service, &chunk_account(account, chunk)) {
+            Ok(data) => secret.extend_from_slice(data.expose()),
+            Err(ref error) if error.status == KeychainErrorCode::ItemNotFound => {
+                return Err(KeychainError::invalid_data(format!(
+                    "Chunk {} of {} {} is missing",
+                    chunk, service, account
+                )));
+            }
+            Err(error) => return Err(error),
+        }
+    }
+    if secret.len() == index.length
+        && ct_eq(sha256_hex(&secret).as_bytes(), index.sha256.as_bytes())
+    {
+        Ok(secret)
+    } else {
+        Err(KeychainError::invalid_data(format!(
+            "Chunked secret of {} {} does not match its index",
+            service, account
+        )))
+    }
+}
+
+/// Delete the secret of `service` and `account` with all its chunks.
+///
+/// If `keychain` is given only delete items of this keychain.
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::ItemNotFound` if the item does not exist, and
+/// `KeychainError` if keychain access fails otherwise.
+pub fn delete(keychain: Option<&Keychain>, service: &str, account: &str) -> Result<()> {
+    let chunks = find_chunks(keychain, service, account)?;
+    items::delete_item(keychain, service, account)?;
+    delete_chunks(keychain, service, account, 0..chunks)
+}
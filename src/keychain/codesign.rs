@@ -0,0 +1,249 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! The code signature of the running binary.
+//!
+//! The data protection keychain, and with it Secure Enclave keys and
+//! synchronizable items, only admits signed binaries whose entitlements grant
+//! a keychain access group.  Without such entitlements keychain access fails
+//! with `errSecMissingEntitlement`; this module finds out why.
+
+use std::fmt;
+use std::os::raw::c_void;
+use std::ptr;
+
+use super::cfutil::*;
+use super::native::*;
+use super::{copy_matching, status_to_result, KeychainErrorCode, Result};
+
+/// The entitlement listing keychain access groups.
+const KEYCHAIN_ACCESS_GROUPS: &str = "keychain-access-groups";
+
+/// The entitlement with the application identifier, ie, `TEAM.bundle-id`.
+///
+/// The application identifier is an implicit keychain access group.
+const APPLICATION_IDENTIFIER: &str = "com.apple.application-identifier";
+
+/// The application identifier entitlement on macOS before 10.15.
+const LEGACY_APPLICATION_IDENTIFIER: &str = "application-identifier";
+
+/// Signing information about the running binary.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SigningInfo {
+    /// Whether the binary has a valid code signature.
+    pub signed: bool,
+    /// Whether the signature is ad-hoc, ie, without signing identity.
+    ///
+    /// Ad-hoc signatures cannot carry restricted entitlements like keychain
+    /// access groups.
+    pub adhoc: bool,
+    /// The signing identifier, usually the bundle identifier.
+    pub identifier: Option<String>,
+    /// The team identifier of the signing certificate.
+    pub team: Option<String>,
+    /// The application identifier entitlement.
+    pub application_identifier: Option<String>,
+    /// The keychain access groups granted by the entitlements.
+    pub access_groups: Vec<String>,
+}
+
+impl SigningInfo {
+    /// Whether the entitlements grant any keychain access group at all.
+    pub fn has_access_group(&self) -> bool {
+        self.application_identifier.is_some() || !self.access_groups.is_empty()
+    }
+
+    /// Explain why the data protection keychain denies access.
+    ///
+    /// Return `None` if the signature looks fine, in which case the requested
+    /// access group is likely not among the granted ones.
+    pub fn diagnosis(&self) -> Option<&'static str> {
+        if !self.signed {
+            Some("the binary is not signed; sign it with a keychain-access-groups entitlement")
+        } else if self.adhoc {
+            Some("the binary is signed ad-hoc, which cannot grant keychain access groups")
+        } else if !self.has_access_group() {
+            Some("the entitlements grant no keychain access group")
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for SigningInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.signed {
+            return write!(f, "unsigned binary");
+        }
+        write!(
+            f,
+            "signed {}as {} (team {})",
+            if self.adhoc { "ad-hoc " } else { "" },
+            self.identifier.as_deref().unwrap_or("unknown"),
+            self.team.as_deref().unwrap_or("none")
+        )?;
+        let mut groups: Vec<&str> = self.access_groups.iter().map(String::as_str).collect();
+        if let Some(ref application_identifier) = self.application_identifier {
+            groups.insert(0, application_identifier);
+        }
+        if groups.is_empty() {
+            write!(f, ", no keychain access groups")
+        } else {
+            write!(f, ", keychain access groups {}", groups.join(", "))
+        }
+    }
+}
+
+/// Get the value of the entitlement named `key` from `entitlements`.
+unsafe fn entitlement(entitlements: CFDictionaryRef, key: &str) -> CFTypeRef {
+    let cf_key = create_cf_string_no_copy(key);
+    let value = CFDictionaryGetValue(entitlements, cf_key as *const c_void);
+    CFRelease(cf_key as CFTypeRef);
+    value
+}
+
+/// Read access groups from the `keychain-access-groups` array.
+unsafe fn access_groups(entitlements: CFDictionaryRef) -> Vec<String> {
+    let groups = entitlement(entitlements, KEYCHAIN_ACCESS_GROUPS);
+    if groups.is_null() || CFGetTypeID(groups) != CFArrayGetTypeID() {
+        return Vec::new();
+    }
+    let groups = groups as CFArrayRef;
+    (0..CFArrayGetCount(groups))
+        .map(|i| CFArrayGetValueAtIndex(groups, i))
+        .filter(|group| CFGetTypeID(*group) == CFStringGetTypeID())
+        .filter_map(|group| string_from_cf_string(group as CFStringRef).ok())
+        .collect()
+}
+
+/// Read string entitlement `key`.
+unsafe fn string_entitlement(entitlements: CFDictionaryRef, key: &str) -> Option<String> {
+    let value = entitlement(entitlements, key);
+    if !value.is_null() && CFGetTypeID(value) == CFStringGetTypeID() {
+        string_from_cf_string(value as CFStringRef).ok()
+    } else {
+        None
+    }
+}
+
+/// Read signing information from the `information` dictionary of the code.
+unsafe fn from_information(information: CFDictionaryRef) -> SigningInfo {
+    let mut flags: u32 = 0;
+    let cf_flags = CFDictionaryGetValue(information, kSecCodeInfoFlags() as *const c_void);
+    if !cf_flags.is_null() && CFGetTypeID(cf_flags) == CFNumberGetTypeID() {
+        CFNumberGetValue(
+            cf_flags as CFNumberRef,
+            kCFNumberSInt32Type as CFNumberType,
+            &mut flags as *mut u32 as *mut c_void,
+        );
+    }
+    let mut info = SigningInfo {
+        signed: true,
+        adhoc: flags & kSecCodeSignatureAdhoc != 0,
+        identifier: string_value(information, kSecCodeInfoIdentifier()),
+        team: string_value(information, kSecCodeInfoTeamIdentifier()),
+        ..SigningInfo::default()
+    };
+    let entitlements =
+        CFDictionaryGetValue(information, kSecCodeInfoEntitlementsDict() as *const c_void);
+    if !entitlements.is_null() && CFGetTypeID(entitlements) == CFDictionaryGetTypeID() {
+        let entitlements = entitlements as CFDictionaryRef;
+        info.access_groups = access_groups(entitlements);
+        info.application_identifier = string_entitlement(entitlements, APPLICATION_IDENTIFIER)
+            .or_else(|| string_entitlement(entitlements, LEGACY_APPLICATION_IDENTIFIER));
+    }
+    info
+}
+
+/// Get signing information about the running binary.
+///
+/// # Errors
+///
+/// Return `KeychainError` if the code signature cannot be inspected.  An
+/// unsigned binary is no error.
+pub fn signing_info() -> Result<SigningInfo> {
+    unsafe {
+        let mut code: SecCodeRef = ptr::null_mut();
+        status_to_result(SecCodeCopySelf(kSecCSDefaultFlags as SecCSFlags, &mut code))?;
+        assert!(!code.is_null());
+
+        let validity =
+            SecCodeCheckValidity(code, kSecCSDefaultFlags as SecCSFlags, ptr::null_mut());
+        if validity == errSecCSUnsigned {
+            CFRelease(code as CFTypeRef);
+            return Ok(SigningInfo::default());
+        }
+
+        let mut information: CFDictionaryRef = ptr::null();
+        let status = SecCodeCopySigningInformation(
+            code as SecStaticCodeRef,
+            kSecCSSigningInformation as SecCSFlags,
+            &mut information,
+        );
+        CFRelease(code as CFTypeRef);
+        status_to_result(status)?;
+        assert!(!information.is_null());
+
+        let mut info = from_information(information);
+        CFRelease(information as CFTypeRef);
+        // A signature which doesn't validate is as good as none
+        info.signed = validity == errSecSuccess;
+        Ok(info)
+    }
+}
+
+/// Check whether this binary may query the data protection keychain.
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::MissingEntitlement` if the binary lacks a
+/// keychain access group, and `KeychainError` if keychain access fails
+/// otherwise.  Finding no item is no error.
+pub fn probe_data_protection_keychain() -> Result<()> {
+    let items = unsafe {
+        [
+            (
+                kSecClass() as CFTypeRef,
+                kSecClassGenericPassword() as CFTypeRef,
+            ),
+            (
+                kSecMatchLimit() as CFTypeRef,
+                kSecMatchLimitOne() as CFTypeRef,
+            ),
+            (
+                kSecUseDataProtectionKeychain() as CFTypeRef,
+                kCFBooleanTrue as CFTypeRef,
+            ),
+        ]
+    };
+    match unsafe { copy_matching(&items) } {
+        Ok(result) => {
+            unsafe { CFRelease(result) };
+            Ok(())
+        }
+        Err(ref error) if error.status == KeychainErrorCode::ItemNotFound => Ok(()),
+        Err(error) => Err(error),
+    }
+}
+
+/// Explain a missing entitlement, for the message of `KeychainError`.
+pub(super) fn explain_missing_entitlement() -> String {
+    match signing_info() {
+        Ok(info) => match info.diagnosis() {
+            Some(diagnosis) => format!("{} ({})", diagnosis, info),
+            None => format!("the requested access group is not granted ({})", info),
+        },
+        Err(error) => format!("cannot inspect the code signature: {}", error.message),
+    }
+}
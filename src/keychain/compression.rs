@@ -0,0 +1,101 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Transparent compression of large secrets.
+//!
+//! Machine credentials like service account keys are mostly JSON, and
+//! compress well.  With the `zstd` feature `chunked::store` compresses secrets
+//! larger than `THRESHOLD` with zstd, and records the compression in the
+//! metadata of the item, so that `chunked::fetch` decompresses them again.
+//! Without the feature secrets are stored as they are, and `chunked::fetch`
+//! fails on compressed secrets.
+//!
+//! Compression keeps the secret only if it gets smaller, so random keys and
+//! other incompressible secrets stay as they are.
+
+use super::metadata::Metadata;
+use super::{KeychainError, Result, SecretBytes};
+
+/// The size above which to compress secrets.
+pub const THRESHOLD: usize = 4 * 1024;
+
+/// The metadata key of the compression of a secret.
+pub(super) const COMPRESSION_KEY: &str = "compression";
+
+/// The compression of zstd compressed secrets.
+const ZSTD: &str = "zstd";
+
+/// The zstd compression level, zstd's own default.
+#[cfg(feature = "zstd")]
+const LEVEL: i32 = 3;
+
+/// Compress `secret` if it's large and compressible.
+///
+/// Return the compressed secret with its compression, or `None` to store
+/// `secret` as it is.
+#[cfg(feature = "zstd")]
+pub(super) fn compress(secret: &[u8]) -> Result<Option<(Vec<u8>, &'static str)>> {
+    if secret.len() <= THRESHOLD {
+        return Ok(None);
+    }
+    let compressed = zstd::bulk::compress(secret, LEVEL)?;
+    if compressed.len() < secret.len() {
+        Ok(Some((compressed, ZSTD)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Compress `secret` if it's large and compressible.
+///
+/// Without the `zstd` feature this never compresses.
+#[cfg(not(feature = "zstd"))]
+pub(super) fn compress(_secret: &[u8]) -> Result<Option<(Vec<u8>, &'static str)>> {
+    Ok(None)
+}
+
+/// Decompress the `payload` of an item with `metadata`.
+///
+/// Return `payload` as it is if `metadata` records no compression.
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::InvalidData` if the compression is unknown or
+/// not supported in this build, or `payload` doesn't decompress.
+pub(super) fn decompress(metadata: &Metadata, payload: SecretBytes) -> Result<SecretBytes> {
+    match metadata.get(COMPRESSION_KEY) {
+        None => Ok(payload),
+        Some(ZSTD) => decompress_zstd(&payload),
+        Some(other) => Err(KeychainError::invalid_data(format!(
+            "Unknown compression: {}",
+            other
+        ))),
+    }
+}
+
+/// Decompress a zstd compressed `payload`.
+#[cfg(feature = "zstd")]
+fn decompress_zstd(payload: &[u8]) -> Result<SecretBytes> {
+    zstd::stream::decode_all(payload).map_err(|error| {
+        KeychainError::invalid_data(format!("Cannot decompress secret: {}", error))
+    })
+}
+
+/// Fail, because this build has no zstd support.
+#[cfg(not(feature = "zstd"))]
+fn decompress_zstd(_payload: &[u8]) -> Result<SecretBytes> {
+    Err(KeychainError::invalid_data(
+        "Secret is compressed with zstd, but zstd support is not enabled",
+    ))
+}
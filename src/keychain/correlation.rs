@@ -0,0 +1,117 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Correlation IDs of keychain operations.
+//!
+//! Every keychain operation runs with a correlation ID, which ends up in the
+//! errors and audit events of the operation; see `metrics::set_audit_hook`.
+//! Operations within `scope` share the ID of the scope, so the steps of a
+//! flow like a migration or a sync can be told from others in logs:
+//!
+//! ```
+//! use rust_ffi_demo::keychain::correlation;
+//!
+//! assert_eq!(correlation::current(), None);
+//! correlation::scope(|id| {
+//!     assert_eq!(correlation::current(), Some(id));
+//!     // Nested scopes belong to the same flow
+//!     correlation::scope(|nested| assert_eq!(nested, id));
+//! });
+//! assert_eq!(correlation::current(), None);
+//! ```
+//!
+//! IDs are per thread: a flow which hands work to other threads passes its
+//! ID along with `with_id`.
+//!
+//! With the `tracing` feature every scope also runs in a `correlation` span
+//! whose `id` field holds the ID, so that events of the flow carry it too.
+
+use std::cell::Cell;
+use std::fmt;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The ID of a flow of keychain operations.
+///
+/// Prints as 16 hex digits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CorrelationId(pub u64);
+
+impl fmt::Display for CorrelationId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+thread_local! {
+    /// The ID of the current scope of this thread.
+    static CURRENT: Cell<Option<CorrelationId>> = const { Cell::new(None) };
+}
+
+/// The number of IDs made so far.
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Make a new ID.
+///
+/// Mix the process ID and the time into a counter, to tell the IDs of
+/// different processes apart.
+fn next_id() -> CorrelationId {
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    let seed = (u64::from(process::id()) << 40) ^ nanos;
+    // Spread consecutive IDs with the golden ratio, like splitmix64
+    CorrelationId(seed ^ count.wrapping_mul(0x9e37_79b9_7f4a_7c15))
+}
+
+/// The ID of the current scope, if any.
+pub fn current() -> Option<CorrelationId> {
+    CURRENT.with(Cell::get)
+}
+
+/// Restores the previous ID when a scope ends, even on panics.
+struct Restore(Option<CorrelationId>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.set(self.0));
+    }
+}
+
+/// Run `f` with `id` as the current ID.
+///
+/// With the `tracing` feature run `f` in a `correlation` span with `id`.
+pub fn with_id<T, F: FnOnce() -> T>(id: CorrelationId, f: F) -> T {
+    let _restore = Restore(CURRENT.with(|current| current.replace(Some(id))));
+    #[cfg(feature = "tracing")]
+    let _span = tracing::info_span!("correlation", id = %id).entered();
+    f()
+}
+
+/// Run `f` in a scope with an ID.
+///
+/// Use the ID of the current scope if any, or a new ID otherwise, and pass
+/// it to `f`.
+pub fn scope<T, F: FnOnce(CorrelationId) -> T>(f: F) -> T {
+    match current() {
+        Some(id) => f(id),
+        None => {
+            let id = next_id();
+            with_id(id, || f(id))
+        }
+    }
+}
@@ -0,0 +1,67 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Message digests with CommonCrypto, and constant-time comparison.
+
+use std::hint::black_box;
+use std::os::raw::c_void;
+
+use super::native::*;
+
+/// Compute the SHA-256 digest of `data`.
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut digest = [0; 32];
+    // CC_SHA256 takes a 32-bit length; secrets never get near this limit
+    assert!(data.len() <= CC_LONG::MAX as usize);
+    unsafe {
+        CC_SHA256(
+            data.as_ptr() as *const c_void,
+            data.len() as CC_LONG,
+            digest.as_mut_ptr(),
+        );
+    }
+    digest
+}
+
+/// Compute the SHA-256 digest of `data` as lowercase hex string.
+pub fn sha256_hex(data: &[u8]) -> String {
+    sha256(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Compare `a` and `b` in constant time.
+///
+/// The time only depends on the lengths of `a` and `b`, not on their
+/// contents, so comparing a secret to a guess does not tell where they first
+/// differ.  Slices of different length compare unequal right away, so this
+/// does not hide the length; compare digests to hide it, like
+/// `items::verify_password` does.
+///
+/// ```
+/// use rust_ffi_demo::keychain::digest::ct_eq;
+///
+/// assert!(ct_eq(b"secret", b"secret"));
+/// assert!(!ct_eq(b"secret", b"secreT"));
+/// assert!(!ct_eq(b"secret", b"secrets"));
+/// ```
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    // Keep the optimizer from turning this into an early-exit loop
+    let difference = a
+        .iter()
+        .zip(b)
+        .fold(0, |difference, (x, y)| black_box(difference | (x ^ y)));
+    difference == 0
+}
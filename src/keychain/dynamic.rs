@@ -0,0 +1,163 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Security.framework, loaded at runtime.
+//!
+//! With the `dlopen-security` feature this crate doesn't link
+//! Security.framework, so programs start even where the framework isn't
+//! available, and only load it when they need the keychain.  `load` loads the
+//! framework and reports whether it's there:
+//!
+//! ```no_run
+//! use rust_ffi_demo::keychain::{dynamic, items};
+//!
+//! match dynamic::load() {
+//!     Ok(()) => println!("{:?}", items::find_password(None, "my-service", "jane")),
+//!     Err(error) => eprintln!("No keychain: {}", error),
+//! }
+//! ```
+//!
+//! Functions and constants of the framework, eg, `kSecClass()`, are looked up
+//! on their first use, which loads the framework as well, and panic if the
+//! framework or the symbol are missing.
+//! LocalAuthentication comes along with Security.
+
+use std::ffi::CStr;
+use std::io;
+use std::os::raw::{c_char, c_int, c_void};
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::OnceLock;
+
+use super::Result;
+
+/// The path of Security.framework.
+const SECURITY: &[u8] = b"/System/Library/Frameworks/Security.framework/Security\0";
+
+/// The path of LocalAuthentication.framework.
+const LOCAL_AUTHENTICATION: &[u8] =
+    b"/System/Library/Frameworks/LocalAuthentication.framework/LocalAuthentication\0";
+
+/// Resolve symbols on first use.
+const RTLD_LAZY: c_int = 0x1;
+/// Keep symbols out of the global namespace.
+const RTLD_LOCAL: c_int = 0x4;
+
+extern "C" {
+    fn dlopen(path: *const c_char, mode: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    fn dlerror() -> *const c_char;
+}
+
+/// A handle of a loaded library.
+struct Library(*mut c_void);
+
+// Handles are process-wide, and dlsym is thread-safe
+unsafe impl Send for Library {}
+unsafe impl Sync for Library {}
+
+/// Security.framework, or why it didn't load.
+static SECURITY_FRAMEWORK: OnceLock<std::result::Result<Library, String>> = OnceLock::new();
+
+/// The last error of the dynamic linker.
+fn last_error() -> String {
+    unsafe {
+        let message = dlerror();
+        if message.is_null() {
+            "unknown error".to_string()
+        } else {
+            CStr::from_ptr(message).to_string_lossy().into_owned()
+        }
+    }
+}
+
+/// Load Security.framework.
+fn open() -> std::result::Result<Library, String> {
+    unsafe {
+        let library = dlopen(SECURITY.as_ptr() as *const c_char, RTLD_LAZY | RTLD_LOCAL);
+        if library.is_null() {
+            return Err(last_error());
+        }
+        // Registers LAContext with the Objective-C runtime; without it
+        // local_auth reports that LocalAuthentication is unavailable
+        dlopen(
+            LOCAL_AUTHENTICATION.as_ptr() as *const c_char,
+            RTLD_LAZY | RTLD_LOCAL,
+        );
+        Ok(Library(library))
+    }
+}
+
+/// Load Security.framework.
+///
+/// Load the framework only once; later calls return the result of the
+/// first.
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::Io` with the message of the dynamic linker if
+/// the framework cannot be loaded.
+pub fn load() -> Result<()> {
+    match *SECURITY_FRAMEWORK.get_or_init(open) {
+        Ok(_) => Ok(()),
+        Err(ref message) => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Cannot load Security.framework: {}", message),
+        )
+        .into()),
+    }
+}
+
+/// Whether Security.framework is loaded, or loads.
+pub fn is_available() -> bool {
+    load().is_ok()
+}
+
+/// Look up `symbol` in `library`.
+///
+/// `symbol` must end with a NUL byte.  Return null if `library` lacks
+/// `symbol`.
+///
+/// # Safety
+///
+/// `library` must be a handle from `dlopen`.
+unsafe fn lookup(library: *mut c_void, symbol: &[u8]) -> *mut c_void {
+    debug_assert_eq!(symbol.last(), Some(&0));
+    dlsym(library, symbol.as_ptr() as *const c_char)
+}
+
+/// Get the address of the function or static `symbol` of Security.framework.
+///
+/// Remember the address in `address`, so that every symbol is looked up only
+/// once.  `symbol` must end with a NUL byte.
+///
+/// # Panics
+///
+/// Panic if Security.framework doesn't load, or lacks `symbol`.
+pub(crate) fn symbol(address: &AtomicPtr<c_void>, symbol: &[u8]) -> *mut c_void {
+    let known = address.load(Ordering::Acquire);
+    if !known.is_null() {
+        return known;
+    }
+    let library = match *SECURITY_FRAMEWORK.get_or_init(open) {
+        Ok(ref library) => library,
+        Err(ref message) => panic!("Cannot load Security.framework: {}", message),
+    };
+    let found = unsafe { lookup(library.0, symbol) };
+    if found.is_null() {
+        let name = String::from_utf8_lossy(&symbol[..symbol.len() - 1]);
+        panic!("Security.framework has no symbol {}", name);
+    }
+    address.store(found, Ordering::Release);
+    found
+}
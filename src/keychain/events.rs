@@ -0,0 +1,261 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Keychain change notifications.
+//!
+//! The keychain notifies registered callbacks about items being added,
+//! updated and deleted by any process, and about keychains being locked and
+//! unlocked.  Notifications arrive through the run loop of the thread that
+//! registered the callback.
+
+use std::fmt;
+use std::os::raw::c_void;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::cfutil::*;
+use super::guard::ffi_guard;
+use super::keychains::keychain_path;
+use super::native::*;
+use super::{copy_matching, owned_with_type, status_to_result, Result};
+
+/// The kind of a keychain event.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventKind {
+    /// A keychain was locked.
+    Lock,
+    /// A keychain was unlocked.
+    Unlock,
+    /// An item was added.
+    Add,
+    /// An item was deleted.
+    Delete,
+    /// An item was updated.
+    Update,
+    /// The password of a keychain changed.
+    PasswordChanged,
+    /// The default keychain changed.
+    DefaultChanged,
+    /// The keychain search list changed.
+    ListChanged,
+    /// Any other event, with its `SecKeychainEvent` code.
+    Other(u32),
+}
+
+impl From<SecKeychainEvent> for EventKind {
+    fn from(event: SecKeychainEvent) -> EventKind {
+        use self::EventKind::*;
+        match event {
+            e if e == kSecLockEvent as SecKeychainEvent => Lock,
+            e if e == kSecUnlockEvent as SecKeychainEvent => Unlock,
+            e if e == kSecAddEvent as SecKeychainEvent => Add,
+            e if e == kSecDeleteEvent as SecKeychainEvent => Delete,
+            e if e == kSecUpdateEvent as SecKeychainEvent => Update,
+            e if e == kSecPasswordChangedEvent as SecKeychainEvent => PasswordChanged,
+            e if e == kSecDefaultChangedEvent as SecKeychainEvent => DefaultChanged,
+            e if e == kSecKeychainListChangedEvent as SecKeychainEvent => ListChanged,
+            e => Other(e),
+        }
+    }
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::EventKind::*;
+        let name = match *self {
+            Lock => "lock",
+            Unlock => "unlock",
+            Add => "add",
+            Delete => "delete",
+            Update => "update",
+            PasswordChanged => "password-changed",
+            DefaultChanged => "default-changed",
+            ListChanged => "list-changed",
+            Other(code) => return write!(f, "event-{}", code),
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A keychain event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    /// What happened.
+    pub kind: EventKind,
+    /// The service, or server, of the affected password item.
+    ///
+    /// `None` if the event is not about an item, or if the item is no
+    /// password, or is already gone, as for deletions.
+    pub service: Option<String>,
+    /// The account of the affected password item.
+    pub account: Option<String>,
+    /// The path of the affected keychain, if any.
+    pub keychain: Option<PathBuf>,
+    /// The process which caused the event.
+    pub pid: i32,
+}
+
+/// Get the service and account of the password `item`.
+///
+/// Return `None` if `item` is no password or cannot be found.
+unsafe fn item_service_and_account(item: SecKeychainItemRef) -> Option<(String, String)> {
+    let item_list = create_array(&[item as CFTypeRef]);
+    assert!(!item_list.is_null());
+    let classes = [
+        (kSecClassGenericPassword(), kSecAttrService()),
+        (kSecClassInternetPassword(), kSecAttrServer()),
+    ];
+    let mut found = None;
+    for &(class, service_key) in &classes {
+        let items = [
+            (kSecClass() as CFTypeRef, class as CFTypeRef),
+            (kSecMatchItemList() as CFTypeRef, item_list as CFTypeRef),
+            (
+                kSecMatchLimit() as CFTypeRef,
+                kSecMatchLimitOne() as CFTypeRef,
+            ),
+            (
+                kSecReturnAttributes() as CFTypeRef,
+                kCFBooleanTrue as CFTypeRef,
+            ),
+        ];
+        let attributes = copy_matching(&items)
+            .and_then(|r| owned_with_type(r, "CFDictionary", CFDictionaryGetTypeID()));
+        if let Ok(attributes) = attributes {
+            let attributes = attributes as CFDictionaryRef;
+            let string = |key: CFStringRef| {
+                let value = CFDictionaryGetValue(attributes, key as *const c_void);
+                if !value.is_null() && CFGetTypeID(value) == CFStringGetTypeID() {
+                    string_from_cf_string(value as CFStringRef).unwrap_or_default()
+                } else {
+                    String::new()
+                }
+            };
+            found = Some((string(service_key), string(kSecAttrAccount())));
+            CFRelease(attributes as CFTypeRef);
+            break;
+        }
+    }
+    CFRelease(item_list as CFTypeRef);
+    found
+}
+
+/// The callback for `SecKeychainAddCallback`.
+///
+/// `context` points to the handler of a `Subscription`.  A panicking handler
+/// is logged, and misses the event.
+unsafe extern "C" fn on_event(
+    event: SecKeychainEvent,
+    info: *mut SecKeychainCallbackInfo,
+    context: *mut c_void,
+) -> OSStatus {
+    ffi_guard("on_event", errSecSuccess, || {
+        let handler = &mut *(context as *mut Box<dyn FnMut(Event)>);
+        let mut event = Event {
+            kind: event.into(),
+            service: None,
+            account: None,
+            keychain: None,
+            pid: 0,
+        };
+        if !info.is_null() {
+            let info = &*info;
+            event.pid = info.pid;
+            if !info.keychain.is_null() {
+                event.keychain = keychain_path(info.keychain);
+            }
+            if !info.item.is_null() {
+                if let Some((service, account)) = item_service_and_account(info.item) {
+                    event.service = Some(service);
+                    event.account = Some(account);
+                }
+            }
+        }
+        handler(event);
+        errSecSuccess
+    })
+}
+
+/// The handler of a subscription.
+type Handler = Box<dyn FnMut(Event)>;
+
+/// A subscription to keychain events.
+///
+/// Events are delivered through the run loop of the thread which subscribed,
+/// so the handler only runs while this thread runs its run loop, eg, with
+/// `Subscription::run_for`.  Dropping the subscription unsubscribes.
+pub struct Subscription {
+    // Boxed twice to pass a thin pointer to the handler as context.  Only the
+    // keychain calls the handler, and it's freed after `drop` unsubscribed.
+    _handler: Box<Handler>,
+}
+
+impl Subscription {
+    /// Subscribe `handler` to all keychain events.
+    ///
+    /// Only one subscription may be active per process.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainError` if the callback cannot be registered, eg,
+    /// because another subscription is active.
+    pub fn new<F: FnMut(Event) + 'static>(handler: F) -> Result<Subscription> {
+        let mut handler: Box<Handler> = Box::new(Box::new(handler));
+        status_to_result(unsafe {
+            SecKeychainAddCallback(
+                Some(on_event),
+                kSecEveryEventMask as SecKeychainEventMask,
+                handler.as_mut() as *mut Handler as *mut c_void,
+            )
+        })?;
+        Ok(Subscription { _handler: handler })
+    }
+
+    /// Run the run loop of the current thread until it stops.
+    ///
+    /// The run loop normally runs forever.
+    pub fn run(&self) {
+        unsafe { CFRunLoopRun() };
+    }
+
+    /// Run the run loop of the current thread for `duration`.
+    pub fn run_for(&self, duration: Duration) {
+        unsafe {
+            CFRunLoopRunInMode(
+                kCFRunLoopDefaultMode,
+                duration.as_secs_f64(),
+                false as Boolean,
+            );
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        unsafe { SecKeychainRemoveCallback(Some(on_event)) };
+    }
+}
+
+/// Call `handler` for every keychain event until the run loop stops.
+///
+/// Subscribe to keychain events and run the run loop of the current thread,
+/// so this function normally blocks forever.
+///
+/// # Errors
+///
+/// See `Subscription::new`.
+pub fn watch<F: FnMut(Event) + 'static>(handler: F) -> Result<()> {
+    Subscription::new(handler)?.run();
+    Ok(())
+}
@@ -0,0 +1,80 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Keep panics from unwinding into C.
+//!
+//! C code which calls back into Rust, like the keychain with notifications or
+//! LocalAuthentication with replies, cannot handle Rust panics unwinding
+//! through its frames.  `ffi_guard` wraps the bodies of `extern "C"`
+//! callbacks: it catches panics, logs them to standard error, and returns a
+//! fallback value to C instead:
+//!
+//! ```
+//! use rust_ffi_demo::keychain::guard::ffi_guard;
+//!
+//! extern "C" fn double(value: i32) -> i32 {
+//!     ffi_guard("double", -1, || {
+//!         if value < 0 {
+//!             panic!("negative value {}", value);
+//!         }
+//!         value * 2
+//!     })
+//! }
+//!
+//! assert_eq!(double(21), 42);
+//! // The panic stops at the guard, and the process lives on
+//! assert_eq!(double(-1), -1);
+//! assert_eq!(double(2), 4);
+//! ```
+//!
+//! Only code which may panic needs the guard; signal handlers, which must
+//! stay async-signal-safe, must not use it.
+
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+
+/// The message of a panic `payload`, for the usual payloads of `panic!`.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+/// Run the body `f` of the callback `name`, and return its result.
+///
+/// If `f` panics log the panic and return `fallback`.  Abort the process if
+/// dropping the panic payload panics as well, because then there is no way
+/// left to keep the panic from unwinding into C.
+pub fn ffi_guard<R, F: FnOnce() -> R>(name: &str, fallback: R, f: F) -> R {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(payload) => {
+            eprintln!(
+                "Panic in callback {}: {}",
+                name,
+                panic_message(payload.as_ref())
+            );
+            if panic::catch_unwind(AssertUnwindSafe(move || drop(payload))).is_err() {
+                eprintln!("Panic while dropping panic in callback {}, aborting", name);
+                process::abort();
+            }
+            fallback
+        }
+    }
+}
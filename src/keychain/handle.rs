@@ -0,0 +1,247 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! A configured handle for generic passwords.
+//!
+//! The free functions of `items` take their configuration on every call.  A
+//! `Handle` is configured once, with the keychain to use, a namespace for
+//! services, whether the keychain may prompt, and how often to retry, and then
+//! adds, finds and deletes items with this configuration:
+//!
+//! ```no_run
+//! use rust_ffi_demo::keychain::handle::{Handle, Interaction};
+//!
+//! let handle = Handle::new()
+//!     .namespace("com.example.app")
+//!     .interaction(Interaction::Fail)
+//!     .retries(2);
+//! handle.add("api", "jane", b"secret").unwrap();
+//! assert_eq!(handle.find("api", "jane").unwrap(), b"secret");
+//! handle.delete("api", "jane").unwrap();
+//! ```
+
+use std::ptr;
+use std::thread;
+use std::time::Duration;
+
+use super::cfutil::{cast_cf_data, vec_from_cfdata};
+use super::items::{self, Item};
+use super::keychains::Keychain;
+use super::native::*;
+use super::{metrics, status_to_result, KeychainErrorCode, Result, SecretBytes};
+
+/// Whether the keychain may prompt the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interaction {
+    /// Prompt if an item needs authentication.
+    Allow,
+    /// Fail with an error instead of prompting.
+    Fail,
+    /// Skip items which need authentication, as if they didn't exist.
+    Skip,
+}
+
+impl Interaction {
+    /// The `kSecUseAuthenticationUI` value of this policy.
+    unsafe fn as_cf_type(self) -> CFTypeRef {
+        let value = match self {
+            Interaction::Allow => kSecUseAuthenticationUIAllow(),
+            Interaction::Fail => kSecUseAuthenticationUIFail(),
+            Interaction::Skip => kSecUseAuthenticationUISkip(),
+        };
+        value as CFTypeRef
+    }
+}
+
+/// Whether an operation which failed with `status` may succeed on retry.
+///
+/// A keychain which is not yet available, eg, while the user session starts,
+/// refuses interaction.
+fn is_transient(status: &KeychainErrorCode) -> bool {
+    *status == KeychainErrorCode::UnknownStatusCode(errSecInteractionNotAllowed)
+}
+
+/// A handle for generic passwords with a fixed configuration.
+#[derive(Debug, Clone)]
+pub struct Handle {
+    keychain: Option<Keychain>,
+    namespace: Option<String>,
+    interaction: Interaction,
+    retries: usize,
+    retry_delay: Duration,
+}
+
+impl Default for Handle {
+    fn default() -> Handle {
+        Handle::new()
+    }
+}
+
+impl Handle {
+    /// A handle for the search list and the default keychain.
+    ///
+    /// The handle uses no namespace, allows prompts, and does not retry.
+    pub fn new() -> Handle {
+        Handle {
+            keychain: None,
+            namespace: None,
+            interaction: Interaction::Allow,
+            retries: 0,
+            retry_delay: Duration::from_millis(100),
+        }
+    }
+
+    /// Only use `keychain`.
+    pub fn keychain(mut self, keychain: Keychain) -> Handle {
+        self.keychain = Some(keychain);
+        self
+    }
+
+    /// Prefix all services with `namespace` and a dot.
+    pub fn namespace<S: Into<String>>(mut self, namespace: S) -> Handle {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    /// Whether finding items may prompt the user.
+    pub fn interaction(mut self, interaction: Interaction) -> Handle {
+        self.interaction = interaction;
+        self
+    }
+
+    /// Retry operations `retries` times if the keychain is unavailable.
+    pub fn retries(mut self, retries: usize) -> Handle {
+        self.retries = retries;
+        self
+    }
+
+    /// Wait `delay` before retrying.
+    pub fn retry_delay(mut self, delay: Duration) -> Handle {
+        self.retry_delay = delay;
+        self
+    }
+
+    /// The service of `service` in the namespace of this handle.
+    fn service(&self, service: &str) -> String {
+        match self.namespace {
+            Some(ref namespace) => format!("{}.{}", namespace, service),
+            None => service.to_string(),
+        }
+    }
+
+    /// Run `f`, and retry as configured if it fails transiently.
+    fn retry<T, F>(&self, mut f: F) -> Result<T>
+    where
+        F: FnMut() -> Result<T>,
+    {
+        let mut attempt = 0;
+        loop {
+            match f() {
+                Err(ref error) if attempt < self.retries && is_transient(&error.status) => {
+                    attempt += 1;
+                    thread::sleep(self.retry_delay);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Add a generic password for `service` and `account` with `secret`.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::DuplicateItem` if the item already exists,
+    /// and `KeychainError` if keychain access fails otherwise.
+    pub fn add(&self, service: &str, account: &str, secret: &[u8]) -> Result<()> {
+        let item = Item::new(self.service(service), account);
+        self.retry(|| items::add_item(self.keychain.as_ref(), &item, secret))
+    }
+
+    /// Find the secret of `service` and `account`.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::ItemNotFound` if the item does not exist, or
+    /// interaction is `Skip` and the item needs authentication, and
+    /// `KeychainError` if keychain access fails otherwise, eg, if interaction
+    /// is `Fail` and the item needs authentication.
+    pub fn find(&self, service: &str, account: &str) -> Result<SecretBytes> {
+        let service = self.service(service);
+        self.retry(|| {
+            metrics::measure("find_data", || unsafe {
+                let extra = [
+                    (
+                        kSecMatchLimit() as CFTypeRef,
+                        kSecMatchLimitOne() as CFTypeRef,
+                    ),
+                    (kSecReturnData() as CFTypeRef, kCFBooleanTrue as CFTypeRef),
+                    (
+                        kSecUseAuthenticationUI() as CFTypeRef,
+                        self.interaction.as_cf_type(),
+                    ),
+                ];
+                let mut result: CFTypeRef = ptr::null();
+                let status = items::with_item_query(
+                    self.keychain.as_ref(),
+                    &service,
+                    account,
+                    &extra,
+                    |query| SecItemCopyMatching(query, &mut result),
+                );
+                status_to_result(status)?;
+                assert!(!result.is_null());
+                let data = cast_cf_data(result).and_then(|data| vec_from_cfdata(data));
+                CFRelease(result);
+                Ok(data?)
+            })
+        })
+    }
+
+    /// Delete the generic password of `service` and `account`.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainError` if the item does not exist, or keychain access
+    /// fails otherwise.
+    pub fn delete(&self, service: &str, account: &str) -> Result<()> {
+        let service = self.service(service);
+        self.retry(|| items::delete_item(self.keychain.as_ref(), &service, account))
+    }
+
+    /// List all items in the namespace of this handle.
+    ///
+    /// Services of listed items are relative to the namespace.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainError` if keychain access fails.
+    pub fn items(&self) -> Result<Vec<Item>> {
+        let items = self.retry(|| items::list_items(self.keychain.as_ref()))?;
+        let prefix = self
+            .namespace
+            .as_ref()
+            .map(|namespace| format!("{}.", namespace));
+        Ok(items
+            .into_iter()
+            .filter_map(|mut item| match prefix {
+                Some(ref prefix) => {
+                    let service = item.service.strip_prefix(prefix.as_str())?.to_string();
+                    item.service = service;
+                    Some(item)
+                }
+                None => Some(item),
+            })
+            .collect())
+    }
+}
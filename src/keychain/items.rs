@@ -0,0 +1,824 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! List and edit generic password items.
+//!
+//! Unlike the functions in the parent module these functions can confine
+//! themselves to a single keychain.  Listing items only reads attributes and
+//! never the password data, so it does not prompt for access.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ptr;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+pub use cf_util::Encoding;
+
+use super::attributes::{self, Attributes};
+use super::cfutil::*;
+use super::digest::{ct_eq, sha256};
+use super::keychains::{with_search_list, Keychain};
+use super::labels;
+use super::metrics;
+use super::native::*;
+use super::protection::{self, Protection};
+use super::read_only;
+use super::sensitive::Sensitive;
+use super::{
+    copy_matching, owned_with_type, status_to_result, KeychainError, KeychainErrorCode, Result,
+};
+
+/// A four character code, as used for the creator and type of items.
+///
+/// Parses from and prints as four ASCII characters:
+///
+/// ```
+/// use rust_ffi_demo::keychain::items::FourCC;
+///
+/// let note: FourCC = "note".parse().unwrap();
+/// assert_eq!(note, FourCC(0x6e6f_7465));
+/// assert_eq!(note.to_string(), "note");
+/// assert!("notes".parse::<FourCC>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FourCC(pub u32);
+
+impl FromStr for FourCC {
+    type Err = KeychainError;
+
+    fn from_str(s: &str) -> Result<FourCC> {
+        let bytes = s.as_bytes();
+        if bytes.len() != 4 || !s.is_ascii() {
+            return Err(KeychainError::invalid_data(format!(
+                "Not a four character code: {}",
+                s
+            )));
+        }
+        Ok(FourCC(u32::from_be_bytes([
+            bytes[0], bytes[1], bytes[2], bytes[3],
+        ])))
+    }
+}
+
+impl fmt::Display for FourCC {
+    /// Print the code as characters, or in hex if it's not printable.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = self.0.to_be_bytes();
+        if bytes.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+            bytes.iter().try_for_each(|b| write!(f, "{}", *b as char))
+        } else {
+            write!(f, "0x{:08x}", self.0)
+        }
+    }
+}
+
+/// The attributes of a generic password item.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Item {
+    /// The service of the item.
+    pub service: String,
+    /// The account of the item.
+    pub account: String,
+    /// The label, as shown in Keychain Access.
+    pub label: Option<String>,
+    /// The kind of the item, eg, `secure note`.
+    pub description: Option<String>,
+    /// The comment of the item.
+    pub comment: Option<String>,
+    /// Application specific data of the item.
+    ///
+    /// Unlike the password data, reading this attribute never prompts.
+    pub generic: Option<Vec<u8>>,
+    /// The creator code of the item, as set by some legacy apps.
+    pub creator: Option<FourCC>,
+    /// The type code of the item, eg, `note` for secure notes.
+    pub item_type: Option<FourCC>,
+    /// Whether the item is hidden from Keychain Access.
+    ///
+    /// The system creates many invisible items.
+    pub invisible: bool,
+    /// Whether the item is a negative entry, which records that the user
+    /// declined to store a password, and holds no data.
+    pub negative: bool,
+    /// The access control of the item, or `None` if it has none.
+    ///
+    /// Only items in the data protection keychain can have access control.
+    pub protection: Option<Protection>,
+    /// The access group through which apps share the item, or `None` if it
+    /// has none.
+    ///
+    /// Only items in the data protection keychain belong to access groups.
+    pub access_group: Option<String>,
+    /// When the item was created.
+    pub created: Option<SystemTime>,
+    /// When the item was last modified.
+    pub modified: Option<SystemTime>,
+    /// The encoding of text attributes which a legacy app stored as bytes.
+    ///
+    /// `None` if all text attributes are strings, as usual.
+    pub encoding: Option<Encoding>,
+}
+
+impl Item {
+    /// Create an item for `service` and `account` without other attributes.
+    pub fn new<S: Into<String>, A: Into<String>>(service: S, account: A) -> Item {
+        Item {
+            service: service.into(),
+            account: account.into(),
+            label: None,
+            description: None,
+            comment: None,
+            generic: None,
+            creator: None,
+            item_type: None,
+            invisible: false,
+            negative: false,
+            protection: None,
+            access_group: None,
+            created: None,
+            modified: None,
+            encoding: None,
+        }
+    }
+}
+
+/// Get the date value of `key` in `dictionary`.
+///
+/// Return `None` if `key` is absent or no date.
+unsafe fn date_value(dictionary: CFDictionaryRef, key: CFStringRef) -> Option<SystemTime> {
+    cf_dict_get_date(dictionary, key).ok().flatten()
+}
+
+/// Get the four character code of `key` in `dictionary`.
+unsafe fn fourcc_value(dictionary: CFDictionaryRef, key: CFStringRef) -> Option<FourCC> {
+    number_value(dictionary, key).map(|code| FourCC(code as u32))
+}
+
+/// Read an `Item` from the `attributes` of a generic password.
+pub(super) unsafe fn item_from_attributes(attributes: CFDictionaryRef) -> Item {
+    let mut encoding = None;
+    let mut text = |key| {
+        text_value(attributes, key).map(|(text, text_encoding)| {
+            if text_encoding != Encoding::Unicode && encoding.is_none() {
+                encoding = Some(text_encoding);
+            }
+            text
+        })
+    };
+    let service = text(kSecAttrService()).unwrap_or_default();
+    let account = text(kSecAttrAccount()).unwrap_or_default();
+    let label = text(kSecAttrLabel());
+    let description = text(kSecAttrDescription());
+    let comment = text(kSecAttrComment());
+    let access_group = text(kSecAttrAccessGroup());
+    Item {
+        service,
+        account,
+        label,
+        description,
+        comment,
+        generic: data_value(attributes, kSecAttrGeneric()),
+        creator: fourcc_value(attributes, kSecAttrCreator()),
+        item_type: fourcc_value(attributes, kSecAttrType()),
+        invisible: bool_value(attributes, kSecAttrIsInvisible()).unwrap_or(false),
+        negative: bool_value(attributes, kSecAttrIsNegative()).unwrap_or(false),
+        protection: protection::from_attributes(attributes),
+        access_group,
+        created: date_value(attributes, kSecAttrCreationDate()),
+        modified: date_value(attributes, kSecAttrModificationDate()),
+        encoding,
+    }
+}
+
+/// Copy the attributes of all generic passwords matching `filter`.
+///
+/// Return an empty list rather than `KeychainErrorCode::ItemNotFound` if no
+/// items match.
+unsafe fn copy_items(keychain: Option<&Keychain>, filter: &Filter) -> Result<Vec<Item>> {
+    let mut items = DictionaryItems::new(&[
+        (
+            kSecClass() as CFTypeRef,
+            kSecClassGenericPassword() as CFTypeRef,
+        ),
+        (
+            kSecMatchLimit() as CFTypeRef,
+            kSecMatchLimitAll() as CFTypeRef,
+        ),
+        (
+            kSecReturnAttributes() as CFTypeRef,
+            kCFBooleanTrue as CFTypeRef,
+        ),
+    ]);
+    let fixed = items.len();
+    for &(key, code) in &[
+        (kSecAttrCreator(), filter.creator),
+        (kSecAttrType(), filter.item_type),
+    ] {
+        if let Some(code) = code {
+            let cf_code = create_cf_number(i64::from(code.0)) as CFTypeRef;
+            items.push((key as CFTypeRef, cf_code));
+        }
+    }
+    let result = with_search_list(keychain, &items, |items| {
+        copy_matching(items).and_then(|r| owned_with_type(r, "CFArray", CFArrayGetTypeID()))
+    });
+    for &(_, cf_code) in &items[fixed..] {
+        CFRelease(cf_code);
+    }
+    let result = match result {
+        Err(ref error) if error.status == KeychainErrorCode::ItemNotFound => return Ok(Vec::new()),
+        result => result? as CFArrayRef,
+    };
+    let count = CFArrayGetCount(result);
+    let mut listed = Vec::with_capacity(count as usize);
+    for index in 0..count {
+        let attributes = match cast_cf_dictionary(CFArrayGetValueAtIndex(result, index)) {
+            Ok(attributes) => attributes,
+            Err(error) => {
+                CFRelease(result as CFTypeRef);
+                return Err(error.into());
+            }
+        };
+        let item = item_from_attributes(attributes);
+        if filter.include_invisible || !item.invisible {
+            listed.push(item);
+        }
+    }
+    CFRelease(result as CFTypeRef);
+    Ok(listed)
+}
+
+/// List all generic password items, including invisible items.
+///
+/// If `keychain` is given only list items in this keychain, otherwise list
+/// items of all keychains in the search list.
+///
+/// # Errors
+///
+/// Return `KeychainError` if keychain access fails.  Return an empty list
+/// rather than `KeychainErrorCode::ItemNotFound` if there are no items.
+pub fn list_items(keychain: Option<&Keychain>) -> Result<Vec<Item>> {
+    let filter = Filter {
+        include_invisible: true,
+        ..Filter::default()
+    };
+    metrics::measure("list_items", || unsafe { copy_items(keychain, &filter) })
+}
+
+/// The attribute to sort listings by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortBy {
+    /// Sort by service, then by account.
+    Service,
+    /// Sort by account, then by service.
+    Account,
+    /// Sort by creation date.
+    Created,
+    /// Sort by modification date.
+    Modified,
+}
+
+impl FromStr for SortBy {
+    type Err = KeychainError;
+
+    fn from_str(s: &str) -> Result<SortBy> {
+        match s {
+            "service" => Ok(SortBy::Service),
+            "account" => Ok(SortBy::Account),
+            "created" => Ok(SortBy::Created),
+            "modified" => Ok(SortBy::Modified),
+            _ => Err(KeychainError::invalid_data(format!(
+                "Unknown sort attribute: {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// Which items to list.
+///
+/// The default filter lists all visible items.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Filter {
+    /// Only list items with this creator code.
+    pub creator: Option<FourCC>,
+    /// Only list items with this type code.
+    pub item_type: Option<FourCC>,
+    /// Whether to list invisible items as well.
+    pub include_invisible: bool,
+}
+
+/// Options for listings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ListOptions {
+    /// The attribute to sort by.
+    pub sort_by: SortBy,
+    /// Whether to sort in descending rather than ascending order.
+    pub descending: bool,
+    /// Which items to list.
+    pub filter: Filter,
+}
+
+impl Default for ListOptions {
+    /// List all items sorted by service and account, in ascending order.
+    fn default() -> ListOptions {
+        ListOptions {
+            sort_by: SortBy::Service,
+            descending: false,
+            filter: Filter::default(),
+        }
+    }
+}
+
+impl ListOptions {
+    /// Compare `a` and `b` according to these options.
+    ///
+    /// Items without the date to sort by come before items with a date.  Ties
+    /// are broken by service and account.
+    pub fn compare(&self, a: &Item, b: &Item) -> Ordering {
+        let by_service = || (&a.service, &a.account).cmp(&(&b.service, &b.account));
+        let ordering = match self.sort_by {
+            SortBy::Service => by_service(),
+            SortBy::Account => (&a.account, &a.service).cmp(&(&b.account, &b.service)),
+            SortBy::Created => a.created.cmp(&b.created).then_with(by_service),
+            SortBy::Modified => a.modified.cmp(&b.modified).then_with(by_service),
+        };
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+
+    /// Sort `items` according to these options.
+    pub fn sort(&self, items: &mut [Item]) {
+        items.sort_by(|a, b| self.compare(a, b));
+    }
+}
+
+/// List generic password items matching the filter of `options`, sorted
+/// according to `options`.
+///
+/// See `list_items`.
+///
+/// # Errors
+///
+/// Return `KeychainError` if keychain access fails.
+pub fn list_items_sorted(keychain: Option<&Keychain>, options: &ListOptions) -> Result<Vec<Item>> {
+    let mut items = metrics::measure("list_items", || unsafe {
+        copy_items(keychain, &options.filter)
+    })?;
+    options.sort(&mut items);
+    Ok(items)
+}
+
+/// Run `f` with a query for the generic password of `service` and `account`.
+///
+/// `extra` is appended to the query, which is confined to `keychain` if given.
+pub(super) unsafe fn with_item_query<T, F>(
+    keychain: Option<&Keychain>,
+    service: &str,
+    account: &str,
+    extra: &[(CFTypeRef, CFTypeRef)],
+    f: F,
+) -> T
+where
+    F: FnOnce(CFDictionaryRef) -> T,
+{
+    let mut attributes = Attributes::generic_password()
+        .set(attributes::SERVICE, service)
+        .set(attributes::ACCOUNT, account);
+    for &(key, value) in extra {
+        attributes.push(key, value);
+    }
+    let query = with_search_list(keychain, attributes.items(), |items| {
+        create_dictionary(items)
+    });
+    assert!(!query.is_null());
+
+    let result = f(query);
+
+    CFRelease(query as CFTypeRef);
+    result
+}
+
+/// Find the data of the generic password of `service` and `account`.
+///
+/// If `keychain` is given only look in this keychain.  The data is
+/// `Sensitive`, so that formatting it doesn't leak it.
+///
+/// # Errors
+///
+/// Return `KeychainError` if the item does not exist, or keychain access fails
+/// otherwise.
+pub fn find_data(
+    keychain: Option<&Keychain>,
+    service: &str,
+    account: &str,
+) -> Result<Sensitive<Vec<u8>>> {
+    metrics::measure("find_data", || unsafe {
+        let extra = [
+            (
+                kSecMatchLimit() as CFTypeRef,
+                kSecMatchLimitOne() as CFTypeRef,
+            ),
+            (kSecReturnData() as CFTypeRef, kCFBooleanTrue as CFTypeRef),
+        ];
+        let mut result: CFTypeRef = ptr::null();
+        let status = with_item_query(keychain, service, account, &extra, |query| {
+            SecItemCopyMatching(query, &mut result)
+        });
+        status_to_result(status)?;
+        assert!(!result.is_null());
+        let data = cast_cf_data(result).and_then(|data| vec_from_cfdata(data));
+        CFRelease(result);
+        Ok(Sensitive::new(data?))
+    })
+}
+
+/// Find the attributes of the generic password of `service` and `account`.
+///
+/// If `keychain` is given only look in this keychain.  Only reads attributes,
+/// so this does not prompt for access.
+///
+/// # Errors
+///
+/// Return `KeychainError` if the item does not exist, or keychain access fails
+/// otherwise.
+pub fn find_item(keychain: Option<&Keychain>, service: &str, account: &str) -> Result<Item> {
+    metrics::measure("find_item", || unsafe {
+        let extra = [
+            (
+                kSecMatchLimit() as CFTypeRef,
+                kSecMatchLimitOne() as CFTypeRef,
+            ),
+            (
+                kSecReturnAttributes() as CFTypeRef,
+                kCFBooleanTrue as CFTypeRef,
+            ),
+        ];
+        let mut result: CFTypeRef = ptr::null();
+        let status = with_item_query(keychain, service, account, &extra, |query| {
+            SecItemCopyMatching(query, &mut result)
+        });
+        status_to_result(status)?;
+        assert!(!result.is_null());
+        let result = owned_with_type(result, "CFDictionary", CFDictionaryGetTypeID())?;
+        let item = item_from_attributes(result as CFDictionaryRef);
+        CFRelease(result);
+        Ok(item)
+    })
+}
+
+/// Find the generic password of `service` and `account` if it was modified
+/// after `since`.
+///
+/// If `keychain` is given only look in this keychain.  Only reads attributes,
+/// so this is cheap enough to poll, and never prompts; callers fetch the data
+/// with `find_data` when this returns an item:
+///
+/// ```no_run
+/// use rust_ffi_demo::keychain::items;
+/// use std::time::SystemTime;
+///
+/// let mut synced = SystemTime::UNIX_EPOCH;
+/// if let Some(item) = items::find_if_modified_since(None, "api.example.com", "jane", synced)? {
+///     let data = items::find_data(None, &item.service, &item.account)?;
+///     // Mirror data elsewhere
+///     synced = item.modified.unwrap_or_else(SystemTime::now);
+/// }
+/// # Ok::<(), rust_ffi_demo::keychain::KeychainError>(())
+/// ```
+///
+/// Return `None` if the item was last modified at or before `since`.  Items
+/// without modification date count as modified.
+///
+/// # Errors
+///
+/// Return `KeychainError` if the item does not exist, or keychain access fails
+/// otherwise.
+pub fn find_if_modified_since(
+    keychain: Option<&Keychain>,
+    service: &str,
+    account: &str,
+    since: SystemTime,
+) -> Result<Option<Item>> {
+    let item = find_item(keychain, service, account)?;
+    match item.modified {
+        Some(modified) if modified <= since => Ok(None),
+        _ => Ok(Some(item)),
+    }
+}
+
+/// Find the password of the generic password of `service` and `account`.
+///
+/// If `keychain` is given only look in this keychain.  Like `find_data`, the
+/// password is `Sensitive`.
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::InvalidData` if the password is not UTF-8, and
+/// `KeychainError` if the item does not exist, or keychain access fails
+/// otherwise.
+pub fn find_password(
+    keychain: Option<&Keychain>,
+    service: &str,
+    account: &str,
+) -> Result<Sensitive<String>> {
+    String::from_utf8(find_data(keychain, service, account)?.into_exposed())
+        .map(Sensitive::new)
+        .map_err(|_| KeychainError::invalid_data("Password is not UTF-8"))
+}
+
+/// Check `candidate` against the data of `service` and `account`.
+///
+/// If `keychain` is given only look in this keychain.  Compare the digests
+/// of the data and `candidate` in constant time, so that neither the time
+/// nor the result for wrong candidates tell anything about the data.
+///
+/// # Errors
+///
+/// Return `KeychainError` if the item does not exist, or keychain access fails
+/// otherwise.
+pub fn verify_password(
+    keychain: Option<&Keychain>,
+    service: &str,
+    account: &str,
+    candidate: &[u8],
+) -> Result<bool> {
+    let data = find_data(keychain, service, account)?;
+    Ok(ct_eq(&sha256(data.expose()), &sha256(candidate)))
+}
+
+/// Add a generic password with the attributes of `item` and `data`.
+///
+/// Add the item to `keychain` if given, or to the default keychain otherwise.
+/// Dates are set by the keychain, and not taken from `item`.  Without label
+/// the item gets the default label; see `labels`.
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::DuplicateItem` if an item with the service and
+/// account of `item` already exists in the keychain, and `KeychainError` if
+/// keychain access fails otherwise.
+pub fn add_item(keychain: Option<&Keychain>, item: &Item, data: &[u8]) -> Result<()> {
+    read_only::check()?;
+    metrics::measure("add_item", || unsafe {
+        let label = labels::for_new_item(item.label.as_deref(), &item.service, &item.account);
+        let mut attributes = Attributes::generic_password()
+            .set(attributes::SERVICE, item.service.as_str())
+            .set(attributes::ACCOUNT, item.account.as_str())
+            .set(attributes::VALUE_DATA, data)
+            .set_optional(attributes::LABEL, label.as_deref())
+            .set_optional(attributes::DESCRIPTION, item.description.as_deref())
+            .set_optional(attributes::COMMENT, item.comment.as_deref())
+            .set_optional(attributes::GENERIC, item.generic.as_deref())
+            .set_optional(attributes::CREATOR, item.creator.as_ref())
+            .set_optional(attributes::TYPE, item.item_type.as_ref());
+        if item.invisible {
+            attributes = attributes.set(attributes::INVISIBLE, &true);
+        }
+        if item.negative {
+            attributes = attributes.set(attributes::NEGATIVE, &true);
+        }
+        if let Some(keychain) = keychain {
+            let (key, value) = keychain.use_keychain_item();
+            attributes.push(key, value);
+        }
+        let dictionary = create_dictionary(attributes.items());
+        assert!(!dictionary.is_null());
+
+        let status = SecItemAdd(dictionary, ptr::null_mut());
+
+        CFRelease(dictionary as CFTypeRef);
+        status_to_result(status)
+    })
+}
+
+/// Set the password of the generic password of `service` and `account`.
+///
+/// Update the password of an existing item, or add a new item with the
+/// default label; see `labels`.  If `keychain` is given, only update items in
+/// this keychain, and add new items to this keychain rather than the default
+/// keychain.
+///
+/// # Errors
+///
+/// Return `KeychainError` if keychain access fails.
+pub fn set_password(
+    keychain: Option<&Keychain>,
+    service: &str,
+    account: &str,
+    password: &str,
+) -> Result<()> {
+    set_data(keychain, service, account, password.as_bytes())
+}
+
+/// Set the data of the generic password of `service` and `account`.
+///
+/// See `set_password`.
+///
+/// # Errors
+///
+/// Return `KeychainError` if keychain access fails.
+pub fn set_data(
+    keychain: Option<&Keychain>,
+    service: &str,
+    account: &str,
+    data: &[u8],
+) -> Result<()> {
+    read_only::check()?;
+    metrics::measure("set_data", || unsafe {
+        let cf_password = create_cf_data_no_copy(data) as CFTypeRef;
+        let attributes = create_dictionary(&[(kSecValueData() as CFTypeRef, cf_password)]);
+        assert!(!attributes.is_null());
+        let mut status = with_item_query(keychain, service, account, &[], |query| {
+            SecItemUpdate(query, attributes)
+        });
+        if status == errSecItemNotFound {
+            // Search lists are for lookups; new items go to a keychain directly
+            let cf_service = create_cf_string_no_copy(service) as CFTypeRef;
+            let cf_account = create_cf_string_no_copy(account) as CFTypeRef;
+            let mut items = DictionaryItems::new(&[
+                (
+                    kSecClass() as CFTypeRef,
+                    kSecClassGenericPassword() as CFTypeRef,
+                ),
+                (kSecAttrService() as CFTypeRef, cf_service),
+                (kSecAttrAccount() as CFTypeRef, cf_account),
+                (kSecValueData() as CFTypeRef, cf_password),
+            ]);
+            let cf_label = labels::for_new_item(None, service, account)
+                .map(|label| create_cf_string(&label) as CFTypeRef);
+            if let Some(cf_label) = cf_label {
+                items.push((kSecAttrLabel() as CFTypeRef, cf_label));
+            }
+            if let Some(keychain) = keychain {
+                items.push(keychain.use_keychain_item());
+            }
+            let new_item = create_dictionary(&items);
+            assert!(!new_item.is_null());
+            status = SecItemAdd(new_item, ptr::null_mut());
+            CFRelease(new_item as CFTypeRef);
+            CFRelease(cf_service);
+            CFRelease(cf_account);
+            if let Some(cf_label) = cf_label {
+                CFRelease(cf_label);
+            }
+        }
+        CFRelease(attributes as CFTypeRef);
+        CFRelease(cf_password);
+        status_to_result(status)
+    })
+}
+
+/// Change only the data of the generic password of `service` and `account`.
+///
+/// Unlike `set_data` and `update_item` write nothing but the data of the
+/// item: its label, description, comment, generic attribute, creation date,
+/// access control and access group stay as they are.  The keychain itself
+/// updates the modification date.
+///
+/// If `keychain` is given only change the item in this keychain.
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::ItemNotFound` if the item does not exist, and
+/// `KeychainError` if keychain access fails otherwise.
+///
+/// ```no_run
+/// use rust_ffi_demo::keychain::items;
+///
+/// let before = items::find_item(None, "api.example.com", "jane").unwrap();
+/// items::change_password(None, "api.example.com", "jane", b"n3w s3cret").unwrap();
+/// let data = items::find_data(None, "api.example.com", "jane").unwrap();
+/// assert_eq!(data.expose(), b"n3w s3cret");
+/// let after = items::find_item(None, "api.example.com", "jane").unwrap();
+/// assert_eq!(after.label, before.label);
+/// assert_eq!(after.description, before.description);
+/// assert_eq!(after.comment, before.comment);
+/// assert_eq!(after.generic, before.generic);
+/// assert_eq!(after.created, before.created);
+/// assert_eq!(after.protection, before.protection);
+/// assert_eq!(after.access_group, before.access_group);
+/// ```
+pub fn change_password(
+    keychain: Option<&Keychain>,
+    service: &str,
+    account: &str,
+    new_secret: &[u8],
+) -> Result<()> {
+    read_only::check()?;
+    metrics::measure("change_password", || unsafe {
+        let cf_data = create_cf_data_no_copy(new_secret) as CFTypeRef;
+        let attributes = create_dictionary(&[(kSecValueData() as CFTypeRef, cf_data)]);
+        assert!(!attributes.is_null());
+        let status = with_item_query(keychain, service, account, &[], |query| {
+            SecItemUpdate(query, attributes)
+        });
+        CFRelease(attributes as CFTypeRef);
+        CFRelease(cf_data);
+        status_to_result(status)
+    })
+}
+
+/// Update the generic password of `item` with its label and `data`.
+///
+/// Find the item by the service and account of `item`, in `keychain` if
+/// given, and replace its data, and its label and generic data if `item` has
+/// any.
+///
+/// # Errors
+///
+/// Return `KeychainError` if the item does not exist, or keychain access fails
+/// otherwise.
+pub fn update_item(keychain: Option<&Keychain>, item: &Item, data: &[u8]) -> Result<()> {
+    read_only::check()?;
+    metrics::measure("update_item", || unsafe {
+        let cf_data = create_cf_data_no_copy(data) as CFTypeRef;
+        let cf_label = item
+            .label
+            .as_ref()
+            .map(|label| create_cf_string_no_copy(label) as CFTypeRef);
+        let cf_generic = item
+            .generic
+            .as_ref()
+            .map(|generic| create_cf_data_no_copy(generic) as CFTypeRef);
+        let mut items = DictionaryItems::new(&[(kSecValueData() as CFTypeRef, cf_data)]);
+        if let Some(cf_label) = cf_label {
+            items.push((kSecAttrLabel() as CFTypeRef, cf_label));
+        }
+        if let Some(cf_generic) = cf_generic {
+            items.push((kSecAttrGeneric() as CFTypeRef, cf_generic));
+        }
+        let attributes = create_dictionary(&items);
+        assert!(!attributes.is_null());
+        let status = with_item_query(keychain, &item.service, &item.account, &[], |query| {
+            SecItemUpdate(query, attributes)
+        });
+        CFRelease(attributes as CFTypeRef);
+        CFRelease(cf_data);
+        for cf_value in cf_label.into_iter().chain(cf_generic) {
+            CFRelease(cf_value);
+        }
+        status_to_result(status)
+    })
+}
+
+/// Change the label of the generic password of `service` and `account`.
+///
+/// If `keychain` is given only change the item in this keychain.
+///
+/// # Errors
+///
+/// Return `KeychainError` if the item does not exist, or keychain access fails
+/// otherwise.
+pub fn set_label(
+    keychain: Option<&Keychain>,
+    service: &str,
+    account: &str,
+    label: &str,
+) -> Result<()> {
+    read_only::check()?;
+    metrics::measure("set_label", || unsafe {
+        let cf_label = create_cf_string_no_copy(label) as CFTypeRef;
+        let attributes = create_dictionary(&[(kSecAttrLabel() as CFTypeRef, cf_label)]);
+        assert!(!attributes.is_null());
+        let status = with_item_query(keychain, service, account, &[], |query| {
+            SecItemUpdate(query, attributes)
+        });
+        CFRelease(attributes as CFTypeRef);
+        CFRelease(cf_label);
+        status_to_result(status)
+    })
+}
+
+/// Delete the generic password of `service` and `account`.
+///
+/// If `keychain` is given only delete the item in this keychain.
+///
+/// # Errors
+///
+/// Return `KeychainError` if the item does not exist, or keychain access fails
+/// otherwise.
+pub fn delete_item(keychain: Option<&Keychain>, service: &str, account: &str) -> Result<()> {
+    read_only::check()?;
+    metrics::measure("delete_item", || unsafe {
+        let status = with_item_query(keychain, service, account, &[], |query| {
+            SecItemDelete(query)
+        });
+        status_to_result(status)
+    })
+}
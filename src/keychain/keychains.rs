@@ -0,0 +1,563 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Keychain files.
+//!
+//! By default the keychain API searches all keychains in the search list of
+//! the user, and adds items to the default keychain.  A `Keychain` confines
+//! lookups and new items to a single keychain file instead.
+//!
+//! Everyone may read the System keychain, but writing to it needs an
+//! administrator; a `SystemKeychain` obtains the necessary authorization.
+
+use std::convert::TryFrom;
+use std::env;
+use std::ffi::{CString, OsStr};
+use std::fs::OpenOptions;
+use std::io::{self, Read};
+use std::ops::Deref;
+use std::os::raw::{c_char, c_void};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::ptr;
+#[cfg(feature = "dlopen-security")]
+use std::{mem, sync::atomic::AtomicPtr};
+
+use super::authorization::{self, Authorization};
+use super::cfutil::*;
+#[cfg(feature = "dlopen-security")]
+use super::dynamic;
+use super::native::*;
+use super::read_only;
+use super::{status_to_result, KeychainError, Result};
+
+extern "C" {
+    // Exported by Security.framework, but only declared in a private header
+    #[cfg(not(feature = "dlopen-security"))]
+    fn SecKeychainChangePassword(
+        keychain: SecKeychainRef,
+        old_password_length: UInt32,
+        old_password: *const c_void,
+        new_password_length: UInt32,
+        new_password: *const c_void,
+    ) -> OSStatus;
+
+    fn geteuid() -> u32;
+}
+
+/// `SecKeychainChangePassword`, looked up in the loaded Security.framework.
+#[cfg(feature = "dlopen-security")]
+#[allow(non_snake_case)]
+unsafe fn SecKeychainChangePassword(
+    keychain: SecKeychainRef,
+    old_password_length: UInt32,
+    old_password: *const c_void,
+    new_password_length: UInt32,
+    new_password: *const c_void,
+) -> OSStatus {
+    static ADDRESS: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+    let function: unsafe extern "C" fn(
+        SecKeychainRef,
+        UInt32,
+        *const c_void,
+        UInt32,
+        *const c_void,
+    ) -> OSStatus = mem::transmute(dynamic::symbol(&ADDRESS, b"SecKeychainChangePassword\0"));
+    function(
+        keychain,
+        old_password_length,
+        old_password,
+        new_password_length,
+        new_password,
+    )
+}
+
+/// `O_NOFOLLOW` from `<sys/fcntl.h>`, to refuse opening symlinks.
+const O_NOFOLLOW: i32 = 0x0100;
+
+/// `O_NONBLOCK` from `<sys/fcntl.h>`, lest opening a FIFO block.
+const O_NONBLOCK: i32 = 0x0004;
+
+/// `ELOOP` from `<sys/errno.h>`, with which `O_NOFOLLOW` refuses symlinks.
+const ELOOP: i32 = 62;
+
+/// Read the key file at `path`, if only its owner may access it.
+///
+/// The key file must be a regular file rather than a symlink, be owned by
+/// root or the effective user, and grant no permissions to group or others.
+/// Open the file only once, and check and read the open file, so that it
+/// cannot be replaced between checking and reading.
+///
+/// The caller should `wipe` the returned password.
+fn read_key_file(path: &Path) -> io::Result<Vec<u8>> {
+    let denied = |reason: &str| {
+        io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("Refusing key file {}: {}", path.display(), reason),
+        )
+    };
+    let mut file = OpenOptions::new()
+        .read(true)
+        .custom_flags(O_NOFOLLOW | O_NONBLOCK)
+        .open(path)
+        .map_err(|error| match error.raw_os_error() {
+            Some(ELOOP) => denied("not a regular file"),
+            _ => error,
+        })?;
+    let metadata = file.metadata()?;
+    if !metadata.file_type().is_file() {
+        return Err(denied("not a regular file"));
+    }
+    let uid = metadata.uid();
+    if uid != 0 && uid != unsafe { geteuid() } {
+        return Err(denied("owned by another user"));
+    }
+    if metadata.mode() & 0o077 != 0 {
+        return Err(denied("accessible by group or others"));
+    }
+    // Reserve enough up front, so that reading never leaves copies of the
+    // password behind in reallocated memory
+    let mut password = Vec::with_capacity(metadata.len() as usize + 1);
+    if let Err(error) = file.read_to_end(&mut password) {
+        wipe(&mut password);
+        return Err(error);
+    }
+    Ok(password)
+}
+
+/// Overwrite `bytes` with zeros.
+fn wipe(bytes: &mut [u8]) {
+    for byte in bytes.iter_mut() {
+        // Volatile writes, so that the compiler doesn't elide them
+        unsafe { ptr::write_volatile(byte, 0) };
+    }
+}
+
+/// The path of the System keychain.
+pub const SYSTEM_KEYCHAIN_PATH: &str = "/Library/Keychains/System.keychain";
+
+/// Get the path of `keychain`.
+pub(super) unsafe fn keychain_path(keychain: SecKeychainRef) -> Option<PathBuf> {
+    let mut buffer = vec![0 as c_char; 1024];
+    let mut length = buffer.len() as UInt32;
+    let status = SecKeychainGetPath(keychain, &mut length, buffer.as_mut_ptr());
+    if status != errSecSuccess {
+        return None;
+    }
+    let bytes = std::slice::from_raw_parts(buffer.as_ptr() as *const u8, length as usize);
+    Some(PathBuf::from(OsStr::from_bytes(bytes)))
+}
+
+/// The status of a keychain.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Status {
+    /// Whether the keychain is unlocked.
+    pub unlocked: bool,
+    /// Whether the keychain can be read.
+    pub readable: bool,
+    /// Whether the keychain can be written.
+    pub writable: bool,
+}
+
+/// A keychain file.
+#[derive(Debug)]
+pub struct Keychain {
+    keychain: SecKeychainRef,
+    path: PathBuf,
+}
+
+impl Keychain {
+    /// Open the keychain file at `path`.
+    ///
+    /// The keychain is not accessed before its first use, so this function
+    /// succeeds even if there is no keychain at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::InvalidData` if `path` contains a NUL byte,
+    /// and `KeychainError` if the keychain cannot be opened.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Keychain> {
+        let path = path.as_ref();
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| KeychainError::invalid_data("Keychain path contains NUL byte"))?;
+        let mut keychain: SecKeychainRef = ptr::null_mut();
+        status_to_result(unsafe { SecKeychainOpen(c_path.as_ptr(), &mut keychain) })?;
+        assert!(!keychain.is_null());
+        Ok(Keychain {
+            keychain,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Create a new keychain file at `path`, locked with `password`.
+    ///
+    /// The new keychain is unlocked, and like `security create-keychain` this
+    /// adds it to the search list; `delete` removes it again.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::InvalidData` if `path` contains a NUL byte or
+    /// `password` is too long, and `KeychainError` if there already is a
+    /// keychain at `path`, or the keychain cannot be created otherwise.
+    pub fn create<P: AsRef<Path>>(path: P, password: &[u8]) -> Result<Keychain> {
+        read_only::check()?;
+        let path = path.as_ref();
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| KeychainError::invalid_data("Keychain path contains NUL byte"))?;
+        let length = UInt32::try_from(password.len())
+            .map_err(|_| KeychainError::invalid_data("Keychain password too long"))?;
+        let mut keychain: SecKeychainRef = ptr::null_mut();
+        status_to_result(unsafe {
+            SecKeychainCreate(
+                c_path.as_ptr(),
+                length,
+                password.as_ptr() as *const c_void,
+                0,
+                ptr::null_mut(),
+                &mut keychain,
+            )
+        })?;
+        assert!(!keychain.is_null());
+        Ok(Keychain {
+            keychain,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Get the default keychain of the user.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainError` if there is no default keychain.
+    pub fn default_keychain() -> Result<Keychain> {
+        let mut keychain: SecKeychainRef = ptr::null_mut();
+        status_to_result(unsafe { SecKeychainCopyDefault(&mut keychain) })?;
+        assert!(!keychain.is_null());
+        let path = unsafe { keychain_path(keychain) }.unwrap_or_default();
+        Ok(Keychain { keychain, path })
+    }
+
+    /// Open the System keychain.
+    ///
+    /// # Errors
+    ///
+    /// See `Keychain::open`.
+    pub fn system() -> Result<Keychain> {
+        Keychain::open(SYSTEM_KEYCHAIN_PATH)
+    }
+
+    /// Open a keychain by `name`.
+    ///
+    /// `system` refers to the System keychain, and names with a slash or a
+    /// `.keychain` or `.keychain-db` extension are paths.  Any other name
+    /// refers to a keychain of the user, eg, `login`.
+    ///
+    /// # Errors
+    ///
+    /// See `Keychain::open`.
+    pub fn named(name: &str) -> Result<Keychain> {
+        if name == "system" {
+            Keychain::system()
+        } else if name.contains('/')
+            || name.ends_with(".keychain")
+            || name.ends_with(".keychain-db")
+        {
+            Keychain::open(name)
+        } else {
+            let directory = env::var_os("HOME")
+                .map(PathBuf::from)
+                .unwrap_or_default()
+                .join("Library/Keychains");
+            // Since Sierra keychain files have a `-db` suffix
+            let modern = directory.join(format!("{}.keychain-db", name));
+            if modern.exists() {
+                Keychain::open(modern)
+            } else {
+                Keychain::open(directory.join(format!("{}.keychain", name)))
+            }
+        }
+    }
+
+    /// Get the status of this keychain.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainError` if the keychain does not exist.
+    pub fn status(&self) -> Result<Status> {
+        let mut status: SecKeychainStatus = 0;
+        status_to_result(unsafe { SecKeychainGetStatus(self.keychain, &mut status) })?;
+        let has = |flag: SecKeychainStatus| status & flag != 0;
+        Ok(Status {
+            unlocked: has(kSecUnlockStateStatus as SecKeychainStatus),
+            readable: has(kSecReadPermStatus as SecKeychainStatus),
+            writable: has(kSecWritePermStatus as SecKeychainStatus),
+        })
+    }
+
+    /// Change the password of this keychain from `old` to `new`.
+    ///
+    /// The keychain needs not be unlocked.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::AuthFailed` if `old` is wrong,
+    /// `KeychainErrorCode::InvalidData` if a password is too long, and
+    /// `KeychainError` if the password cannot be changed otherwise.
+    pub fn change_password(&self, old: &str, new: &str) -> Result<()> {
+        read_only::check()?;
+        let length = |password: &str| {
+            UInt32::try_from(password.len())
+                .map_err(|_| KeychainError::invalid_data("Keychain password too long"))
+        };
+        let (old_length, new_length) = (length(old)?, length(new)?);
+        status_to_result(unsafe {
+            SecKeychainChangePassword(
+                self.keychain,
+                old_length,
+                old.as_ptr() as *const c_void,
+                new_length,
+                new.as_ptr() as *const c_void,
+            )
+        })
+    }
+
+    /// Unlock this keychain with `password`.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::AuthFailed` if `password` is wrong,
+    /// `KeychainErrorCode::InvalidData` if it is too long, and `KeychainError`
+    /// if the keychain cannot be unlocked otherwise.
+    pub fn unlock(&self, password: &[u8]) -> Result<()> {
+        let length = UInt32::try_from(password.len())
+            .map_err(|_| KeychainError::invalid_data("Keychain password too long"))?;
+        status_to_result(unsafe {
+            SecKeychainUnlock(self.keychain, length, password.as_ptr() as *const c_void, 1)
+        })
+    }
+
+    /// Unlock this keychain with the password in the key file at `path`.
+    ///
+    /// The key file holds the password, optionally followed by a newline.  It
+    /// must be a regular file owned by root or the effective user, which only
+    /// its owner may access, as is common for secrets provisioned to CI
+    /// machines.  The file is checked before it is read, and the password
+    /// wiped from memory after unlocking.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::Io` with `PermissionDenied` if the key file is
+    /// not private, `KeychainErrorCode::Io` if it cannot be read, and see
+    /// `Keychain::unlock`.
+    pub fn unlock_with_key_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut password = read_key_file(path.as_ref())?;
+        let length = match password.last() {
+            Some(&b'\n') => password.len() - 1,
+            _ => password.len(),
+        };
+        let result = self.unlock(&password[..length]);
+        wipe(&mut password);
+        result
+    }
+
+    /// The path of this keychain file as resolved by the keychain.
+    ///
+    /// Unlike `path` this path is absolute, and has the `-db` suffix of modern
+    /// keychain files.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::ItemNotFound` if the keychain file does not
+    /// exist.
+    pub fn resolved_path(&self) -> Result<PathBuf> {
+        unsafe { keychain_path(self.keychain) }
+            .filter(|path| path.exists())
+            .ok_or_else(|| {
+                KeychainError::item_not_found(format!("No keychain at {}", self.path.display()))
+            })
+    }
+
+    /// Delete this keychain file, and remove it from the search list.
+    ///
+    /// To guard against deleting the wrong keychain, `confirmed_path` must be
+    /// the `resolved_path` of this keychain.  The System keychain cannot be
+    /// deleted.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::InvalidData` if `confirmed_path` is not the
+    /// resolved path or this is the System keychain, and `KeychainError` if
+    /// the keychain does not exist or cannot be deleted.
+    pub fn delete(self, confirmed_path: &Path) -> Result<()> {
+        read_only::check()?;
+        let path = self.resolved_path()?;
+        if path != confirmed_path {
+            return Err(KeychainError::invalid_data(format!(
+                "Refusing to delete {}: confirmed path {} differs",
+                path.display(),
+                confirmed_path.display()
+            )));
+        }
+        if path == Path::new(SYSTEM_KEYCHAIN_PATH) {
+            return Err(KeychainError::invalid_data(
+                "Refusing to delete the System keychain",
+            ));
+        }
+        status_to_result(unsafe { SecKeychainDelete(self.keychain) })
+    }
+
+    /// The path of this keychain.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// This keychain as CoreFoundation type, for use in queries.
+    ///
+    /// The returned reference is only valid as long as this keychain.
+    pub(crate) fn as_cf_type(&self) -> CFTypeRef {
+        self.keychain as CFTypeRef
+    }
+
+    /// The query item which adds new items to this keychain.
+    ///
+    /// The returned reference is only valid as long as this keychain.
+    pub(crate) fn use_keychain_item(&self) -> (CFTypeRef, CFTypeRef) {
+        (kSecUseKeychain() as CFTypeRef, self.as_cf_type())
+    }
+}
+
+impl Clone for Keychain {
+    fn clone(&self) -> Keychain {
+        unsafe { CFRetain(self.as_cf_type()) };
+        Keychain {
+            keychain: self.keychain,
+            path: self.path.clone(),
+        }
+    }
+}
+
+impl Drop for Keychain {
+    fn drop(&mut self) {
+        unsafe { CFRelease(self.as_cf_type()) };
+    }
+}
+
+/// Get the default keychain of the user.
+///
+/// See `Keychain::default_keychain`.
+///
+/// # Errors
+///
+/// Return `KeychainError` if there is no default keychain.
+pub fn default_keychain() -> Result<Keychain> {
+    Keychain::default_keychain()
+}
+
+/// Make `keychain` the default keychain of the user.
+///
+/// New items go to the default keychain unless a keychain is given
+/// explicitly.
+///
+/// # Errors
+///
+/// Return `KeychainError` if the default keychain cannot be changed.
+pub fn set_default_keychain(keychain: &Keychain) -> Result<()> {
+    read_only::check()?;
+    status_to_result(unsafe { SecKeychainSetDefault(keychain.keychain) })
+}
+
+/// The System keychain, with the authorization to write to it, if obtained.
+///
+/// Dereferences to the `Keychain`, for use with all keychain functions.
+#[derive(Debug)]
+pub struct SystemKeychain {
+    keychain: Keychain,
+    authorization: Option<Authorization>,
+}
+
+impl SystemKeychain {
+    /// Open the System keychain for reading.
+    ///
+    /// # Errors
+    ///
+    /// See `Keychain::open`.
+    pub fn open() -> Result<SystemKeychain> {
+        Ok(SystemKeychain {
+            keychain: Keychain::system()?,
+            authorization: None,
+        })
+    }
+
+    /// Obtain the authorization to write to the System keychain.
+    ///
+    /// Ask the user for administrator credentials, unless this keychain is
+    /// authorized already.  The authorization lasts until this keychain is
+    /// dropped.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::AuthFailed` if the user canceled or is no
+    /// administrator, and `KeychainError` if authorization fails otherwise.
+    pub fn authorize(&mut self) -> Result<()> {
+        if self.authorization.is_none() {
+            self.authorization = Some(Authorization::obtain(
+                authorization::SYSTEM_KEYCHAIN_MODIFY,
+            )?);
+        }
+        Ok(())
+    }
+
+    /// Whether this keychain has the authorization to write.
+    pub fn is_authorized(&self) -> bool {
+        self.authorization.is_some()
+    }
+}
+
+impl Deref for SystemKeychain {
+    type Target = Keychain;
+
+    fn deref(&self) -> &Keychain {
+        &self.keychain
+    }
+}
+
+/// Run `f` with `items` confined to `keychain`, if any.
+///
+/// Adds a search list with only `keychain` to the query `items`.
+///
+/// # Safety
+///
+/// `items` must form a valid keychain query, see `create_dictionary`.
+pub(super) unsafe fn with_search_list<T, F>(
+    keychain: Option<&Keychain>,
+    items: &[(CFTypeRef, CFTypeRef)],
+    f: F,
+) -> T
+where
+    F: FnOnce(&[(CFTypeRef, CFTypeRef)]) -> T,
+{
+    match keychain {
+        None => f(items),
+        Some(keychain) => {
+            let search_list = create_array(&[keychain.as_cf_type()]);
+            assert!(!search_list.is_null());
+            let mut items = items.to_vec();
+            items.push((kSecMatchSearchList() as CFTypeRef, search_list as CFTypeRef));
+            let result = f(&items);
+            CFRelease(search_list as CFTypeRef);
+            result
+        }
+    }
+}
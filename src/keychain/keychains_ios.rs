@@ -0,0 +1,53 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Keychain files, which iOS doesn't have.
+//!
+//! iOS and Mac Catalyst only have the data protection keychain, so `Keychain`
+//! has no values there, and APIs which take an optional keychain always get
+//! `None`.
+
+use super::native::*;
+
+/// A keychain file.
+///
+/// iOS has no keychain files, so this type has no values.
+#[derive(Debug, Clone)]
+pub enum Keychain {}
+
+impl Keychain {
+    /// The query item which adds new items to this keychain.
+    pub(crate) fn use_keychain_item(&self) -> (CFTypeRef, CFTypeRef) {
+        match *self {}
+    }
+}
+
+/// Adds a search list with only `keychain` to the query `items`.
+///
+/// # Safety
+///
+/// `items` must form a valid keychain query, see `create_dictionary`.
+pub(super) unsafe fn with_search_list<T, F>(
+    keychain: Option<&Keychain>,
+    items: &[(CFTypeRef, CFTypeRef)],
+    f: F,
+) -> T
+where
+    F: FnOnce(&[(CFTypeRef, CFTypeRef)]) -> T,
+{
+    match keychain {
+        None => f(items),
+        Some(keychain) => match *keychain {},
+    }
+}
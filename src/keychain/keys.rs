@@ -0,0 +1,301 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Private keys in the keychain.
+//!
+//! Keys never leave the keychain; this module only exposes their public parts
+//! and signs data with them.
+
+use std::os::raw::c_void;
+use std::ptr;
+
+use super::cfutil::*;
+use super::metrics;
+use super::native::*;
+use super::{copy_matching, owned_with_type, KeychainError, KeychainErrorCode, Result};
+
+/// The type of a key.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyType {
+    /// An RSA key.
+    Rsa,
+    /// An elliptic curve key on the NIST P-256 curve.
+    EcdsaP256,
+    /// An elliptic curve key on the NIST P-384 curve.
+    EcdsaP384,
+}
+
+/// A signature algorithm.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SignatureAlgorithm {
+    /// PKCS#1 v1.5 RSA signature over the SHA-1 digest of a message.
+    RsaPkcs1Sha1,
+    /// PKCS#1 v1.5 RSA signature over the SHA-256 digest of a message.
+    RsaPkcs1Sha256,
+    /// PKCS#1 v1.5 RSA signature over the SHA-384 digest of a message.
+    RsaPkcs1Sha384,
+    /// PKCS#1 v1.5 RSA signature over the SHA-512 digest of a message.
+    RsaPkcs1Sha512,
+    /// RSA-PSS signature over the SHA-256 digest of a message.
+    RsaPssSha256,
+    /// RSA-PSS signature over the SHA-384 digest of a message.
+    RsaPssSha384,
+    /// RSA-PSS signature over the SHA-512 digest of a message.
+    RsaPssSha512,
+    /// DER-encoded X9.62 ECDSA signature over the SHA-256 digest of a message.
+    EcdsaSha256,
+    /// DER-encoded X9.62 ECDSA signature over the SHA-384 digest of a message.
+    EcdsaSha384,
+}
+
+impl SignatureAlgorithm {
+    unsafe fn sec_key_algorithm(self) -> SecKeyAlgorithm {
+        match self {
+            SignatureAlgorithm::RsaPkcs1Sha1 => kSecKeyAlgorithmRSASignatureMessagePKCS1v15SHA1(),
+            SignatureAlgorithm::RsaPkcs1Sha256 => {
+                kSecKeyAlgorithmRSASignatureMessagePKCS1v15SHA256()
+            }
+            SignatureAlgorithm::RsaPkcs1Sha384 => {
+                kSecKeyAlgorithmRSASignatureMessagePKCS1v15SHA384()
+            }
+            SignatureAlgorithm::RsaPkcs1Sha512 => {
+                kSecKeyAlgorithmRSASignatureMessagePKCS1v15SHA512()
+            }
+            SignatureAlgorithm::RsaPssSha256 => kSecKeyAlgorithmRSASignatureMessagePSSSHA256(),
+            SignatureAlgorithm::RsaPssSha384 => kSecKeyAlgorithmRSASignatureMessagePSSSHA384(),
+            SignatureAlgorithm::RsaPssSha512 => kSecKeyAlgorithmRSASignatureMessagePSSSHA512(),
+            SignatureAlgorithm::EcdsaSha256 => kSecKeyAlgorithmECDSASignatureMessageX962SHA256(),
+            SignatureAlgorithm::EcdsaSha384 => kSecKeyAlgorithmECDSASignatureMessageX962SHA384(),
+        }
+    }
+}
+
+/// A private key in the keychain.
+#[derive(Debug)]
+pub struct PrivateKey {
+    key: SecKeyRef,
+    /// The label of the key, if any.
+    pub label: Option<String>,
+    /// The type of the key, or `None` if this API doesn’t support it.
+    pub key_type: Option<KeyType>,
+    /// Whether the key resides in the Secure Enclave.
+    pub secure_enclave: bool,
+}
+
+// SecKey objects are immutable, and the Security framework may use them from
+// any thread.
+unsafe impl Send for PrivateKey {}
+unsafe impl Sync for PrivateKey {}
+
+impl PrivateKey {
+    /// Create a private key from a dictionary of key attributes.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::InvalidData` if the dictionary lacks the key
+    /// reference, and `KeychainErrorCode::UnexpectedCfType` if the reference
+    /// is no key.
+    unsafe fn from_attributes(attributes: CFDictionaryRef) -> Result<PrivateKey> {
+        let key = CFDictionaryGetValue(attributes, kSecValueRef() as *const c_void);
+        if key.is_null() {
+            return Err(KeychainError::invalid_data("Key attributes lack the key"));
+        }
+        check_type(key, "SecKey", SecKeyGetTypeID())?;
+        let key = key as SecKeyRef;
+        // The key follows the `Get` rule, so retain it to keep it around
+        // after the dictionary is gone.
+        CFRetain(key as CFTypeRef);
+        Ok(PrivateKey::from_key_and_attributes(key, attributes))
+    }
+
+    /// Create a private key from a `key` reference.
+    ///
+    /// # Safety
+    ///
+    /// `key` must be a private key.  The returned key takes ownership of `key`.
+    #[cfg(feature = "rustls")]
+    pub(super) unsafe fn from_key(key: SecKeyRef) -> PrivateKey {
+        let attributes = SecKeyCopyAttributes(key);
+        assert!(!attributes.is_null());
+        let private_key = PrivateKey::from_key_and_attributes(key, attributes);
+        CFRelease(attributes as CFTypeRef);
+        private_key
+    }
+
+    /// Create a private key from an owned `key` and its `attributes`.
+    unsafe fn from_key_and_attributes(key: SecKeyRef, attributes: CFDictionaryRef) -> PrivateKey {
+        let get = |key: CFStringRef| CFDictionaryGetValue(attributes, key as *const c_void);
+
+        let label = string_value(attributes, kSecAttrLabel());
+
+        let cf_type = get(kSecAttrKeyType());
+        let size = number_value(attributes, kSecAttrKeySizeInBits()).unwrap_or(0);
+        let key_type = if cf_type.is_null() {
+            None
+        } else if CFEqual(cf_type, kSecAttrKeyTypeRSA() as CFTypeRef) != 0 {
+            Some(KeyType::Rsa)
+        } else if CFEqual(cf_type, kSecAttrKeyTypeECSECPrimeRandom() as CFTypeRef) != 0 {
+            match size {
+                256 => Some(KeyType::EcdsaP256),
+                384 => Some(KeyType::EcdsaP384),
+                _ => None,
+            }
+        } else {
+            None
+        };
+
+        let cf_token = get(kSecAttrTokenID());
+        let secure_enclave = !cf_token.is_null()
+            && CFEqual(cf_token, kSecAttrTokenIDSecureEnclave() as CFTypeRef) != 0;
+
+        PrivateKey {
+            key,
+            label,
+            key_type,
+            secure_enclave,
+        }
+    }
+
+    /// Get the external representation of the public key.
+    ///
+    /// For RSA keys this is a DER-encoded PKCS#1 `RSAPublicKey`, for EC keys
+    /// the uncompressed X9.63 point `04 || X || Y`.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainError` if the public key cannot be derived or exported.
+    pub fn public_key_data(&self) -> Result<Vec<u8>> {
+        unsafe {
+            let public_key = SecKeyCopyPublicKey(self.key);
+            if public_key.is_null() {
+                return Err(KeychainError::from(errSecItemNotFound));
+            }
+            let mut error: CFErrorRef = ptr::null_mut();
+            let data = SecKeyCopyExternalRepresentation(public_key, &mut error);
+            CFRelease(public_key as CFTypeRef);
+            if data.is_null() {
+                return Err(KeychainError::from_cf_error(error));
+            }
+            let bytes = vec_from_cfdata(data);
+            CFRelease(data as CFTypeRef);
+            Ok(bytes?)
+        }
+    }
+
+    /// Sign `message` with this key using `algorithm`.
+    ///
+    /// This may prompt the user to authorize the use of the key.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainError` if the key does not support `algorithm`, the user
+    /// denied access, or signing fails otherwise.
+    pub fn sign(&self, algorithm: SignatureAlgorithm, message: &[u8]) -> Result<Vec<u8>> {
+        metrics::measure("sign", || unsafe {
+            let cf_message = create_cf_data_no_copy(message);
+            let mut error: CFErrorRef = ptr::null_mut();
+            let signature = SecKeyCreateSignature(
+                self.key,
+                algorithm.sec_key_algorithm(),
+                cf_message,
+                &mut error,
+            );
+            CFRelease(cf_message as CFTypeRef);
+            if signature.is_null() {
+                return Err(KeychainError::from_cf_error(error));
+            }
+            let bytes = vec_from_cfdata(signature);
+            CFRelease(signature as CFTypeRef);
+            Ok(bytes?)
+        })
+    }
+}
+
+impl Drop for PrivateKey {
+    fn drop(&mut self) {
+        unsafe { CFRelease(self.key as CFTypeRef) }
+    }
+}
+
+/// Copy all private keys from either the file-based or the data protection
+/// keychain.
+///
+/// # Safety
+///
+/// The caller must call `CFRelease` on the returned array.
+unsafe fn copy_private_keys(data_protection: bool) -> Result<CFArrayRef> {
+    let mut items = vec![
+        (kSecClass() as CFTypeRef, kSecClassKey() as CFTypeRef),
+        (
+            kSecAttrKeyClass() as CFTypeRef,
+            kSecAttrKeyClassPrivate() as CFTypeRef,
+        ),
+        (kSecMatchLimit() as CFTypeRef, kSecMatchLimitAll() as CFTypeRef),
+        (kSecReturnRef() as CFTypeRef, kCFBooleanTrue as CFTypeRef),
+        (
+            kSecReturnAttributes() as CFTypeRef,
+            kCFBooleanTrue as CFTypeRef,
+        ),
+    ];
+    if data_protection {
+        items.push((
+            kSecUseDataProtectionKeychain() as CFTypeRef,
+            kCFBooleanTrue as CFTypeRef,
+        ));
+    }
+    copy_matching(&items)
+        .and_then(|r| owned_with_type(r, "CFArray", CFArrayGetTypeID()))
+        .map(|r| r as CFArrayRef)
+}
+
+/// Find all private keys in the keychain, including Secure Enclave keys.
+///
+/// Secure Enclave keys live in the data protection keychain which requires
+/// a signed binary with a keychain access group entitlement; without it only
+/// keys from the file-based keychains are returned.
+///
+/// # Errors
+///
+/// Return `KeychainError` if keychain access fails.
+pub fn find_private_keys() -> Result<Vec<PrivateKey>> {
+    metrics::measure("find_private_keys", copy_all_private_keys)
+}
+
+/// Copy all private keys, see `find_private_keys`.
+fn copy_all_private_keys() -> Result<Vec<PrivateKey>> {
+    let mut keys = Vec::new();
+    for &data_protection in [false, true].iter() {
+        unsafe {
+            let result = match copy_private_keys(data_protection) {
+                Ok(result) => result,
+                Err(ref error) if error.status == KeychainErrorCode::ItemNotFound => continue,
+                Err(ref error)
+                    if data_protection && error.status == KeychainErrorCode::MissingEntitlement =>
+                {
+                    continue
+                }
+                Err(error) => return Err(error),
+            };
+            let found = (0..CFArrayGetCount(result))
+                .map(|i| {
+                    let attributes = cast_cf_dictionary(CFArrayGetValueAtIndex(result, i))?;
+                    PrivateKey::from_attributes(attributes)
+                })
+                .collect::<Result<Vec<_>>>();
+            CFRelease(result as CFTypeRef);
+            keys.extend(found?);
+        }
+    }
+    Ok(keys)
+}
@@ -0,0 +1,120 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Labels of new items without a label.
+//!
+//! Keychain Access shows items by label, and like most applications it sets
+//! the label of a new password to its service.  Items added by this crate
+//! without a label get a label by the default-label policy, so they don't show
+//! up as blank rows:
+//!
+//! ```
+//! use rust_ffi_demo::keychain::labels::{self, DefaultLabel};
+//!
+//! assert_eq!(labels::default_label(), DefaultLabel::Service);
+//! assert_eq!(DefaultLabel::Service.label("api.example.com", "jane").as_deref(), Some("api.example.com"));
+//! assert_eq!(
+//!     DefaultLabel::ServiceAndAccount.label("api.example.com", "jane").as_deref(),
+//!     Some("api.example.com (jane)")
+//! );
+//! assert_eq!(DefaultLabel::None.label("api.example.com", "jane"), None);
+//! ```
+//!
+//! Set the policy for the whole process with `set_default_label`.  Explicit
+//! labels always win, and updates never touch the label of existing items.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use super::{KeychainError, Result};
+
+/// How to label new items without a label.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultLabel {
+    /// Use the service, like Keychain Access.
+    Service,
+    /// Use the service with the account in parentheses.
+    ServiceAndAccount,
+    /// Leave the label empty.
+    None,
+}
+
+impl DefaultLabel {
+    /// All policies, in the order of their discriminants.
+    const ALL: [DefaultLabel; 3] = [
+        DefaultLabel::Service,
+        DefaultLabel::ServiceAndAccount,
+        DefaultLabel::None,
+    ];
+
+    /// The name of this policy, as parsed by `from_str`.
+    pub fn name(self) -> &'static str {
+        match self {
+            DefaultLabel::Service => "service",
+            DefaultLabel::ServiceAndAccount => "service-and-account",
+            DefaultLabel::None => "none",
+        }
+    }
+
+    /// The label of a new item of `service` and `account` under this policy.
+    pub fn label(self, service: &str, account: &str) -> Option<String> {
+        match self {
+            DefaultLabel::Service => Some(service.to_string()),
+            DefaultLabel::ServiceAndAccount => Some(format!("{} ({})", service, account)),
+            DefaultLabel::None => None,
+        }
+    }
+}
+
+impl fmt::Display for DefaultLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for DefaultLabel {
+    type Err = KeychainError;
+
+    fn from_str(s: &str) -> Result<DefaultLabel> {
+        DefaultLabel::ALL
+            .iter()
+            .copied()
+            .find(|policy| policy.name() == s)
+            .ok_or_else(|| KeychainError::invalid_data(format!("Unknown default label: {}", s)))
+    }
+}
+
+/// The current policy, as index into `DefaultLabel::ALL`.
+static DEFAULT_LABEL: AtomicU8 = AtomicU8::new(0);
+
+/// Label new items without a label by `policy` for the rest of the process.
+pub fn set_default_label(policy: DefaultLabel) {
+    DEFAULT_LABEL.store(policy as u8, Ordering::SeqCst);
+}
+
+/// The current policy for new items without a label.
+pub fn default_label() -> DefaultLabel {
+    DefaultLabel::ALL[usize::from(DEFAULT_LABEL.load(Ordering::SeqCst))]
+}
+
+/// The label of a new item of `service` and `account` with `label`.
+///
+/// Return `label` if given, and the label by the current policy otherwise.
+pub(super) fn for_new_item(label: Option<&str>, service: &str, account: &str) -> Option<String> {
+    match label {
+        Some(label) => Some(label.to_string()),
+        None => default_label().label(service, account),
+    }
+}
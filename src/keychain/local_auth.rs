@@ -0,0 +1,264 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! User authentication with LocalAuthentication, eg, Touch ID.
+//!
+//! LocalAuthentication is an Objective-C framework, so bindgen doesn’t help
+//! here; we talk to `LAContext` through the Objective-C runtime instead, and
+//! pass a hand-made block for the reply.
+
+use std::ffi::CString;
+use std::mem;
+use std::os::raw::{c_char, c_int, c_ulong, c_void};
+use std::ptr;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+
+use super::cfutil::*;
+use super::guard::ffi_guard;
+use super::native::*;
+use super::{correlation, KeychainError, KeychainErrorCode, Result};
+
+/// An Objective-C object.
+type Id = *mut c_void;
+/// An Objective-C selector.
+type Sel = *const c_void;
+
+/// An Objective-C boolean.
+#[cfg(target_arch = "aarch64")]
+type Bool = bool;
+/// An Objective-C boolean.
+#[cfg(not(target_arch = "aarch64"))]
+type Bool = i8;
+
+extern "C" {
+    fn objc_getClass(name: *const c_char) -> Id;
+    fn sel_registerName(name: *const c_char) -> Sel;
+    fn objc_msgSend();
+    static _NSConcreteStackBlock: *const c_void;
+}
+
+/// Send a message without arguments to `receiver`.
+unsafe fn send(receiver: Id, selector: &str) -> Id {
+    let send: unsafe extern "C" fn(Id, Sel) -> Id = mem::transmute(objc_msgSend as *const ());
+    send(receiver, selector_named(selector))
+}
+
+/// Get the selector with the given `name`.
+unsafe fn selector_named(name: &str) -> Sel {
+    let name = CString::new(name).unwrap();
+    sel_registerName(name.as_ptr())
+}
+
+/// The authentication policy.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    /// Authenticate with biometrics, ie, Touch ID, only.
+    Biometrics,
+    /// Authenticate with biometrics, a watch, or the account password.
+    DeviceOwner,
+}
+
+impl Policy {
+    /// The `LAPolicy` value of this policy.
+    fn la_policy(self) -> isize {
+        match self {
+            Policy::Biometrics => 1,
+            Policy::DeviceOwner => 2,
+        }
+    }
+}
+
+/// The state shared between `AuthContext::evaluate` and the reply block.
+struct Reply {
+    result: Mutex<Option<std::result::Result<(), isize>>>,
+    done: Condvar,
+}
+
+#[repr(C)]
+struct BlockDescriptor {
+    reserved: c_ulong,
+    size: c_ulong,
+}
+
+/// The reply block for `evaluatePolicy:localizedReason:reply:`.
+///
+/// A stack block without copy and dispose helpers; copying it just copies the
+/// pointer to the `Reply`.  The block owns a reference to the `Reply`, which
+/// `invoke_reply` releases, so the `Reply` outlives the reply even if
+/// `evaluate` returns as soon as it sees the result.
+#[repr(C)]
+struct ReplyBlock {
+    isa: *const c_void,
+    flags: c_int,
+    reserved: c_int,
+    invoke: unsafe extern "C" fn(*mut ReplyBlock, Bool, Id),
+    descriptor: *const BlockDescriptor,
+    reply: *const Reply,
+}
+
+static REPLY_BLOCK_DESCRIPTOR: BlockDescriptor = BlockDescriptor {
+    reserved: 0,
+    size: mem::size_of::<ReplyBlock>() as c_ulong,
+};
+
+unsafe extern "C" fn invoke_reply(block: *mut ReplyBlock, success: Bool, error: Id) {
+    // The evaluation replies exactly once; take over the reference of the
+    // block, so the `Reply` lives until after notifying the waiter
+    let reply = Arc::from_raw((*block).reply);
+    // Reply with an unknown error if reading the error panics, lest the
+    // waiting thread waits forever
+    let result = ffi_guard("invoke_reply", Err(0), || {
+        if success as u8 != 0 {
+            Ok(())
+        } else if error.is_null() {
+            Err(0)
+        } else {
+            // NSError is toll-free bridged to CFError
+            Err(CFErrorGetCode(error as CFErrorRef) as isize)
+        }
+    });
+    // Deliver the reply even if the lock is poisoned, for the same reason
+    *reply.result.lock().unwrap_or_else(PoisonError::into_inner) = Some(result);
+    reply.done.notify_all();
+}
+
+/// A LocalAuthentication context.
+///
+/// A context remembers a successful authentication, so evaluating a policy
+/// again on the same context does not prompt again for a while.
+#[derive(Debug)]
+pub struct AuthContext {
+    context: Id,
+}
+
+// `LAContext` is thread-safe.
+unsafe impl Send for AuthContext {}
+unsafe impl Sync for AuthContext {}
+
+impl AuthContext {
+    /// Create a new context.
+    pub fn new() -> AuthContext {
+        unsafe {
+            let class = objc_getClass(b"LAContext\0".as_ptr() as *const c_char);
+            assert!(!class.is_null(), "LocalAuthentication not available");
+            let context = send(send(class, "alloc"), "init");
+            assert!(!context.is_null());
+            AuthContext { context }
+        }
+    }
+
+    /// Whether `policy` can be evaluated, eg, whether Touch ID is available.
+    pub fn can_evaluate(&self, policy: Policy) -> bool {
+        unsafe {
+            let can_evaluate: unsafe extern "C" fn(Id, Sel, isize, *mut Id) -> Bool =
+                mem::transmute(objc_msgSend as *const ());
+            let result = can_evaluate(
+                self.context,
+                selector_named("canEvaluatePolicy:error:"),
+                policy.la_policy(),
+                ptr::null_mut(),
+            );
+            result as u8 != 0
+        }
+    }
+
+    /// Authenticate the user with `policy`, telling them `reason`.
+    ///
+    /// Block until the user authenticated or cancelled.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::AuthFailed` if the user did not authenticate.
+    pub fn evaluate(&self, policy: Policy, reason: &str) -> Result<()> {
+        let reply = Arc::new(Reply {
+            result: Mutex::new(None),
+            done: Condvar::new(),
+        });
+        let mut block = ReplyBlock {
+            isa: unsafe { &_NSConcreteStackBlock as *const _ as *const c_void },
+            flags: 0,
+            reserved: 0,
+            invoke: invoke_reply,
+            descriptor: &REPLY_BLOCK_DESCRIPTOR,
+            reply: Arc::into_raw(reply.clone()),
+        };
+        unsafe {
+            // The context may hold on to the reason beyond this call
+            let cf_reason = create_cf_string(reason);
+            let evaluate: unsafe extern "C" fn(Id, Sel, isize, Id, *mut ReplyBlock) =
+                mem::transmute(objc_msgSend as *const ());
+            evaluate(
+                self.context,
+                selector_named("evaluatePolicy:localizedReason:reply:"),
+                policy.la_policy(),
+                cf_reason as Id,
+                &mut block,
+            );
+            CFRelease(cf_reason as CFTypeRef);
+        }
+
+        let mut result = reply.result.lock().unwrap();
+        while result.is_none() {
+            result = reply.done.wait(result).unwrap();
+        }
+        match result.take().unwrap() {
+            Ok(()) => Ok(()),
+            Err(code) => Err(KeychainError {
+                status: KeychainErrorCode::AuthFailed,
+                message: format!("User authentication failed (LAError {})", code),
+                correlation_id: correlation::current(),
+            }),
+        }
+    }
+
+    /// The `LAContext` object, eg, for `kSecUseAuthenticationContext`.
+    ///
+    /// The object is only valid as long as this context.
+    #[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+    pub(super) fn as_ptr(&self) -> *const c_void {
+        self.context
+    }
+
+    /// Invalidate this context.
+    ///
+    /// Pending evaluations are cancelled, and all further evaluations fail.
+    pub fn invalidate(&self) {
+        unsafe {
+            send(self.context, "invalidate");
+        }
+    }
+}
+
+impl Default for AuthContext {
+    fn default() -> AuthContext {
+        AuthContext::new()
+    }
+}
+
+impl Drop for AuthContext {
+    fn drop(&mut self) {
+        unsafe {
+            send(self.context, "release");
+        }
+    }
+}
+
+/// Authenticate the user with `policy` on a fresh context.
+///
+/// # Errors
+///
+/// See `AuthContext::evaluate`.
+pub fn authenticate(policy: Policy, reason: &str) -> Result<()> {
+    AuthContext::new().evaluate(policy, reason)
+}
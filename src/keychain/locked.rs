@@ -0,0 +1,172 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Secret buffers in locked memory.
+//!
+//! A `LockedBytes` lives in whole pages of its own which are locked into RAM
+//! with `mlock`, so the kernel never swaps them out, and zeroed before they
+//! are freed.  macOS has no `MADV_DONTDUMP`, so the pages still end up in core
+//! dumps; disable core dumps of processes which hold secrets.
+//!
+//! Secrets pass through the memory of Security.framework before they reach a
+//! `LockedBytes`; this module only protects the copy of this crate.
+
+use std::alloc::{self, Layout};
+use std::fmt;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::os::raw::{c_int, c_void};
+use std::ptr::{self, NonNull};
+use std::slice;
+
+use super::cfutil::*;
+use super::items;
+use super::keychains::Keychain;
+use super::native::*;
+use super::{metrics, status_to_result, KeychainError, Result};
+
+extern "C" {
+    fn getpagesize() -> c_int;
+    fn mlock(address: *const c_void, length: usize) -> c_int;
+    fn munlock(address: *const c_void, length: usize) -> c_int;
+}
+
+/// Bytes in locked, non-swappable memory.
+pub struct LockedBytes {
+    pointer: NonNull<u8>,
+    length: usize,
+    layout: Layout,
+}
+
+// We own the pages exclusively.
+unsafe impl Send for LockedBytes {}
+unsafe impl Sync for LockedBytes {}
+
+impl LockedBytes {
+    /// Allocate `length` zero bytes in locked memory.
+    ///
+    /// # Errors
+    ///
+    /// Return an IO error if the memory cannot be locked, eg, because the
+    /// process exceeds its limit of locked memory.
+    pub fn zeroed(length: usize) -> io::Result<LockedBytes> {
+        let page_size = unsafe { getpagesize() } as usize;
+        // Round up to whole pages, so that no other data shares our pages
+        let size = length.max(1).div_ceil(page_size) * page_size;
+        let layout = Layout::from_size_align(size, page_size)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+        unsafe {
+            let pointer = match NonNull::new(alloc::alloc_zeroed(layout)) {
+                Some(pointer) => pointer,
+                None => alloc::handle_alloc_error(layout),
+            };
+            if mlock(pointer.as_ptr() as *const c_void, size) != 0 {
+                let error = io::Error::last_os_error();
+                alloc::dealloc(pointer.as_ptr(), layout);
+                return Err(error);
+            }
+            Ok(LockedBytes {
+                pointer,
+                length,
+                layout,
+            })
+        }
+    }
+
+    /// Copy `bytes` into locked memory.
+    ///
+    /// # Errors
+    ///
+    /// See `LockedBytes::zeroed`.
+    pub fn from_slice(bytes: &[u8]) -> io::Result<LockedBytes> {
+        let mut locked = LockedBytes::zeroed(bytes.len())?;
+        locked.copy_from_slice(bytes);
+        Ok(locked)
+    }
+}
+
+impl Deref for LockedBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.pointer.as_ptr(), self.length) }
+    }
+}
+
+impl DerefMut for LockedBytes {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.pointer.as_ptr(), self.length) }
+    }
+}
+
+impl fmt::Debug for LockedBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LockedBytes({} bytes)", self.length)
+    }
+}
+
+impl Drop for LockedBytes {
+    fn drop(&mut self) {
+        unsafe {
+            let base = self.pointer.as_ptr();
+            // Volatile writes, so that the compiler doesn't elide them
+            for offset in 0..self.layout.size() {
+                ptr::write_volatile(base.add(offset), 0);
+            }
+            munlock(base as *const c_void, self.layout.size());
+            alloc::dealloc(base, self.layout);
+        }
+    }
+}
+
+/// Find the data of the generic password of `service` and `account` into
+/// locked memory.
+///
+/// Like `items::find_data`, but copy the data right into a `LockedBytes`.
+///
+/// # Errors
+///
+/// Return `KeychainError` if the item does not exist, keychain access fails
+/// otherwise, or if the memory cannot be locked.
+pub fn find_data(keychain: Option<&Keychain>, service: &str, account: &str) -> Result<LockedBytes> {
+    metrics::measure("find_data", || unsafe {
+        let c = constants();
+        let extra = [
+            (c.match_limit, c.match_limit_one),
+            (c.return_data, c.boolean_true),
+        ];
+        let mut result: CFTypeRef = ptr::null();
+        let status = items::with_item_query(keychain, service, account, &extra, |query| {
+            SecItemCopyMatching(query, &mut result)
+        });
+        status_to_result(status)?;
+        assert!(!result.is_null());
+        let locked = cast_cf_data(result).map(|data| {
+            let length = CFDataGetLength(data) as usize;
+            // Empty data need not have any bytes to point to
+            if length == 0 {
+                return Ok(LockedBytes::zeroed(0)?);
+            }
+            let bytes = CFDataGetBytePtr(data);
+            if bytes.is_null() {
+                return Err(KeychainError::invalid_data("Item data has no bytes"));
+            }
+            Ok(LockedBytes::from_slice(slice::from_raw_parts(
+                bytes, length,
+            ))?)
+        });
+        CFRelease(result);
+        locked?
+    })
+}
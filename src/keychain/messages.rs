@@ -0,0 +1,230 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Error messages of `OSStatus` codes.
+//!
+//! Asking the system for the message of a code allocates a CFString every
+//! time, which adds up in workloads that expect many errors, eg, scanning for
+//! mostly absent items.  Messages are cached after the first lookup.  A table
+//! has the messages of common codes, in case the system has no message.
+//!
+//! These messages are English, so that logs read the same everywhere.
+//! Security.framework has translations of its messages in its bundle, which
+//! `localized_message` looks up for a given locale.
+//!
+//! The symbolic names of codes, like `errSecMissingEntitlement`, come from a
+//! table which the build script generates from the constants in the headers.
+
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::ptr;
+use std::sync::Mutex;
+
+use super::cfutil::*;
+use super::native::*;
+
+/// How many messages to cache at most.
+///
+/// Programs only ever see a handful of codes, so this bound is merely a
+/// safeguard against unbounded growth.
+const CACHE_CAPACITY: usize = 256;
+
+/// Messages of codes, by code.
+static CACHE: Mutex<BTreeMap<OSStatus, String>> = Mutex::new(BTreeMap::new());
+
+/// Messages of common codes, as the system has them, for when the system
+/// has no message.
+const COMMON_MESSAGES: [(OSStatus, &str); 8] = [
+    (errSecSuccess, "No error."),
+    (
+        errSecItemNotFound,
+        "The specified item could not be found in the keychain.",
+    ),
+    (
+        errSecDuplicateItem,
+        "The specified item already exists in the keychain.",
+    ),
+    (
+        errSecAuthFailed,
+        "The user name or passphrase you entered is not correct.",
+    ),
+    (errSecUserCanceled, "User canceled the operation."),
+    (
+        errSecInteractionNotAllowed,
+        "User interaction is not allowed.",
+    ),
+    (
+        errSecInvalidOwnerEdit,
+        "Invalid attempt to change the owner of this item.",
+    ),
+    (
+        errSecMissingEntitlement,
+        "A required entitlement isn't present.",
+    ),
+];
+
+include!(concat!(env!("OUT_DIR"), "/status_names.rs"));
+
+/// Get the symbolic name of `status`, if the headers define one.
+pub(super) fn status_name(status: OSStatus) -> Option<&'static str> {
+    STATUS_NAMES
+        .binary_search_by_key(&status, |&(code, _)| code)
+        .ok()
+        .map(|index| STATUS_NAMES[index].1)
+}
+
+/// The bundle identifier of Security.framework.
+const SECURITY_BUNDLE: &str = "com.apple.security";
+
+/// The strings table of Security.framework with error messages by code.
+const MESSAGE_TABLE: &str = "SecErrorMessages";
+
+/// Find the message table of Security.framework for `localization`.
+unsafe fn message_table_path(localization: &str) -> Option<PathBuf> {
+    let cf_identifier = create_cf_string_no_copy(SECURITY_BUNDLE);
+    let bundle = CFBundleGetBundleWithIdentifier(cf_identifier);
+    CFRelease(cf_identifier as CFTypeRef);
+    if bundle.is_null() {
+        return None;
+    }
+    let cf_name = create_cf_string_no_copy(MESSAGE_TABLE);
+    let cf_type = create_cf_string_no_copy("strings");
+    let cf_localization = create_cf_string_no_copy(localization);
+    let url = CFBundleCopyResourceURLForLocalization(
+        bundle,
+        cf_name,
+        cf_type,
+        ptr::null(),
+        cf_localization,
+    );
+    CFRelease(cf_localization as CFTypeRef);
+    CFRelease(cf_type as CFTypeRef);
+    CFRelease(cf_name as CFTypeRef);
+    if url.is_null() {
+        return None;
+    }
+    let mut buffer = [0u8; 1024];
+    let found = CFURLGetFileSystemRepresentation(
+        url,
+        1,
+        buffer.as_mut_ptr(),
+        buffer.len() as CFIndex,
+    );
+    CFRelease(url as CFTypeRef);
+    if found == 0 {
+        return None;
+    }
+    let length = buffer.iter().position(|&b| b == 0)?;
+    Some(PathBuf::from(OsStr::from_bytes(&buffer[..length])))
+}
+
+/// Look up the message of `status` in the message table for `localization`.
+fn table_message(status: OSStatus, localization: &str) -> Option<String> {
+    let path = unsafe { message_table_path(localization) }?;
+    let contents = fs::read(path).ok()?;
+    unsafe {
+        let data = create_cf_data_no_copy(&contents);
+        let table = CFPropertyListCreateWithData(
+            ptr::null_mut(),
+            data,
+            kCFPropertyListImmutable as CFOptionFlags,
+            ptr::null_mut(),
+            ptr::null_mut(),
+        );
+        CFRelease(data as CFTypeRef);
+        if table.is_null() {
+            return None;
+        }
+        let message = if CFGetTypeID(table) == CFDictionaryGetTypeID() {
+            let cf_key = create_cf_string(&status.to_string());
+            let message = string_value(table as CFDictionaryRef, cf_key);
+            CFRelease(cf_key as CFTypeRef);
+            message
+        } else {
+            None
+        };
+        CFRelease(table);
+        message
+    }
+}
+
+/// The localizations to try for `locale`, most specific first.
+///
+/// Bundles name localizations like `pt_BR`, and fall back to the language,
+/// eg, `pt`.
+fn localizations(locale: &str) -> Vec<String> {
+    let locale = locale.replace('-', "_");
+    let mut localizations = vec![locale.clone()];
+    if let Some((language, _)) = locale.split_once('_') {
+        localizations.push(language.to_string());
+    }
+    localizations
+}
+
+/// Get the message of `status` in `locale`, eg, `de` or `pt-BR`.
+///
+/// Return `None` if Security.framework has no message of `status` for
+/// `locale`.
+pub(super) fn localized_message(status: OSStatus, locale: &str) -> Option<String> {
+    localizations(locale)
+        .iter()
+        .find_map(|localization| table_message(status, localization))
+}
+
+/// Ask the system for the message of `status`.
+///
+/// The system answers in the language of the user.  Return `None` if the
+/// system has no message.
+fn copy_message(status: OSStatus) -> Option<String> {
+    unsafe {
+        let cf_message = SecCopyErrorMessageString(status, ptr::null_mut());
+        if cf_message.is_null() {
+            return None;
+        }
+        let message = string_from_cf_string(cf_message);
+        CFRelease(cf_message as CFTypeRef);
+        message.ok()
+    }
+}
+
+/// Get the message of `status` from `COMMON_MESSAGES`.
+fn common_message(status: OSStatus) -> Option<String> {
+    COMMON_MESSAGES
+        .iter()
+        .find(|entry| entry.0 == status)
+        .map(|&(_, message)| message.to_string())
+}
+
+/// Get the English message of `status`.
+///
+/// Fall back to the message of the system if Security.framework has no
+/// English message of `status`, and to `COMMON_MESSAGES` if the system has
+/// no message either.
+pub(super) fn message(status: OSStatus) -> String {
+    let mut cache = CACHE.lock().unwrap();
+    if let Some(message) = cache.get(&status) {
+        return message.clone();
+    }
+    let message = table_message(status, "en")
+        .or_else(|| copy_message(status))
+        .or_else(|| common_message(status))
+        .unwrap_or_else(|| format!("OSStatus {}", status));
+    if cache.len() < CACHE_CAPACITY {
+        cache.insert(status, message.clone());
+    }
+    message
+}
@@ -0,0 +1,614 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Versioned metadata in the generic attribute of items.
+//!
+//! Structured metadata of items, like the parameters of a TOTP, goes into the
+//! generic attribute of generic passwords as `key=value` lines, led by a
+//! `schema=N` line with the version of its schema:
+//!
+//! ```text
+//! schema=1
+//! digits=6
+//! period=30
+//! ```
+//!
+//! Versions of the schema so far:
+//!
+//! - 0: `key=value` lines without a schema line, as written by the first
+//!   TOTP support.
+//! - 1: a leading `schema=1` line.
+//!
+//! Besides fields of their own, like the TOTP parameters, all items share a
+//! few well-known fields with typed accessors: comma-separated `tags`, the
+//! `expires` time in seconds since the epoch, and the `origin` of the secret,
+//! eg, the URL or tool it came from:
+//!
+//! ```
+//! use rust_ffi_demo::keychain::metadata::Metadata;
+//!
+//! let mut metadata = Metadata::new();
+//! metadata.add_tag("work").unwrap();
+//! metadata.add_tag("ci").unwrap();
+//! metadata.set_origin("https://ci.example.com").unwrap();
+//! metadata.set("owner", "jane").unwrap();
+//! assert_eq!(metadata.tags(), vec!["work", "ci"]);
+//! assert_eq!(
+//!     metadata.to_bytes(),
+//!     b"schema=1\ntags=work,ci\norigin=https://ci.example.com\nowner=jane\n"
+//! );
+//! ```
+//!
+//! `Item::metadata` reads the metadata of an item, and `set_metadata` writes
+//! it without touching the secret.
+//!
+//! Migrations upgrade metadata of older versions one version at a time.  This
+//! crate upgrades the metadata of an item lazily whenever it reads it, and
+//! `migrate_all` upgrades all items of a keychain at once.  Metadata of a
+//! newer version than `SCHEMA_VERSION` is an error, since this crate cannot
+//! know what changed.
+
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::cfutil::*;
+use super::items::{self, Item};
+use super::keychains::Keychain;
+use super::native::*;
+use super::read_only;
+use super::totp::TOTP_DESCRIPTION;
+use super::{metrics, status_to_result, KeychainError, Result};
+
+/// The current version of the metadata schema.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The key of the schema version.
+const SCHEMA_KEY: &str = "schema";
+
+/// The key of the tags of an item.
+const TAGS_KEY: &str = "tags";
+
+/// The key of the expiry of an item.
+const EXPIRES_KEY: &str = "expires";
+
+/// The key of the origin of an item.
+const ORIGIN_KEY: &str = "origin";
+
+/// A migration of metadata fields from one version to the next.
+type Migration = fn(&mut Vec<(String, String)>);
+
+/// The migrations, by the version they upgrade from.
+const MIGRATIONS: [Migration; SCHEMA_VERSION as usize] = [v0_to_v1];
+
+/// Version 1 only adds the schema line, which `Metadata::to_bytes` writes.
+fn v0_to_v1(_fields: &mut Vec<(String, String)>) {}
+
+/// Metadata of an item.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+    version: u32,
+    fields: Vec<(String, String)>,
+}
+
+impl Default for Metadata {
+    /// Empty metadata of the current version.
+    fn default() -> Metadata {
+        Metadata {
+            version: SCHEMA_VERSION,
+            fields: Vec::new(),
+        }
+    }
+}
+
+impl Metadata {
+    /// Empty metadata of the current version.
+    pub fn new() -> Metadata {
+        Metadata::default()
+    }
+
+    /// Parse metadata from the generic attribute of an item.
+    ///
+    /// Skip empty lines.
+    ///
+    /// ```
+    /// use rust_ffi_demo::keychain::metadata::Metadata;
+    ///
+    /// let metadata = Metadata::parse(b"schema=1\ndigits=8\n").unwrap();
+    /// assert!(metadata.is_current());
+    /// assert_eq!(metadata.get("digits"), Some("8"));
+    ///
+    /// // Metadata of a future version
+    /// assert!(Metadata::parse(b"schema=99\ndigits=8\n").is_err());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::InvalidData` if `bytes` is not UTF-8, has a
+    /// line without `=`, or its version is invalid or newer than
+    /// `SCHEMA_VERSION`.
+    pub fn parse(bytes: &[u8]) -> Result<Metadata> {
+        let text = std::str::from_utf8(bytes)
+            .map_err(|_| KeychainError::invalid_data("Metadata is not UTF-8"))?;
+        let mut version = 0;
+        let mut fields = Vec::new();
+        for line in text.lines().filter(|line| !line.is_empty()) {
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("");
+            let value = parts.next().ok_or_else(|| {
+                KeychainError::invalid_data(format!("Invalid metadata: {}", line))
+            })?;
+            if key == SCHEMA_KEY {
+                version = value.parse().map_err(|_| {
+                    KeychainError::invalid_data(format!("Invalid metadata schema: {}", value))
+                })?;
+            } else {
+                fields.push((key.to_string(), value.to_string()));
+            }
+        }
+        if SCHEMA_VERSION < version {
+            return Err(KeychainError::invalid_data(format!(
+                "Metadata schema {} is newer than supported schema {}",
+                version, SCHEMA_VERSION
+            )));
+        }
+        Ok(Metadata { version, fields })
+    }
+
+    /// The schema version of this metadata.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Whether this metadata has the current schema version.
+    pub fn is_current(&self) -> bool {
+        self.version == SCHEMA_VERSION
+    }
+
+    /// Get the value of `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Set `key` to `value`.
+    ///
+    /// Keys must be non-empty and free of `=` and line breaks, and values
+    /// free of line breaks, lest they corrupt the `key=value` lines.  The
+    /// `schema` key is reserved for the schema version.
+    ///
+    /// ```
+    /// use rust_ffi_demo::keychain::metadata::Metadata;
+    ///
+    /// let mut metadata = Metadata::new();
+    /// metadata.set("url", "https://example.com/?a=b").unwrap();
+    /// assert_eq!(metadata.get("url"), Some("https://example.com/?a=b"));
+    ///
+    /// assert!(metadata.set("", "jane").is_err());
+    /// assert!(metadata.set("owner=root", "jane").is_err());
+    /// assert!(metadata.set("owner\nschema", "jane").is_err());
+    /// assert!(metadata.set("owner", "jane\nschema=99").is_err());
+    /// assert!(metadata.set("owner", "jane\rschema=99").is_err());
+    /// assert!(metadata.set("schema", "99").is_err());
+    /// assert_eq!(metadata.to_bytes(), b"schema=1\nurl=https://example.com/?a=b\n");
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::InvalidData` if `key` or `value` is invalid,
+    /// or `key` is `schema`.
+    pub fn set<S: Into<String>>(&mut self, key: &str, value: S) -> Result<()> {
+        let value = value.into();
+        if key.is_empty() || key.contains(['=', '\n', '\r']) {
+            return Err(KeychainError::invalid_data(format!(
+                "Invalid metadata key: {:?}",
+                key
+            )));
+        }
+        if key == SCHEMA_KEY {
+            return Err(KeychainError::invalid_data(
+                "Metadata key schema is reserved",
+            ));
+        }
+        if value.contains(['\n', '\r']) {
+            return Err(KeychainError::invalid_data(format!(
+                "Metadata value of {} has a line break",
+                key
+            )));
+        }
+        self.insert(key, value);
+        Ok(())
+    }
+
+    /// Set `key` to `value`, which are known to be valid.
+    fn insert(&mut self, key: &str, value: String) {
+        match self.fields.iter_mut().find(|(k, _)| k == key) {
+            Some(field) => field.1 = value,
+            None => self.fields.push((key.to_string(), value)),
+        }
+    }
+
+    /// Remove `key`, and return its value.
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let position = self.fields.iter().position(|(k, _)| k == key)?;
+        Some(self.fields.remove(position).1)
+    }
+
+    /// All fields, in order.
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// The tags of the item, in order.
+    pub fn tags(&self) -> Vec<&str> {
+        self.get(TAGS_KEY)
+            .map(|tags| tags.split(',').filter(|tag| !tag.is_empty()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Whether the item has `tag`.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags().contains(&tag)
+    }
+
+    /// Add `tag` to the tags of the item.
+    ///
+    /// Return whether the item didn't have `tag` yet.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::InvalidData` if `tag` is invalid; see
+    /// `check_tag`.
+    pub fn add_tag(&mut self, tag: &str) -> Result<bool> {
+        check_tag(tag)?;
+        if self.has_tag(tag) {
+            return Ok(false);
+        }
+        let mut tags = self.tags();
+        tags.push(tag);
+        let tags = tags.join(",");
+        self.insert(TAGS_KEY, tags);
+        Ok(true)
+    }
+
+    /// Remove `tag` from the tags of the item.
+    ///
+    /// Return whether the item had `tag`.  Remove the field altogether with
+    /// the last tag.
+    pub fn remove_tag(&mut self, tag: &str) -> bool {
+        if !self.has_tag(tag) {
+            return false;
+        }
+        let tags = self.tags();
+        let tags: Vec<&str> = tags.into_iter().filter(|&t| t != tag).collect();
+        if tags.is_empty() {
+            self.remove(TAGS_KEY);
+        } else {
+            let tags = tags.join(",");
+            self.insert(TAGS_KEY, tags);
+        }
+        true
+    }
+
+    /// When the item expires.
+    ///
+    /// `None` if it doesn't expire, or its expiry is invalid.
+    pub fn expires(&self) -> Option<SystemTime> {
+        self.get(EXPIRES_KEY)
+            .and_then(|seconds| seconds.parse().ok())
+            .map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds))
+    }
+
+    /// Set when the item expires, or remove its expiry with `None`.
+    ///
+    /// Store whole seconds since the epoch; times before the epoch count as
+    /// the epoch.
+    pub fn set_expires(&mut self, expires: Option<SystemTime>) {
+        match expires {
+            Some(expires) => {
+                let seconds = expires
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |since| since.as_secs());
+                self.insert(EXPIRES_KEY, seconds.to_string());
+            }
+            None => {
+                self.remove(EXPIRES_KEY);
+            }
+        }
+    }
+
+    /// Whether the item has expired at `now`.
+    pub fn is_expired_at(&self, now: SystemTime) -> bool {
+        self.expires().is_some_and(|expires| expires <= now)
+    }
+
+    /// Where the secret of the item came from.
+    pub fn origin(&self) -> Option<&str> {
+        self.get(ORIGIN_KEY)
+    }
+
+    /// Set where the secret of the item came from.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::InvalidData` if `origin` has a line break.
+    pub fn set_origin(&mut self, origin: &str) -> Result<()> {
+        if origin.contains(['\n', '\r']) {
+            return Err(KeychainError::invalid_data("Origin has a line break"));
+        }
+        self.insert(ORIGIN_KEY, origin.to_string());
+        Ok(())
+    }
+
+    /// Upgrade this metadata to the current schema version.
+    ///
+    /// Return whether this metadata was outdated.
+    ///
+    /// ```
+    /// use rust_ffi_demo::keychain::metadata::Metadata;
+    ///
+    /// // Version 0 to 1
+    /// let mut metadata = Metadata::parse(b"digits=6\nperiod=30\n").unwrap();
+    /// assert_eq!(metadata.version(), 0);
+    /// assert!(metadata.migrate());
+    /// assert_eq!(metadata.version(), 1);
+    /// assert_eq!(metadata.get("digits"), Some("6"));
+    /// assert_eq!(metadata.to_bytes(), b"schema=1\ndigits=6\nperiod=30\n");
+    ///
+    /// // Current metadata stays as it is
+    /// assert!(!metadata.migrate());
+    /// ```
+    pub fn migrate(&mut self) -> bool {
+        let outdated = !self.is_current();
+        while !self.is_current() {
+            MIGRATIONS[self.version as usize](&mut self.fields);
+            self.version += 1;
+        }
+        outdated
+    }
+
+    /// Serialize this metadata for the generic attribute of an item.
+    ///
+    /// Metadata of version 0 has no schema line.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut text = String::new();
+        if 0 < self.version {
+            text.push_str(&format!("{}={}\n", SCHEMA_KEY, self.version));
+        }
+        for (key, value) in &self.fields {
+            text.push_str(&format!("{}={}\n", key, value));
+        }
+        text.into_bytes()
+    }
+}
+
+/// Check that `tag` is a valid tag.
+///
+/// Tags are separated by commas, so they must not contain commas, nor
+/// whitespace or `=`:
+///
+/// ```
+/// use rust_ffi_demo::keychain::metadata::check_tag;
+///
+/// assert!(check_tag("work").is_ok());
+/// assert!(check_tag("work,ci").is_err());
+/// assert!(check_tag("").is_err());
+/// ```
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::InvalidData` if `tag` is invalid.
+pub fn check_tag(tag: &str) -> Result<()> {
+    if tag.is_empty() {
+        Err(KeychainError::invalid_data("Empty tag"))
+    } else if tag.contains(|c: char| c == ',' || c == '=' || c.is_whitespace()) {
+        Err(KeychainError::invalid_data(format!(
+            "Invalid tag {}: no commas, whitespace or = allowed",
+            tag
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+impl Item {
+    /// The metadata of this item.
+    ///
+    /// Return empty metadata if the item has none yet.  Unlike reading
+    /// TOTP items or attachments this does not upgrade outdated metadata in
+    /// the keychain.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::InvalidData` if the generic attribute holds
+    /// data of another application, or invalid metadata; see
+    /// `Metadata::parse`.
+    pub fn metadata(&self) -> Result<Metadata> {
+        match self.generic {
+            None => Ok(Metadata::new()),
+            Some(ref generic) if generic.is_empty() => Ok(Metadata::new()),
+            Some(ref generic) if has_metadata(self) => {
+                let mut metadata = Metadata::parse(generic)?;
+                metadata.migrate();
+                Ok(metadata)
+            }
+            Some(_) => Err(KeychainError::invalid_data(format!(
+                "Generic attribute of {} {} holds data of another application",
+                self.service, self.account
+            ))),
+        }
+    }
+}
+
+/// Whether the generic attribute of `item` holds metadata of this crate.
+///
+/// Other applications store arbitrary data in the generic attribute, so only
+/// items with a schema line, or legacy TOTP items, count.
+fn has_metadata(item: &Item) -> bool {
+    match item.generic {
+        Some(ref generic) => {
+            is_versioned(generic) || item.description.as_deref() == Some(TOTP_DESCRIPTION)
+        }
+        None => false,
+    }
+}
+
+/// Whether `generic` starts with a schema line.
+pub(super) fn is_versioned(generic: &[u8]) -> bool {
+    generic.starts_with(format!("{}=", SCHEMA_KEY).as_bytes())
+}
+
+/// Write `metadata` to the generic attribute of the generic password of
+/// `service` and `account`.
+///
+/// If `keychain` is given only change the item in this keychain.
+///
+/// # Errors
+///
+/// Return `KeychainError` if the item does not exist, or keychain access fails
+/// otherwise.
+pub fn write(
+    keychain: Option<&Keychain>,
+    service: &str,
+    account: &str,
+    metadata: &Metadata,
+) -> Result<()> {
+    read_only::check()?;
+    let bytes = metadata.to_bytes();
+    metrics::measure("write_metadata", || unsafe {
+        let cf_generic = create_cf_data_no_copy(&bytes) as CFTypeRef;
+        let attributes = create_dictionary(&[(kSecAttrGeneric() as CFTypeRef, cf_generic)]);
+        assert!(!attributes.is_null());
+        let status = items::with_item_query(keychain, service, account, &[], |query| {
+            SecItemUpdate(query, attributes)
+        });
+        CFRelease(attributes as CFTypeRef);
+        CFRelease(cf_generic);
+        status_to_result(status)
+    })
+}
+
+/// Write `metadata` to `item`, leaving its secret alone.
+///
+/// If `keychain` is given only change the item in this keychain.  Refuse to
+/// replace data of other applications in the generic attribute of `item`.
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::InvalidData` if the generic attribute of `item`
+/// holds data of another application, and `KeychainError` if the item does
+/// not exist, or keychain access fails otherwise.
+pub fn set_metadata(keychain: Option<&Keychain>, item: &Item, metadata: &Metadata) -> Result<()> {
+    item.metadata()?;
+    write(keychain, &item.service, &item.account, metadata)
+}
+
+/// Parse the `generic` attribute of the item of `service` and `account`, and
+/// upgrade the item if its metadata is outdated.
+///
+/// Ignore failures to write the upgraded metadata, so that reading works
+/// without write access; the next read tries again.
+///
+/// # Errors
+///
+/// See `Metadata::parse`.
+pub(super) fn read_and_upgrade(
+    keychain: Option<&Keychain>,
+    service: &str,
+    account: &str,
+    generic: &[u8],
+) -> Result<Metadata> {
+    let mut metadata = Metadata::parse(generic)?;
+    if metadata.migrate() {
+        let _ = write(keychain, service, account, &metadata);
+    }
+    Ok(metadata)
+}
+
+/// What `migrate_all` did to the metadata of an item.
+#[derive(Debug)]
+pub enum Migrated {
+    /// The metadata was upgraded from this version.
+    Upgraded(u32),
+    /// The metadata has this outdated version; a dry run left it alone.
+    Outdated(u32),
+    /// The metadata has the current version already.
+    Current,
+    /// Reading or upgrading the metadata failed.
+    Failed(KeychainError),
+}
+
+impl fmt::Display for Migrated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Migrated::Upgraded(version) => write!(f, "upgraded from schema {}", version),
+            Migrated::Outdated(version) => write!(f, "outdated, schema {}", version),
+            Migrated::Current => write!(f, "current"),
+            Migrated::Failed(ref error) => write!(f, "failed: {}", error),
+        }
+    }
+}
+
+/// The outcome of migrating the metadata of a single item.
+#[derive(Debug)]
+pub struct ItemMigration {
+    /// The attributes of the item.
+    pub item: Item,
+    /// What happened to the metadata of the item.
+    pub outcome: Migrated,
+}
+
+/// Upgrade the metadata of `item`.
+fn migrate_item(keychain: Option<&Keychain>, item: &Item, dry_run: bool) -> Migrated {
+    let generic = item.generic.as_deref().unwrap_or_default();
+    let mut metadata = match Metadata::parse(generic) {
+        Ok(metadata) => metadata,
+        Err(error) => return Migrated::Failed(error),
+    };
+    let version = metadata.version();
+    if !metadata.migrate() {
+        Migrated::Current
+    } else if dry_run {
+        Migrated::Outdated(version)
+    } else {
+        match write(keychain, &item.service, &item.account, &metadata) {
+            Ok(()) => Migrated::Upgraded(version),
+            Err(error) => Migrated::Failed(error),
+        }
+    }
+}
+
+/// Upgrade the metadata of all items in `keychain` to the current version.
+///
+/// Only touch items with metadata of this crate.  If `dry_run` is set only
+/// report outdated items.  Failures of single items do not stop the
+/// migration; they are reported in the returned list.
+///
+/// # Errors
+///
+/// Return `KeychainError` if the items cannot be listed.
+pub fn migrate_all(keychain: Option<&Keychain>, dry_run: bool) -> Result<Vec<ItemMigration>> {
+    let listed = items::list_items(keychain)?;
+    Ok(listed
+        .into_iter()
+        .filter(has_metadata)
+        .map(|item| {
+            let outcome = migrate_item(keychain, &item, dry_run);
+            ItemMigration { item, outcome }
+        })
+        .collect())
+}
@@ -0,0 +1,275 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Opt-in metrics about keychain operations.
+//!
+//! Once enabled with `enable`, keychain operations count how often they ran,
+//! how often they failed with which error, and how long they took.  Metrics
+//! are process-wide; `snapshot` returns their current values, which print in
+//! the Prometheus text format.
+//!
+//! ```
+//! use rust_ffi_demo::keychain::{metrics, Result};
+//!
+//! metrics::enable();
+//! let result: Result<()> = metrics::measure("example", || Ok(()));
+//! assert!(result.is_ok());
+//!
+//! let snapshot = metrics::snapshot();
+//! assert_eq!(snapshot.operations["example"].count, 1);
+//! assert!(snapshot
+//!     .to_prometheus()
+//!     .contains("keychain_operations_total{operation=\"example\"} 1"));
+//! ```
+//!
+//! An audit hook hears about every operation as it finishes, with the
+//! correlation ID of the operation, eg, to write an audit log or tracing
+//! events:
+//!
+//! ```
+//! use rust_ffi_demo::keychain::{metrics, Result};
+//!
+//! metrics::set_audit_hook(|event| {
+//!     eprintln!("{} {} failed: {}", event.correlation_id, event.operation, event.error.is_some());
+//! });
+//! let result: Result<()> = metrics::measure("example", || Ok(()));
+//! metrics::clear_audit_hook();
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use super::correlation::{self, CorrelationId};
+use super::{KeychainError, KeychainErrorCode, Result};
+
+/// The upper bounds of latency buckets, in seconds.
+pub const BUCKETS: [f64; 8] = [0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+/// Whether to record metrics.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The metrics of all operations, by operation.
+static REGISTRY: Mutex<BTreeMap<&'static str, OperationMetrics>> = Mutex::new(BTreeMap::new());
+
+/// A hook for audit events.
+type AuditHook = Box<dyn Fn(&AuditEvent<'_>) + Send + Sync>;
+
+/// The audit hook, if any.
+static AUDIT_HOOK: RwLock<Option<AuditHook>> = RwLock::new(None);
+
+/// A finished keychain operation.
+#[derive(Debug, Clone, Copy)]
+pub struct AuditEvent<'a> {
+    /// The name of the operation, eg, `find_data`.
+    pub operation: &'static str,
+    /// The correlation ID of the operation.
+    pub correlation_id: CorrelationId,
+    /// The error of the operation, if it failed.
+    pub error: Option<&'a KeychainError>,
+    /// How long the operation took.
+    pub duration: Duration,
+}
+
+/// A histogram of latencies.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Histogram {
+    /// The number of observations at or below each of `BUCKETS`.
+    pub buckets: [u64; 8],
+    /// The sum of all observations, in seconds.
+    pub sum: f64,
+    /// The number of observations.
+    pub count: u64,
+}
+
+impl Histogram {
+    /// Record an observation of `duration`.
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bucket, bound) in self.buckets.iter_mut().zip(BUCKETS.iter()) {
+            if seconds <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+/// The metrics of a single operation.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct OperationMetrics {
+    /// How often the operation ran.
+    pub count: u64,
+    /// How often the operation failed, by error code.
+    pub failures: BTreeMap<String, u64>,
+    /// How long the operation took.
+    pub latency: Histogram,
+}
+
+/// The metrics of all operations at some point in time.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Snapshot {
+    /// The metrics of every operation which ran at least once.
+    pub operations: BTreeMap<&'static str, OperationMetrics>,
+}
+
+impl Snapshot {
+    /// Format these metrics in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let mut text = String::new();
+        text.push_str("# HELP keychain_operations_total Keychain operations.\n");
+        text.push_str("# TYPE keychain_operations_total counter\n");
+        for (operation, metrics) in &self.operations {
+            writeln!(
+                text,
+                "keychain_operations_total{{operation=\"{}\"}} {}",
+                operation, metrics.count
+            )
+            .unwrap();
+        }
+        text.push_str("# HELP keychain_operation_failures_total Failed keychain operations.\n");
+        text.push_str("# TYPE keychain_operation_failures_total counter\n");
+        for (operation, metrics) in &self.operations {
+            for (error, count) in &metrics.failures {
+                writeln!(
+                    text,
+                    "keychain_operation_failures_total{{operation=\"{}\",error=\"{}\"}} {}",
+                    operation, error, count
+                )
+                .unwrap();
+            }
+        }
+        text.push_str(
+            "# HELP keychain_operation_duration_seconds Latency of keychain operations.\n",
+        );
+        text.push_str("# TYPE keychain_operation_duration_seconds histogram\n");
+        for (operation, metrics) in &self.operations {
+            let latency = &metrics.latency;
+            for (bound, count) in BUCKETS.iter().zip(latency.buckets.iter()) {
+                writeln!(
+                    text,
+                    "keychain_operation_duration_seconds_bucket{{operation=\"{}\",le=\"{}\"}} {}",
+                    operation, bound, count
+                )
+                .unwrap();
+            }
+            writeln!(
+                text,
+                "keychain_operation_duration_seconds_bucket{{operation=\"{}\",le=\"+Inf\"}} {}",
+                operation, latency.count
+            )
+            .unwrap();
+            writeln!(
+                text,
+                "keychain_operation_duration_seconds_sum{{operation=\"{}\"}} {}",
+                operation, latency.sum
+            )
+            .unwrap();
+            writeln!(
+                text,
+                "keychain_operation_duration_seconds_count{{operation=\"{}\"}} {}",
+                operation, latency.count
+            )
+            .unwrap();
+        }
+        text
+    }
+}
+
+/// Start recording metrics.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Whether metrics are recorded.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Get the current metrics of all operations.
+pub fn snapshot() -> Snapshot {
+    Snapshot {
+        operations: REGISTRY.lock().unwrap().clone(),
+    }
+}
+
+/// The label of an error code in metrics.
+fn error_label(status: &KeychainErrorCode) -> String {
+    match *status {
+        KeychainErrorCode::UnknownStatusCode(code) => code.to_string(),
+        ref status => format!("{:?}", status),
+    }
+}
+
+/// Call `hook` with an event for every finished operation.
+///
+/// Replace any previous hook.  The hook runs on the thread of the operation,
+/// and must not set or clear the hook itself.
+pub fn set_audit_hook<F: Fn(&AuditEvent<'_>) + Send + Sync + 'static>(hook: F) {
+    *AUDIT_HOOK.write().unwrap() = Some(Box::new(hook));
+}
+
+/// Remove the audit hook.
+pub fn clear_audit_hook() {
+    *AUDIT_HOOK.write().unwrap() = None;
+}
+
+/// Record the metrics of `operation`, which took `elapsed` and ended with
+/// `result`.
+fn record<T>(operation: &'static str, elapsed: Duration, result: &Result<T>) {
+    let mut registry = REGISTRY.lock().unwrap();
+    let metrics = registry.entry(operation).or_default();
+    metrics.count += 1;
+    metrics.latency.observe(elapsed);
+    if let Err(ref error) = *result {
+        *metrics
+            .failures
+            .entry(error_label(&error.status))
+            .or_insert(0) += 1;
+    }
+}
+
+/// Run `f` as `operation` in a correlation scope, record its metrics if
+/// enabled, and tell the audit hook if any.
+///
+/// Operations within `f` share the correlation ID of `operation`.
+pub fn measure<T, F>(operation: &'static str, f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T>,
+{
+    correlation::scope(|correlation_id| {
+        let audited = AUDIT_HOOK.read().unwrap().is_some();
+        if !is_enabled() && !audited {
+            return f();
+        }
+        let start = Instant::now();
+        let result = f();
+        let elapsed = start.elapsed();
+        if is_enabled() {
+            record(operation, elapsed, &result);
+        }
+        if let Some(ref hook) = *AUDIT_HOOK.read().unwrap() {
+            hook(&AuditEvent {
+                operation,
+                correlation_id,
+                error: result.as_ref().err(),
+                duration: elapsed,
+            });
+        }
+        result
+    })
+}
@@ -13,6 +13,10 @@
 // the License.
 
 //! Provide acccess to the macOS Keychain.
+//!
+//! On iOS and Mac Catalyst only the data protection keychain exists, and the
+//! modules for keychain files and other macOS-only APIs aren't available; see
+//! `capabilities`.
 
 #[allow(non_upper_case_globals)]
 #[allow(non_camel_case_types)]
@@ -20,17 +24,71 @@
 #[allow(dead_code)]
 mod native;
 mod cfutil;
+mod messages;
+
+#[cfg(feature = "age")]
+pub mod age;
+pub mod attachments;
+pub mod attributes;
+#[cfg(target_os = "macos")]
+pub mod authorization;
+pub mod capabilities;
+pub mod chunked;
+#[cfg(target_os = "macos")]
+pub mod codesign;
+pub mod compression;
+pub mod correlation;
+pub mod digest;
+#[cfg(feature = "dlopen-security")]
+pub mod dynamic;
+#[cfg(target_os = "macos")]
+pub mod events;
+pub mod guard;
+pub mod handle;
+pub mod items;
+#[cfg(target_os = "macos")]
+pub mod keychains;
+#[cfg(not(target_os = "macos"))]
+#[path = "keychains_ios.rs"]
+pub mod keychains;
+pub mod keys;
+pub mod labels;
+pub mod local_auth;
+#[cfg(feature = "locked-memory")]
+pub mod locked;
+pub mod metadata;
+pub mod metrics;
+pub mod notes;
+pub mod protection;
+pub mod query;
+pub mod read_only;
+pub mod seal;
+pub mod sensitive;
+#[cfg(target_os = "macos")]
+pub mod session;
+pub mod tokens;
+pub mod totp;
+#[cfg(feature = "rustls")]
+pub mod tls;
+#[cfg(target_os = "macos")]
+pub mod wifi;
 
 use std;
+use std::error;
 use std::fmt;
+use std::io;
 use std::ptr;
 use std::os::raw::c_void;
 
 use self::native::*;
 use self::cfutil::*;
+use self::correlation::CorrelationId;
+use self::sensitive::Sensitive;
+
+pub use self::cfutil::with_autorelease_pool;
 
 /// A keychain error code.
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum KeychainErrorCode {
     /// Authorization and/or authentication failed.
     AuthFailed,
@@ -40,10 +98,38 @@ pub enum KeychainErrorCode {
     ItemNotFound,
     /// An invalid attempt to change the owner of an item
     InvalidOwnerEdit,
+    /// The binary lacks the entitlements to access the item.
+    ///
+    /// The data protection keychain only admits signed binaries with a
+    /// keychain access group; see `codesign::signing_info`.
+    MissingEntitlement,
+    /// The data of an item, or the data to store in an item, is malformed.
+    ///
+    /// Unlike the other variants this code does not come from the keychain
+    /// itself; it’s raised by this API when it cannot make sense of data.
+    InvalidData,
+    /// Reading or writing a file failed, with the kind of the IO error.
+    ///
+    /// Like `InvalidData` this code does not come from the keychain; it’s
+    /// raised by stores which keep secrets in files.
+    Io(io::ErrorKind),
+    /// The item, key or keychain cannot be changed in read-only mode.
+    ///
+    /// Like `InvalidData` this code does not come from the keychain; see
+    /// `read_only`.
+    ReadOnly,
+    /// The keychain returned a value of another CoreFoundation type than
+    /// expected, eg, a string instead of data.
+    ///
+    /// Like `InvalidData` this code does not come from the keychain; it’s
+    /// raised by type checks of keychain values, which debug builds and the
+    /// `strict-cf-types` feature enable.
+    UnexpectedCfType,
     /// An unknown keychain error code.
     ///
     /// This API wraps only common status codes; uncommon status codes end up
-    /// in this variant.
+    /// in this variant.  Its display includes the symbolic name of the code,
+    /// if the Security framework headers define one.
     UnknownStatusCode(i32),
 }
 
@@ -51,12 +137,59 @@ impl fmt::Display for KeychainErrorCode {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use self::KeychainErrorCode::*;
         match *self {
-            UnknownStatusCode(code) => write!(f, "{}", code),
+            UnknownStatusCode(code) => match messages::status_name(code) {
+                Some(name) => write!(f, "{} ({})", name, code),
+                None => write!(f, "{}", code),
+            },
             _ => write!(f, "{:?}", self),
         }
     }
 }
 
+impl KeychainErrorCode {
+    /// The name of this code, ie, the name of its variant.
+    pub fn name(&self) -> &'static str {
+        use self::KeychainErrorCode::*;
+        match *self {
+            AuthFailed => "AuthFailed",
+            DuplicateItem => "DuplicateItem",
+            ItemNotFound => "ItemNotFound",
+            InvalidOwnerEdit => "InvalidOwnerEdit",
+            MissingEntitlement => "MissingEntitlement",
+            InvalidData => "InvalidData",
+            Io(_) => "Io",
+            ReadOnly => "ReadOnly",
+            UnexpectedCfType => "UnexpectedCfType",
+            UnknownStatusCode(_) => "UnknownStatusCode",
+        }
+    }
+
+    /// The `OSStatus` of this code.
+    ///
+    /// Return `None` for codes which don't come from the keychain.
+    pub fn os_status(&self) -> Option<i32> {
+        use self::KeychainErrorCode::*;
+        match *self {
+            AuthFailed => Some(errSecAuthFailed),
+            DuplicateItem => Some(errSecDuplicateItem),
+            ItemNotFound => Some(errSecItemNotFound),
+            InvalidOwnerEdit => Some(errSecInvalidOwnerEdit),
+            MissingEntitlement => Some(errSecMissingEntitlement),
+            InvalidData | Io(_) | ReadOnly | UnexpectedCfType => None,
+            UnknownStatusCode(code) => Some(code),
+        }
+    }
+
+    /// The symbolic name of the `OSStatus` of this code, eg,
+    /// `errSecMissingEntitlement`.
+    ///
+    /// Return `None` for codes which don't come from the keychain, and for
+    /// status codes which the Security framework headers don't name.
+    pub fn status_name(&self) -> Option<&'static str> {
+        self.os_status().and_then(messages::status_name)
+    }
+}
+
 impl From<OSStatus> for KeychainErrorCode {
     fn from(status: OSStatus) -> KeychainErrorCode {
         use self::KeychainErrorCode::*;
@@ -68,6 +201,8 @@ impl From<OSStatus> for KeychainErrorCode {
             ItemNotFound
         } else if status == errSecInvalidOwnerEdit {
             InvalidOwnerEdit
+        } else if status == errSecMissingEntitlement {
+            MissingEntitlement
         } else {
             UnknownStatusCode(status)
         }
@@ -75,30 +210,116 @@ impl From<OSStatus> for KeychainErrorCode {
 }
 
 /// A keychain error.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct KeychainError {
     /// The status code of the error.
     ///
     /// Use this code to unambiguously identify the cause of an error.
     pub status: KeychainErrorCode,
     /// A human-readable, non-localized message for the error.
+    ///
+    /// The message is English, for logs; see `localized_message` for users.
     pub message: String,
+    /// The correlation ID of the operation which failed, if any.
+    pub correlation_id: Option<CorrelationId>,
 }
 
 impl From<OSStatus> for KeychainError {
     /// Creates a `KeychainError` from an `OSStatus` value.
     ///
-    /// Gets the error message from the system.
+    /// Gets the error message from the system, or from a cache of messages.
+    /// For a missing entitlement the message explains what the code
+    /// signature lacks, on macOS.
     fn from(status: OSStatus) -> KeychainError {
-        let message = unsafe {
-            let cf_message = SecCopyErrorMessageString(status, ptr::null_mut());
-            let s = string_from_cf_string(cf_message);
-            CFRelease(cf_message as CFTypeRef);
-            s
+        let message = messages::message(status);
+        // Only macOS tells about code signatures
+        #[cfg(target_os = "macos")]
+        let message = if status == errSecMissingEntitlement {
+            format!("{}: {}", message, codesign::explain_missing_entitlement())
+        } else {
+            message
         };
         KeychainError {
             status: status.into(),
             message,
+            correlation_id: correlation::current(),
+        }
+    }
+}
+
+impl From<io::Error> for KeychainError {
+    fn from(error: io::Error) -> KeychainError {
+        KeychainError {
+            status: KeychainErrorCode::Io(error.kind()),
+            message: error.to_string(),
+            correlation_id: correlation::current(),
+        }
+    }
+}
+
+impl From<UnexpectedCfType> for KeychainError {
+    fn from(error: UnexpectedCfType) -> KeychainError {
+        KeychainError {
+            status: KeychainErrorCode::UnexpectedCfType,
+            message: error.to_string(),
+            correlation_id: correlation::current(),
+        }
+    }
+}
+
+impl KeychainError {
+    /// Creates a `KeychainError` for malformed data with the given `message`.
+    pub(crate) fn invalid_data<S: Into<String>>(message: S) -> KeychainError {
+        KeychainError {
+            status: KeychainErrorCode::InvalidData,
+            message: message.into(),
+            correlation_id: correlation::current(),
+        }
+    }
+
+    /// Creates a `KeychainError` for a missing item with the given `message`.
+    #[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+    pub(crate) fn item_not_found<S: Into<String>>(message: S) -> KeychainError {
+        KeychainError {
+            status: KeychainErrorCode::ItemNotFound,
+            message: message.into(),
+            correlation_id: correlation::current(),
+        }
+    }
+
+    /// Creates a `KeychainError` from a `CFError` and releases the `error`.
+    ///
+    /// The code of the error is taken as `OSStatus`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must own `error`, and `error` must not be null.
+    unsafe fn from_cf_error(error: CFErrorRef) -> KeychainError {
+        assert!(!error.is_null());
+        let status = CFErrorGetCode(error) as OSStatus;
+        CFRelease(error as CFTypeRef);
+        KeychainError::from(status)
+    }
+
+    /// The message of this error in `locale`, eg, `de` or `pt-BR`, for users.
+    ///
+    /// Translate the part of `message` which comes from Security.framework,
+    /// and keep the rest, eg, explanations of this crate, in English.  Return
+    /// `message` if this error doesn't come from the keychain, or the
+    /// framework has no translation for `locale`.
+    pub fn localized_message(&self, locale: &str) -> String {
+        let status = match self.status.os_status() {
+            Some(status) => status,
+            None => return self.message.clone(),
+        };
+        let english = messages::message(status);
+        let rest = match self.message.strip_prefix(english.as_str()) {
+            Some(rest) => rest,
+            None => return self.message.clone(),
+        };
+        match messages::localized_message(status, locale) {
+            Some(localized) => format!("{}{}", localized, rest),
+            None => self.message.clone(),
         }
     }
 }
@@ -114,16 +335,24 @@ impl fmt::Display for KeychainError {
     }
 }
 
+impl error::Error for KeychainError {}
+
 /// An account, with an account, eg, user `name` and a `password`.
 #[derive(Debug)]
 pub struct Account {
     pub name: String,
-    pub password: String,
+    pub password: Sensitive<String>,
 }
 
 /// The Result of a keychain operation.
 pub type Result<T> = std::result::Result<T, KeychainError>;
 
+/// The raw bytes of a secret.
+pub type SecretBytes = Vec<u8>;
+
+/// How often `get_or_create` retries when racing other processes.
+const GET_OR_CREATE_ATTEMPTS: usize = 3;
+
 /// Create a result from a `status`.
 ///
 /// If `status` is `errSecSuccess` return Ok of unit, otherwise return `Err`
@@ -136,52 +365,196 @@ fn status_to_result(status: OSStatus) -> Result<()> {
     }
 }
 
+/// Generate `count` cryptographically secure random bytes.
+///
+/// # Errors
+///
+/// Return `KeychainError` if the system has no randomness to offer.
+pub fn random_bytes(count: usize) -> Result<Vec<u8>> {
+    let mut bytes = vec![0; count];
+    let status = unsafe {
+        SecRandomCopyBytes(
+            kSecRandomDefault(),
+            count as size_t,
+            bytes.as_mut_ptr() as *mut c_void,
+        )
+    };
+    status_to_result(status)?;
+    Ok(bytes)
+}
+
+/// Run `SecItemCopyMatching` for a query made of `items`.
+///
+/// # Safety
+///
+/// `items` must form a valid keychain query, see `create_dictionary`.
+///
+/// The caller must call `CFRelease` on the returned result.
+unsafe fn copy_matching(items: &[(CFTypeRef, CFTypeRef)]) -> Result<CFTypeRef> {
+    let query = create_dictionary(items);
+    assert!(!query.is_null());
+
+    let mut result: CFTypeRef = ptr::null();
+    let status = SecItemCopyMatching(query, &mut result);
+
+    CFRelease(query as CFTypeRef);
+
+    status_to_result(status)?;
+    assert!(!result.is_null());
+    Ok(result)
+}
+
+/// Check that the owned `value` has `type_id`, named `expected`.
+///
+/// Return `value` if it has this type, see `check_type`, and release it
+/// otherwise.
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::UnexpectedCfType` if `value` has another type.
+///
+/// # Safety
+///
+/// `value` must be a valid CoreFoundation object, and the caller must own it.
+/// The caller must call `CFRelease` on the returned value.
+unsafe fn owned_with_type(
+    value: CFTypeRef,
+    expected: &'static str,
+    type_id: CFTypeID,
+) -> Result<CFTypeRef> {
+    match check_type(value, expected, type_id) {
+        Ok(()) => Ok(value),
+        Err(error) => {
+            CFRelease(value);
+            Err(error.into())
+        }
+    }
+}
+
+/// Copy attributes and data of the first generic password for `service`.
+///
+/// If `account` is given, only consider items for this account.
+///
+/// # Safety
+///
+/// The caller must call `CFRelease` on the returned dictionary.
+unsafe fn copy_first_generic_password(
+    service: &str,
+    account: Option<&str>,
+) -> Result<CFDictionaryRef> {
+    let cf_service = create_cf_string_no_copy(service) as CFTypeRef;
+    let cf_account = account.map(|a| create_cf_string_no_copy(a) as CFTypeRef);
+    let c = constants();
+    let mut items = DictionaryItems::new(&[
+        (c.class, c.class_generic_password),
+        (c.attr_service, cf_service),
+        (c.match_limit, c.match_limit_one),
+        (c.return_attributes, c.boolean_true),
+        (c.return_data, c.boolean_true),
+    ]);
+    if let Some(cf_account) = cf_account {
+        items.push((c.attr_account, cf_account));
+    }
+    let result = copy_matching(&items);
+    CFRelease(cf_service);
+    if let Some(cf_account) = cf_account {
+        CFRelease(cf_account);
+    }
+    result
+        .and_then(|r| owned_with_type(r, "CFDictionary", CFDictionaryGetTypeID()))
+        .map(|r| r as CFDictionaryRef)
+}
+
+/// Copy the attributes of generic passwords for `service`.
+///
+/// If `account` is given, only consider items for this account.  If `all` is
+/// true return an array of all matching items, otherwise a dictionary of the
+/// first one.
+///
+/// # Safety
+///
+/// The caller must call `CFRelease` on the returned result.
+unsafe fn copy_generic_attributes(
+    service: &str,
+    account: Option<&str>,
+    all: bool,
+) -> Result<CFTypeRef> {
+    let cf_service = create_cf_string_no_copy(service) as CFTypeRef;
+    let cf_account = account.map(|a| create_cf_string_no_copy(a) as CFTypeRef);
+    let c = constants();
+    let limit = if all {
+        c.match_limit_all
+    } else {
+        c.match_limit_one
+    };
+    let mut items = DictionaryItems::new(&[
+        (c.class, c.class_generic_password),
+        (c.attr_service, cf_service),
+        (c.match_limit, limit),
+        (c.return_attributes, c.boolean_true),
+    ]);
+    if let Some(cf_account) = cf_account {
+        items.push((c.attr_account, cf_account));
+    }
+    let result = copy_matching(&items);
+    CFRelease(cf_service);
+    if let Some(cf_account) = cf_account {
+        CFRelease(cf_account);
+    }
+    result
+}
+
+/// Read an `Account` from the attributes and data of a generic password.
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::InvalidData` if `item` lacks the account name
+/// or the password data, or the password is not UTF-8, and
+/// `KeychainErrorCode::UnexpectedCfType` if either has the wrong type.
+unsafe fn account_from_item(item: Borrowed<'_, CFDictionaryRef>) -> Result<Account> {
+    let c = constants();
+    let name = cf_dict_get_string(item.get(), c.attr_account as CFStringRef)?
+        .ok_or_else(|| KeychainError::invalid_data("Generic password without account"))?;
+    let password = cf_dict_get_data(item.get(), c.value_data as CFStringRef)?
+        .ok_or_else(|| KeychainError::invalid_data("Generic password without data"))?;
+    let password = String::from_utf8(password)
+        .map_err(|_| KeychainError::invalid_data("Generic password is not UTF-8"))?;
+    Ok(Account {
+        name,
+        password: Sensitive::new(password),
+    })
+}
+
 /// Add a generic account.
 ///
 /// The `service` identifies the application or service for which the `account`
-/// is being stored.
+/// is being stored.  The item gets the default label; see `labels`.
 ///
 /// # Errors
 ///
 /// Return `KeychainError` when the combination of `service` and `account.name`
 /// already exist in keychain, or keychain access fails otherwise.
 pub fn add_generic_password(service: &str, account: &Account) -> Result<()> {
-    unsafe {
-        let cf_service = CFStringCreateWithBytesNoCopy(
-            std::ptr::null_mut(),
-            service.as_ptr(),
-            service.len() as i64,
-            kCFStringEncodingUTF8,
-            false as u8,
-            kCFAllocatorNull,
-        ) as CFTypeRef;
-        assert!(!cf_service.is_null());
-        let cf_account = CFStringCreateWithBytesNoCopy(
-            ptr::null_mut(),
-            account.name.as_ptr(),
-            account.name.len() as i64,
-            kCFStringEncodingUTF8,
-            false as u8,
-            kCFAllocatorNull,
-        ) as CFTypeRef;
-        assert!(!cf_account.is_null());
-        let cf_password = CFDataCreateWithBytesNoCopy(
-            ptr::null_mut(),
-            account.password.as_ptr(),
-            account.password.len() as i64,
-            kCFAllocatorNull,
-        ) as CFTypeRef;
-        assert!(!cf_password.is_null());
-
-        let items = [
-            (
-                kSecClass as CFTypeRef,
-                kSecClassGenericPassword as CFTypeRef,
-            ),
-            (kSecAttrService as CFTypeRef, cf_service),
-            (kSecAttrAccount as CFTypeRef, cf_account),
-            (kSecValueData as CFTypeRef, cf_password),
-        ];
+    read_only::check()?;
+    metrics::measure("add_generic_password", || unsafe {
+        let cf_service = create_cf_string_no_copy(service) as CFTypeRef;
+        let cf_account = create_cf_string_no_copy(&account.name) as CFTypeRef;
+        let cf_password = create_cf_data_no_copy(account.password.expose().as_bytes()) as CFTypeRef;
+        let label = labels::for_new_item(None, service, &account.name);
+        let cf_label = label
+            .as_ref()
+            .map(|label| create_cf_string_no_copy(label) as CFTypeRef);
+
+        let c = constants();
+        let mut items = DictionaryItems::new(&[
+            (c.class, c.class_generic_password),
+            (c.attr_service, cf_service),
+            (c.attr_account, cf_account),
+            (c.value_data, cf_password),
+        ]);
+        if let Some(cf_label) = cf_label {
+            items.push((kSecAttrLabel() as CFTypeRef, cf_label));
+        }
         let attributes = create_dictionary(&items);
         assert!(!attributes.is_null());
 
@@ -191,9 +564,12 @@ pub fn add_generic_password(service: &str, account: &Account) -> Result<()> {
         CFRelease(cf_service);
         CFRelease(cf_account);
         CFRelease(cf_password);
+        if let Some(cf_label) = cf_label {
+            CFRelease(cf_label);
+        }
 
         status_to_result(status)
-    }
+    })
 }
 
 /// Delete all generic passwords from keychain matching the given `service`.
@@ -203,23 +579,14 @@ pub fn add_generic_password(service: &str, account: &Account) -> Result<()> {
 /// This function will fail with `KeychainErrorCode::ItemNotFound` if no
 /// generic passwords exist for the given `service`.
 pub fn delete_generic_passwords_by_service(service: &str) -> Result<()> {
-    unsafe {
-        let cf_service = CFStringCreateWithBytesNoCopy(
-            std::ptr::null_mut(),
-            service.as_ptr(),
-            service.len() as i64,
-            kCFStringEncodingUTF8,
-            false as u8,
-            kCFAllocatorNull,
-        ) as CFTypeRef;
-        assert!(!cf_service.is_null());
+    read_only::check()?;
+    metrics::measure("delete_generic_passwords_by_service", || unsafe {
+        let cf_service = create_cf_string_no_copy(service) as CFTypeRef;
 
+        let c = constants();
         let items = [
-            (
-                kSecClass as CFTypeRef,
-                kSecClassGenericPassword as CFTypeRef,
-            ),
-            (kSecAttrService as CFTypeRef, cf_service),
+            (c.class, c.class_generic_password),
+            (c.attr_service, cf_service),
         ];
         let query = create_dictionary(&items);
         assert!(!query.is_null());
@@ -230,71 +597,114 @@ pub fn delete_generic_passwords_by_service(service: &str) -> Result<()> {
         CFRelease(cf_service);
 
         status_to_result(status)
-    }
+    })
 }
 
 /// Find the first generic password for the given `service`.
 ///
+/// If there are several accounts for `service` the keychain returns an
+/// arbitrary one; use `find_generic_password` to look up a specific account.
+///
 /// # Errors
 ///
 /// Return `KeychainError` when the item does not exist, or keychain access
 /// fails otherwise.
 pub fn find_generic_password_by_service(service: &str) -> Result<Account> {
-    unsafe {
-        let cf_service = CFStringCreateWithBytesNoCopy(
-            std::ptr::null_mut(),
-            service.as_ptr(),
-            service.len() as i64,
-            kCFStringEncodingUTF8,
-            false as u8,
-            kCFAllocatorNull,
-        ) as CFTypeRef;
-        assert!(!cf_service.is_null());
-
-        let items = [
-            (
-                kSecClass as CFTypeRef,
-                kSecClassGenericPassword as CFTypeRef,
-            ),
-            (kSecAttrService as CFTypeRef, cf_service),
-            (kSecMatchLimit as CFTypeRef, kSecMatchLimitOne as CFTypeRef),
-            (
-                kSecReturnAttributes as CFTypeRef,
-                kCFBooleanTrue as CFTypeRef,
-            ),
-            (kSecReturnData as CFTypeRef, kCFBooleanTrue as CFTypeRef),
-        ];
-        let query = create_dictionary(&items);
-        assert!(!query.is_null());
-
-        let mut result: CFTypeRef = ptr::null();
-        let status = SecItemCopyMatching(query, &mut result);
-
-        CFRelease(cf_service);
-        CFRelease(query as CFTypeRef);
-
-        status_to_result(status)?;
-
-        assert!(!result.is_null());
+    metrics::measure("find_generic_password_by_service", || unsafe {
+        let result = Owned::from_create(copy_first_generic_password(service, None)?);
+        account_from_item(result.borrow())
+    })
+}
 
-        let cf_account =
-            CFDictionaryGetValue(result as CFDictionaryRef, kSecAttrAccount as *const c_void)
-                as CFStringRef;
-        let cf_password =
-            CFDictionaryGetValue(result as CFDictionaryRef, kSecValueData as *const c_void)
-                as CFDataRef;
+/// Find the generic password for the given `service` and `account_name`.
+///
+/// Unlike `find_generic_password_by_service` this function matches the
+/// account as well, so it always finds the same item.
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::ItemNotFound` if `service` has no account named
+/// `account_name`, and `KeychainError` if keychain access fails otherwise.
+pub fn find_generic_password(service: &str, account_name: &str) -> Result<Account> {
+    metrics::measure("find_generic_password", || unsafe {
+        let result = Owned::from_create(copy_first_generic_password(service, Some(account_name))?);
+        account_from_item(result.borrow())
+    })
+}
 
-        let account = Account {
-            name: string_from_cf_string(cf_account),
-            password: String::from_utf8_unchecked(vec_from_cfdata(cf_password)),
-        };
+/// Whether a generic password for `service` and `account_name` exists.
+///
+/// Only queries attributes, so this never prompts for access to the password.
+///
+/// # Errors
+///
+/// Return `KeychainError` if keychain access fails.
+pub fn exists(service: &str, account_name: &str) -> Result<bool> {
+    metrics::measure("exists", || unsafe {
+        match copy_generic_attributes(service, Some(account_name), false) {
+            Ok(result) => {
+                CFRelease(result);
+                Ok(true)
+            }
+            Err(ref error) if error.status == KeychainErrorCode::ItemNotFound => Ok(false),
+            Err(error) => Err(error),
+        }
+    })
+}
 
-        // As `CFDictionaryGetValue` follows the `Get` rule, ie, ownership of
-        // returned values is tied to the containing dictionary, we must NOT
-        // free `cf_account` and `cf_password` here!  We just free the entire
-        // `result` dictionary and it’ll free everything that’s in it.
-        CFRelease(result);
+/// Count the generic passwords for `service`.
+///
+/// Only queries attributes, so this never prompts for access to passwords.
+///
+/// # Errors
+///
+/// Return `KeychainError` if keychain access fails.  Return 0 rather than
+/// `KeychainErrorCode::ItemNotFound` if there are no items for `service`.
+pub fn count_by_service(service: &str) -> Result<usize> {
+    metrics::measure("count_by_service", || unsafe {
+        match copy_generic_attributes(service, None, true) {
+            Ok(result) => {
+                let count = cast_cf_array(result).map(|array| CFArrayGetCount(array));
+                CFRelease(result);
+                Ok(count? as usize)
+            }
+            Err(ref error) if error.status == KeychainErrorCode::ItemNotFound => Ok(0),
+            Err(error) => Err(error),
+        }
+    })
+}
 
-        Ok(account)
+/// Get the secret of `service` and `account_name`, or create it with `generate`.
+///
+/// If no item exists, call `generate` for a new secret, add it to the default
+/// keychain and return it.  When another process adds the item in between,
+/// return its secret instead, so that all processes end up with the same
+/// secret.  `generate` may be called even if its result is discarded.
+///
+/// # Errors
+///
+/// Return `KeychainError` if keychain access fails, or if the item keeps
+/// disappearing and reappearing while racing other processes.
+pub fn get_or_create<F>(service: &str, account_name: &str, mut generate: F) -> Result<SecretBytes>
+where
+    F: FnMut() -> SecretBytes,
+{
+    let mut last_error = None;
+    for _ in 0..GET_OR_CREATE_ATTEMPTS {
+        match items::find_data(None, service, account_name) {
+            Err(ref error) if error.status == KeychainErrorCode::ItemNotFound => (),
+            result => return result.map(Sensitive::into_exposed),
+        }
+        let secret = generate();
+        let item = items::Item::new(service, account_name);
+        match items::add_item(None, &item, &secret) {
+            Ok(()) => return Ok(secret),
+            // Someone else won the race; read their secret on the next attempt
+            Err(error) if error.status == KeychainErrorCode::DuplicateItem => {
+                last_error = Some(error)
+            }
+            Err(error) => return Err(error),
+        }
     }
+    Err(last_error.unwrap())
 }
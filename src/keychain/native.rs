@@ -13,5 +13,22 @@
 // the License.
 
 //! Native wrappers for CoreFoundation and Security.
+//!
+//! `cf` holds the CoreFoundation bindings of `cf-util`, and `security` the
+//! bindings of the Security framework and CommonCrypto.  Modules which only
+//! talk to CoreFoundation can use `cf` alone.  `security` re-exports `cf`, so
+//! that both share the same CoreFoundation types.
 
-include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+/// Bindings to CoreFoundation.
+pub mod cf {
+    pub use cf_util::sys::*;
+}
+
+/// Bindings to the Security framework and CommonCrypto.
+pub mod security {
+    pub use super::cf::*;
+
+    include!(concat!(env!("OUT_DIR"), "/security.rs"));
+}
+
+pub use self::security::*;
@@ -0,0 +1,238 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Secure notes, as created by Keychain Access.
+//!
+//! A secure note is a generic password of type `note` with the title as
+//! service and label, and an empty account.  Its data is a property list with
+//! the plain text of the note under `NOTE`; Keychain Access adds the rich text
+//! under `RTFD`, which this module ignores when reading and doesn't write.
+
+use std::fmt;
+use std::os::raw::c_void;
+use std::ptr;
+
+use super::cfutil::*;
+use super::native::*;
+use super::read_only;
+use super::sensitive::redact;
+use super::{status_to_result, KeychainError, Result};
+
+/// The FourCC item type of secure notes, ie, `note`.
+const NOTE_TYPE: i64 = 0x6e6f_7465;
+
+/// The description of secure notes, as shown in Keychain Access.
+const NOTE_DESCRIPTION: &str = "secure note";
+
+/// The property list key for the plain text of a note.
+const NOTE_KEY: &str = "NOTE";
+
+/// A secure note.
+///
+/// `Debug` redacts the body.
+#[derive(Clone, PartialEq)]
+pub struct Note {
+    /// The title of the note.
+    pub title: String,
+    /// The plain text content of the note.
+    pub body: String,
+}
+
+impl fmt::Debug for Note {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Note")
+            .field("title", &self.title)
+            .field("body", &format_args!("{}", redact(self.body.as_bytes())))
+            .finish()
+    }
+}
+
+/// Run `f` with a query for the secure note with the given `title`.
+///
+/// `extra` is appended to the query.
+unsafe fn with_note_query<T, F>(title: &str, extra: &[(CFTypeRef, CFTypeRef)], f: F) -> T
+where
+    F: FnOnce(CFDictionaryRef) -> T,
+{
+    let cf_title = create_cf_string_no_copy(title) as CFTypeRef;
+    let cf_type = create_cf_number(NOTE_TYPE) as CFTypeRef;
+    let mut items = vec![
+        (
+            kSecClass() as CFTypeRef,
+            kSecClassGenericPassword() as CFTypeRef,
+        ),
+        (kSecAttrService() as CFTypeRef, cf_title),
+        (kSecAttrType() as CFTypeRef, cf_type),
+    ];
+    items.extend_from_slice(extra);
+    let query = create_dictionary(&items);
+    assert!(!query.is_null());
+
+    let result = f(query);
+
+    CFRelease(query as CFTypeRef);
+    CFRelease(cf_title);
+    CFRelease(cf_type);
+    result
+}
+
+/// Encode `body` as property list for the data of a note.
+///
+/// The caller must call `CFRelease` on the returned data.
+unsafe fn create_note_data(body: &str) -> CFDataRef {
+    let cf_key = create_cf_string_no_copy(NOTE_KEY) as CFTypeRef;
+    let cf_body = create_cf_string_no_copy(body) as CFTypeRef;
+    let plist = create_dictionary(&[(cf_key, cf_body)]);
+    assert!(!plist.is_null());
+    let data = CFPropertyListCreateData(
+        ptr::null_mut(),
+        plist as CFPropertyListRef,
+        kCFPropertyListXMLFormat_v1_0 as CFPropertyListFormat,
+        0,
+        ptr::null_mut(),
+    );
+    assert!(!data.is_null());
+    CFRelease(plist as CFTypeRef);
+    CFRelease(cf_key);
+    CFRelease(cf_body);
+    data
+}
+
+/// Decode the plain text of a note from its `data`.
+///
+/// Notes created by other apps may have plain text data instead of a property
+/// list; return such data as is.
+unsafe fn read_note_data(data: CFDataRef) -> Result<String> {
+    let plist = CFPropertyListCreateWithData(
+        ptr::null_mut(),
+        data,
+        kCFPropertyListImmutable as CFOptionFlags,
+        ptr::null_mut(),
+        ptr::null_mut(),
+    );
+    if plist.is_null() {
+        return String::from_utf8(vec_from_cfdata(data)?)
+            .map_err(|_| KeychainError::invalid_data("Secure note is not UTF-8"));
+    }
+    let body = if CFGetTypeID(plist) == CFDictionaryGetTypeID() {
+        let cf_key = create_cf_string_no_copy(NOTE_KEY);
+        let cf_body = CFDictionaryGetValue(plist as CFDictionaryRef, cf_key as *const c_void);
+        CFRelease(cf_key as CFTypeRef);
+        if !cf_body.is_null() && CFGetTypeID(cf_body) == CFStringGetTypeID() {
+            string_from_cf_string(cf_body as CFStringRef).ok()
+        } else {
+            None
+        }
+    } else {
+        None
+    };
+    CFRelease(plist);
+    body.ok_or_else(|| KeychainError::invalid_data("Secure note without plain text"))
+}
+
+/// Add a secure note with the given `title` and `body`.
+///
+/// # Errors
+///
+/// Return `KeychainError` when a note with `title` already exists, or keychain
+/// access fails otherwise.
+pub fn add_note(title: &str, body: &str) -> Result<()> {
+    read_only::check()?;
+    unsafe {
+        let cf_label = create_cf_string_no_copy(title) as CFTypeRef;
+        let cf_account = create_cf_string_no_copy("") as CFTypeRef;
+        let cf_description = create_cf_string_no_copy(NOTE_DESCRIPTION) as CFTypeRef;
+        let cf_data = create_note_data(body) as CFTypeRef;
+        let extra = [
+            (kSecAttrLabel() as CFTypeRef, cf_label),
+            (kSecAttrAccount() as CFTypeRef, cf_account),
+            (kSecAttrDescription() as CFTypeRef, cf_description),
+            (kSecValueData() as CFTypeRef, cf_data),
+        ];
+        let status = with_note_query(title, &extra, |attributes| {
+            SecItemAdd(attributes, ptr::null_mut())
+        });
+        CFRelease(cf_label);
+        CFRelease(cf_account);
+        CFRelease(cf_description);
+        CFRelease(cf_data);
+        status_to_result(status)
+    }
+}
+
+/// Find the secure note with the given `title`.
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::InvalidData` if the note has no plain text, and
+/// `KeychainError` when the note does not exist, or keychain access fails
+/// otherwise.
+pub fn find_note(title: &str) -> Result<Note> {
+    unsafe {
+        let extra = [
+            (
+                kSecMatchLimit() as CFTypeRef,
+                kSecMatchLimitOne() as CFTypeRef,
+            ),
+            (kSecReturnData() as CFTypeRef, kCFBooleanTrue as CFTypeRef),
+        ];
+        let mut result: CFTypeRef = ptr::null();
+        let status = with_note_query(title, &extra, |query| {
+            SecItemCopyMatching(query, &mut result)
+        });
+        status_to_result(status)?;
+        assert!(!result.is_null());
+        let body = cast_cf_data(result)
+            .map_err(KeychainError::from)
+            .and_then(|data| read_note_data(data));
+        CFRelease(result);
+        Ok(Note {
+            title: title.to_string(),
+            body: body?,
+        })
+    }
+}
+
+/// Replace the body of the secure note with the given `title`.
+///
+/// # Errors
+///
+/// Return `KeychainError` when the note does not exist, or keychain access
+/// fails otherwise.
+pub fn update_note(title: &str, body: &str) -> Result<()> {
+    read_only::check()?;
+    unsafe {
+        let cf_data = create_note_data(body) as CFTypeRef;
+        let attributes = create_dictionary(&[(kSecValueData() as CFTypeRef, cf_data)]);
+        assert!(!attributes.is_null());
+        let status = with_note_query(title, &[], |query| SecItemUpdate(query, attributes));
+        CFRelease(attributes as CFTypeRef);
+        CFRelease(cf_data);
+        status_to_result(status)
+    }
+}
+
+/// Delete the secure note with the given `title`.
+///
+/// # Errors
+///
+/// Return `KeychainError` when the note does not exist, or keychain access
+/// fails otherwise.
+pub fn delete_note(title: &str) -> Result<()> {
+    read_only::check()?;
+    unsafe {
+        let status = with_note_query(title, &[], |query| SecItemDelete(query));
+        status_to_result(status)
+    }
+}
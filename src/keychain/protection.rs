@@ -0,0 +1,815 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Access control of keychain items.
+//!
+//! Items in the data protection keychain may carry a `SecAccessControl`, which
+//! demands user presence, biometry, or the device passcode before the keychain
+//! hands out the data.  Security.framework offers no API to read the flags of
+//! an access control back, so `Protection` recovers them from its description,
+//! which lists the constraints of every operation.
+//!
+//! Not all combinations of accessibility, iCloud sync and flags work: synced
+//! items must not be bound to this device, and items with flags never sync.
+//! A `Preset` names a combination which does:
+//!
+//! ```no_run
+//! use rust_ffi_demo::keychain::items::Item;
+//! use rust_ffi_demo::keychain::protection::{self, Preset};
+//!
+//! let item = Item::new("api.example.com", "jane");
+//! protection::add_preset_item(&item, b"s3cret", Preset::BiometryThisDeviceOnly).unwrap();
+//! ```
+//!
+//! `probe_access` tells how well an existing item is protected, without
+//! prompting the user.
+
+use std::fmt;
+use std::os::raw::c_void;
+use std::ptr;
+use std::str::FromStr;
+
+use super::cfutil::*;
+use super::items::{self, Item};
+use super::keychains::Keychain;
+use super::labels;
+use super::metrics;
+use super::native::*;
+use super::read_only;
+use super::{
+    copy_matching, owned_with_type, status_to_result, KeychainError, KeychainErrorCode, Result,
+    SecretBytes,
+};
+
+/// When an item is accessible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Accessibility {
+    /// While the device is unlocked.
+    WhenUnlocked,
+    /// While the device is unlocked, and never on another device.
+    WhenUnlockedThisDeviceOnly,
+    /// After the first unlock until the next restart.
+    AfterFirstUnlock,
+    /// After the first unlock, and never on another device.
+    AfterFirstUnlockThisDeviceOnly,
+    /// While the device is unlocked and has a passcode, and never on another
+    /// device.
+    WhenPasscodeSetThisDeviceOnly,
+}
+
+/// All accessibilities, for lookups.
+const ACCESSIBILITIES: [Accessibility; 5] = [
+    Accessibility::WhenUnlocked,
+    Accessibility::WhenUnlockedThisDeviceOnly,
+    Accessibility::AfterFirstUnlock,
+    Accessibility::AfterFirstUnlockThisDeviceOnly,
+    Accessibility::WhenPasscodeSetThisDeviceOnly,
+];
+
+impl Accessibility {
+    /// The name of this accessibility, as printed and parsed.
+    pub fn name(self) -> &'static str {
+        match self {
+            Accessibility::WhenUnlocked => "when-unlocked",
+            Accessibility::WhenUnlockedThisDeviceOnly => "when-unlocked-this-device-only",
+            Accessibility::AfterFirstUnlock => "after-first-unlock",
+            Accessibility::AfterFirstUnlockThisDeviceOnly => "after-first-unlock-this-device-only",
+            Accessibility::WhenPasscodeSetThisDeviceOnly => "when-passcode-set-this-device-only",
+        }
+    }
+
+    /// The `kSecAttrAccessible` constant of this accessibility.
+    unsafe fn as_cf_string(self) -> CFStringRef {
+        match self {
+            Accessibility::WhenUnlocked => kSecAttrAccessibleWhenUnlocked(),
+            Accessibility::WhenUnlockedThisDeviceOnly => {
+                kSecAttrAccessibleWhenUnlockedThisDeviceOnly()
+            }
+            Accessibility::AfterFirstUnlock => kSecAttrAccessibleAfterFirstUnlock(),
+            Accessibility::AfterFirstUnlockThisDeviceOnly => {
+                kSecAttrAccessibleAfterFirstUnlockThisDeviceOnly()
+            }
+            Accessibility::WhenPasscodeSetThisDeviceOnly => {
+                kSecAttrAccessibleWhenPasscodeSetThisDeviceOnly()
+            }
+        }
+    }
+
+    /// Find the accessibility of a `kSecAttrAccessible` constant.
+    unsafe fn from_cf_type(value: CFTypeRef) -> Option<Accessibility> {
+        ACCESSIBILITIES
+            .iter()
+            .cloned()
+            .find(|accessibility| CFEqual(value, accessibility.as_cf_string() as CFTypeRef) != 0)
+    }
+}
+
+impl fmt::Display for Accessibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Accessibility {
+    type Err = KeychainError;
+
+    fn from_str(s: &str) -> Result<Accessibility> {
+        ACCESSIBILITIES
+            .iter()
+            .cloned()
+            .find(|accessibility| accessibility.name() == s)
+            .ok_or_else(|| KeychainError::invalid_data(format!("Unknown accessibility: {}", s)))
+    }
+}
+
+/// A constraint of an access control.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Flag {
+    /// Biometry or the device passcode.
+    UserPresence,
+    /// Any enrolled finger or face.
+    BiometryAny,
+    /// The fingers or faces enrolled when the item was added.
+    BiometryCurrentSet,
+    /// The device passcode.
+    DevicePasscode,
+}
+
+/// All flags, for lookups.
+const FLAGS: [Flag; 4] = [
+    Flag::UserPresence,
+    Flag::BiometryAny,
+    Flag::BiometryCurrentSet,
+    Flag::DevicePasscode,
+];
+
+impl Flag {
+    /// The name of this flag, as printed and parsed.
+    pub fn name(self) -> &'static str {
+        match self {
+            Flag::UserPresence => "user-presence",
+            Flag::BiometryAny => "biometry",
+            Flag::BiometryCurrentSet => "biometry-current-set",
+            Flag::DevicePasscode => "passcode",
+        }
+    }
+
+    /// The `SecAccessControlCreateFlags` of this flag.
+    fn as_create_flags(self) -> SecAccessControlCreateFlags {
+        let flags = match self {
+            Flag::UserPresence => kSecAccessControlUserPresence,
+            Flag::BiometryAny => kSecAccessControlBiometryAny,
+            Flag::BiometryCurrentSet => kSecAccessControlBiometryCurrentSet,
+            Flag::DevicePasscode => kSecAccessControlDevicePasscode,
+        };
+        flags as SecAccessControlCreateFlags
+    }
+}
+
+impl fmt::Display for Flag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Flag {
+    type Err = KeychainError;
+
+    fn from_str(s: &str) -> Result<Flag> {
+        FLAGS
+            .iter()
+            .cloned()
+            .find(|flag| flag.name() == s)
+            .ok_or_else(|| {
+                KeychainError::invalid_data(format!("Unknown access control flag: {}", s))
+            })
+    }
+}
+
+/// A working combination of accessibility, iCloud sync and flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Preset {
+    /// While the device is unlocked, never synced.
+    DeviceOnlyWhenUnlocked,
+    /// After the first unlock, never synced, eg, for background services.
+    DeviceOnlyAfterFirstUnlock,
+    /// While the device is unlocked, synced to all devices of the user.
+    SyncedWhenUnlocked,
+    /// After the first unlock, synced to all devices of the user.
+    SyncedAfterFirstUnlock,
+    /// While the device has a passcode, never synced.
+    ///
+    /// Removing the passcode deletes the item.
+    PasscodeSetThisDeviceOnly,
+    /// Biometry or the device passcode on every read, never synced.
+    UserPresenceThisDeviceOnly,
+    /// The fingers or faces enrolled when the item was added on every read,
+    /// never synced.
+    ///
+    /// Enrolling another finger or face makes the item unreadable.
+    BiometryThisDeviceOnly,
+}
+
+/// All presets, for lookups.
+const PRESETS: [Preset; 7] = [
+    Preset::DeviceOnlyWhenUnlocked,
+    Preset::DeviceOnlyAfterFirstUnlock,
+    Preset::SyncedWhenUnlocked,
+    Preset::SyncedAfterFirstUnlock,
+    Preset::PasscodeSetThisDeviceOnly,
+    Preset::UserPresenceThisDeviceOnly,
+    Preset::BiometryThisDeviceOnly,
+];
+
+impl Preset {
+    /// The name of this preset, as printed and parsed.
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::DeviceOnlyWhenUnlocked => "device-only-when-unlocked",
+            Preset::DeviceOnlyAfterFirstUnlock => "device-only-after-first-unlock",
+            Preset::SyncedWhenUnlocked => "synced-when-unlocked",
+            Preset::SyncedAfterFirstUnlock => "synced-after-first-unlock",
+            Preset::PasscodeSetThisDeviceOnly => "passcode-set-this-device-only",
+            Preset::UserPresenceThisDeviceOnly => "user-presence-this-device-only",
+            Preset::BiometryThisDeviceOnly => "biometry-this-device-only",
+        }
+    }
+
+    /// When items of this preset are accessible.
+    ///
+    /// ```
+    /// use rust_ffi_demo::keychain::protection::{Accessibility, Preset};
+    ///
+    /// assert_eq!(Preset::SyncedAfterFirstUnlock.accessibility(), Accessibility::AfterFirstUnlock);
+    /// assert!(Preset::SyncedAfterFirstUnlock.synchronizable());
+    /// assert_eq!(Preset::SyncedAfterFirstUnlock.flag(), None);
+    /// ```
+    pub fn accessibility(self) -> Accessibility {
+        match self {
+            Preset::DeviceOnlyWhenUnlocked
+            | Preset::UserPresenceThisDeviceOnly
+            | Preset::BiometryThisDeviceOnly => Accessibility::WhenUnlockedThisDeviceOnly,
+            Preset::DeviceOnlyAfterFirstUnlock => Accessibility::AfterFirstUnlockThisDeviceOnly,
+            Preset::SyncedWhenUnlocked => Accessibility::WhenUnlocked,
+            Preset::SyncedAfterFirstUnlock => Accessibility::AfterFirstUnlock,
+            Preset::PasscodeSetThisDeviceOnly => Accessibility::WhenPasscodeSetThisDeviceOnly,
+        }
+    }
+
+    /// Whether items of this preset sync with iCloud Keychain.
+    pub fn synchronizable(self) -> bool {
+        matches!(
+            self,
+            Preset::SyncedWhenUnlocked | Preset::SyncedAfterFirstUnlock
+        )
+    }
+
+    /// The authentication items of this preset demand, if any.
+    pub fn flag(self) -> Option<Flag> {
+        match self {
+            Preset::UserPresenceThisDeviceOnly => Some(Flag::UserPresence),
+            Preset::BiometryThisDeviceOnly => Some(Flag::BiometryCurrentSet),
+            _ => None,
+        }
+    }
+
+    /// Whether `protection` is the accessibility and flag of this preset.
+    ///
+    /// ```
+    /// use rust_ffi_demo::keychain::protection::{Accessibility, Flag, Preset, Protection};
+    ///
+    /// let protection = Protection {
+    ///     accessibility: Some(Accessibility::WhenUnlockedThisDeviceOnly),
+    ///     flags: vec![Flag::UserPresence],
+    /// };
+    /// assert!(Preset::UserPresenceThisDeviceOnly.matches(&protection));
+    /// assert!(!Preset::DeviceOnlyWhenUnlocked.matches(&protection));
+    /// ```
+    ///
+    /// Protections carry no iCloud sync, but presets which sync differ in
+    /// accessibility from those which don't.
+    pub fn matches(self, protection: &Protection) -> bool {
+        protection.accessibility == Some(self.accessibility())
+            && protection.flags == self.flag().into_iter().collect::<Vec<_>>()
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Preset {
+    type Err = KeychainError;
+
+    fn from_str(s: &str) -> Result<Preset> {
+        PRESETS
+            .iter()
+            .cloned()
+            .find(|preset| preset.name() == s)
+            .ok_or_else(|| KeychainError::invalid_data(format!("Unknown protection preset: {}", s)))
+    }
+}
+
+/// The access control of an item.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Protection {
+    /// When the item is accessible, if known.
+    pub accessibility: Option<Accessibility>,
+    /// The constraints the item demands, in no particular order.
+    ///
+    /// Empty if the access control only restricts accessibility.
+    pub flags: Vec<Flag>,
+}
+
+impl Protection {
+    /// Whether the item demands any authentication at all.
+    pub fn requires_authentication(&self) -> bool {
+        !self.flags.is_empty()
+    }
+}
+
+impl fmt::Display for Protection {
+    /// Print flags separated by `+`, or `none`, and the accessibility.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.flags.is_empty() {
+            write!(f, "none")?;
+        } else {
+            let flags: Vec<&str> = self.flags.iter().map(|flag| flag.name()).collect();
+            write!(f, "{}", flags.join("+"))?;
+        }
+        if let Some(accessibility) = self.accessibility {
+            write!(f, " ({})", accessibility)?;
+        }
+        Ok(())
+    }
+}
+
+/// What it takes to read the data of an item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Readability {
+    /// Any process of this user can read the data right now.
+    FreelyReadable,
+    /// The keychain refuses to hand out the data without asking the user,
+    /// because it is locked, or on macOS because the access list of the item
+    /// doesn't admit this binary.
+    RequiresUnlock,
+    /// The access control of the item demands biometry or the device
+    /// passcode on every read.
+    RequiresUserPresence,
+}
+
+impl Readability {
+    /// The name of this readability, as printed.
+    pub fn name(self) -> &'static str {
+        match self {
+            Readability::FreelyReadable => "freely-readable",
+            Readability::RequiresUnlock => "requires-unlock",
+            Readability::RequiresUserPresence => "requires-user-presence",
+        }
+    }
+}
+
+impl fmt::Display for Readability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Guess flags from the description of an access control.
+///
+/// Descriptions list constraints like `cbio(…)` for biometry; the biometry
+/// constraint of the current set also names a hash of the enrolled set.
+fn flags_from_description(description: &str) -> Vec<Flag> {
+    let mut flags = Vec::new();
+    if description.contains("cup(") {
+        flags.push(Flag::UserPresence);
+    }
+    if description.contains("pbioh(") {
+        flags.push(Flag::BiometryCurrentSet);
+    } else if description.contains("cbio(") {
+        flags.push(Flag::BiometryAny);
+    }
+    if description.contains("cpo(") {
+        flags.push(Flag::DevicePasscode);
+    }
+    flags
+}
+
+/// Read the protection from the `attributes` of an item.
+///
+/// Return `None` if the item has no access control.
+pub(crate) unsafe fn from_attributes(attributes: CFDictionaryRef) -> Option<Protection> {
+    let access_control = CFDictionaryGetValue(attributes, kSecAttrAccessControl() as *const c_void);
+    if access_control.is_null() || CFGetTypeID(access_control) != SecAccessControlGetTypeID() {
+        return None;
+    }
+    let accessible = CFDictionaryGetValue(attributes, kSecAttrAccessible() as *const c_void);
+    let accessibility = if accessible.is_null() {
+        None
+    } else {
+        Accessibility::from_cf_type(accessible)
+    };
+    let description = CFCopyDescription(access_control);
+    assert!(!description.is_null());
+    let text = string_from_cf_string(description);
+    CFRelease(description as CFTypeRef);
+    let flags = flags_from_description(&text.unwrap_or_default());
+    Some(Protection {
+        accessibility,
+        flags,
+    })
+}
+
+/// Run `f` with a query for the generic password of `service` and `account`
+/// in the data protection keychain.
+unsafe fn with_protected_query<T, F>(
+    service: &str,
+    account: &str,
+    extra: &[(CFTypeRef, CFTypeRef)],
+    f: F,
+) -> T
+where
+    F: FnOnce(&[(CFTypeRef, CFTypeRef)]) -> T,
+{
+    let cf_service = create_cf_string_no_copy(service) as CFTypeRef;
+    let cf_account = create_cf_string_no_copy(account) as CFTypeRef;
+    let mut query = vec![
+        (
+            kSecClass() as CFTypeRef,
+            kSecClassGenericPassword() as CFTypeRef,
+        ),
+        (kSecAttrService() as CFTypeRef, cf_service),
+        (kSecAttrAccount() as CFTypeRef, cf_account),
+        (
+            kSecUseDataProtectionKeychain() as CFTypeRef,
+            kCFBooleanTrue as CFTypeRef,
+        ),
+    ];
+    query.extend_from_slice(extra);
+    let result = f(&query);
+    CFRelease(cf_service);
+    CFRelease(cf_account);
+    result
+}
+
+/// Create the label, kind and comment of `item` for a new item.
+///
+/// Without label in `item` use the default label; see `labels`.
+///
+/// # Safety
+///
+/// The caller must call `CFRelease` on all returned values.
+unsafe fn create_optional_attributes(item: &Item) -> Vec<(CFTypeRef, CFTypeRef)> {
+    let label = labels::for_new_item(item.label.as_deref(), &item.service, &item.account);
+    let optional = [
+        (kSecAttrLabel(), label.as_ref()),
+        (kSecAttrDescription(), item.description.as_ref()),
+        (kSecAttrComment(), item.comment.as_ref()),
+    ];
+    optional
+        .iter()
+        .filter_map(|&(key, value)| {
+            value.map(|value| (key as CFTypeRef, create_cf_string(value) as CFTypeRef))
+        })
+        .collect()
+}
+
+/// Add a generic password protected by `flag` to the data protection keychain.
+///
+/// Take service, account, label, kind and comment from `item`, and make the
+/// item accessible according to `accessibility`.  Reading the data of the new
+/// item demands the authentication of `flag`.
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::DuplicateItem` if the data protection keychain
+/// already has an item with the service and account of `item`,
+/// `KeychainErrorCode::MissingEntitlement` if this binary may not use the data
+/// protection keychain, and `KeychainError` if keychain access fails otherwise.
+pub fn add_protected_item(
+    item: &Item,
+    data: &[u8],
+    accessibility: Accessibility,
+    flag: Flag,
+) -> Result<()> {
+    read_only::check()?;
+    metrics::measure("add_protected_item", || unsafe {
+        let mut error: CFErrorRef = ptr::null_mut();
+        let access_control = SecAccessControlCreateWithFlags(
+            ptr::null(),
+            accessibility.as_cf_string() as CFTypeRef,
+            flag.as_create_flags(),
+            &mut error,
+        );
+        if access_control.is_null() {
+            return Err(KeychainError::from_cf_error(error));
+        }
+        let cf_data = create_cf_data_no_copy(data) as CFTypeRef;
+        let cf_optional = create_optional_attributes(item);
+        let mut extra = vec![
+            (kSecValueData() as CFTypeRef, cf_data),
+            (
+                kSecAttrAccessControl() as CFTypeRef,
+                access_control as CFTypeRef,
+            ),
+        ];
+        extra.extend_from_slice(&cf_optional);
+        let status = with_protected_query(&item.service, &item.account, &extra, |items| {
+            let attributes = create_dictionary(items);
+            assert!(!attributes.is_null());
+            let status = SecItemAdd(attributes, ptr::null_mut());
+            CFRelease(attributes as CFTypeRef);
+            status
+        });
+        CFRelease(access_control as CFTypeRef);
+        CFRelease(cf_data);
+        for &(_, cf_value) in &cf_optional {
+            CFRelease(cf_value);
+        }
+        status_to_result(status)
+    })
+}
+
+/// Add a generic password protected by `preset` to the data protection
+/// keychain.
+///
+/// Take service, account, label, kind and comment from `item`.  With a flag
+/// in `preset` this is `add_protected_item`.
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::DuplicateItem` if the data protection keychain
+/// already has an item with the service and account of `item`,
+/// `KeychainErrorCode::MissingEntitlement` if this binary may not use the data
+/// protection keychain or iCloud Keychain, and `KeychainError` if keychain
+/// access fails otherwise.
+pub fn add_preset_item(item: &Item, data: &[u8], preset: Preset) -> Result<()> {
+    if let Some(flag) = preset.flag() {
+        return add_protected_item(item, data, preset.accessibility(), flag);
+    }
+    read_only::check()?;
+    metrics::measure("add_preset_item", || unsafe {
+        let cf_data = create_cf_data_no_copy(data) as CFTypeRef;
+        let cf_optional = create_optional_attributes(item);
+        let synchronizable = if preset.synchronizable() {
+            kCFBooleanTrue
+        } else {
+            kCFBooleanFalse
+        };
+        let mut extra = vec![
+            (kSecValueData() as CFTypeRef, cf_data),
+            (
+                kSecAttrAccessible() as CFTypeRef,
+                preset.accessibility().as_cf_string() as CFTypeRef,
+            ),
+            (
+                kSecAttrSynchronizable() as CFTypeRef,
+                synchronizable as CFTypeRef,
+            ),
+        ];
+        extra.extend_from_slice(&cf_optional);
+        let status = with_protected_query(&item.service, &item.account, &extra, |items| {
+            let attributes = create_dictionary(items);
+            assert!(!attributes.is_null());
+            let status = SecItemAdd(attributes, ptr::null_mut());
+            CFRelease(attributes as CFTypeRef);
+            status
+        });
+        CFRelease(cf_data);
+        for &(_, cf_value) in &cf_optional {
+            CFRelease(cf_value);
+        }
+        status_to_result(status)
+    })
+}
+
+/// Change the protection of an item in the data protection keychain to
+/// `preset`, and set its label, if any, and its data, if given.
+///
+/// Take service, account and label from `item`.  Whether the item syncs with
+/// iCloud Keychain stays as it is.
+///
+/// # Errors
+///
+/// Return `KeychainError` if the item does not exist, or keychain access fails
+/// otherwise.
+pub fn update_preset_item(item: &Item, data: Option<&[u8]>, preset: Preset) -> Result<()> {
+    read_only::check()?;
+    metrics::measure("update_preset_item", || unsafe {
+        let access_control = match preset.flag() {
+            Some(flag) => {
+                let mut error: CFErrorRef = ptr::null_mut();
+                let access_control = SecAccessControlCreateWithFlags(
+                    ptr::null(),
+                    preset.accessibility().as_cf_string() as CFTypeRef,
+                    flag.as_create_flags(),
+                    &mut error,
+                );
+                if access_control.is_null() {
+                    return Err(KeychainError::from_cf_error(error));
+                }
+                Some(access_control as CFTypeRef)
+            }
+            None => None,
+        };
+        let cf_data = data.map(|data| create_cf_data_no_copy(data) as CFTypeRef);
+        let cf_label = item
+            .label
+            .as_ref()
+            .map(|label| create_cf_string_no_copy(label) as CFTypeRef);
+        let mut changes = vec![match access_control {
+            Some(access_control) => (kSecAttrAccessControl() as CFTypeRef, access_control),
+            None => (
+                kSecAttrAccessible() as CFTypeRef,
+                preset.accessibility().as_cf_string() as CFTypeRef,
+            ),
+        }];
+        if let Some(cf_data) = cf_data {
+            changes.push((kSecValueData() as CFTypeRef, cf_data));
+        }
+        if let Some(cf_label) = cf_label {
+            changes.push((kSecAttrLabel() as CFTypeRef, cf_label));
+        }
+        let attributes = create_dictionary(&changes);
+        assert!(!attributes.is_null());
+        let status = with_protected_query(&item.service, &item.account, &[], |items| {
+            let query = create_dictionary(items);
+            assert!(!query.is_null());
+            let status = SecItemUpdate(query, attributes);
+            CFRelease(query as CFTypeRef);
+            status
+        });
+        CFRelease(attributes as CFTypeRef);
+        for cf_value in access_control.into_iter().chain(cf_data).chain(cf_label) {
+            CFRelease(cf_value);
+        }
+        status_to_result(status)
+    })
+}
+
+/// Find the attributes of the generic password of `service` and `account` in
+/// the data protection keychain.
+///
+/// Only reads attributes, so this does not demand authentication.
+///
+/// # Errors
+///
+/// Return `KeychainError` if the item does not exist, or keychain access fails
+/// otherwise.
+pub fn find_protected_item(service: &str, account: &str) -> Result<Item> {
+    metrics::measure("find_protected_item", || unsafe {
+        let extra = [
+            (
+                kSecMatchLimit() as CFTypeRef,
+                kSecMatchLimitOne() as CFTypeRef,
+            ),
+            (
+                kSecReturnAttributes() as CFTypeRef,
+                kCFBooleanTrue as CFTypeRef,
+            ),
+        ];
+        let attributes =
+            with_protected_query(service, account, &extra, |items| copy_matching(items))?;
+        let attributes = owned_with_type(attributes, "CFDictionary", CFDictionaryGetTypeID())?;
+        let item = items::item_from_attributes(attributes as CFDictionaryRef);
+        CFRelease(attributes);
+        Ok(item)
+    })
+}
+
+/// Find the data of the generic password of `service` and `account` in the
+/// data protection keychain.
+///
+/// Prompts for authentication if the item demands it.
+///
+/// # Errors
+///
+/// Return `KeychainError` if the item does not exist, the user cancels
+/// authentication, or keychain access fails otherwise.
+pub fn find_protected_data(service: &str, account: &str) -> Result<SecretBytes> {
+    metrics::measure("find_protected_data", || unsafe {
+        let extra = [
+            (
+                kSecMatchLimit() as CFTypeRef,
+                kSecMatchLimitOne() as CFTypeRef,
+            ),
+            (kSecReturnData() as CFTypeRef, kCFBooleanTrue as CFTypeRef),
+        ];
+        let data = with_protected_query(service, account, &extra, |items| copy_matching(items))?;
+        let bytes = cast_cf_data(data).and_then(|data| vec_from_cfdata(data));
+        CFRelease(data);
+        Ok(bytes?)
+    })
+}
+
+/// Find out what it takes to read the generic password of `service` and
+/// `account`.
+///
+/// Look in `keychain` if given, and otherwise in the search list and then the
+/// data protection keychain.  Read the attributes first, and only if they
+/// demand no authentication try to read the data without interaction, so this
+/// never prompts.  The data itself is discarded right away.
+///
+/// ```no_run
+/// use rust_ffi_demo::keychain::protection::{self, Readability};
+///
+/// let readability = protection::probe_access(None, "api.example.com", "jane").unwrap();
+/// if readability == Readability::FreelyReadable {
+///     println!("api.example.com is not protected");
+/// }
+/// ```
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::ItemNotFound` if the item does not exist, and
+/// `KeychainError` if keychain access fails otherwise.
+pub fn probe_access(
+    keychain: Option<&Keychain>,
+    service: &str,
+    account: &str,
+) -> Result<Readability> {
+    metrics::measure("probe_access", || unsafe {
+        let (item, protected) = match items::find_item(keychain, service, account) {
+            Ok(item) => (item, false),
+            Err(ref error)
+                if keychain.is_none() && error.status == KeychainErrorCode::ItemNotFound =>
+            {
+                (find_protected_item(service, account)?, true)
+            }
+            Err(error) => return Err(error),
+        };
+        if item
+            .protection
+            .as_ref()
+            .is_some_and(Protection::requires_authentication)
+        {
+            return Ok(Readability::RequiresUserPresence);
+        }
+        let extra = [
+            (
+                kSecMatchLimit() as CFTypeRef,
+                kSecMatchLimitOne() as CFTypeRef,
+            ),
+            (kSecReturnData() as CFTypeRef, kCFBooleanTrue as CFTypeRef),
+            (
+                kSecUseAuthenticationUI() as CFTypeRef,
+                kSecUseAuthenticationUIFail() as CFTypeRef,
+            ),
+        ];
+        let mut result: CFTypeRef = ptr::null();
+        let status = if protected {
+            with_protected_query(service, account, &extra, |items| {
+                let query = create_dictionary(items);
+                assert!(!query.is_null());
+                let status = SecItemCopyMatching(query, &mut result);
+                CFRelease(query as CFTypeRef);
+                status
+            })
+        } else {
+            items::with_item_query(keychain, service, account, &extra, |query| {
+                SecItemCopyMatching(query, &mut result)
+            })
+        };
+        if status == errSecInteractionNotAllowed {
+            return Ok(Readability::RequiresUnlock);
+        }
+        status_to_result(status)?;
+        assert!(!result.is_null());
+        CFRelease(result);
+        Ok(Readability::FreelyReadable)
+    })
+}
+
+/// Delete the generic password of `service` and `account` from the data
+/// protection keychain.
+///
+/// # Errors
+///
+/// Return `KeychainError` if the item does not exist, or keychain access fails
+/// otherwise.
+pub fn delete_protected_item(service: &str, account: &str) -> Result<()> {
+    read_only::check()?;
+    metrics::measure("delete_protected_item", || unsafe {
+        let status = with_protected_query(service, account, &[], |items| {
+            let query = create_dictionary(items);
+            assert!(!query.is_null());
+            let status = SecItemDelete(query);
+            CFRelease(query as CFTypeRef);
+            status
+        });
+        status_to_result(status)
+    })
+}
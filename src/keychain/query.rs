@@ -0,0 +1,179 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Select items by tags, services and dates.
+//!
+//! A `FilterExpr` combines predicates over the attributes and metadata of
+//! items with AND, OR and NOT.  Filters run on attributes-only listings in
+//! this process, so selecting items never reads secrets or prompts:
+//!
+//! ```
+//! use rust_ffi_demo::keychain::items::Item;
+//! use rust_ffi_demo::keychain::metadata::Metadata;
+//! use rust_ffi_demo::keychain::query::FilterExpr;
+//!
+//! let mut metadata = Metadata::new();
+//! metadata.add_tag("work").unwrap();
+//! let mut item = Item::new("github.com", "jane");
+//! item.generic = Some(metadata.to_bytes());
+//!
+//! let forge = FilterExpr::service("github*").or(FilterExpr::service("gitlab*"));
+//! let filter = FilterExpr::tag("work").and(forge);
+//! assert!(filter.matches(&item));
+//! assert!(!FilterExpr::tag("home").matches(&item));
+//! assert!(FilterExpr::tag("home").negate().matches(&item));
+//! ```
+
+use std::time::SystemTime;
+
+use super::items::{self, Item, ListOptions};
+use super::keychains::Keychain;
+use super::Result;
+use crate::pattern::glob_match;
+
+/// A predicate over items.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterExpr {
+    /// Every item.
+    All,
+    /// Items with this tag in their metadata.
+    Tag(String),
+    /// Items whose service matches this wildcard pattern.
+    Service(String),
+    /// Items whose account matches this wildcard pattern.
+    Account(String),
+    /// Items created before this time.
+    CreatedBefore(SystemTime),
+    /// Items created after this time.
+    CreatedAfter(SystemTime),
+    /// Items last modified before this time.
+    ModifiedBefore(SystemTime),
+    /// Items last modified after this time.
+    ModifiedAfter(SystemTime),
+    /// Items whose metadata says they expire before this time.
+    ExpiresBefore(SystemTime),
+    /// Items matching all of these filters.
+    And(Vec<FilterExpr>),
+    /// Items matching any of these filters.
+    Or(Vec<FilterExpr>),
+    /// Items not matching this filter.
+    Not(Box<FilterExpr>),
+}
+
+impl Default for FilterExpr {
+    /// Every item.
+    fn default() -> FilterExpr {
+        FilterExpr::All
+    }
+}
+
+impl FilterExpr {
+    /// Items with `tag`.
+    pub fn tag<S: Into<String>>(tag: S) -> FilterExpr {
+        FilterExpr::Tag(tag.into())
+    }
+
+    /// Items whose service matches the wildcard `pattern`.
+    pub fn service<S: Into<String>>(pattern: S) -> FilterExpr {
+        FilterExpr::Service(pattern.into())
+    }
+
+    /// Items whose account matches the wildcard `pattern`.
+    pub fn account<S: Into<String>>(pattern: S) -> FilterExpr {
+        FilterExpr::Account(pattern.into())
+    }
+
+    /// Items with all of `tags`.
+    pub fn all_tags<I, S>(tags: I) -> FilterExpr
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        FilterExpr::And(tags.into_iter().map(FilterExpr::tag).collect())
+    }
+
+    /// Items matching this filter and `other`.
+    pub fn and(self, other: FilterExpr) -> FilterExpr {
+        match self {
+            FilterExpr::All => other,
+            FilterExpr::And(mut filters) => {
+                filters.push(other);
+                FilterExpr::And(filters)
+            }
+            filter => FilterExpr::And(vec![filter, other]),
+        }
+    }
+
+    /// Items matching this filter or `other`.
+    pub fn or(self, other: FilterExpr) -> FilterExpr {
+        match self {
+            FilterExpr::Or(mut filters) => {
+                filters.push(other);
+                FilterExpr::Or(filters)
+            }
+            filter => FilterExpr::Or(vec![filter, other]),
+        }
+    }
+
+    /// Items not matching this filter.
+    pub fn negate(self) -> FilterExpr {
+        FilterExpr::Not(Box::new(self))
+    }
+
+    /// Whether `item` matches this filter.
+    ///
+    /// Date predicates never match items without the date.  Tag and expiry
+    /// predicates never match items with invalid metadata, or data of other
+    /// applications in their generic attribute.
+    pub fn matches(&self, item: &Item) -> bool {
+        let before =
+            |time: Option<SystemTime>, limit: &SystemTime| time.is_some_and(|t| t < *limit);
+        let after = |time: Option<SystemTime>, limit: &SystemTime| time.is_some_and(|t| *limit < t);
+        match *self {
+            FilterExpr::All => true,
+            FilterExpr::Tag(ref tag) => item.metadata().is_ok_and(|m| m.has_tag(tag)),
+            FilterExpr::Service(ref pattern) => glob_match(pattern, &item.service),
+            FilterExpr::Account(ref pattern) => glob_match(pattern, &item.account),
+            FilterExpr::CreatedBefore(ref limit) => before(item.created, limit),
+            FilterExpr::CreatedAfter(ref limit) => after(item.created, limit),
+            FilterExpr::ModifiedBefore(ref limit) => before(item.modified, limit),
+            FilterExpr::ModifiedAfter(ref limit) => after(item.modified, limit),
+            FilterExpr::ExpiresBefore(ref limit) => {
+                before(item.metadata().ok().and_then(|m| m.expires()), limit)
+            }
+            FilterExpr::And(ref filters) => filters.iter().all(|filter| filter.matches(item)),
+            FilterExpr::Or(ref filters) => filters.iter().any(|filter| filter.matches(item)),
+            FilterExpr::Not(ref filter) => !filter.matches(item),
+        }
+    }
+}
+
+/// List the items matching `filter`, with `options`.
+///
+/// If `keychain` is given only list items in this keychain.  Only reads
+/// attributes, so this does not prompt for access.
+///
+/// # Errors
+///
+/// Return `KeychainError` if keychain access fails.  Return an empty list
+/// rather than `KeychainErrorCode::ItemNotFound` if no items match.
+pub fn list_matching(
+    keychain: Option<&Keychain>,
+    options: &ListOptions,
+    filter: &FilterExpr,
+) -> Result<Vec<Item>> {
+    let mut listed = items::list_items_sorted(keychain, options)?;
+    listed.retain(|item| filter.matches(item));
+    Ok(listed)
+}
@@ -0,0 +1,65 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! A global read-only mode.
+//!
+//! In read-only mode all functions of this crate which add, change or delete
+//! items, keys or keychains fail with `KeychainErrorCode::ReadOnly` before they
+//! touch the keychain, so audit tools and untrusted scripts cannot change
+//! anything even if they try.  Reads which would upgrade items on the way,
+//! like reads of outdated metadata, still work and leave the items alone.
+//!
+//! Enable read-only mode with `enable`, or by setting `KEYCHAIN_READONLY=1` in
+//! the environment.  There is deliberately no way to leave read-only mode.
+
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+use super::{correlation, KeychainError, KeychainErrorCode, Result};
+
+/// The environment variable which enables read-only mode.
+pub const READ_ONLY_VAR: &str = "KEYCHAIN_READONLY";
+
+/// Whether `enable` was called.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Enable read-only mode for the rest of the process.
+pub fn enable() {
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+/// Whether read-only mode is enabled.
+pub fn is_enabled() -> bool {
+    static FROM_ENV: OnceLock<bool> = OnceLock::new();
+    ENABLED.load(Ordering::SeqCst)
+        || *FROM_ENV.get_or_init(|| env::var_os(READ_ONLY_VAR).is_some_and(|value| value == "1"))
+}
+
+/// Fail if read-only mode is enabled.
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::ReadOnly` in read-only mode.
+pub(super) fn check() -> Result<()> {
+    if is_enabled() {
+        Err(KeychainError {
+            status: KeychainErrorCode::ReadOnly,
+            message: "The keychain is in read-only mode".to_string(),
+            correlation_id: correlation::current(),
+        })
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Encrypt data under keys in the keychain.
+//!
+//! `seal` encrypts data with ECIES, ie, AES-GCM under a key agreed between a
+//! fresh ephemeral key and a P-256 key in the keychain, and `open` decrypts it
+//! again with the private key, which never leaves the keychain.  Keys are
+//! identified by label, and `seal` creates the key of a label on first use:
+//!
+//! ```no_run
+//! use rust_ffi_demo::keychain::seal;
+//!
+//! let sealed = seal::seal("com.example.app.database", b"some data").unwrap();
+//! assert_eq!(seal::open("com.example.app.database", &sealed).unwrap(), b"some data");
+//! ```
+//!
+//! Sealed data holds the ephemeral public key and the authentication tag, so
+//! it's 81 bytes larger than the plain text.  Deleting the key loses all data
+//! sealed under it.
+
+use std::ptr;
+
+use super::cfutil::*;
+use super::metrics;
+use super::native::*;
+use super::read_only;
+use super::{copy_matching, owned_with_type, KeychainError, KeychainErrorCode, Result};
+
+/// The application tag of sealing keys, to tell them from other keys.
+const TAG: &[u8] = b"rust-ffi-demo.seal";
+
+/// Copy the private sealing key with `label`.
+///
+/// # Safety
+///
+/// The caller must call `CFRelease` on the returned key.
+unsafe fn copy_key(label: &str) -> Result<SecKeyRef> {
+    let cf_label = create_cf_string_no_copy(label);
+    let cf_tag = create_cf_data_no_copy(TAG);
+    let c = constants();
+    let items = [
+        (c.class, kSecClassKey() as CFTypeRef),
+        (
+            kSecAttrKeyClass() as CFTypeRef,
+            kSecAttrKeyClassPrivate() as CFTypeRef,
+        ),
+        (kSecAttrLabel() as CFTypeRef, cf_label as CFTypeRef),
+        (kSecAttrApplicationTag() as CFTypeRef, cf_tag as CFTypeRef),
+        (c.match_limit, c.match_limit_one),
+        (kSecReturnRef() as CFTypeRef, c.boolean_true),
+    ];
+    let result = copy_matching(&items);
+    CFRelease(cf_tag as CFTypeRef);
+    CFRelease(cf_label as CFTypeRef);
+    result
+        .and_then(|r| owned_with_type(r, "SecKey", SecKeyGetTypeID()))
+        .map(|key| key as SecKeyRef)
+}
+
+/// Create a private sealing key with `label`.
+///
+/// # Safety
+///
+/// The caller must call `CFRelease` on the returned key.
+unsafe fn create_key(label: &str) -> Result<SecKeyRef> {
+    read_only::check()?;
+    let cf_label = create_cf_string_no_copy(label);
+    let cf_tag = create_cf_data_no_copy(TAG);
+    let cf_size = create_cf_number(256);
+    let private_attributes = create_dictionary(&[
+        (
+            kSecAttrIsPermanent() as CFTypeRef,
+            kCFBooleanTrue as CFTypeRef,
+        ),
+        (kSecAttrLabel() as CFTypeRef, cf_label as CFTypeRef),
+        (kSecAttrApplicationTag() as CFTypeRef, cf_tag as CFTypeRef),
+    ]);
+    assert!(!private_attributes.is_null());
+    let parameters = create_dictionary(&[
+        (
+            kSecAttrKeyType() as CFTypeRef,
+            kSecAttrKeyTypeECSECPrimeRandom() as CFTypeRef,
+        ),
+        (kSecAttrKeySizeInBits() as CFTypeRef, cf_size as CFTypeRef),
+        (
+            kSecPrivateKeyAttrs() as CFTypeRef,
+            private_attributes as CFTypeRef,
+        ),
+    ]);
+    assert!(!parameters.is_null());
+
+    let mut error: CFErrorRef = ptr::null_mut();
+    let key = SecKeyCreateRandomKey(parameters, &mut error);
+
+    CFRelease(parameters as CFTypeRef);
+    CFRelease(private_attributes as CFTypeRef);
+    CFRelease(cf_size as CFTypeRef);
+    CFRelease(cf_tag as CFTypeRef);
+    CFRelease(cf_label as CFTypeRef);
+
+    if key.is_null() {
+        Err(KeychainError::from_cf_error(error))
+    } else {
+        Ok(key)
+    }
+}
+
+/// Transform `data` with `key`, for `SecKeyCreateEncryptedData` and
+/// `SecKeyCreateDecryptedData`.
+unsafe fn transform(
+    key: SecKeyRef,
+    data: &[u8],
+    f: unsafe extern "C" fn(SecKeyRef, SecKeyAlgorithm, CFDataRef, *mut CFErrorRef) -> CFDataRef,
+) -> Result<Vec<u8>> {
+    let cf_data = create_cf_data_no_copy(data);
+    let mut error: CFErrorRef = ptr::null_mut();
+    let result = f(
+        key,
+        kSecKeyAlgorithmECIESEncryptionCofactorVariableIVX963SHA256AESGCM(),
+        cf_data,
+        &mut error,
+    );
+    CFRelease(cf_data as CFTypeRef);
+    if result.is_null() {
+        return Err(KeychainError::from_cf_error(error));
+    }
+    let bytes = vec_from_cfdata(result);
+    CFRelease(result as CFTypeRef);
+    Ok(bytes?)
+}
+
+/// Encrypt `plaintext` under the key with `label`.
+///
+/// Create the key if it doesn't exist yet.
+///
+/// # Errors
+///
+/// Return `KeychainError` if the key cannot be found or created, or
+/// encryption fails.
+pub fn seal(label: &str, plaintext: &[u8]) -> Result<Vec<u8>> {
+    metrics::measure("seal", || unsafe {
+        let key = match copy_key(label) {
+            Err(ref error) if error.status == KeychainErrorCode::ItemNotFound => create_key(label)?,
+            result => result?,
+        };
+        let public_key = SecKeyCopyPublicKey(key);
+        CFRelease(key as CFTypeRef);
+        assert!(!public_key.is_null());
+        let result = transform(public_key, plaintext, SecKeyCreateEncryptedData);
+        CFRelease(public_key as CFTypeRef);
+        result
+    })
+}
+
+/// Decrypt `ciphertext` sealed under the key with `label`.
+///
+/// Using the private key may prompt for access.
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::ItemNotFound` if there is no key with `label`,
+/// and `KeychainError` if decryption fails, eg, because `ciphertext` was
+/// sealed under another key or modified.
+pub fn open(label: &str, ciphertext: &[u8]) -> Result<Vec<u8>> {
+    metrics::measure("open", || unsafe {
+        let key = copy_key(label)?;
+        let result = transform(key, ciphertext, SecKeyCreateDecryptedData);
+        CFRelease(key as CFTypeRef);
+        result
+    })
+}
@@ -0,0 +1,208 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Values which may hold secrets, redacted wherever they are shown.
+//!
+//! A `Sensitive` wraps a secret so that formatting and serializing it never
+//! shows the secret itself, but a redaction:
+//!
+//! ```
+//! use rust_ffi_demo::keychain::sensitive::{self, Sensitive};
+//!
+//! let password = Sensitive::new("hunter2".to_string());
+//! assert_eq!(format!("{} {:?}", password, password), "[redacted] Sensitive([redacted])");
+//! assert_eq!(serde_json::to_string(&password).unwrap(), r#""[redacted]""#);
+//! assert_eq!(password.expose(), "hunter2");
+//!
+//! // Exports reveal secrets on purpose
+//! let exported = sensitive::revealing(|| serde_json::to_string(&password).unwrap());
+//! assert_eq!(exported, r#""hunter2""#);
+//! ```
+//!
+//! So a log or audit record which includes a struct with sensitive fields
+//! redacts them by construction; code which needs the secret says so with
+//! `expose`, and code which writes secrets on purpose, like exports, runs in
+//! `revealing`.  Deserializing takes the plain secret.
+//!
+//! The process-wide `Redaction` says how much a redaction tells about the
+//! secret: nothing, its length, or the start of its SHA-256 digest, to tell
+//! whether two redacted secrets are the same.  A digest of a short or guessable
+//! secret gives it away to brute force, so only redact digests of random
+//! secrets.
+
+use std::cell::Cell;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::digest::sha256_hex;
+
+/// The environment variable which sets the redaction of the command line.
+pub const REDACTION_VAR: &str = "RUST_FFI_DEMO_REDACTION";
+
+/// How much a redaction tells about a secret.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Redaction {
+    /// Nothing, the default.
+    Full,
+    /// The length of the secret, in bytes.
+    Length,
+    /// The first 12 hex digits of the SHA-256 digest of the secret.
+    Digest,
+}
+
+impl Redaction {
+    /// The name of this redaction.
+    pub fn name(self) -> &'static str {
+        match self {
+            Redaction::Full => "full",
+            Redaction::Length => "length",
+            Redaction::Digest => "digest",
+        }
+    }
+}
+
+impl fmt::Display for Redaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Redaction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Redaction, String> {
+        match s {
+            "full" => Ok(Redaction::Full),
+            "length" => Ok(Redaction::Length),
+            "digest" => Ok(Redaction::Digest),
+            _ => Err(format!(
+                "Unknown redaction {}, expected one of full, length, digest",
+                s
+            )),
+        }
+    }
+}
+
+/// The process-wide redaction, as index of the `Redaction` variant.
+static REDACTION: AtomicU8 = AtomicU8::new(0);
+
+thread_local! {
+    /// Whether this thread reveals sensitive values when serializing.
+    static REVEALING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Redact secrets with `redaction` from now on.
+pub fn set_redaction(redaction: Redaction) {
+    REDACTION.store(redaction as u8, Ordering::Relaxed);
+}
+
+/// The current redaction.
+pub fn redaction() -> Redaction {
+    match REDACTION.load(Ordering::Relaxed) {
+        1 => Redaction::Length,
+        2 => Redaction::Digest,
+        _ => Redaction::Full,
+    }
+}
+
+/// Redact `secret` according to the current redaction.
+pub fn redact(secret: &[u8]) -> String {
+    match redaction() {
+        Redaction::Full => "[redacted]".to_string(),
+        Redaction::Length => format!("[redacted, {} bytes]", secret.len()),
+        Redaction::Digest => format!("[redacted, sha256:{}]", &sha256_hex(secret)[..12]),
+    }
+}
+
+/// Restores the previous state when `revealing` ends, even on panics.
+struct Restore(bool);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        REVEALING.with(|revealing| revealing.set(self.0));
+    }
+}
+
+/// Run `f` with serialization of sensitive values revealing their secrets.
+///
+/// Formatting still redacts.
+pub fn revealing<T, F: FnOnce() -> T>(f: F) -> T {
+    let _restore = Restore(REVEALING.with(|revealing| revealing.replace(true)));
+    f()
+}
+
+/// Run `f` with serialization of sensitive values redacting them, even
+/// within `revealing`.
+pub fn redacting<T, F: FnOnce() -> T>(f: F) -> T {
+    let _restore = Restore(REVEALING.with(|revealing| revealing.replace(false)));
+    f()
+}
+
+/// A value which may hold a secret.
+#[derive(Clone, PartialEq, Eq, Default)]
+pub struct Sensitive<T>(T);
+
+impl<T> Sensitive<T> {
+    /// Wrap `value`.
+    pub fn new(value: T) -> Sensitive<T> {
+        Sensitive(value)
+    }
+
+    /// The secret value.
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+
+    /// Unwrap the secret value.
+    pub fn into_exposed(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Sensitive<T> {
+    fn from(value: T) -> Sensitive<T> {
+        Sensitive(value)
+    }
+}
+
+impl<T: AsRef<[u8]>> fmt::Display for Sensitive<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", redact(self.0.as_ref()))
+    }
+}
+
+impl<T: AsRef<[u8]>> fmt::Debug for Sensitive<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Sensitive({})", redact(self.0.as_ref()))
+    }
+}
+
+impl<T: AsRef<[u8]> + Serialize> Serialize for Sensitive<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if REVEALING.with(Cell::get) {
+            self.0.serialize(serializer)
+        } else {
+            serializer.serialize_str(&redact(self.0.as_ref()))
+        }
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Sensitive<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Sensitive<T>, D::Error> {
+        T::deserialize(deserializer).map(Sensitive)
+    }
+}
@@ -0,0 +1,227 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Sessions which authenticate once for many secrets.
+//!
+//! Every lookup of a protected item on its own prompts the user.  A `Session`
+//! passes the same `LAContext` to all its lookups, so the user authenticates
+//! once, and remembers the items it found, so later lookups go straight to the
+//! item.  `Session::lock` forgets both, and `Session::watch` forgets items as
+//! they change and everything when a keychain gets locked:
+//!
+//! ```no_run
+//! use rust_ffi_demo::keychain::local_auth::Policy;
+//! use rust_ffi_demo::keychain::session::Session;
+//!
+//! let session = Session::new(None);
+//! session.authenticate(Policy::DeviceOwner, "read the database credentials").unwrap();
+//! let user = session.find("db.example.com", "user").unwrap();
+//! let password = session.find("db.example.com", "password").unwrap();
+//! session.lock();
+//! ```
+
+use std::collections::BTreeMap;
+use std::ptr;
+use std::sync::{Arc, Mutex};
+
+use super::cfutil::*;
+use super::events::{Event, EventKind, Subscription};
+use super::items;
+use super::keychains::Keychain;
+use super::local_auth::{AuthContext, Policy};
+use super::native::*;
+use super::{copy_matching, status_to_result, KeychainErrorCode, Result, SecretBytes};
+
+/// A retained reference to a keychain item.
+struct ItemRef(SecKeychainItemRef);
+
+// Item references are immutable CoreFoundation objects.
+unsafe impl Send for ItemRef {}
+
+impl Clone for ItemRef {
+    fn clone(&self) -> ItemRef {
+        unsafe { CFRetain(self.0 as CFTypeRef) };
+        ItemRef(self.0)
+    }
+}
+
+impl Drop for ItemRef {
+    fn drop(&mut self) {
+        unsafe { CFRelease(self.0 as CFTypeRef) };
+    }
+}
+
+/// The state of a session, shared with its subscription.
+struct State {
+    context: Mutex<AuthContext>,
+    items: Mutex<BTreeMap<(String, String), ItemRef>>,
+}
+
+impl State {
+    /// Replace the context and forget all items.
+    fn lock(&self) {
+        let mut context = self.context.lock().unwrap();
+        context.invalidate();
+        *context = AuthContext::new();
+        self.items.lock().unwrap().clear();
+    }
+
+    /// Forget what `event` makes stale.
+    fn handle(&self, event: &Event) {
+        match event.kind {
+            EventKind::Lock => self.lock(),
+            EventKind::Update | EventKind::Delete => {
+                let mut items = self.items.lock().unwrap();
+                match (&event.service, &event.account) {
+                    (Some(service), Some(account)) => {
+                        items.remove(&(service.clone(), account.clone()));
+                    }
+                    // Deleted items are already gone, so we can't tell which
+                    _ => items.clear(),
+                }
+            }
+            EventKind::DefaultChanged | EventKind::ListChanged => {
+                self.items.lock().unwrap().clear()
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A session for reading many secrets with a single authentication.
+///
+/// Sessions may be shared between threads.
+pub struct Session {
+    keychain: Option<Keychain>,
+    state: Arc<State>,
+}
+
+impl Session {
+    /// Create a session for items in `keychain`, or in the search list.
+    pub fn new(keychain: Option<Keychain>) -> Session {
+        Session {
+            keychain,
+            state: Arc::new(State {
+                context: Mutex::new(AuthContext::new()),
+                items: Mutex::new(BTreeMap::new()),
+            }),
+        }
+    }
+
+    /// Authenticate the user with `policy` up front, telling them `reason`.
+    ///
+    /// Lookups of protected items then use this authentication instead of
+    /// prompting on their own.  Without a prior authentication the first
+    /// lookup of a protected item prompts, and later lookups reuse it.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::AuthFailed` if the user did not authenticate.
+    pub fn authenticate(&self, policy: Policy, reason: &str) -> Result<()> {
+        self.state.context.lock().unwrap().evaluate(policy, reason)
+    }
+
+    /// Find the reference to the item of `service` and `account`.
+    ///
+    /// Only reads attributes, so this does not prompt.
+    unsafe fn find_ref(&self, service: &str, account: &str) -> Result<SecKeychainItemRef> {
+        let c = constants();
+        let extra = [
+            (c.match_limit, c.match_limit_one),
+            (kSecReturnRef() as CFTypeRef, c.boolean_true),
+        ];
+        let mut result: CFTypeRef = ptr::null();
+        let status =
+            items::with_item_query(self.keychain.as_ref(), service, account, &extra, |query| {
+                SecItemCopyMatching(query, &mut result)
+            });
+        status_to_result(status)?;
+        assert!(!result.is_null());
+        Ok(result as SecKeychainItemRef)
+    }
+
+    /// Copy the data of `item`, authenticating with the context.
+    unsafe fn copy_data(&self, item: SecKeychainItemRef) -> Result<SecretBytes> {
+        let c = constants();
+        let context = self.state.context.lock().unwrap();
+        let query = [
+            (c.class, c.class_generic_password),
+            (kSecValueRef() as CFTypeRef, item as CFTypeRef),
+            (c.return_data, c.boolean_true),
+            (
+                kSecUseAuthenticationContext() as CFTypeRef,
+                context.as_ptr() as CFTypeRef,
+            ),
+        ];
+        let result = copy_matching(&query)?;
+        let data = cast_cf_data(result).and_then(|data| vec_from_cfdata(data));
+        CFRelease(result);
+        Ok(data?)
+    }
+
+    /// Find the secret of `service` and `account`.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::ItemNotFound` if the item does not exist,
+    /// `KeychainErrorCode::AuthFailed` if the user did not authenticate, and
+    /// `KeychainError` if keychain access fails otherwise.
+    pub fn find(&self, service: &str, account: &str) -> Result<SecretBytes> {
+        let key = (service.to_string(), account.to_string());
+        let cached = self.state.items.lock().unwrap().get(&key).cloned();
+        unsafe {
+            if let Some(item) = cached {
+                match self.copy_data(item.0) {
+                    // The item went away since we found it
+                    Err(ref error) if error.status == KeychainErrorCode::ItemNotFound => {
+                        self.state.items.lock().unwrap().remove(&key);
+                    }
+                    result => return result,
+                }
+            }
+            let item = ItemRef(self.find_ref(service, account)?);
+            let data = self.copy_data(item.0)?;
+            self.state.items.lock().unwrap().insert(key, item);
+            Ok(data)
+        }
+    }
+
+    /// Whether this session remembers the item of `service` and `account`.
+    pub fn is_cached(&self, service: &str, account: &str) -> bool {
+        let key = (service.to_string(), account.to_string());
+        self.state.items.lock().unwrap().contains_key(&key)
+    }
+
+    /// Forget the authentication and all items of this session.
+    ///
+    /// The next lookup of a protected item prompts again.  This does not lock
+    /// any keychain.
+    pub fn lock(&self) {
+        self.state.lock()
+    }
+
+    /// Forget items as they change, and lock this session if a keychain gets
+    /// locked.
+    ///
+    /// Events only arrive while the current thread runs its run loop, see
+    /// `Subscription`.  Dropping the subscription stops watching.
+    ///
+    /// # Errors
+    ///
+    /// See `Subscription::new`.
+    pub fn watch(&self) -> Result<Subscription> {
+        let state = self.state.clone();
+        Subscription::new(move |event| state.handle(&event))
+    }
+}
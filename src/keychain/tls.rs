@@ -0,0 +1,272 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! TLS client certificates from keychain identities for rustls.
+//!
+//! An identity is a certificate with its private key.  `ClientCertResolver`
+//! finds an identity by label, or on macOS by identity preference for a host
+//! as set with `security set-identity-preference`, sends its certificate, and
+//! signs the handshake with its key, which never leaves the keychain:
+//!
+//! ```ignore
+//! use rust_ffi_demo::keychain::tls::ClientCertResolver;
+//! use std::sync::Arc;
+//!
+//! let config = rustls::ClientConfig::builder()
+//!     .with_root_certificates(roots)
+//!     .with_client_cert_resolver(Arc::new(ClientCertResolver::for_host("api.example.com")));
+//! ```
+//!
+//! The resolver only sends the certificate of the identity, and no
+//! intermediate certificates.
+//!
+//! The resolver looks up the identity for every handshake, so it picks up
+//! renewed certificates.  Signing may prompt for access to the key.
+
+use std::ptr;
+use std::sync::Arc;
+
+use rustls::client::ResolvesClientCert;
+use rustls::pki_types::CertificateDer;
+use rustls::sign::{CertifiedKey, Signer, SigningKey};
+use rustls::{SignatureAlgorithm as TlsAlgorithm, SignatureScheme};
+
+use super::cfutil::*;
+use super::keys::{KeyType, PrivateKey, SignatureAlgorithm};
+use super::native::*;
+use super::{copy_matching, owned_with_type, status_to_result, KeychainError, Result};
+
+/// How to find the identity.
+#[derive(Debug, Clone, PartialEq)]
+enum Selector {
+    /// The identity with this label.
+    Label(String),
+    /// The preferred identity for this host.
+    #[cfg(target_os = "macos")]
+    Host(String),
+}
+
+/// Find the identity for `selector`.
+///
+/// # Safety
+///
+/// The caller must call `CFRelease` on the returned identity.
+unsafe fn copy_identity(selector: &Selector) -> Result<SecIdentityRef> {
+    match *selector {
+        Selector::Label(ref label) => {
+            let cf_label = create_cf_string_no_copy(label);
+            let c = constants();
+            let items = [
+                (c.class, kSecClassIdentity() as CFTypeRef),
+                (kSecAttrLabel() as CFTypeRef, cf_label as CFTypeRef),
+                (c.match_limit, c.match_limit_one),
+                (kSecReturnRef() as CFTypeRef, c.boolean_true),
+            ];
+            let result = copy_matching(&items);
+            CFRelease(cf_label as CFTypeRef);
+            result
+                .and_then(|r| owned_with_type(r, "SecIdentity", SecIdentityGetTypeID()))
+                .map(|identity| identity as SecIdentityRef)
+        }
+        #[cfg(target_os = "macos")]
+        Selector::Host(ref host) => {
+            let cf_host = create_cf_string_no_copy(host);
+            let identity = SecIdentityCopyPreferred(cf_host, ptr::null(), ptr::null());
+            CFRelease(cf_host as CFTypeRef);
+            if identity.is_null() {
+                Err(KeychainError::item_not_found(format!(
+                    "No identity preference for {}",
+                    host
+                )))
+            } else {
+                Ok(identity)
+            }
+        }
+    }
+}
+
+/// Get the DER-encoded certificate and the private key of `identity`.
+unsafe fn certificate_and_key(identity: SecIdentityRef) -> Result<(Vec<u8>, PrivateKey)> {
+    let mut certificate: SecCertificateRef = ptr::null_mut();
+    status_to_result(SecIdentityCopyCertificate(identity, &mut certificate))?;
+    assert!(!certificate.is_null());
+    let cf_der = SecCertificateCopyData(certificate);
+    CFRelease(certificate as CFTypeRef);
+    assert!(!cf_der.is_null());
+    let der = vec_from_cfdata(cf_der);
+    CFRelease(cf_der as CFTypeRef);
+    let der = der?;
+
+    let mut key: SecKeyRef = ptr::null_mut();
+    status_to_result(SecIdentityCopyPrivateKey(identity, &mut key))?;
+    assert!(!key.is_null());
+    Ok((der, PrivateKey::from_key(key)))
+}
+
+/// The TLS signature schemes for keys of `key_type`, by preference.
+fn schemes(key_type: KeyType) -> &'static [(SignatureScheme, SignatureAlgorithm)] {
+    match key_type {
+        KeyType::EcdsaP256 => &[(
+            SignatureScheme::ECDSA_NISTP256_SHA256,
+            SignatureAlgorithm::EcdsaSha256,
+        )],
+        KeyType::EcdsaP384 => &[(
+            SignatureScheme::ECDSA_NISTP384_SHA384,
+            SignatureAlgorithm::EcdsaSha384,
+        )],
+        KeyType::Rsa => &[
+            (
+                SignatureScheme::RSA_PSS_SHA512,
+                SignatureAlgorithm::RsaPssSha512,
+            ),
+            (
+                SignatureScheme::RSA_PSS_SHA384,
+                SignatureAlgorithm::RsaPssSha384,
+            ),
+            (
+                SignatureScheme::RSA_PSS_SHA256,
+                SignatureAlgorithm::RsaPssSha256,
+            ),
+            (
+                SignatureScheme::RSA_PKCS1_SHA512,
+                SignatureAlgorithm::RsaPkcs1Sha512,
+            ),
+            (
+                SignatureScheme::RSA_PKCS1_SHA384,
+                SignatureAlgorithm::RsaPkcs1Sha384,
+            ),
+            (
+                SignatureScheme::RSA_PKCS1_SHA256,
+                SignatureAlgorithm::RsaPkcs1Sha256,
+            ),
+        ],
+    }
+}
+
+/// A key of a keychain identity which signs TLS handshakes.
+#[derive(Debug)]
+struct KeychainSigningKey {
+    key: Arc<PrivateKey>,
+    key_type: KeyType,
+}
+
+impl SigningKey for KeychainSigningKey {
+    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn Signer>> {
+        schemes(self.key_type)
+            .iter()
+            .find(|(scheme, _)| offered.contains(scheme))
+            .map(|&(scheme, algorithm)| {
+                Box::new(KeychainSigner {
+                    key: self.key.clone(),
+                    scheme,
+                    algorithm,
+                }) as Box<dyn Signer>
+            })
+    }
+
+    fn algorithm(&self) -> TlsAlgorithm {
+        match self.key_type {
+            KeyType::Rsa => TlsAlgorithm::RSA,
+            KeyType::EcdsaP256 | KeyType::EcdsaP384 => TlsAlgorithm::ECDSA,
+        }
+    }
+}
+
+/// Signs a TLS handshake with a keychain key.
+#[derive(Debug)]
+struct KeychainSigner {
+    key: Arc<PrivateKey>,
+    scheme: SignatureScheme,
+    algorithm: SignatureAlgorithm,
+}
+
+impl Signer for KeychainSigner {
+    fn sign(&self, message: &[u8]) -> std::result::Result<Vec<u8>, rustls::Error> {
+        self.key
+            .sign(self.algorithm, message)
+            .map_err(|error| rustls::Error::General(error.to_string()))
+    }
+
+    fn scheme(&self) -> SignatureScheme {
+        self.scheme
+    }
+}
+
+/// Resolves TLS client certificates to a keychain identity.
+#[derive(Debug)]
+pub struct ClientCertResolver {
+    selector: Selector,
+}
+
+impl ClientCertResolver {
+    /// Use the identity with `label`, as shown in Keychain Access.
+    pub fn by_label<S: Into<String>>(label: S) -> ClientCertResolver {
+        ClientCertResolver {
+            selector: Selector::Label(label.into()),
+        }
+    }
+
+    /// Use the preferred identity of `host`.
+    ///
+    /// Only macOS has identity preferences.
+    #[cfg(target_os = "macos")]
+    pub fn for_host<S: Into<String>>(host: S) -> ClientCertResolver {
+        ClientCertResolver {
+            selector: Selector::Host(host.into()),
+        }
+    }
+
+    /// Find the certificate and key of the identity.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::ItemNotFound` if there is no identity, and
+    /// `KeychainErrorCode::InvalidData` if its key type is not supported.
+    pub fn certified_key(&self) -> Result<CertifiedKey> {
+        let (der, key) = unsafe {
+            let identity = copy_identity(&self.selector)?;
+            let result = certificate_and_key(identity);
+            CFRelease(identity as CFTypeRef);
+            result?
+        };
+        let key_type = key
+            .key_type
+            .ok_or_else(|| KeychainError::invalid_data("Unsupported key type of identity"))?;
+        let signing_key = KeychainSigningKey {
+            key: Arc::new(key),
+            key_type,
+        };
+        Ok(CertifiedKey::new(
+            vec![CertificateDer::from(der)],
+            Arc::new(signing_key),
+        ))
+    }
+}
+
+impl ResolvesClientCert for ClientCertResolver {
+    fn resolve(
+        &self,
+        _root_hint_subjects: &[&[u8]],
+        sigschemes: &[SignatureScheme],
+    ) -> Option<Arc<CertifiedKey>> {
+        let certified_key = self.certified_key().ok()?;
+        // Continue without certificate if the server takes none of our schemes
+        certified_key.key.choose_scheme(sigschemes)?;
+        Some(Arc::new(certified_key))
+    }
+
+    fn has_certs(&self) -> bool {
+        self.certified_key().is_ok()
+    }
+}
@@ -0,0 +1,157 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! OAuth tokens in the keychain.
+//!
+//! Access and refresh token of a service are stored together with the expiry
+//! of the access token as JSON in the data of a generic password.
+//! `get_valid_token` returns the access token, and refreshes it first if it
+//! expired:
+//!
+//! ```no_run
+//! use rust_ffi_demo::keychain::tokens::{self, Tokens};
+//! use std::time::Duration;
+//!
+//! let tokens = Tokens::expiring_in("access", Some("refresh"), Duration::from_secs(3600));
+//! tokens::store_tokens("api.example.com", "jane", &tokens).unwrap();
+//! let access_token: Result<String, Box<dyn std::error::Error>> =
+//!     tokens::get_valid_token("api.example.com", |expired| {
+//!         // Ask the authorization server for new tokens here
+//!         let refresh_token = expired.refresh_token.as_deref();
+//!         Ok(Tokens::expiring_in("new access", refresh_token, Duration::from_secs(3600)))
+//!     });
+//! ```
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use super::cfutil::*;
+use super::items;
+use super::native::*;
+use super::{copy_first_generic_password, KeychainError, Result};
+
+/// How long before its expiry an access token counts as expired.
+///
+/// Leaves time to actually use the token.
+const EXPIRY_MARGIN: Duration = Duration::from_secs(30);
+
+/// An access token with its refresh token.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tokens {
+    /// The access token.
+    pub access_token: String,
+    /// The refresh token, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    /// When the access token expires, in seconds since the epoch.
+    ///
+    /// `None` if the access token does not expire.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+impl Tokens {
+    /// Tokens whose access token expires `expires_in` from now.
+    ///
+    /// Authorization servers report expiry relative to the time of the
+    /// response, as `expires_in`.
+    pub fn expiring_in(
+        access_token: &str,
+        refresh_token: Option<&str>,
+        expires_in: Duration,
+    ) -> Tokens {
+        let expires_at = SystemTime::now() + expires_in;
+        Tokens {
+            access_token: access_token.to_string(),
+            refresh_token: refresh_token.map(str::to_string),
+            expires_at: expires_at
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|expires_at| expires_at.as_secs()),
+        }
+    }
+
+    /// Whether the access token has expired at `now`, or is about to.
+    pub fn is_expired_at(&self, now: SystemTime) -> bool {
+        match self.expires_at {
+            None => false,
+            Some(expires_at) => UNIX_EPOCH + Duration::from_secs(expires_at) <= now + EXPIRY_MARGIN,
+        }
+    }
+
+    /// Whether the access token has expired, or is about to.
+    pub fn is_expired(&self) -> bool {
+        self.is_expired_at(SystemTime::now())
+    }
+}
+
+/// Store `tokens` for `service` and `account`.
+///
+/// Replace the tokens of an existing item, or add a new item to the default
+/// keychain.
+///
+/// # Errors
+///
+/// Return `KeychainError` if keychain access fails.
+pub fn store_tokens(service: &str, account: &str, tokens: &Tokens) -> Result<()> {
+    let payload = serde_json::to_string(tokens)
+        .map_err(|error| KeychainError::invalid_data(format!("Cannot encode tokens: {}", error)))?;
+    items::set_password(None, service, account, &payload)
+}
+
+/// Find the tokens for `service`, with the account they belong to.
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::InvalidData` if the item of `service` holds no
+/// tokens, and `KeychainError` if the item does not exist, or keychain access
+/// fails otherwise.
+pub fn find_tokens(service: &str) -> Result<(String, Tokens)> {
+    let (account, payload) = unsafe {
+        let result = copy_first_generic_password(service, None)?;
+        let account = string_value(result, kSecAttrAccount()).unwrap_or_default();
+        let payload = data_value(result, kSecValueData()).unwrap_or_default();
+        CFRelease(result as CFTypeRef);
+        (account, payload)
+    };
+    let tokens = serde_json::from_slice(&payload)
+        .map_err(|error| KeychainError::invalid_data(format!("Item holds no tokens: {}", error)))?;
+    Ok((account, tokens))
+}
+
+/// Get a valid access token for `service`.
+///
+/// Return the stored access token if it is still valid.  Otherwise call
+/// `refresh` with the expired tokens, store the tokens it returns, and return
+/// their access token.  Concurrent refreshes by several processes are not
+/// coordinated; the tokens stored last win.
+///
+/// # Errors
+///
+/// Return the error of `refresh`, and `KeychainError` if the tokens cannot
+/// be found or stored; see `find_tokens`.
+pub fn get_valid_token<F, E>(service: &str, refresh: F) -> std::result::Result<String, E>
+where
+    F: FnOnce(&Tokens) -> std::result::Result<Tokens, E>,
+    E: From<KeychainError>,
+{
+    let (account, tokens) = find_tokens(service)?;
+    if !tokens.is_expired() {
+        return Ok(tokens.access_token);
+    }
+    let refreshed = refresh(&tokens)?;
+    store_tokens(service, &account, &refreshed)?;
+    Ok(refreshed.access_token)
+}
@@ -0,0 +1,434 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Time-based one-time passwords (TOTP, RFC 6238) in the keychain.
+//!
+//! A TOTP is stored as a generic password whose data is the base32 encoded
+//! seed; the remaining parameters go into the metadata of the item, see
+//! `metadata`.
+
+use std::fmt;
+use std::os::raw::c_void;
+use std::ptr;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::cfutil::*;
+use super::labels;
+use super::metadata::{self, Metadata};
+use super::native::*;
+use super::read_only;
+use super::sensitive::redact;
+use super::{copy_first_generic_password, status_to_result, KeychainError, Result};
+
+/// The description of TOTP items, as shown in Keychain Access.
+pub(super) const TOTP_DESCRIPTION: &str = "TOTP seed";
+
+/// The HMAC algorithm of a TOTP.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    /// HMAC-SHA1, the default of RFC 6238 and most authenticator apps.
+    Sha1,
+    /// HMAC-SHA256.
+    Sha256,
+    /// HMAC-SHA512.
+    Sha512,
+}
+
+impl Algorithm {
+    fn cc_algorithm(self) -> CCHmacAlgorithm {
+        match self {
+            Algorithm::Sha1 => kCCHmacAlgSHA1,
+            Algorithm::Sha256 => kCCHmacAlgSHA256,
+            Algorithm::Sha512 => kCCHmacAlgSHA512,
+        }
+    }
+
+    fn digest_length(self) -> usize {
+        match self {
+            Algorithm::Sha1 => 20,
+            Algorithm::Sha256 => 32,
+            Algorithm::Sha512 => 64,
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            Algorithm::Sha1 => "SHA1",
+            Algorithm::Sha256 => "SHA256",
+            Algorithm::Sha512 => "SHA512",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = KeychainError;
+
+    fn from_str(s: &str) -> Result<Algorithm> {
+        match s.to_ascii_uppercase().as_str() {
+            "SHA1" => Ok(Algorithm::Sha1),
+            "SHA256" => Ok(Algorithm::Sha256),
+            "SHA512" => Ok(Algorithm::Sha512),
+            _ => Err(KeychainError::invalid_data(format!(
+                "Unknown TOTP algorithm: {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// A TOTP generator.
+///
+/// `Debug` redacts the key.
+#[derive(Clone, PartialEq)]
+pub struct Totp {
+    /// The shared secret.
+    pub key: Vec<u8>,
+    /// The issuer, ie, the provider this TOTP authenticates against.
+    pub issuer: Option<String>,
+    /// The number of digits in a code, between 1 and 9.
+    pub digits: u32,
+    /// The time step in seconds.
+    pub period: u64,
+    /// The HMAC algorithm.
+    pub algorithm: Algorithm,
+}
+
+impl fmt::Debug for Totp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Totp")
+            .field("key", &format_args!("{}", redact(&self.key)))
+            .field("issuer", &self.issuer)
+            .field("digits", &self.digits)
+            .field("period", &self.period)
+            .field("algorithm", &self.algorithm)
+            .finish()
+    }
+}
+
+impl Totp {
+    /// Creates a TOTP for `key` with the usual parameters.
+    ///
+    /// The TOTP has no issuer and generates 6-digit codes every 30 seconds
+    /// with HMAC-SHA1.
+    pub fn new(key: Vec<u8>) -> Totp {
+        Totp {
+            key,
+            issuer: None,
+            digits: 6,
+            period: 30,
+            algorithm: Algorithm::Sha1,
+        }
+    }
+
+    /// Creates a TOTP for a base32 encoded `secret` with the usual parameters.
+    ///
+    /// Case, whitespace, dashes and padding in `secret` are ignored.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::InvalidData` if `secret` is not valid base32.
+    pub fn from_base32(secret: &str) -> Result<Totp> {
+        decode_base32(secret)
+            .map(Totp::new)
+            .ok_or_else(|| KeychainError::invalid_data("Invalid base32 TOTP secret"))
+    }
+
+    /// The base32 encoded secret, without padding.
+    pub fn secret_base32(&self) -> String {
+        encode_base32(&self.key)
+    }
+
+    /// Generates the code at `time`, in seconds since the Unix epoch.
+    ///
+    /// # Panics
+    ///
+    /// Panic if `digits` is not between 1 and 9, or if `period` is 0.
+    ///
+    /// # Examples
+    ///
+    /// The test vectors from RFC 6238, Appendix B:
+    ///
+    /// ```
+    /// use rust_ffi_demo::keychain::totp::{Algorithm, Totp};
+    ///
+    /// let totp = |algorithm, key: &[u8]| Totp {
+    ///     digits: 8,
+    ///     algorithm,
+    ///     ..Totp::new(key.to_vec())
+    /// };
+    /// let sha1 = totp(Algorithm::Sha1, b"12345678901234567890");
+    /// let sha256 = totp(Algorithm::Sha256, b"12345678901234567890123456789012");
+    /// let sha512 = totp(
+    ///     Algorithm::Sha512,
+    ///     b"1234567890123456789012345678901234567890123456789012345678901234",
+    /// );
+    ///
+    /// let vectors = [
+    ///     (59, "94287082", "46119246", "90693936"),
+    ///     (1111111109, "07081804", "68084774", "25091201"),
+    ///     (1111111111, "14050471", "67062674", "99943326"),
+    ///     (1234567890, "89005924", "91819424", "93441116"),
+    ///     (2000000000, "69279037", "90698825", "38618901"),
+    ///     (20000000000, "65353130", "77737706", "47863826"),
+    /// ];
+    /// for &(time, code_sha1, code_sha256, code_sha512) in vectors.iter() {
+    ///     assert_eq!(sha1.code_at(time), code_sha1);
+    ///     assert_eq!(sha256.code_at(time), code_sha256);
+    ///     assert_eq!(sha512.code_at(time), code_sha512);
+    /// }
+    /// ```
+    pub fn code_at(&self, time: u64) -> String {
+        assert!(0 < self.digits && self.digits < 10);
+        assert!(0 < self.period);
+
+        let counter = time / self.period;
+        let mac = hmac(self.algorithm, &self.key, &counter.to_be_bytes());
+        // Dynamic truncation, see RFC 4226, Section 5.3
+        let offset = (mac[mac.len() - 1] & 0xf) as usize;
+        let binary = (u32::from(mac[offset]) & 0x7f) << 24
+            | u32::from(mac[offset + 1]) << 16
+            | u32::from(mac[offset + 2]) << 8
+            | u32::from(mac[offset + 3]);
+        format!(
+            "{:0width$}",
+            binary % 10u32.pow(self.digits),
+            width = self.digits as usize
+        )
+    }
+
+    /// Generates the current code.
+    pub fn code(&self) -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("System time before Unix epoch");
+        self.code_at(now.as_secs())
+    }
+
+    /// Check the parameters of this TOTP.
+    fn validate(&self) -> Result<()> {
+        if self.key.is_empty() {
+            Err(KeychainError::invalid_data("Empty TOTP secret"))
+        } else if self.digits < 1 || 9 < self.digits {
+            Err(KeychainError::invalid_data(format!(
+                "Invalid number of TOTP digits: {}",
+                self.digits
+            )))
+        } else if self.period == 0 {
+            Err(KeychainError::invalid_data("Invalid TOTP period: 0"))
+        } else if self.issuer.as_ref().is_some_and(|i| i.contains('\n')) {
+            Err(KeychainError::invalid_data("TOTP issuer contains a newline"))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The parameters of this TOTP as metadata.
+    ///
+    /// The result has one `key=value` pair per line.
+    ///
+    /// Return `KeychainErrorCode::InvalidData` if the issuer has a line break.
+    fn metadata(&self) -> Result<Metadata> {
+        let mut metadata = Metadata::new();
+        metadata.set("digits", self.digits.to_string())?;
+        metadata.set("period", self.period.to_string())?;
+        metadata.set("algorithm", self.algorithm.to_string())?;
+        if let Some(ref issuer) = self.issuer {
+            metadata.set("issuer", issuer.as_str())?;
+        }
+        Ok(metadata)
+    }
+
+    /// Read parameters from `metadata` as created by `Totp::metadata`.
+    fn read_metadata(&mut self, metadata: &Metadata) -> Result<()> {
+        for (key, value) in metadata.fields() {
+            let invalid = || {
+                KeychainError::invalid_data(format!("Invalid TOTP metadata: {}={}", key, value))
+            };
+            match key {
+                "digits" => self.digits = value.parse().map_err(|_| invalid())?,
+                "period" => self.period = value.parse().map_err(|_| invalid())?,
+                "algorithm" => self.algorithm = value.parse()?,
+                "issuer" => self.issuer = Some(value.to_string()),
+                // Ignore unknown keys for forwards compatibility
+                _ => (),
+            }
+        }
+        self.validate()
+    }
+}
+
+/// Add a TOTP for `account` at the given `service`.
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::InvalidData` if the parameters of `totp` are
+/// invalid, and `KeychainError` when the combination of `service` and
+/// `account` already exist in keychain, or keychain access fails otherwise.
+pub fn add_totp(service: &str, account: &str, totp: &Totp) -> Result<()> {
+    read_only::check()?;
+    totp.validate()?;
+    let secret = totp.secret_base32();
+    let metadata = totp.metadata()?.to_bytes();
+    unsafe {
+        let cf_service = create_cf_string_no_copy(service) as CFTypeRef;
+        let cf_account = create_cf_string_no_copy(account) as CFTypeRef;
+        let cf_description = create_cf_string_no_copy(TOTP_DESCRIPTION) as CFTypeRef;
+        let cf_secret = create_cf_data_no_copy(secret.as_bytes()) as CFTypeRef;
+        let cf_metadata = create_cf_data_no_copy(&metadata) as CFTypeRef;
+        let cf_label = labels::for_new_item(None, service, account)
+            .map(|label| create_cf_string(&label) as CFTypeRef);
+
+        let mut items = vec![
+            (
+                kSecClass() as CFTypeRef,
+                kSecClassGenericPassword() as CFTypeRef,
+            ),
+            (kSecAttrService() as CFTypeRef, cf_service),
+            (kSecAttrAccount() as CFTypeRef, cf_account),
+            (kSecAttrDescription() as CFTypeRef, cf_description),
+            (kSecAttrGeneric() as CFTypeRef, cf_metadata),
+            (kSecValueData() as CFTypeRef, cf_secret),
+        ];
+        if let Some(cf_label) = cf_label {
+            items.push((kSecAttrLabel() as CFTypeRef, cf_label));
+        }
+        let attributes = create_dictionary(&items);
+        assert!(!attributes.is_null());
+
+        let status = SecItemAdd(attributes, ptr::null_mut());
+
+        CFRelease(attributes as CFTypeRef);
+        CFRelease(cf_service);
+        CFRelease(cf_account);
+        CFRelease(cf_description);
+        CFRelease(cf_secret);
+        CFRelease(cf_metadata);
+        if let Some(cf_label) = cf_label {
+            CFRelease(cf_label);
+        }
+
+        status_to_result(status)
+    }
+}
+
+/// Find the TOTP for the given `service`.
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::InvalidData` if the item for `service` is no
+/// TOTP item, and `KeychainError` when the item does not exist, or keychain
+/// access fails otherwise.
+pub fn find_totp(service: &str) -> Result<Totp> {
+    let (account, secret, metadata) = unsafe {
+        let result = copy_first_generic_password(service, None)?;
+
+        let account = string_value(result, kSecAttrAccount()).unwrap_or_default();
+        let secret = cf_dict_get_data(result, kSecValueData());
+        let metadata = data_value(result, kSecAttrGeneric()).unwrap_or_default();
+
+        // Values follow the `Get` rule; releasing the dictionary frees them.
+        CFRelease(result as CFTypeRef);
+        let secret =
+            secret?.ok_or_else(|| KeychainError::invalid_data("TOTP item without secret"))?;
+        (account, secret, metadata)
+    };
+
+    let secret = String::from_utf8(secret)
+        .map_err(|_| KeychainError::invalid_data("TOTP secret is not UTF-8"))?;
+    let metadata = metadata::read_and_upgrade(None, service, &account, &metadata)?;
+    let mut totp = Totp::from_base32(&secret)?;
+    totp.read_metadata(&metadata)?;
+    Ok(totp)
+}
+
+/// Generate the current code of the TOTP for the given `service`.
+///
+/// # Errors
+///
+/// See `find_totp`.
+pub fn current_code(service: &str) -> Result<String> {
+    find_totp(service).map(|totp| totp.code())
+}
+
+/// Compute the HMAC of `data` under `key` with CommonCrypto.
+fn hmac(algorithm: Algorithm, key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = vec![0; algorithm.digest_length()];
+    unsafe {
+        CCHmac(
+            algorithm.cc_algorithm(),
+            key.as_ptr() as *const c_void,
+            key.len() as size_t,
+            data.as_ptr() as *const c_void,
+            data.len() as size_t,
+            mac.as_mut_ptr() as *mut c_void,
+        );
+    }
+    mac
+}
+
+/// The RFC 4648 base32 alphabet.
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Decode base32, ignoring case, whitespace, dashes and padding.
+///
+/// Return `None` if `s` contains other invalid characters, or no data at all.
+fn decode_base32(s: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(s.len() * 5 / 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in s.bytes() {
+        let value = match c.to_ascii_uppercase() {
+            c @ b'A'..=b'Z' => c - b'A',
+            c @ b'2'..=b'7' => c - b'2' + 26,
+            b' ' | b'\t' | b'-' | b'=' => continue,
+            _ => return None,
+        };
+        buffer = (buffer << 5) | u32::from(value);
+        bits += 5;
+        if 8 <= bits {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    if decoded.is_empty() {
+        None
+    } else {
+        Some(decoded)
+    }
+}
+
+/// Encode `bytes` as base32, without padding.
+fn encode_base32(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity((bytes.len() * 8).div_ceil(5));
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for &byte in bytes {
+        buffer = (buffer << 8) | u32::from(byte);
+        bits += 8;
+        while 5 <= bits {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+        buffer &= (1 << bits) - 1;
+    }
+    if 0 < bits {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
@@ -0,0 +1,94 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Wi-Fi network passwords.
+//!
+//! macOS keeps the pre-shared keys of known Wi-Fi networks in the System
+//! keychain, as generic passwords of the `AirPort` service with the SSID as
+//! account.  Reading them requires administrator authorization.
+
+use super::cfutil::*;
+use super::keychains::{with_search_list, Keychain};
+use super::native::*;
+use super::{copy_matching, KeychainError, KeychainErrorCode, Result};
+
+/// The service of Wi-Fi network passwords.
+const AIRPORT_SERVICE: &str = "AirPort";
+
+/// Whether `status` means that the user did not authorize access.
+fn is_authorization_failure(status: &KeychainErrorCode) -> bool {
+    match *status {
+        KeychainErrorCode::AuthFailed => true,
+        KeychainErrorCode::UnknownStatusCode(code) => {
+            code == errSecInteractionNotAllowed
+                || code == errSecUserCanceled
+                || code == errSecNoAccessForItem
+        }
+        _ => false,
+    }
+}
+
+/// Find the password of the Wi-Fi network with the given `ssid`.
+///
+/// This prompts for administrator credentials.
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::ItemNotFound` if the network is unknown, and a
+/// `KeychainError` explaining that administrator authorization is required if
+/// the user did not authorize access.  Return `KeychainError` if keychain
+/// access fails otherwise.
+pub fn find_wifi_password(ssid: &str) -> Result<String> {
+    let keychain = Keychain::system()?;
+    let data = unsafe {
+        let cf_service = create_cf_string_no_copy(AIRPORT_SERVICE) as CFTypeRef;
+        let cf_account = create_cf_string_no_copy(ssid) as CFTypeRef;
+        let items = [
+            (
+                kSecClass() as CFTypeRef,
+                kSecClassGenericPassword() as CFTypeRef,
+            ),
+            (kSecAttrService() as CFTypeRef, cf_service),
+            (kSecAttrAccount() as CFTypeRef, cf_account),
+            (
+                kSecMatchLimit() as CFTypeRef,
+                kSecMatchLimitOne() as CFTypeRef,
+            ),
+            (kSecReturnData() as CFTypeRef, kCFBooleanTrue as CFTypeRef),
+        ];
+        let result = with_search_list(Some(&keychain), &items, |items| copy_matching(items));
+
+        CFRelease(cf_service);
+        CFRelease(cf_account);
+
+        let result = match result {
+            Err(ref error) if is_authorization_failure(&error.status) => {
+                return Err(KeychainError {
+                    status: error.status.clone(),
+                    message: format!(
+                        "Reading the password of Wi-Fi network {} requires administrator \
+                         authorization: {}",
+                        ssid, error.message
+                    ),
+                    correlation_id: error.correlation_id,
+                });
+            }
+            result => result?,
+        };
+        let data = cast_cf_data(result).and_then(|data| vec_from_cfdata(data));
+        CFRelease(result);
+        data?
+    };
+    String::from_utf8(data).map_err(|_| KeychainError::invalid_data("Wi-Fi password is not UTF-8"))
+}
@@ -0,0 +1,198 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! The API of the keyring crate, on top of this crate.
+//!
+//! `Entry` and `Error` mirror the types of the same name in the keyring
+//! crate, so code written against keyring works with this crate by changing
+//! imports:
+//!
+//! ```no_run
+//! use rust_ffi_demo::keyring::{Entry, Error};
+//!
+//! let entry = Entry::new("my-service", "my-name")?;
+//! entry.set_password("topS3cr3tP4$$w0rd")?;
+//! assert_eq!(entry.get_password()?, "topS3cr3tP4$$w0rd");
+//! entry.delete_credential()?;
+//! assert!(matches!(entry.get_password(), Err(Error::NoEntry)));
+//! # Ok::<(), Error>(())
+//! ```
+//!
+//! Entries are generic passwords with the service and user as account.  A
+//! target names the keychain of an entry.
+
+use std::error;
+use std::fmt;
+
+use crate::keychain::items;
+use crate::keychain::keychains::Keychain;
+use crate::keychain::{KeychainError, KeychainErrorCode};
+
+/// An error of an entry.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The keychain failed.
+    PlatformFailure(KeychainError),
+    /// The keychain refused access, eg, because the user denied it.
+    NoStorageAccess(KeychainError),
+    /// The entry does not exist.
+    NoEntry,
+    /// The secret of the entry is not UTF-8, so it has no password.
+    BadEncoding(Vec<u8>),
+    /// An attribute of the entry is invalid, with its name and why.
+    Invalid(String, String),
+}
+
+impl From<KeychainError> for Error {
+    fn from(error: KeychainError) -> Error {
+        match error.status {
+            KeychainErrorCode::ItemNotFound => Error::NoEntry,
+            KeychainErrorCode::AuthFailed | KeychainErrorCode::MissingEntitlement => {
+                Error::NoStorageAccess(error)
+            }
+            _ => Error::PlatformFailure(error),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Error::PlatformFailure(ref error) => {
+                write!(f, "Platform secure storage failure: {}", error)
+            }
+            Error::NoStorageAccess(ref error) => {
+                write!(f, "Couldn't access platform secure storage: {}", error)
+            }
+            Error::NoEntry => write!(f, "No matching entry found in secure storage"),
+            Error::BadEncoding(_) => write!(f, "Data is not UTF-8 encoded"),
+            Error::Invalid(ref attribute, ref reason) => {
+                write!(f, "Attribute {} is invalid: {}", attribute, reason)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::PlatformFailure(ref error) | Error::NoStorageAccess(ref error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// The result of entry operations.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// A named entry for a secret.
+#[derive(Debug)]
+pub struct Entry {
+    keychain: Option<Keychain>,
+    service: String,
+    user: String,
+}
+
+impl Entry {
+    /// The entry of `user` at `service`, in the search list and the default
+    /// keychain.
+    ///
+    /// # Errors
+    ///
+    /// Return `Error::Invalid` if `service` or `user` is empty.
+    pub fn new(service: &str, user: &str) -> Result<Entry> {
+        Entry::create(None, service, user)
+    }
+
+    /// The entry of `user` at `service`, in the keychain named `target`.
+    ///
+    /// `target` is a name like `login`, or a path to a keychain file.
+    ///
+    /// # Errors
+    ///
+    /// Return `Error::Invalid` if `service` or `user` is empty, or there is
+    /// no keychain `target`.
+    pub fn new_with_target(target: &str, service: &str, user: &str) -> Result<Entry> {
+        let keychain = Keychain::named(target)
+            .map_err(|error| Error::Invalid("target".to_string(), error.message))?;
+        Entry::create(Some(keychain), service, user)
+    }
+
+    /// Create an entry after checking `service` and `user`.
+    fn create(keychain: Option<Keychain>, service: &str, user: &str) -> Result<Entry> {
+        for &(name, value) in &[("service", service), ("user", user)] {
+            if value.is_empty() {
+                return Err(Error::Invalid(name.to_string(), "empty".to_string()));
+            }
+        }
+        Ok(Entry {
+            keychain,
+            service: service.to_string(),
+            user: user.to_string(),
+        })
+    }
+
+    /// Set the password of this entry, creating the entry if needed.
+    ///
+    /// # Errors
+    ///
+    /// Return `Error` if the keychain fails.
+    pub fn set_password(&self, password: &str) -> Result<()> {
+        self.set_secret(password.as_bytes())
+    }
+
+    /// Set the secret of this entry, creating the entry if needed.
+    ///
+    /// # Errors
+    ///
+    /// Return `Error` if the keychain fails.
+    pub fn set_secret(&self, secret: &[u8]) -> Result<()> {
+        items::set_data(self.keychain.as_ref(), &self.service, &self.user, secret)?;
+        Ok(())
+    }
+
+    /// Get the password of this entry.
+    ///
+    /// # Errors
+    ///
+    /// Return `Error::NoEntry` if the entry does not exist,
+    /// `Error::BadEncoding` if the secret is not UTF-8, and `Error` if the
+    /// keychain fails otherwise.
+    pub fn get_password(&self) -> Result<String> {
+        String::from_utf8(self.get_secret()?)
+            .map_err(|error| Error::BadEncoding(error.into_bytes()))
+    }
+
+    /// Get the secret of this entry.
+    ///
+    /// # Errors
+    ///
+    /// Return `Error::NoEntry` if the entry does not exist, and `Error` if the
+    /// keychain fails otherwise.
+    pub fn get_secret(&self) -> Result<Vec<u8>> {
+        Ok(items::find_data(self.keychain.as_ref(), &self.service, &self.user)?.into_exposed())
+    }
+
+    /// Delete this entry.
+    ///
+    /// # Errors
+    ///
+    /// Return `Error::NoEntry` if the entry does not exist, and `Error` if the
+    /// keychain fails otherwise.
+    pub fn delete_credential(&self) -> Result<()> {
+        items::delete_item(self.keychain.as_ref(), &self.service, &self.user)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,263 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Run the SSH agent as a launchd agent of the user.
+//!
+//! `Agent::install` writes a property list to `~/Library/LaunchAgents` which
+//! starts the agent at login and restarts it if it exits, and loads it with
+//! `launchctl`, so the agent survives reboots.  The agent logs to
+//! `~/Library/Logs/rust-ffi-demo`.
+//!
+//! ```no_run
+//! use rust_ffi_demo::launchd::{Agent, Status};
+//!
+//! let agent = Agent::new(
+//!     "de.swsnr.rust-ffi-demo.ssh-agent",
+//!     vec!["/usr/local/bin/rust-ffi-demo".into(), "ssh-agent".into()],
+//! );
+//! agent.install().unwrap();
+//! assert!(matches!(agent.status().unwrap(), Status::Running(_)));
+//! agent.uninstall().unwrap();
+//! ```
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+extern "C" {
+    fn getuid() -> u32;
+}
+
+/// The state of a launchd agent.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Status {
+    /// The agent has no property list.
+    NotInstalled,
+    /// The agent has a property list, but launchd did not load it.
+    NotLoaded,
+    /// launchd loaded the agent, and it runs with this process ID.
+    Running(u32),
+    /// launchd loaded the agent, but it's not running; it last exited with
+    /// this status, if it ever ran.
+    Stopped(Option<i32>),
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Status::NotInstalled => write!(f, "not installed"),
+            Status::NotLoaded => write!(f, "installed, not loaded"),
+            Status::Running(pid) => write!(f, "running, pid {}", pid),
+            Status::Stopped(Some(status)) => write!(f, "stopped, last exit status {}", status),
+            Status::Stopped(None) => write!(f, "stopped"),
+        }
+    }
+}
+
+/// Escape `s` for XML text.
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Run `launchctl` with `args`.
+fn launchctl(args: &[&str]) -> io::Result<Output> {
+    Command::new("launchctl").args(args).output()
+}
+
+/// The value of `key` in the output of `launchctl list LABEL`.
+///
+/// The output has lines like `"PID" = 1234;`.
+fn list_value<'a>(output: &'a str, key: &str) -> Option<&'a str> {
+    let prefix = format!("\"{}\" = ", key);
+    output.lines().find_map(|line| {
+        line.trim()
+            .strip_prefix(&prefix)
+            .map(|value| value.trim_end_matches(';'))
+    })
+}
+
+/// A launchd agent of the user.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Agent {
+    /// The label of the agent, by reverse domain name.
+    pub label: String,
+    /// The program of the agent, with its arguments.
+    pub program_arguments: Vec<String>,
+}
+
+impl Agent {
+    /// An agent with `label` which runs `program_arguments`.
+    pub fn new<S: Into<String>>(label: S, program_arguments: Vec<String>) -> Agent {
+        Agent {
+            label: label.into(),
+            program_arguments,
+        }
+    }
+
+    /// The path of the property list of this agent.
+    pub fn plist_path(&self) -> PathBuf {
+        home()
+            .join("Library/LaunchAgents")
+            .join(format!("{}.plist", self.label))
+    }
+
+    /// The path of the log of this agent.
+    pub fn log_path(&self) -> PathBuf {
+        home()
+            .join("Library/Logs/rust-ffi-demo")
+            .join(format!("{}.log", self.label))
+    }
+
+    /// The property list of this agent.
+    ///
+    /// ```
+    /// use rust_ffi_demo::launchd::Agent;
+    ///
+    /// let agent = Agent::new("com.example.agent", vec!["/bin/agent".into(), "--a&b".into()]);
+    /// let plist = agent.plist();
+    /// assert!(plist.contains("<key>Label</key>\n    <string>com.example.agent</string>"));
+    /// assert!(plist.contains("<string>--a&amp;b</string>"));
+    /// assert!(plist.contains("<key>KeepAlive</key>\n    <true/>"));
+    /// ```
+    pub fn plist(&self) -> String {
+        let mut arguments = String::new();
+        for argument in &self.program_arguments {
+            arguments.push_str(&format!(
+                "        <string>{}</string>\n",
+                xml_escape(argument)
+            ));
+        }
+        let log = xml_escape(&self.log_path().to_string_lossy());
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{label}</string>
+    <key>ProgramArguments</key>
+    <array>
+{arguments}    </array>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <true/>
+    <key>StandardOutPath</key>
+    <string>{log}</string>
+    <key>StandardErrorPath</key>
+    <string>{log}</string>
+</dict>
+</plist>
+"#,
+            label = xml_escape(&self.label),
+            arguments = arguments,
+            log = log
+        )
+    }
+
+    /// The launchd service target of this agent, in the GUI domain of the
+    /// user.
+    fn target(&self) -> String {
+        format!("{}/{}", domain(), self.label)
+    }
+
+    /// Write the property list of this agent and load it.
+    ///
+    /// Replace an installed agent of the same label, and restart it.
+    ///
+    /// # Errors
+    ///
+    /// Return an IO error if writing the property list fails, or `launchctl`
+    /// fails to load it.
+    pub fn install(&self) -> io::Result<()> {
+        let plist_path = self.plist_path();
+        if let Some(directory) = plist_path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        if let Some(directory) = self.log_path().parent() {
+            fs::create_dir_all(directory)?;
+        }
+        // Unload an old version first; this fails if none is loaded
+        launchctl(&["bootout", &self.target()])?;
+        fs::write(&plist_path, self.plist())?;
+        let output = launchctl(&["bootstrap", &domain(), &plist_path.to_string_lossy()])?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!(
+                "launchctl failed to load {}: {}",
+                plist_path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )))
+        }
+    }
+
+    /// Unload this agent and remove its property list.
+    ///
+    /// Keep the log.
+    ///
+    /// # Errors
+    ///
+    /// Return `io::ErrorKind::NotFound` if the agent is not installed, and an
+    /// IO error if removing the property list fails.
+    pub fn uninstall(&self) -> io::Result<()> {
+        let plist_path = self.plist_path();
+        if !plist_path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is not installed", self.label),
+            ));
+        }
+        // Ignore failures, because the agent may not be loaded
+        launchctl(&["bootout", &self.target()])?;
+        fs::remove_file(plist_path)
+    }
+
+    /// Get the status of this agent.
+    ///
+    /// # Errors
+    ///
+    /// Return an IO error if `launchctl` cannot be run.
+    pub fn status(&self) -> io::Result<Status> {
+        if !self.plist_path().exists() {
+            return Ok(Status::NotInstalled);
+        }
+        let output = launchctl(&["list", &self.label])?;
+        if !output.status.success() {
+            return Ok(Status::NotLoaded);
+        }
+        let output = String::from_utf8_lossy(&output.stdout);
+        match list_value(&output, "PID").and_then(|pid| pid.parse().ok()) {
+            Some(pid) => Ok(Status::Running(pid)),
+            None => Ok(Status::Stopped(
+                list_value(&output, "LastExitStatus").and_then(|status| status.parse().ok()),
+            )),
+        }
+    }
+}
+
+/// The home directory of the user.
+fn home() -> PathBuf {
+    env::var_os("HOME").map(PathBuf::from).unwrap_or_default()
+}
+
+/// The launchd GUI domain of the user.
+fn domain() -> String {
+    format!("gui/{}", unsafe { getuid() })
+}
@@ -0,0 +1,128 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Access the macOS keychain through bindgen-generated FFI bindings.
+
+#![deny(warnings)]
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+pub mod keychain;
+
+#[cfg(all(target_os = "macos", feature = "age"))]
+pub mod age_plugin;
+
+#[cfg(target_os = "macos")]
+pub mod audit_log;
+
+#[cfg(target_os = "macos")]
+pub mod batch;
+
+#[cfg(target_os = "macos")]
+pub mod bulk;
+
+#[cfg(target_os = "macos")]
+pub mod canary;
+
+#[cfg(target_os = "macos")]
+pub mod credentials;
+
+#[cfg(target_os = "macos")]
+pub mod diff;
+
+#[cfg(target_os = "macos")]
+pub mod doctor;
+
+#[cfg(target_os = "macos")]
+pub mod dump;
+
+#[cfg(target_os = "macos")]
+pub mod export;
+
+pub mod formatter;
+
+#[cfg(target_os = "macos")]
+pub mod harden;
+
+#[cfg(target_os = "macos")]
+pub mod hook;
+
+#[cfg(all(target_os = "macos", feature = "keyring"))]
+pub mod keyring;
+
+#[cfg(target_os = "macos")]
+pub mod launchd;
+
+pub mod lint;
+
+#[cfg(target_os = "macos")]
+pub mod mapping;
+
+#[cfg(target_os = "macos")]
+pub mod manifest;
+
+#[cfg(target_os = "macos")]
+pub mod metrics_endpoint;
+
+#[cfg(target_os = "macos")]
+pub mod migrate;
+
+#[cfg(target_os = "macos")]
+pub mod mount;
+
+pub mod output;
+
+pub mod pattern;
+
+#[cfg(target_os = "macos")]
+pub mod pinentry;
+
+pub mod policy;
+
+pub mod profile;
+
+pub mod progress;
+
+pub mod prompts;
+
+#[cfg(target_os = "macos")]
+pub mod provider;
+
+#[cfg(target_os = "macos")]
+pub mod repl;
+
+#[cfg(target_os = "macos")]
+pub mod reveal;
+
+#[cfg(target_os = "macos")]
+pub mod runtime;
+
+#[cfg(target_os = "macos")]
+pub mod search;
+
+pub mod shell;
+
+#[cfg(target_os = "macos")]
+pub mod ssh_agent;
+
+#[cfg(target_os = "macos")]
+pub mod store;
+
+#[cfg(target_os = "macos")]
+pub mod sync;
+
+#[cfg(target_os = "macos")]
+pub mod transaction;
+
+#[cfg(all(target_os = "macos", feature = "tui"))]
+pub mod tui;
@@ -0,0 +1,350 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Naming rules for services, accounts and labels.
+//!
+//! An organization keeps names of credentials consistent with rules in a JSON
+//! file; all keys are optional:
+//!
+//! ```json
+//! {
+//!     "service-pattern": "^com\\.example\\.[a-z0-9.-]+$",
+//!     "account-pattern": "^[a-z][a-z0-9._-]*(@example\\.com)?$",
+//!     "label-pattern": "^[A-Z]",
+//!     "required-prefixes": ["com.example."],
+//!     "forbid-secret-accounts": true,
+//!     "ignore": ["com.apple.*"]
+//! }
+//! ```
+//!
+//! Patterns are regular expressions which must match somewhere in a name,
+//! so anchor them to match whole names.  A service must start with one of the
+//! required prefixes, if any.  Accounts which look like secrets, eg, tokens
+//! pasted into the wrong field, are forbidden unless `forbid-secret-accounts`
+//! is false.  Services matching wildcard patterns in `ignore`, see
+//! `pattern::glob_match`, are not checked at all.
+//!
+//! `check` returns findings instead of failing, so callers decide whether a
+//! finding is fatal.
+
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::pattern::glob_match;
+
+/// Where the rules live unless `RULES_VAR` says otherwise.
+pub const DEFAULT_PATH: &str = "/Library/Application Support/rust-ffi-demo/lint.json";
+
+/// The environment variable with the path to the rules.
+pub const RULES_VAR: &str = "RUST_FFI_DEMO_LINT_RULES";
+
+/// Known prefixes of tokens, which no account name starts with.
+const TOKEN_PREFIXES: [&str; 9] = [
+    "ghp_",
+    "gho_",
+    "ghs_",
+    "github_pat_",
+    "glpat-",
+    "xoxb-",
+    "xoxp-",
+    "sk-",
+    "AKIA",
+];
+
+/// The rules as written.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Declared {
+    service_pattern: Option<String>,
+    account_pattern: Option<String>,
+    label_pattern: Option<String>,
+    required_prefixes: Vec<String>,
+    forbid_secret_accounts: bool,
+    ignore: Vec<String>,
+}
+
+impl Default for Declared {
+    fn default() -> Declared {
+        Declared {
+            service_pattern: None,
+            account_pattern: None,
+            label_pattern: None,
+            required_prefixes: Vec::new(),
+            forbid_secret_accounts: true,
+            ignore: Vec::new(),
+        }
+    }
+}
+
+/// A rule which a name can break.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rule {
+    /// The service does not match `service-pattern`.
+    ServicePattern,
+    /// The account does not match `account-pattern`.
+    AccountPattern,
+    /// The label does not match `label-pattern`.
+    LabelPattern,
+    /// The service starts with none of `required-prefixes`.
+    RequiredPrefix,
+    /// The account looks like a secret.
+    SecretAccount,
+}
+
+impl Rule {
+    /// The name of this rule, as in the rules file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Rule::ServicePattern => "service-pattern",
+            Rule::AccountPattern => "account-pattern",
+            Rule::LabelPattern => "label-pattern",
+            Rule::RequiredPrefix => "required-prefixes",
+            Rule::SecretAccount => "forbid-secret-accounts",
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A name which breaks a rule.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    /// The service of the item.
+    pub service: String,
+    /// The account of the item.
+    pub account: String,
+    /// The rule the item breaks.
+    pub rule: Rule,
+    /// What is wrong.
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}: {} ({})",
+            self.service, self.account, self.message, self.rule
+        )
+    }
+}
+
+/// Naming rules.
+#[derive(Debug, Clone)]
+pub struct Rules {
+    service_pattern: Option<Regex>,
+    account_pattern: Option<Regex>,
+    label_pattern: Option<Regex>,
+    required_prefixes: Vec<String>,
+    forbid_secret_accounts: bool,
+    ignore: Vec<String>,
+}
+
+impl Default for Rules {
+    /// The rules without rules file: only forbid accounts which look like
+    /// secrets.
+    fn default() -> Rules {
+        Rules::compile(Declared::default()).unwrap()
+    }
+}
+
+/// Compile the regular expression for the rule `name`.
+fn compile(name: &str, pattern: Option<String>) -> io::Result<Option<Regex>> {
+    pattern
+        .map(|pattern| {
+            Regex::new(&pattern).map_err(|error| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid {}: {}", name, error),
+                )
+            })
+        })
+        .transpose()
+}
+
+/// Whether `account` looks like a secret rather than a name, and why.
+///
+/// An account looks like a secret if it starts like a well-known token, holds
+/// a key, or is a long run of letters and digits without separators.
+fn secret_reason(account: &str) -> Option<&'static str> {
+    if TOKEN_PREFIXES
+        .iter()
+        .any(|prefix| account.starts_with(prefix) && account.len() >= prefix.len() + 16)
+    {
+        return Some("account looks like an access token");
+    }
+    if account.contains("-----BEGIN") {
+        return Some("account holds a key");
+    }
+    if account.len() >= 24
+        && account
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '/' | '=' | '_'))
+        && account.chars().any(|c| c.is_ascii_digit())
+        && account.chars().any(|c| c.is_ascii_uppercase())
+        && account.chars().any(|c| c.is_ascii_lowercase())
+    {
+        return Some("account looks like a random secret");
+    }
+    None
+}
+
+impl Rules {
+    /// Compile `declared` rules.
+    fn compile(declared: Declared) -> io::Result<Rules> {
+        Ok(Rules {
+            service_pattern: compile("service-pattern", declared.service_pattern)?,
+            account_pattern: compile("account-pattern", declared.account_pattern)?,
+            label_pattern: compile("label-pattern", declared.label_pattern)?,
+            required_prefixes: declared.required_prefixes,
+            forbid_secret_accounts: declared.forbid_secret_accounts,
+            ignore: declared.ignore,
+        })
+    }
+
+    /// Read rules from JSON in `reader`.
+    ///
+    /// # Errors
+    ///
+    /// Return an IO error if reading fails, or `io::ErrorKind::InvalidData` if
+    /// the rules are malformed or a pattern is no valid regular expression.
+    pub fn from_json<R: Read>(reader: R) -> io::Result<Rules> {
+        let declared: Declared = serde_json::from_reader(reader).map_err(io::Error::from)?;
+        Rules::compile(declared)
+    }
+
+    /// Load the rules at `path`.
+    ///
+    /// # Errors
+    ///
+    /// See `Rules::from_json`.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Rules> {
+        Rules::from_json(File::open(path)?)
+    }
+
+    /// Load the rules at the path in `RULES_VAR`, or else at `DEFAULT_PATH`.
+    ///
+    /// Return the default rules if `RULES_VAR` is unset and there are no
+    /// rules at `DEFAULT_PATH`.
+    ///
+    /// # Errors
+    ///
+    /// See `Rules::from_json`; it's an error if `RULES_VAR` points to a file
+    /// which does not exist.
+    pub fn load_default() -> io::Result<Rules> {
+        match env::var_os(RULES_VAR) {
+            Some(path) => Rules::load(PathBuf::from(path)),
+            None => match Rules::load(DEFAULT_PATH) {
+                Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(Rules::default()),
+                result => result,
+            },
+        }
+    }
+
+    /// Check the names of an item of `account` at `service` with `label`.
+    ///
+    /// Return all rules the names break, or nothing if `service` is ignored.
+    ///
+    /// ```
+    /// use rust_ffi_demo::lint::{Rule, Rules};
+    ///
+    /// let rules = Rules::from_json(&br#"{
+    ///     "account-pattern": "^[a-z]+$",
+    ///     "required-prefixes": ["com.example."],
+    ///     "ignore": ["com.apple.*"]
+    /// }"#[..]).unwrap();
+    /// assert!(rules.check("com.example.api", "deploy", None).is_empty());
+    /// assert!(rules.check("com.apple.account", "Deploy", None).is_empty());
+    ///
+    /// let findings = rules.check("api.example.com", "ghp_0123456789abcdefghij", None);
+    /// let broken: Vec<Rule> = findings.iter().map(|finding| finding.rule).collect();
+    /// assert_eq!(
+    ///     broken,
+    ///     [Rule::AccountPattern, Rule::RequiredPrefix, Rule::SecretAccount]
+    /// );
+    /// ```
+    pub fn check(&self, service: &str, account: &str, label: Option<&str>) -> Vec<Finding> {
+        if self
+            .ignore
+            .iter()
+            .any(|pattern| glob_match(pattern, service))
+        {
+            return Vec::new();
+        }
+        let mut findings = Vec::new();
+        let mut report = |rule: Rule, message: String| {
+            findings.push(Finding {
+                service: service.to_string(),
+                account: account.to_string(),
+                rule,
+                message,
+            })
+        };
+        let patterns = [
+            (
+                Rule::ServicePattern,
+                "service",
+                &self.service_pattern,
+                Some(service),
+            ),
+            (
+                Rule::AccountPattern,
+                "account",
+                &self.account_pattern,
+                Some(account),
+            ),
+            (Rule::LabelPattern, "label", &self.label_pattern, label),
+        ];
+        for &(rule, name, pattern, value) in &patterns {
+            if let (Some(pattern), Some(value)) = (pattern, value) {
+                if !pattern.is_match(value) {
+                    report(
+                        rule,
+                        format!("{} does not match {}", name, pattern.as_str()),
+                    );
+                }
+            }
+        }
+        if !self.required_prefixes.is_empty()
+            && !self
+                .required_prefixes
+                .iter()
+                .any(|prefix| service.starts_with(prefix.as_str()))
+        {
+            report(
+                Rule::RequiredPrefix,
+                format!(
+                    "service does not start with {}",
+                    self.required_prefixes.join(" or ")
+                ),
+            );
+        }
+        if self.forbid_secret_accounts {
+            if let Some(reason) = secret_reason(account) {
+                report(Rule::SecretAccount, reason.to_string());
+            }
+        }
+        findings
+    }
+}
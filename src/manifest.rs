@@ -0,0 +1,585 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Converge the keychain to a declarative manifest.
+//!
+//! A manifest lists the generic passwords which should exist, as flat
+//! records in the formats of `export`, eg, YAML:
+//!
+//! ```yaml
+//! - service: api.example.com
+//!   account: jane
+//!   label: Example API
+//!   value-from-env: EXAMPLE_API_TOKEN
+//! - service: db.example.com
+//!   account: app
+//!   protection: device-only-after-first-unlock
+//!   value-from-file: secrets/db-password
+//! - service: queue.example.com
+//!   account: worker
+//!   value-from: random:40
+//! ```
+//!
+//! Every entry names a service and an account, and optionally a label, a
+//! protection preset, and where to take the secret from: `value` holds it
+//! literally, `value-from-env` names an environment variable,
+//! `value-from-file` a file, relative to the manifest, and `value-from` any
+//! `provider`, eg, `command:pass show db` or `random:40`.  Generated secrets
+//! only go into new items.  Without a source the secret of an existing item
+//! stays as it is.  Entries with protection
+//! live in the data protection keychain, all others in the file keychain.
+//!
+//! `plan` compares the manifest to the keychain, and `apply` carries out the
+//! plan.  A plan lists the items to create, update and delete, with the
+//! attributes which differ, like `diff` shows secrets only by their SHA-256
+//! digests.  Applying a manifest twice changes nothing the second time:
+//!
+//! ```no_run
+//! use rust_ffi_demo::manifest::{self, Manifest};
+//! use std::path::Path;
+//!
+//! let manifest = Manifest::load(Path::new("secrets.yaml")).unwrap();
+//! let plan = manifest::plan(None, &manifest, false).unwrap();
+//! print!("{}", plan);
+//! manifest::apply(None, &plan).unwrap();
+//! assert!(manifest::plan(None, &manifest, false).unwrap().is_empty());
+//! ```
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+use serde::{de, Deserialize, Deserializer};
+
+use crate::export::{self, Format};
+use crate::keychain::digest::{ct_eq, sha256_hex};
+use crate::keychain::items::{self, Item};
+use crate::keychain::keychains::Keychain;
+use crate::keychain::protection::{self, Preset, Protection};
+use crate::keychain::sensitive::Sensitive;
+use crate::keychain::{correlation, KeychainError, KeychainErrorCode, Result, SecretBytes};
+use crate::provider;
+use crate::transaction::{self, Change, Report};
+
+/// The number of hex digits of digests shown in plans.
+const SHORT_DIGEST: usize = 12;
+
+/// An entry as written in a manifest.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Declared {
+    service: String,
+    account: String,
+    #[serde(default)]
+    label: Option<String>,
+    #[serde(default, deserialize_with = "preset")]
+    protection: Option<Preset>,
+    #[serde(default)]
+    value: Option<String>,
+    #[serde(default)]
+    value_from_env: Option<String>,
+    #[serde(default)]
+    value_from_file: Option<String>,
+    #[serde(default)]
+    value_from: Option<String>,
+}
+
+/// Deserialize a protection preset by name.
+fn preset<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<Preset>, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    name.parse()
+        .map(Some)
+        .map_err(|error: KeychainError| de::Error::custom(error.message))
+}
+
+/// A desired generic password.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// The service of the item.
+    pub service: String,
+    /// The account of the item.
+    pub account: String,
+    /// The label of the item, or `None` to leave it as it is.
+    pub label: Option<String>,
+    /// The protection of the item in the data protection keychain, or `None`
+    /// for an item in the file keychain.
+    pub protection: Option<Preset>,
+    /// The secret of the item, or `None` to leave it as it is.
+    pub value: Option<Sensitive<SecretBytes>>,
+    /// Whether the secret was generated, so that it only goes into a new
+    /// item.
+    pub generated: bool,
+}
+
+impl Entry {
+    /// The SHA-256 digest of the secret of this entry, as lowercase hex
+    /// string, if it has a secret.
+    pub fn digest(&self) -> Option<String> {
+        self.value.as_ref().map(|value| sha256_hex(value.expose()))
+    }
+
+    /// The secret to update an existing item with, if any.
+    fn update_value(&self) -> Option<&SecretBytes> {
+        self.value
+            .as_ref()
+            .map(Sensitive::expose)
+            .filter(|_| !self.generated)
+    }
+
+    /// The item to create for this entry.
+    fn item(&self) -> Item {
+        let mut item = Item::new(self.service.as_str(), self.account.as_str());
+        item.label = self.label.clone();
+        item
+    }
+}
+
+/// The desired generic passwords, with their secrets.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Manifest {
+    /// The entries, in order of the manifest.
+    pub entries: Vec<Entry>,
+}
+
+impl Manifest {
+    /// The services of the entries whose secrets `plan` reads.
+    ///
+    /// `plan` reads the current secret of every existing item which the
+    /// manifest gives a value, to tell whether it differs.
+    pub fn read_services(&self) -> impl Iterator<Item = &str> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.update_value().is_some())
+            .map(|entry| entry.service.as_str())
+    }
+
+    /// Load the manifest at `path`, in the format of its extension.
+    ///
+    /// Read `value-from-file` paths relative to the directory of `path`, and
+    /// run `command:` providers there.
+    ///
+    /// # Errors
+    ///
+    /// Like `read`.
+    pub fn load(path: &Path) -> io::Result<Manifest> {
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        Manifest::read(fs::File::open(path)?, Format::for_path(path), base)
+    }
+
+    /// Read a manifest in `format` from `reader`, and read the secrets of its
+    /// entries.
+    ///
+    /// Take secrets from providers, with `base` as the directory of the
+    /// manifest.
+    ///
+    /// ```
+    /// use rust_ffi_demo::export::Format;
+    /// use rust_ffi_demo::keychain::sensitive::Sensitive;
+    /// use rust_ffi_demo::manifest::Manifest;
+    /// use std::path::Path;
+    ///
+    /// let yaml = "- service: api.example.com\n  account: jane\n  value: s3cr3t\n";
+    /// let manifest = Manifest::read(yaml.as_bytes(), Format::Yaml, Path::new("")).unwrap();
+    /// assert_eq!(manifest.entries[0].value, Some(Sensitive::new(b"s3cr3t".to_vec())));
+    ///
+    /// let yaml = "- service: api.example.com\n  account: jane\n  value-from: command:echo s3cr3t\n";
+    /// let manifest = Manifest::read(yaml.as_bytes(), Format::Yaml, Path::new("")).unwrap();
+    /// assert_eq!(manifest.entries[0].value, Some(Sensitive::new(b"s3cr3t".to_vec())));
+    ///
+    /// let twice = format!("{}{}", yaml, yaml);
+    /// assert!(Manifest::read(twice.as_bytes(), Format::Yaml, Path::new("")).is_err());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Return an IO error if reading fails, and `io::ErrorKind::InvalidData`
+    /// if the manifest is malformed, lists an item twice, or gives more than
+    /// one source for a secret, and the error of the provider if a source
+    /// fails.
+    pub fn read<R: Read>(reader: R, format: Format, base: &Path) -> io::Result<Manifest> {
+        let mut seen = BTreeSet::new();
+        let mut entries = Vec::new();
+        for declared in export::read_as::<Declared, R>(reader, format)? {
+            let invalid = |message: String| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} {}: {}", declared.service, declared.account, message),
+                )
+            };
+            if declared.service.is_empty() || declared.account.is_empty() {
+                return Err(invalid("empty service or account".to_string()));
+            }
+            if !seen.insert((declared.service.clone(), declared.account.clone())) {
+                return Err(invalid("listed twice".to_string()));
+            }
+            let sources = [
+                declared
+                    .value_from_env
+                    .as_ref()
+                    .map(|name| ("env", name.as_str())),
+                declared
+                    .value_from_file
+                    .as_ref()
+                    .map(|path| ("file", path.as_str())),
+                declared
+                    .value_from
+                    .as_ref()
+                    .map(|source| match source.find(':') {
+                        Some(colon) => (&source[..colon], &source[colon + 1..]),
+                        None => (source.as_str(), ""),
+                    }),
+            ];
+            let mut sources = sources.iter().flatten();
+            let (value, generated) =
+                match (&declared.value, sources.next(), sources.next()) {
+                    (None, None, _) => (None, false),
+                    (Some(value), None, _) => (Some(value.clone().into_bytes()), false),
+                    (None, Some(&(name, argument)), None) => {
+                        let provider = provider::get(name).ok_or_else(|| {
+                            invalid(format!(
+                                "unknown provider {}, expected one of {}",
+                                name,
+                                provider::BUILT_IN.join(", ")
+                            ))
+                        })?;
+                        let value = provider.provide(argument, base).map_err(|error| {
+                            io::Error::new(
+                                error.kind(),
+                                format!("{} {}: {}", declared.service, declared.account, error),
+                            )
+                        })?;
+                        (Some(value), provider.generates())
+                    }
+                    _ => return Err(invalid(
+                        "more than one of value, value-from-env, value-from-file and value-from"
+                            .to_string(),
+                    )),
+                };
+            entries.push(Entry {
+                service: declared.service,
+                account: declared.account,
+                label: declared.label,
+                protection: declared.protection,
+                value: value.map(Sensitive::new),
+                generated,
+            });
+        }
+        Ok(Manifest { entries })
+    }
+}
+
+/// An attribute of an item which differs from the manifest.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Difference {
+    /// The current and the desired label.
+    Label(Option<String>, String),
+    /// The secret, without telling anything about the current one.
+    Value,
+    /// The current and the desired protection.
+    Protection(Option<Protection>, Preset),
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Difference::Label(ref current, ref desired) => write!(
+                f,
+                "label {:?} -> {:?}",
+                current.as_deref().unwrap_or(""),
+                desired
+            ),
+            Difference::Value => write!(f, "value differs"),
+            Difference::Protection(ref current, desired) => match *current {
+                Some(ref current) => write!(f, "protection {} -> {}", current, desired),
+                None => write!(f, "protection none -> {}", desired),
+            },
+        }
+    }
+}
+
+/// What to do to an item to converge to the manifest.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    /// Create the item of an entry.
+    Create(Entry),
+    /// Change the attributes of an existing item which differ from its entry.
+    Update(Entry, Vec<Difference>),
+    /// Delete an item which the manifest does not list.
+    Delete {
+        /// The service of the item.
+        service: String,
+        /// The account of the item.
+        account: String,
+    },
+}
+
+impl Action {
+    /// The service and account of the item of this action.
+    pub fn target(&self) -> (&str, &str) {
+        match *self {
+            Action::Create(ref entry) | Action::Update(ref entry, _) => {
+                (&entry.service, &entry.account)
+            }
+            Action::Delete {
+                ref service,
+                ref account,
+            } => (service, account),
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (service, account) = self.target();
+        match *self {
+            Action::Create(ref entry) => {
+                write!(f, "+ {}\t{}", service, account)?;
+                if let Some(ref label) = entry.label {
+                    write!(f, "\tlabel {:?}", label)?;
+                }
+                match entry.digest() {
+                    Some(_) if entry.generated => write!(f, "\tvalue generated")?,
+                    Some(digest) => write!(f, "\tvalue sha256:{}", &digest[..SHORT_DIGEST])?,
+                    None => {}
+                }
+                if let Some(preset) = entry.protection {
+                    write!(f, "\tprotection {}", preset)?;
+                }
+                Ok(())
+            }
+            Action::Update(_, ref differences) => {
+                write!(f, "~ {}\t{}", service, account)?;
+                for difference in differences {
+                    write!(f, "\t{}", difference)?;
+                }
+                Ok(())
+            }
+            Action::Delete { .. } => write!(f, "- {}\t{}", service, account),
+        }
+    }
+}
+
+/// The actions which converge the keychain to a manifest.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Plan {
+    /// The actions, creates and updates in order of the manifest, followed
+    /// by deletes.
+    pub actions: Vec<Action>,
+}
+
+impl Plan {
+    /// Whether the keychain already matches the manifest.
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    /// How many items to create, update and delete.
+    pub fn counts(&self) -> (usize, usize, usize) {
+        self.actions.iter().fold(
+            (0, 0, 0),
+            |(create, update, delete), action| match *action {
+                Action::Create(_) => (create + 1, update, delete),
+                Action::Update(..) => (create, update + 1, delete),
+                Action::Delete { .. } => (create, update, delete + 1),
+            },
+        )
+    }
+}
+
+impl fmt::Display for Plan {
+    /// Print one action per line, and a summary.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No changes, the keychain matches the manifest.");
+        }
+        for action in &self.actions {
+            writeln!(f, "{}", action)?;
+        }
+        let (create, update, delete) = self.counts();
+        writeln!(
+            f,
+            "Plan: {} to create, {} to update, {} to delete.",
+            create, update, delete
+        )
+    }
+}
+
+/// Find the item of `entry`, or `None` if it doesn't exist.
+fn find(keychain: Option<&Keychain>, entry: &Entry) -> Result<Option<Item>> {
+    let result = match entry.protection {
+        Some(_) => protection::find_protected_item(&entry.service, &entry.account),
+        None => items::find_item(keychain, &entry.service, &entry.account),
+    };
+    match result {
+        Ok(item) => Ok(Some(item)),
+        Err(ref error) if error.status == KeychainErrorCode::ItemNotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Compare the existing `item` to its `entry`.
+fn differences(keychain: Option<&Keychain>, entry: &Entry, item: &Item) -> Result<Vec<Difference>> {
+    let mut differences = Vec::new();
+    if let Some(ref label) = entry.label {
+        if item.label.as_ref() != Some(label) {
+            differences.push(Difference::Label(item.label.clone(), label.clone()));
+        }
+    }
+    if let Some(value) = entry.update_value() {
+        let current = match entry.protection {
+            Some(_) => protection::find_protected_data(&entry.service, &entry.account)?,
+            None => items::find_data(keychain, &entry.service, &entry.account)?.into_exposed(),
+        };
+        if !ct_eq(&current, value) {
+            differences.push(Difference::Value);
+        }
+    }
+    if let Some(preset) = entry.protection {
+        let current = item.protection.clone().unwrap_or_default();
+        if !preset.matches(&current) {
+            differences.push(Difference::Protection(item.protection.clone(), preset));
+        }
+    }
+    Ok(differences)
+}
+
+/// Plan how to converge `keychain`, or the default keychain, to `manifest`.
+///
+/// Reads the secrets of existing items which the manifest gives a value, see
+/// `Manifest::read_services`, which may prompt for access, but changes
+/// nothing, and only tells whether they differ.  With `prune` delete items in
+/// `keychain` which the manifest does not list, but only of services which
+/// the manifest lists, so that pruning never touches items of other apps.
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::InvalidData` if an entry without value has no
+/// item yet, and `KeychainError` if keychain access fails.
+pub fn plan(keychain: Option<&Keychain>, manifest: &Manifest, prune: bool) -> Result<Plan> {
+    correlation::scope(|_| {
+        let mut actions = Vec::new();
+        for entry in &manifest.entries {
+            match find(keychain, entry)? {
+                None if entry.value.is_none() => {
+                    return Err(KeychainError::invalid_data(format!(
+                        "No value to create {} {}",
+                        entry.service, entry.account
+                    )))
+                }
+                None => actions.push(Action::Create(entry.clone())),
+                Some(item) => {
+                    let differences = differences(keychain, entry, &item)?;
+                    if !differences.is_empty() {
+                        actions.push(Action::Update(entry.clone(), differences));
+                    }
+                }
+            }
+        }
+        if prune {
+            let listed: BTreeSet<(&str, &str)> = manifest
+                .entries
+                .iter()
+                .map(|entry| (entry.service.as_str(), entry.account.as_str()))
+                .collect();
+            let services: BTreeSet<&str> = listed.iter().map(|&(service, _)| service).collect();
+            let mut unlisted: Vec<Item> = items::list_items(keychain)?
+                .into_iter()
+                .filter(|item| services.contains(item.service.as_str()))
+                .filter(|item| !listed.contains(&(item.service.as_str(), item.account.as_str())))
+                .collect();
+            unlisted.sort_by(|a, b| (&a.service, &a.account).cmp(&(&b.service, &b.account)));
+            actions.extend(unlisted.into_iter().map(|item| Action::Delete {
+                service: item.service,
+                account: item.account,
+            }));
+        }
+        Ok(Plan { actions })
+    })
+}
+
+/// Carry out `plan` on `keychain`, or the default keychain.
+///
+/// Apply all actions on the file keychain as one transaction, and then the
+/// actions on the data protection keychain one by one.  Updating an item
+/// without new value reads its secret first, which may prompt for access.
+///
+/// # Errors
+///
+/// Return `KeychainError` if an action fails.  If an action on the file
+/// keychain fails, none of them are applied; if an action on the data
+/// protection keychain fails, the actions before it stay applied.
+pub fn apply(keychain: Option<&Keychain>, plan: &Plan) -> Result<Report> {
+    correlation::scope(|_| {
+        let mut changes = Vec::new();
+        let mut protected = Vec::new();
+        for action in &plan.actions {
+            match *action {
+                Action::Create(ref entry) | Action::Update(ref entry, _)
+                    if entry.protection.is_some() =>
+                {
+                    protected.push(action)
+                }
+                Action::Create(ref entry) => changes.push(Change::Add(
+                    entry.item(),
+                    entry
+                        .value
+                        .clone()
+                        .map(Sensitive::into_exposed)
+                        .unwrap_or_default(),
+                )),
+                Action::Update(ref entry, _) => {
+                    let mut item = items::find_item(keychain, &entry.service, &entry.account)?;
+                    if entry.label.is_some() {
+                        item.label = entry.label.clone();
+                    }
+                    let data = match entry.update_value() {
+                        Some(value) => value.clone(),
+                        None => items::find_data(keychain, &entry.service, &entry.account)?
+                            .into_exposed(),
+                    };
+                    changes.push(Change::Update(item, data));
+                }
+                Action::Delete {
+                    ref service,
+                    ref account,
+                } => changes.push(Change::Delete {
+                    service: service.clone(),
+                    account: account.clone(),
+                }),
+            }
+        }
+        let mut report = transaction::apply(keychain, &changes)?;
+        for action in protected {
+            match *action {
+                Action::Create(ref entry) => protection::add_preset_item(
+                    &entry.item(),
+                    entry.value.as_ref().map_or(&[][..], |value| value.expose()),
+                    entry.protection.unwrap(),
+                )?,
+                Action::Update(ref entry, _) => protection::update_preset_item(
+                    &entry.item(),
+                    entry.update_value().map(Vec::as_slice),
+                    entry.protection.unwrap(),
+                )?,
+                Action::Delete { .. } => unreachable!("Deletes are in the file keychain"),
+            }
+            report.applied += 1;
+        }
+        Ok(report)
+    })
+}
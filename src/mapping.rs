@@ -0,0 +1,164 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Map structs to entries of secret stores.
+//!
+//! A `KeychainItem` converts to and from an `Entry` and its secret, and then
+//! saves, loads and deletes itself in any `SecretStore`.  With the `derive`
+//! feature `#[derive(KeychainItem)]` implements the conversion from field
+//! attributes:
+//!
+//! ```ignore
+//! use rust_ffi_demo::mapping::KeychainItem;
+//!
+//! #[derive(KeychainItem)]
+//! struct ApiToken {
+//!     #[keychain(service)]
+//!     host: String,
+//!     #[keychain(account)]
+//!     user: String,
+//!     #[keychain(secret)]
+//!     token: String,
+//!     #[keychain(generic)]
+//!     scopes: Vec<String>,
+//! }
+//! ```
+//!
+//! Fields marked `generic` go to the generic data of the entry, as a JSON
+//! object of all such fields, so their types must implement `Serialize` and
+//! `Deserialize`.
+
+use crate::keychain::{correlation, KeychainError, KeychainErrorCode, Result};
+use crate::store::{Entry, SecretStore};
+
+#[cfg(feature = "derive")]
+pub use keychain_derive::KeychainItem;
+
+/// A type of secrets in structs.
+pub trait Secret: Sized {
+    /// The bytes of this secret.
+    fn to_bytes(&self) -> Vec<u8>;
+
+    /// Read a secret from `bytes`.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::InvalidData` if `bytes` are no valid secret.
+    fn from_bytes(bytes: Vec<u8>) -> Result<Self>;
+}
+
+impl Secret for Vec<u8> {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.clone()
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Result<Vec<u8>> {
+        Ok(bytes)
+    }
+}
+
+impl Secret for String {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Result<String> {
+        String::from_utf8(bytes).map_err(|_| KeychainError::invalid_data("Secret is not UTF-8"))
+    }
+}
+
+/// A struct stored as entry of a secret store.
+pub trait KeychainItem: Sized {
+    /// The entry of this struct, without secret.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::InvalidData` if this struct cannot be
+    /// converted.
+    fn to_entry(&self) -> Result<Entry>;
+
+    /// The secret of this struct.
+    fn secret(&self) -> Vec<u8>;
+
+    /// Create this struct from `entry` and its `secret`.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::InvalidData` if `entry` or `secret` do not
+    /// fit this struct.
+    fn from_entry(entry: Entry, secret: Vec<u8>) -> Result<Self>;
+
+    /// Save this struct to `store`, adding or replacing its entry.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainError` if this struct cannot be converted or the store
+    /// cannot be written.
+    fn save(&self, store: &mut dyn SecretStore) -> Result<()> {
+        store.set(&self.to_entry()?, &self.secret())
+    }
+
+    /// Load the struct of `service` and `account` from `store`.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::ItemNotFound` if there is no such entry, and
+    /// `KeychainError` if the entry does not fit or the store cannot be read.
+    fn load(store: &dyn SecretStore, service: &str, account: &str) -> Result<Self> {
+        let entry = store.entry(service, account)?;
+        let secret = store.get(service, account)?;
+        Self::from_entry(entry, secret)
+    }
+
+    /// Delete the entry of this struct from `store`.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::ItemNotFound` if there is no such entry, and
+    /// `KeychainError` if the store cannot be written otherwise.
+    fn delete(&self, store: &mut dyn SecretStore) -> Result<()> {
+        let entry = self.to_entry()?;
+        store.delete(&entry.service, &entry.account)
+    }
+}
+
+/// Support for derived implementations of `KeychainItem`.
+#[doc(hidden)]
+pub mod __private {
+    pub use serde_json;
+
+    use super::*;
+
+    /// Encode generic `fields` as JSON object.
+    pub fn encode_generic(fields: serde_json::Map<String, serde_json::Value>) -> Vec<u8> {
+        serde_json::Value::Object(fields).to_string().into_bytes()
+    }
+
+    /// Decode the generic data of `entry` as JSON object.
+    pub fn decode_generic(entry: &Entry) -> Result<serde_json::Map<String, serde_json::Value>> {
+        match entry.generic {
+            None => Ok(serde_json::Map::new()),
+            Some(ref generic) => serde_json::from_slice(generic).map_err(json_error),
+        }
+    }
+
+    /// Convert a JSON `error` of a generic field.
+    pub fn json_error(error: serde_json::Error) -> KeychainError {
+        KeychainError {
+            status: KeychainErrorCode::InvalidData,
+            message: format!("Invalid generic data: {}", error),
+            correlation_id: correlation::current(),
+        }
+    }
+}
@@ -0,0 +1,114 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Serve keychain metrics to Prometheus over HTTP.
+//!
+//! A minimal HTTP/1.0 server which answers `GET /metrics` with a snapshot of
+//! `keychain::metrics`, and every other request with 404.  It's meant for
+//! long-running modes like the SSH agent, and should listen on localhost.
+//!
+//! The server answers one request at a time, so it drops clients which take
+//! longer than `TIMEOUT` or send overly long lines, lest a single idle or
+//! rogue client block the endpoint for everyone.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+
+use crate::keychain::metrics;
+use crate::runtime;
+
+/// How long to wait for a client to send its request or take the response.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The maximum length of the request line and of each header, in bytes.
+const MAX_LINE_LENGTH: u64 = 8192;
+
+/// The maximum number of headers of a request.
+const MAX_HEADERS: usize = 100;
+
+/// Read a line of at most `MAX_LINE_LENGTH` bytes from `reader` into `line`.
+///
+/// # Errors
+///
+/// Return `InvalidData` if the line is longer, and an IO error if reading
+/// fails or times out.
+fn read_line<R: BufRead>(reader: &mut R, line: &mut String) -> io::Result<usize> {
+    let read = reader.take(MAX_LINE_LENGTH).read_line(line)?;
+    if read as u64 == MAX_LINE_LENGTH && !line.ends_with('\n') {
+        Err(io::Error::new(io::ErrorKind::InvalidData, "Line too long"))
+    } else {
+        Ok(read)
+    }
+}
+
+/// Answer a single HTTP request on `stream`.
+fn handle(stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    read_line(&mut reader, &mut request_line)?;
+    // Skip headers, up to the empty line
+    let mut header = String::new();
+    let mut headers = 0;
+    while read_line(&mut reader, &mut header)? != 0 && header.trim_end() != "" {
+        headers += 1;
+        if MAX_HEADERS < headers {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Too many headers",
+            ));
+        }
+        header.clear();
+    }
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics::snapshot().to_prometheus()),
+        _ => ("404 Not Found", "Not found\n".to_string()),
+    };
+    let mut stream = reader.into_inner();
+    write!(
+        stream,
+        "HTTP/1.0 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// Enable metrics and serve them at `address` on a background thread.
+///
+/// Stop serving when shutdown is requested, see `runtime`.
+///
+/// # Errors
+///
+/// Return an IO error if binding `address` fails.
+pub fn spawn<A: ToSocketAddrs>(address: A) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    metrics::enable();
+    thread::spawn(move || {
+        let result = runtime::serve_until_shutdown(&listener, |stream| {
+            if let Err(error) = handle(stream) {
+                eprintln!("Metrics request failed: {}", error);
+            }
+        });
+        if let Err(error) = result {
+            eprintln!("Metrics endpoint failed: {}", error);
+        }
+    });
+    Ok(())
+}
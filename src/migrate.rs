@@ -0,0 +1,122 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Copy or move generic passwords between keychains.
+
+use std::fmt;
+
+use crate::keychain::items::{self, Item, ListOptions};
+use crate::keychain::keychains::Keychain;
+use crate::keychain::{
+    correlation, with_autorelease_pool, KeychainError, KeychainErrorCode, Result,
+};
+use crate::pattern::glob_match;
+use crate::progress::{Progress, ProgressFn};
+
+/// What happened to a single item during migration.
+#[derive(Debug)]
+pub enum Outcome {
+    /// The item was copied to the target keychain.
+    Copied,
+    /// The item was copied to the target keychain and deleted from the source.
+    Moved,
+    /// The target keychain already has an item with the same service and
+    /// account; the item was left alone.
+    SkippedDuplicate,
+    /// Copying or deleting the item failed.
+    Failed(KeychainError),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Outcome::Copied => write!(f, "copied"),
+            Outcome::Moved => write!(f, "moved"),
+            Outcome::SkippedDuplicate => write!(f, "skipped, already in target"),
+            Outcome::Failed(ref error) => write!(f, "failed: {}", error),
+        }
+    }
+}
+
+/// The outcome of migrating a single item.
+#[derive(Debug)]
+pub struct ItemOutcome {
+    /// The attributes of the item.
+    pub item: Item,
+    /// What happened to the item.
+    pub outcome: Outcome,
+}
+
+/// Migrate a single `item` from `from` to `to`.
+fn migrate_item(from: &Keychain, to: &Keychain, item: &Item, move_items: bool) -> Outcome {
+    let result = items::find_data(Some(from), &item.service, &item.account)
+        .and_then(|data| items::add_item(Some(to), item, data.expose()));
+    match result {
+        Err(ref error) if error.status == KeychainErrorCode::DuplicateItem => {
+            return Outcome::SkippedDuplicate
+        }
+        Err(error) => return Outcome::Failed(error),
+        Ok(()) => (),
+    }
+    if move_items {
+        match items::delete_item(Some(from), &item.service, &item.account) {
+            Ok(()) => Outcome::Moved,
+            Err(error) => Outcome::Failed(error),
+        }
+    } else {
+        Outcome::Copied
+    }
+}
+
+/// Copy generic passwords from the keychain `from` to the keychain `to`.
+///
+/// Copy service, account, label, description, comment and data of every
+/// item whose service matches the wildcard `pattern`, or of all items if
+/// `pattern` is `None`.  Skip items which already exist in `to`.  If
+/// `move_items` is true delete copied items from `from`.
+///
+/// Failures of single items do not stop the migration; they are reported in
+/// the returned outcomes instead.  Report `progress` after every item.
+///
+/// # Errors
+///
+/// Return `KeychainError` if listing the items of `from` fails.
+pub fn migrate(
+    from: &Keychain,
+    to: &Keychain,
+    pattern: Option<&str>,
+    move_items: bool,
+    progress: &mut ProgressFn<'_>,
+) -> Result<Vec<ItemOutcome>> {
+    correlation::scope(|_| {
+        let mut listed = items::list_items(Some(from))?;
+        listed.retain(|item| pattern.is_none_or(|pattern| glob_match(pattern, &item.service)));
+        ListOptions::default().sort(&mut listed);
+        let total = listed.len();
+        Ok(listed
+            .into_iter()
+            .enumerate()
+            .map(|(index, item)| {
+                let outcome = with_autorelease_pool(|| migrate_item(from, to, &item, move_items));
+                progress(&Progress {
+                    processed: index + 1,
+                    total,
+                    service: &item.service,
+                    account: &item.account,
+                });
+                ItemOutcome { item, outcome }
+            })
+            .collect())
+    })
+}
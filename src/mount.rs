@@ -0,0 +1,288 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! A read-only view of keychain items as a directory tree.
+//!
+//! Some tools only read secrets from files.  A `Mount` materializes generic
+//! passwords as one file per item, at `SERVICE/ACCOUNT` below its directory,
+//! and `serve` keeps the files up to date as the keychain changes, until
+//! shutdown:
+//!
+//! ```no_run
+//! use rust_ffi_demo::keychain::query::FilterExpr;
+//! use rust_ffi_demo::{mount, runtime};
+//! use std::path::Path;
+//!
+//! runtime::install();
+//! let filter = FilterExpr::tag("ci");
+//! mount::serve(None, Path::new("/tmp/secrets"), &filter, &|_| true, true).unwrap();
+//! runtime::exit();
+//! ```
+//!
+//! Without secrets a file lists the attributes of its item, one `key: value`
+//! per line; with secrets it holds just the secret, which may prompt for
+//! access once per item.  Files are readable only by the owner, and replaced
+//! atomically.  Names are escaped so that every service and account makes a
+//! single path component: `%` becomes `%25`, `/` becomes `%2F`, and a
+//! leading `.` becomes `%2E`.  File systems on macOS ignore case by
+//! default, so names which only differ in case cannot be mounted together.
+//!
+//! The directory must not exist yet, and dropping the mount removes it with
+//! all files.
+
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+
+use crate::keychain::events::Subscription;
+use crate::keychain::items::{self, Item};
+use crate::keychain::keychains::Keychain;
+use crate::keychain::query::FilterExpr;
+use crate::keychain::{correlation, KeychainError, KeychainErrorCode, Result};
+use crate::output::format_time;
+use crate::runtime;
+
+/// How often `serve` checks for shutdown.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Escape `name` to a single path component.
+///
+/// ```
+/// use rust_ffi_demo::mount::escape;
+///
+/// assert_eq!(escape("api.example.com"), "api.example.com");
+/// assert_eq!(escape("https://example.com/100%"), "https:%2F%2Fexample.com%2F100%25");
+/// assert_eq!(escape(".."), "%2E.");
+/// ```
+pub fn escape(name: &str) -> String {
+    let escaped = name.replace('%', "%25").replace('/', "%2F");
+    match escaped.strip_prefix('.') {
+        Some(rest) => format!("%2E{}", rest),
+        None if escaped.is_empty() => "%".to_string(),
+        None => escaped,
+    }
+}
+
+/// The attributes of `item`, as contents of its file.
+fn attributes(item: &Item) -> Vec<u8> {
+    let mut lines = vec![
+        format!("service: {}", item.service),
+        format!("account: {}", item.account),
+    ];
+    let optional = [
+        ("label", item.label.clone()),
+        ("comment", item.comment.clone()),
+        ("created", item.created.map(format_time)),
+        ("modified", item.modified.map(format_time)),
+    ];
+    for (key, value) in optional.iter() {
+        if let Some(value) = value {
+            lines.push(format!("{}: {}", key, value.replace('\n', " ")));
+        }
+    }
+    let mut contents = lines.join("\n").into_bytes();
+    contents.push(b'\n');
+    contents
+}
+
+/// Record `name` in `names`, by its lowercase form.
+///
+/// # Errors
+///
+/// Return `KeychainErrorCode::InvalidData` if `names` already has a name
+/// which only differs in case, and would thus name the same file on a case
+/// insensitive file system.
+fn claim(names: &mut BTreeMap<String, String>, name: String) -> Result<()> {
+    match names.get(&name.to_lowercase()) {
+        Some(other) if *other != name => Err(KeychainError::invalid_data(format!(
+            "Cannot mount both {} and {}, which only differ in case",
+            other, name
+        ))),
+        Some(_) => Ok(()),
+        None => {
+            names.insert(name.to_lowercase(), name);
+            Ok(())
+        }
+    }
+}
+
+/// A directory tree of keychain items.
+#[derive(Debug)]
+pub struct Mount {
+    root: PathBuf,
+    secrets: bool,
+    /// The modification time of the item and the contents of every file, by
+    /// service and account directory names.
+    files: BTreeMap<(String, String), (Option<SystemTime>, Vec<u8>)>,
+}
+
+impl Mount {
+    /// Create an empty mount at `root`, with the secrets of items if
+    /// `secrets` is set.
+    ///
+    /// # Errors
+    ///
+    /// Return an IO error if `root` exists or cannot be created.
+    pub fn create(root: &Path, secrets: bool) -> Result<Mount> {
+        match DirBuilder::new().mode(0o700).create(root) {
+            Err(ref error) if error.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(KeychainError {
+                    status: KeychainErrorCode::Io(io::ErrorKind::AlreadyExists),
+                    message: format!("Not mounting over existing {}", root.display()),
+                    correlation_id: correlation::current(),
+                })
+            }
+            result => result?,
+        }
+        Ok(Mount {
+            root: root.to_path_buf(),
+            secrets,
+            files: BTreeMap::new(),
+        })
+    }
+
+    /// The directory of this mount.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Update the files to the items in `keychain`, or the search list, which
+    /// match `filter` and whose service is `readable`.
+    ///
+    /// Only read secrets of items modified since the last refresh, and only
+    /// write files whose contents changed.  Return the number of files.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainError` if listing items or reading a secret fails,
+    /// `KeychainErrorCode::InvalidData` if the names of two items only differ
+    /// in case, and an IO error if writing files fails.
+    pub fn refresh(
+        &mut self,
+        keychain: Option<&Keychain>,
+        filter: &FilterExpr,
+        readable: &dyn Fn(&str) -> bool,
+    ) -> Result<usize> {
+        let mut files = BTreeMap::new();
+        let mut names = BTreeMap::new();
+        for item in items::list_items(keychain)? {
+            if item.invisible || item.negative || !filter.matches(&item) || !readable(&item.service)
+            {
+                continue;
+            }
+            let key = (escape(&item.service), escape(&item.account));
+            // Escaped names have no slash, so paths never clash with services
+            claim(&mut names, key.0.clone())?;
+            claim(&mut names, format!("{}/{}", key.0, key.1))?;
+            let contents = match self.files.get(&key) {
+                Some(&(modified, ref contents))
+                    if self.secrets && modified.is_some() && modified == item.modified =>
+                {
+                    contents.clone()
+                }
+                _ if self.secrets => {
+                    items::find_data(keychain, &item.service, &item.account)?.into_exposed()
+                }
+                _ => attributes(&item),
+            };
+            files.insert(key, (item.modified, contents));
+        }
+        for (key, (_, contents)) in &files {
+            if self.files.get(key).map(|(_, current)| current) != Some(contents) {
+                self.write(key, contents)?;
+            }
+        }
+        for (service, account) in self.files.keys() {
+            if !files.contains_key(&(service.clone(), account.clone())) {
+                let directory = self.root.join(service);
+                match fs::remove_file(directory.join(account)) {
+                    // Someone else removed the file already
+                    Err(ref error) if error.kind() == io::ErrorKind::NotFound => {}
+                    result => result?,
+                }
+                // Fails as long as the service has other items
+                let _ = fs::remove_dir(directory);
+            }
+        }
+        self.files = files;
+        Ok(self.files.len())
+    }
+
+    /// Write the file of `service` and `account` with `contents`.
+    ///
+    /// Write a temporary file next to it first, and rename it over the file,
+    /// so that readers never see partial contents.
+    fn write(&self, (service, account): &(String, String), contents: &[u8]) -> io::Result<()> {
+        let directory = self.root.join(service);
+        match DirBuilder::new().mode(0o700).create(&directory) {
+            Err(ref error) if error.kind() == io::ErrorKind::AlreadyExists => {}
+            result => result?,
+        }
+        // Escaped names never start with a dot
+        let temporary = directory.join(format!(".{}", account));
+        let _ = fs::remove_file(&temporary);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o400)
+            .open(&temporary)?;
+        file.write_all(contents)?;
+        fs::rename(&temporary, directory.join(account))
+    }
+}
+
+impl Drop for Mount {
+    /// Remove the directory of this mount.
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Mount the items of `keychain`, or the search list, which match `filter`
+/// and whose service is `readable` at `root`, until shutdown.
+///
+/// Refresh the files whenever the keychain changes, and remove `root` on
+/// shutdown, see `runtime`.
+///
+/// # Errors
+///
+/// Return `KeychainError` if the mount cannot be created or refreshed, or
+/// keychain events cannot be subscribed to.  Remove `root` on errors too.
+pub fn serve(
+    keychain: Option<&Keychain>,
+    root: &Path,
+    filter: &FilterExpr,
+    readable: &dyn Fn(&str) -> bool,
+    secrets: bool,
+) -> Result<()> {
+    let mut mount = Mount::create(root, secrets)?;
+    mount.refresh(keychain, filter, readable)?;
+    let changed = Rc::new(Cell::new(false));
+    let subscription = {
+        let changed = changed.clone();
+        Subscription::new(move |_| changed.set(true))?
+    };
+    while runtime::shutdown_signal().is_none() {
+        subscription.run_for(POLL_INTERVAL);
+        if changed.replace(false) {
+            mount.refresh(keychain, filter, readable)?;
+        }
+    }
+    Ok(())
+}
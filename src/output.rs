@@ -0,0 +1,199 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Helpers for machine-readable and colored output.
+//!
+//! Colors are off until `set_color` turns them on.  With `ColorChoice::Auto`
+//! colors are on for terminals, unless the `NO_COLOR` environment variable is
+//! set, see <https://no-color.org>.
+
+use std::env;
+use std::fmt::Write;
+use std::io::{self, IsTerminal};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Whether to color standard output.
+static COLOR_STDOUT: AtomicBool = AtomicBool::new(false);
+
+/// Whether to color standard error.
+static COLOR_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Quote `s` as JSON string.
+///
+/// ```
+/// use rust_ffi_demo::output::json_string;
+///
+/// assert_eq!(json_string("say \"hi\"\n"), r#""say \"hi\"\n""#);
+/// assert_eq!(json_string("\u{1}"), r#""\u0001""#);
+/// ```
+pub fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Quote `value` as JSON string, or `null` if absent.
+pub fn json_optional_string(value: Option<&str>) -> String {
+    value.map_or_else(|| "null".to_string(), json_string)
+}
+
+/// Format `time` as UTC date and time.
+///
+/// ```
+/// use std::time::{Duration, UNIX_EPOCH};
+/// use rust_ffi_demo::output::format_time;
+///
+/// let time = UNIX_EPOCH + Duration::from_secs(951_782_400);
+/// assert_eq!(format_time(time), "2000-02-29 00:00:00 UTC");
+/// ```
+pub fn format_time(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    // Convert days to a civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let days = (seconds / 86_400) as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    let second_of_day = seconds % 86_400;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        second_of_day / 3600,
+        second_of_day / 60 % 60,
+        second_of_day % 60
+    )
+}
+
+/// When to color output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    /// Color output to terminals, unless `NO_COLOR` is set.
+    Auto,
+    /// Always color output.
+    Always,
+    /// Never color output.
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ColorChoice, String> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("Unknown color choice: {}", s)),
+        }
+    }
+}
+
+/// Turn colors on or off according to `choice`.
+pub fn set_color(choice: ColorChoice) {
+    let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    let (stdout, stderr) = match choice {
+        ColorChoice::Always => (true, true),
+        ColorChoice::Never => (false, false),
+        ColorChoice::Auto if no_color => (false, false),
+        ColorChoice::Auto => (io::stdout().is_terminal(), io::stderr().is_terminal()),
+    };
+    COLOR_STDOUT.store(stdout, Ordering::Relaxed);
+    COLOR_STDERR.store(stderr, Ordering::Relaxed);
+}
+
+/// A style of colored output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+    /// Highlighted service names, in bold cyan.
+    Service,
+    /// Errors and failures, in red.
+    Error,
+    /// Metadata of lesser interest, dimmed.
+    Dimmed,
+}
+
+impl Style {
+    /// Wrap `text` in the ANSI escape sequences of this style.
+    ///
+    /// ```
+    /// use rust_ffi_demo::output::Style;
+    ///
+    /// assert_eq!(Style::Error.apply("failed"), "\u{1b}[31mfailed\u{1b}[0m");
+    /// ```
+    pub fn apply(self, text: &str) -> String {
+        let code = match self {
+            Style::Service => "1;36",
+            Style::Error => "31",
+            Style::Dimmed => "2",
+        };
+        format!("\u{1b}[{}m{}\u{1b}[0m", code, text)
+    }
+}
+
+/// Style `text` with `style` if colors are on for `enabled`.
+fn paint(enabled: &AtomicBool, style: Style, text: &str) -> String {
+    if enabled.load(Ordering::Relaxed) && !text.is_empty() {
+        style.apply(text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Highlight `service` for standard output.
+pub fn service(service: &str) -> String {
+    paint(&COLOR_STDOUT, Style::Service, service)
+}
+
+/// Dim `metadata` for standard output.
+pub fn dimmed(metadata: &str) -> String {
+    paint(&COLOR_STDOUT, Style::Dimmed, metadata)
+}
+
+/// Color `failure` red for standard output.
+pub fn failure(failure: &str) -> String {
+    paint(&COLOR_STDOUT, Style::Error, failure)
+}
+
+/// Color `error` red for standard error.
+pub fn error(error: &str) -> String {
+    paint(&COLOR_STDERR, Style::Error, error)
+}
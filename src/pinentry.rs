@@ -0,0 +1,194 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! A pinentry answering passphrase requests from the keychain.
+//!
+//! Speaks the Assuan pinentry protocol on stdin and stdout, as used by
+//! gpg-agent.  `GETPIN` looks for a generic password of the configured
+//! service whose account is the keygrip from `SETKEYINFO`, or the first item
+//! of the service if gpg-agent sent no keygrip.  There is no user interface;
+//! confirmations are always declined.
+
+use std::io::{self, BufRead, Write};
+use std::process;
+
+use crate::keychain::local_auth::{authenticate, Policy};
+use crate::keychain::{find_generic_password, find_generic_password_by_service, Account};
+
+/// `GPG_ERR_CANCELED` from source `GPG_ERR_SOURCE_PINENTRY`.
+const ERR_CANCELED: u32 = 83_886_179;
+/// `GPG_ERR_ASS_UNKNOWN_CMD` from source `GPG_ERR_SOURCE_USER_1`.
+const ERR_UNKNOWN_COMMAND: u32 = 536_871_187;
+
+/// The configuration of the pinentry.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The service of passphrase items.
+    pub service: String,
+    /// Whether to require Touch ID before returning a passphrase.
+    pub touch_id: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            service: "GnuPG".to_string(),
+            touch_id: false,
+        }
+    }
+}
+
+/// Decode `%XX` escapes in Assuan data.
+fn unescape(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'%' {
+            s.get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Escape `%`, CR and LF in Assuan data.
+fn escape(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// The state of a pinentry session.
+struct Session<'a> {
+    config: &'a Config,
+    description: Option<String>,
+    keygrip: Option<String>,
+}
+
+impl<'a> Session<'a> {
+    /// Look up the passphrase for the current keygrip.
+    fn passphrase(&self) -> Result<String, String> {
+        if self.config.touch_id {
+            let reason = self
+                .description
+                .clone()
+                .unwrap_or_else(|| "unlock your GnuPG key".to_string());
+            authenticate(Policy::Biometrics, &reason).map_err(|error| error.message)?;
+        }
+        let account: Result<Account, _> = match self.keygrip {
+            Some(ref keygrip) => find_generic_password(&self.config.service, keygrip),
+            None => find_generic_password_by_service(&self.config.service),
+        };
+        account
+            .map(|account| account.password.into_exposed())
+            .map_err(|error| error.message)
+    }
+
+    /// Handle a single command `line` and write the response to `output`.
+    ///
+    /// Return `false` if the session should end.
+    fn handle<W: Write>(&mut self, line: &str, output: &mut W) -> io::Result<bool> {
+        let mut parts = line.splitn(2, ' ');
+        let command = parts.next().unwrap_or("").to_ascii_uppercase();
+        let argument = parts.next().unwrap_or("");
+        match command.as_str() {
+            "SETDESC" => {
+                self.description = Some(unescape(argument));
+                writeln!(output, "OK")?;
+            }
+            "SETKEYINFO" => {
+                // Key info looks like `n/KEYGRIP`, or `--clear`
+                self.keygrip = argument
+                    .split('/')
+                    .nth(1)
+                    .filter(|_| argument != "--clear")
+                    .map(ToString::to_string);
+                writeln!(output, "OK")?;
+            }
+            "GETPIN" => match self.passphrase() {
+                Ok(passphrase) => {
+                    writeln!(output, "D {}", escape(&passphrase))?;
+                    writeln!(output, "OK")?;
+                }
+                Err(message) => writeln!(output, "ERR {} {}", ERR_CANCELED, escape(&message))?,
+            },
+            "CONFIRM" => writeln!(output, "ERR {} Operation cancelled", ERR_CANCELED)?,
+            "GETINFO" => {
+                match argument {
+                    "flavor" => writeln!(output, "D keychain")?,
+                    "version" => writeln!(output, "D {}", env!("CARGO_PKG_VERSION"))?,
+                    "pid" => writeln!(output, "D {}", process::id())?,
+                    _ => (),
+                }
+                writeln!(output, "OK")?;
+            }
+            "BYE" => {
+                writeln!(output, "OK closing connection")?;
+                return Ok(false);
+            }
+            "RESET" => {
+                self.description = None;
+                self.keygrip = None;
+                writeln!(output, "OK")?;
+            }
+            "OPTION" | "SETPROMPT" | "SETTITLE" | "SETERROR" | "SETOK" | "SETCANCEL"
+            | "SETNOTOK" | "SETREPEAT" | "SETREPEATERROR" | "SETQUALITYBAR"
+            | "SETQUALITYBAR_TT" | "SETGENPIN" | "SETGENPIN_TT" | "SETTIMEOUT" | "MESSAGE"
+            | "NOP" => writeln!(output, "OK")?,
+            _ => writeln!(output, "ERR {} Unknown IPC command", ERR_UNKNOWN_COMMAND)?,
+        }
+        Ok(true)
+    }
+}
+
+/// Run a pinentry session on `input` and `output` with `config`.
+///
+/// # Errors
+///
+/// Return an IO error if reading or writing fails.
+pub fn run<R: BufRead, W: Write>(input: &mut R, output: &mut W, config: &Config) -> io::Result<()> {
+    let mut session = Session {
+        config,
+        description: None,
+        keygrip: None,
+    };
+    writeln!(output, "OK Pleased to meet you")?;
+    output.flush()?;
+    let mut line = String::new();
+    while input.read_line(&mut line)? != 0 {
+        let command = line.trim_end_matches(['\n', '\r']);
+        if !command.is_empty() && !command.starts_with('#') {
+            let more = session.handle(command, output)?;
+            output.flush()?;
+            if !more {
+                break;
+            }
+        }
+        line.clear();
+    }
+    Ok(())
+}
@@ -0,0 +1,185 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Policies which restrict the services the command line may touch.
+//!
+//! A policy is a JSON file with lists of wildcard patterns for services, see
+//! `pattern::glob_match`; all lists are optional:
+//!
+//! ```json
+//! {
+//!     "allow": ["com.example.*"],
+//!     "deny": ["com.example.vpn"],
+//!     "read-only": ["com.example.shared.*"],
+//!     "confirm-delete": ["*"]
+//! }
+//! ```
+//!
+//! A policy may also word the prompts of the command line; see `prompts`.
+//!
+//! An organization ships a policy at `DEFAULT_PATH` to keep scripts from
+//! changing or deleting unrelated credentials.  A policy is a guardrail, not
+//! a security boundary: it restricts this program only, and only as long as
+//! the user does not edit the policy.
+
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::pattern::glob_match;
+use crate::prompts::Prompts;
+
+/// Where the policy lives unless `POLICY_VAR` says otherwise.
+pub const DEFAULT_PATH: &str = "/Library/Application Support/rust-ffi-demo/policy.json";
+
+/// The environment variable with the path to the policy.
+pub const POLICY_VAR: &str = "RUST_FFI_DEMO_POLICY";
+
+/// How a command touches a service.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    /// Read items of the service.
+    Read,
+    /// Add or change items of the service.
+    Write,
+    /// Delete items of the service.
+    Delete,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            Access::Read => "reading",
+            Access::Write => "writing",
+            Access::Delete => "deleting",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What a policy says about an access.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decision {
+    /// The access is allowed.
+    Allowed,
+    /// The access is allowed if the user confirms it.
+    Confirm,
+    /// The access is denied, for the given reason.
+    Denied(String),
+}
+
+/// A policy for services.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Policy {
+    /// Patterns of the services commands may touch at all.
+    ///
+    /// If empty, commands may touch all services not denied.
+    pub allow: Vec<String>,
+    /// Patterns of the services commands must not touch.
+    pub deny: Vec<String>,
+    /// Patterns of the services commands may only read.
+    pub read_only: Vec<String>,
+    /// Patterns of the services whose items the user must confirm deletion
+    /// of.
+    pub confirm_delete: Vec<String>,
+    /// Templates for the prompts of the command line.
+    pub prompts: Prompts,
+}
+
+/// The first of `patterns` which matches `service`.
+fn matching<'a>(patterns: &'a [String], service: &str) -> Option<&'a str> {
+    patterns
+        .iter()
+        .find(|pattern| glob_match(pattern, service))
+        .map(String::as_str)
+}
+
+impl Policy {
+    /// Read a policy from JSON in `reader`.
+    ///
+    /// # Errors
+    ///
+    /// Return an IO error if reading fails, or `io::ErrorKind::InvalidData` if
+    /// the policy is malformed.
+    pub fn from_json<R: Read>(reader: R) -> io::Result<Policy> {
+        serde_json::from_reader(reader).map_err(io::Error::from)
+    }
+
+    /// Load the policy at `path`.
+    ///
+    /// # Errors
+    ///
+    /// See `Policy::from_json`.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Policy> {
+        Policy::from_json(File::open(path)?)
+    }
+
+    /// Load the policy at the path in `POLICY_VAR`, or else at `DEFAULT_PATH`.
+    ///
+    /// Return the empty policy, which allows everything, if `POLICY_VAR` is
+    /// unset and there is no policy at `DEFAULT_PATH`.
+    ///
+    /// # Errors
+    ///
+    /// See `Policy::from_json`; it's an error if `POLICY_VAR` points to a file
+    /// which does not exist.
+    pub fn load_default() -> io::Result<Policy> {
+        match env::var_os(POLICY_VAR) {
+            Some(path) => Policy::load(PathBuf::from(path)),
+            None => match Policy::load(DEFAULT_PATH) {
+                Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(Policy::default()),
+                result => result,
+            },
+        }
+    }
+
+    /// Decide about `access` to `service`.
+    ///
+    /// ```
+    /// use rust_ffi_demo::policy::{Access, Decision, Policy};
+    ///
+    /// let policy = Policy::from_json(&br#"{
+    ///     "allow": ["com.example.*"],
+    ///     "read-only": ["com.example.shared"],
+    ///     "confirm-delete": ["*"]
+    /// }"#[..]).unwrap();
+    /// assert_eq!(policy.check("com.example.api", Access::Write), Decision::Allowed);
+    /// assert_eq!(policy.check("com.example.api", Access::Delete), Decision::Confirm);
+    /// assert!(matches!(policy.check("com.example.shared", Access::Write), Decision::Denied(_)));
+    /// assert!(matches!(policy.check("github.com", Access::Read), Decision::Denied(_)));
+    /// ```
+    pub fn check(&self, service: &str, access: Access) -> Decision {
+        if let Some(pattern) = matching(&self.deny, service) {
+            return Decision::Denied(format!("service matches denied {}", pattern));
+        }
+        if !self.allow.is_empty() && matching(&self.allow, service).is_none() {
+            return Decision::Denied("service is not allowed".to_string());
+        }
+        if access != Access::Read {
+            if let Some(pattern) = matching(&self.read_only, service) {
+                return Decision::Denied(format!("service matches read-only {}", pattern));
+            }
+        }
+        if access == Access::Delete && matching(&self.confirm_delete, service).is_some() {
+            Decision::Confirm
+        } else {
+            Decision::Allowed
+        }
+    }
+}
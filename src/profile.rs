@@ -0,0 +1,201 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Environment profiles, like dev, staging and prod.
+//!
+//! A profile names a set of credentials: a prefix for the names of services,
+//! a keychain, and defaults for global flags.  Profiles live in a JSON object
+//! by name; all fields are optional:
+//!
+//! ```json
+//! {
+//!     "dev": {"prefix": "dev."},
+//!     "prod": {
+//!         "prefix": "prod.",
+//!         "keychain": "prod",
+//!         "read-only": true,
+//!         "format": "json",
+//!         "require-biometrics": true
+//!     }
+//! }
+//! ```
+//!
+//! With `--env prod` or `KEYCHAIN_ENV=prod` the command line turns the
+//! service `api.example.com` into `prod.api.example.com`, looks only into
+//! the `prod` keychain, rejects changes, and asks for Touch ID before it
+//! prints passwords, so the same scripts address different credentials
+//! without editing service names.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+/// The path of the profiles relative to the home directory, unless
+/// `PROFILES_VAR` says otherwise.
+pub const DEFAULT_PATH: &str = "Library/Application Support/rust-ffi-demo/profiles.json";
+
+/// The environment variable with the path to the profiles.
+pub const PROFILES_VAR: &str = "RUST_FFI_DEMO_PROFILES";
+
+/// The environment variable with the name of the profile to use.
+pub const ENV_VAR: &str = "KEYCHAIN_ENV";
+
+/// The format of reports and errors.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Human readable text.
+    Text,
+    /// JSON.
+    Json,
+}
+
+/// A profile for an environment.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Profile {
+    /// The prefix of the services of this environment.
+    pub prefix: String,
+    /// The keychain of this environment, by name or path.
+    ///
+    /// If `None` use the search list and the default keychain.
+    pub keychain: Option<String>,
+    /// Whether to reject all changes, like `--read-only`.
+    pub read_only: bool,
+    /// The format of reports and errors, unless `--format` is given.
+    pub format: Option<Format>,
+    /// Whether `get` requires biometrics, like `--require-biometrics`.
+    pub require_biometrics: bool,
+}
+
+impl Profile {
+    /// The name of `service` in this environment.
+    ///
+    /// ```
+    /// use rust_ffi_demo::profile::Profile;
+    ///
+    /// let profile = Profile {
+    ///     prefix: "prod.".to_string(),
+    ///     ..Profile::default()
+    /// };
+    /// assert_eq!(profile.service("api.example.com"), "prod.api.example.com");
+    /// assert_eq!(Profile::default().service("api.example.com"), "api.example.com");
+    /// ```
+    pub fn service(&self, service: &str) -> String {
+        format!("{}{}", self.prefix, service)
+    }
+}
+
+/// Profiles by name.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(transparent)]
+pub struct Profiles {
+    profiles: BTreeMap<String, Profile>,
+}
+
+impl Profiles {
+    /// Read profiles from JSON in `reader`.
+    ///
+    /// # Errors
+    ///
+    /// Return an IO error if reading fails, or `io::ErrorKind::InvalidData` if
+    /// the profiles are malformed.
+    pub fn from_json<R: Read>(reader: R) -> io::Result<Profiles> {
+        serde_json::from_reader(reader).map_err(io::Error::from)
+    }
+
+    /// Load the profiles at `path`.
+    ///
+    /// # Errors
+    ///
+    /// See `Profiles::from_json`.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Profiles> {
+        Profiles::from_json(File::open(path)?)
+    }
+
+    /// Load the profiles at the path in `PROFILES_VAR`, or else at
+    /// `DEFAULT_PATH` in the home directory.
+    ///
+    /// Return no profiles if `PROFILES_VAR` is unset and there are no
+    /// profiles at `DEFAULT_PATH`.
+    ///
+    /// # Errors
+    ///
+    /// See `Profiles::from_json`; it's an error if `PROFILES_VAR` points to a
+    /// file which does not exist.
+    pub fn load_default() -> io::Result<Profiles> {
+        match env::var_os(PROFILES_VAR) {
+            Some(path) => Profiles::load(PathBuf::from(path)),
+            None => {
+                let path = env::var_os("HOME")
+                    .map(PathBuf::from)
+                    .unwrap_or_default()
+                    .join(DEFAULT_PATH);
+                match Profiles::load(path) {
+                    Err(ref error) if error.kind() == io::ErrorKind::NotFound => {
+                        Ok(Profiles::default())
+                    }
+                    result => result,
+                }
+            }
+        }
+    }
+
+    /// Get the profile `name`.
+    ///
+    /// # Errors
+    ///
+    /// Return `io::ErrorKind::NotFound` if there is no profile `name`.
+    ///
+    /// ```
+    /// use rust_ffi_demo::profile::Profiles;
+    ///
+    /// let profiles = Profiles::from_json(&br#"{
+    ///     "dev": {"prefix": "dev."},
+    ///     "prod": {"prefix": "prod.", "keychain": "prod", "read-only": true, "require-biometrics": true}
+    /// }"#[..]).unwrap();
+    /// let prod = profiles.get("prod").unwrap();
+    /// assert_eq!(prod.keychain.as_deref(), Some("prod"));
+    /// assert!(prod.read_only);
+    /// assert!(prod.require_biometrics);
+    /// assert!(!profiles.get("dev").unwrap().require_biometrics);
+    /// assert!(profiles.get("staging").is_err());
+    /// ```
+    pub fn get(&self, name: &str) -> io::Result<&Profile> {
+        self.profiles.get(name).ok_or_else(|| {
+            let known: Vec<&str> = self.names().collect();
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "No environment {}, known environments: {}",
+                    name,
+                    if known.is_empty() {
+                        "none".to_string()
+                    } else {
+                        known.join(", ")
+                    }
+                ),
+            )
+        })
+    }
+
+    /// The names of all profiles, in order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.profiles.keys().map(String::as_str)
+    }
+}
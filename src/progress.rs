@@ -0,0 +1,39 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Progress of long-running operations.
+//!
+//! Operations over many items, like exports, imports and migrations, call a
+//! progress callback after every item.
+
+/// The progress of an operation, after processing an item.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress<'a> {
+    /// How many items were processed so far, including the current one.
+    pub processed: usize,
+    /// How many items there are in total.
+    pub total: usize,
+    /// The service of the current item.
+    pub service: &'a str,
+    /// The account of the current item.
+    pub account: &'a str,
+}
+
+/// A callback for progress.
+pub type ProgressFn<'a> = dyn FnMut(&Progress<'_>) + 'a;
+
+/// Ignore progress.
+///
+/// Pass `&mut progress::ignore` to operations whose progress doesn't matter.
+pub fn ignore(_progress: &Progress<'_>) {}
@@ -0,0 +1,127 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Templates for the prompts of the command line.
+//!
+//! An organization which ships a policy may also word the prompts of the
+//! command line, in the `prompts` of the policy; see `policy`.  All templates
+//! are optional:
+//!
+//! ```json
+//! {
+//!     "prompts": {
+//!         "authenticate": "let ACME IT reveal {account} for {service}",
+//!         "confirm": "ACME asks: really go on {access} {service}? [y/N] ",
+//!         "conflict": "{service} differs for {account}; keep a or b? "
+//!     }
+//! }
+//! ```
+//!
+//! `{service}` and `{account}` stand for the item in question, and
+//! `{access}` for what a command is about to do, eg, `deleting`.  Other text
+//! in braces stays as it is.
+
+use serde::Deserialize;
+
+/// The default reason of authentication dialogs.
+pub const AUTHENTICATE: &str = "reveal the password of {account} for {service}";
+
+/// The default question before accesses which the policy wants confirmed.
+pub const CONFIRM: &str = "Confirm {access} {service} [y/N] ";
+
+/// The default question on sync conflicts.
+pub const CONFLICT: &str = "Conflict for {service} {account}: keep a or b? ";
+
+/// Fill the placeholders in `template` with `values`.
+///
+/// Replace `{name}` with the value of `name`, and leave placeholders without
+/// value as they are.  Values are inserted as they are, even if they contain
+/// braces themselves:
+///
+/// ```
+/// use rust_ffi_demo::prompts::render;
+///
+/// let values = [("service", "{account}"), ("account", "jane")];
+/// assert_eq!(render("{account} at {service} {other}", &values), "jane at {account} {other}");
+/// ```
+pub fn render(template: &str, values: &[(&str, &str)]) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest.find('}').and_then(|end| {
+            let name = &rest[1..end];
+            values
+                .iter()
+                .find(|&&(key, _)| key == name)
+                .map(|&(_, value)| (value, end))
+        });
+        match value {
+            Some((value, end)) => {
+                rendered.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                rendered.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Templates for prompts, or `None` for the default.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Prompts {
+    /// The reason in authentication dialogs, eg, for Touch ID.
+    pub authenticate: Option<String>,
+    /// The question before accesses which the policy wants confirmed.
+    pub confirm: Option<String>,
+    /// The question on sync conflicts.
+    pub conflict: Option<String>,
+}
+
+impl Prompts {
+    /// The reason to authenticate for the item of `service` and `account`.
+    ///
+    /// ```
+    /// use rust_ffi_demo::prompts::Prompts;
+    ///
+    /// let prompts = Prompts::default();
+    /// assert_eq!(
+    ///     prompts.authenticate("api.example.com", "jane"),
+    ///     "reveal the password of jane for api.example.com"
+    /// );
+    /// ```
+    pub fn authenticate(&self, service: &str, account: &str) -> String {
+        let template = self.authenticate.as_deref().unwrap_or(AUTHENTICATE);
+        render(template, &[("service", service), ("account", account)])
+    }
+
+    /// The question whether to go on with `access` to `services`.
+    pub fn confirm(&self, access: &str, services: &str) -> String {
+        let template = self.confirm.as_deref().unwrap_or(CONFIRM);
+        render(template, &[("access", access), ("service", services)])
+    }
+
+    /// The question which side to keep for the item of `service` and
+    /// `account`.
+    pub fn conflict(&self, service: &str, account: &str) -> String {
+        let template = self.conflict.as_deref().unwrap_or(CONFLICT);
+        render(template, &[("service", service), ("account", account)])
+    }
+}
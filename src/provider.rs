@@ -0,0 +1,213 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Pluggable sources for the secrets of manifest entries.
+//!
+//! A manifest entry may take its secret from a provider with `value-from`,
+//! as `name:argument`, so that the manifest needs no plain text secrets.
+//! `get` looks up the provider of a name.  The built-in providers are
+//!
+//! - `env:NAME`, the environment variable `NAME`,
+//! - `file:PATH`, the contents of the file `PATH`, relative to the manifest,
+//! - `command:COMMAND`, the output of the shell command `COMMAND`, run in the
+//!   directory of the manifest, and
+//! - `random:LENGTH`, a fresh random password of `LENGTH` letters and digits,
+//!   32 by default.
+//!
+//! Files and command output lose a single trailing newline.  `register` adds
+//! further providers:
+//!
+//! ```
+//! use std::io;
+//! use std::path::Path;
+//! use std::sync::Arc;
+//! use rust_ffi_demo::provider::{self, Provider};
+//!
+//! /// The argument in reverse, for demonstration.
+//! struct Reversed;
+//!
+//! impl Provider for Reversed {
+//!     fn provide(&self, argument: &str, _base: &Path) -> io::Result<Vec<u8>> {
+//!         Ok(argument.bytes().rev().collect())
+//!     }
+//! }
+//!
+//! provider::register("reversed", Arc::new(Reversed));
+//! let secret = provider::get("reversed").unwrap().provide("terces", Path::new("")).unwrap();
+//! assert_eq!(secret, b"secret");
+//! ```
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command as Process;
+use std::sync::{Arc, RwLock};
+
+use crate::keychain::{self, SecretBytes};
+
+/// A source of secrets.
+pub trait Provider: Send + Sync {
+    /// Get the secret which `argument` names, resolving relative paths
+    /// against `base`.
+    ///
+    /// # Errors
+    ///
+    /// Return an IO error if there is no such secret.
+    fn provide(&self, argument: &str, base: &Path) -> io::Result<SecretBytes>;
+
+    /// Whether this provider makes up a new secret every time.
+    ///
+    /// Generated secrets only go into new items, and never replace the secret
+    /// of an existing item.
+    fn generates(&self) -> bool {
+        false
+    }
+}
+
+/// Remove a single trailing newline from `data`.
+fn strip_newline(mut data: Vec<u8>) -> Vec<u8> {
+    if data.ends_with(b"\n") {
+        data.pop();
+        if data.ends_with(b"\r") {
+            data.pop();
+        }
+    }
+    data
+}
+
+/// The value of an environment variable.
+#[derive(Debug, Clone, Copy)]
+pub struct Env;
+
+impl Provider for Env {
+    fn provide(&self, argument: &str, _base: &Path) -> io::Result<SecretBytes> {
+        env::var(argument).map(String::into_bytes).map_err(|error| {
+            io::Error::new(io::ErrorKind::NotFound, format!("${}: {}", argument, error))
+        })
+    }
+}
+
+/// The contents of a file.
+#[derive(Debug, Clone, Copy)]
+pub struct File;
+
+impl Provider for File {
+    fn provide(&self, argument: &str, base: &Path) -> io::Result<SecretBytes> {
+        fs::read(base.join(argument))
+            .map(strip_newline)
+            .map_err(|error| io::Error::new(error.kind(), format!("{}: {}", argument, error)))
+    }
+}
+
+/// The standard output of a shell command.
+#[derive(Debug, Clone, Copy)]
+pub struct Command;
+
+impl Provider for Command {
+    fn provide(&self, argument: &str, base: &Path) -> io::Result<SecretBytes> {
+        let mut command = Process::new("/bin/sh");
+        command.arg("-c").arg(argument);
+        if !base.as_os_str().is_empty() {
+            command.current_dir(base);
+        }
+        let output = command.output()?;
+        if output.status.success() {
+            Ok(strip_newline(output.stdout))
+        } else {
+            Err(io::Error::other(format!(
+                "{}: {}: {}",
+                argument,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            )))
+        }
+    }
+}
+
+/// A random password of letters and digits.
+#[derive(Debug, Clone, Copy)]
+pub struct Random;
+
+/// The characters of random passwords.
+const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+/// The length of random passwords without explicit length.
+const DEFAULT_LENGTH: usize = 32;
+
+impl Provider for Random {
+    fn provide(&self, argument: &str, _base: &Path) -> io::Result<SecretBytes> {
+        let length = match argument {
+            "" => DEFAULT_LENGTH,
+            argument => argument.parse().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid length {}", argument),
+                )
+            })?,
+        };
+        // Reject bytes beyond the last full multiple of the alphabet, so that
+        // all characters are equally likely
+        let limit = 256 - 256 % ALPHABET.len();
+        let mut password = Vec::with_capacity(length);
+        while password.len() < length {
+            let bytes = keychain::random_bytes(length - password.len())
+                .map_err(|error| io::Error::other(error.message))?;
+            password.extend(
+                bytes
+                    .into_iter()
+                    .filter(|&byte| usize::from(byte) < limit)
+                    .map(|byte| ALPHABET[usize::from(byte) % ALPHABET.len()]),
+            );
+        }
+        Ok(password)
+    }
+
+    fn generates(&self) -> bool {
+        true
+    }
+}
+
+/// The names of the built-in providers.
+pub const BUILT_IN: [&str; 4] = ["env", "file", "command", "random"];
+
+/// Providers registered with `register`, by name.
+static REGISTERED: RwLock<Vec<(String, Arc<dyn Provider>)>> = RwLock::new(Vec::new());
+
+/// Register `provider` for the name `name`.
+///
+/// Replace a provider registered for `name` before, and take precedence over
+/// the built-in provider of `name`.
+pub fn register(name: &str, provider: Arc<dyn Provider>) {
+    let mut registered = REGISTERED.write().unwrap();
+    registered.retain(|(registered, _)| registered != name);
+    registered.push((name.to_string(), provider));
+}
+
+/// Get the provider of `name`.
+///
+/// Return `None` if there is no provider of `name`.
+pub fn get(name: &str) -> Option<Arc<dyn Provider>> {
+    let registered = REGISTERED.read().unwrap();
+    if let Some((_, provider)) = registered.iter().find(|(registered, _)| registered == name) {
+        return Some(provider.clone());
+    }
+    match name {
+        "env" => Some(Arc::new(Env)),
+        "file" => Some(Arc::new(File)),
+        "command" => Some(Arc::new(Command)),
+        "random" => Some(Arc::new(Random)),
+        _ => None,
+    }
+}
@@ -0,0 +1,231 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! An interactive shell for keychain maintenance.
+//!
+//! The shell remembers the selected keychain and service across commands, so
+//! `use github` followed by `get alice` and `rm bob` works on the `github`
+//! items, without repeating the service for every command.
+//!
+//! A guard decides on every access to a service, like the policy of the
+//! command line.  Accesses which the guard wants confirmed need a yes on the
+//! input of the shell, so non-interactive sessions cannot do them.
+
+use std::io::{self, BufRead, Write};
+
+use crate::keychain::items::{self, ListOptions};
+use crate::keychain::keychains::Keychain;
+use crate::keychain::Result;
+use crate::policy::{Access, Decision};
+
+/// Help for the commands of the shell.
+const HELP: &str = "\
+keychain [NAME]          select a keychain by name or path, or show the selected one
+keychain default         search all keychains again
+use [SERVICE]            select a service, or clear the selection
+ls                       list accounts of the service, or all items
+get ACCOUNT              print the password of an account
+set ACCOUNT PASSWORD     set the password of an account, adding it if needed
+rm ACCOUNT               delete an account
+help                     show this help
+exit                     leave the shell";
+
+/// The state of a shell session.
+#[derive(Debug, Default)]
+struct Session {
+    /// The selected keychain; `None` searches all keychains.
+    keychain: Option<Keychain>,
+    /// The selected service.
+    service: Option<String>,
+    /// Whether the session can ask for confirmations.
+    interactive: bool,
+}
+
+impl Session {
+    /// The prompt for this session.
+    fn prompt(&self) -> String {
+        let keychain = self
+            .keychain
+            .as_ref()
+            .and_then(|keychain| keychain.path().file_stem())
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "default".to_string());
+        format!("{}/{}> ", keychain, self.service.as_deref().unwrap_or(""))
+    }
+
+    /// The selected service, for commands which need one.
+    fn service(&self) -> std::result::Result<&str, String> {
+        self.service
+            .as_deref()
+            .ok_or_else(|| "No service selected, see `use`".to_string())
+    }
+
+    /// Run a single `command` with `argument` and write its output to `output`.
+    ///
+    /// Check accesses with `guard`, and read confirmations from `input`.
+    /// Return `Ok(false)` if the session should end, and `Err` with a message
+    /// if the command failed.
+    fn execute<R: BufRead, W: Write>(
+        &mut self,
+        command: &str,
+        argument: &str,
+        input: &mut R,
+        output: &mut W,
+        guard: &mut dyn FnMut(&str, Access) -> Decision,
+    ) -> std::result::Result<bool, String> {
+        let show = |result: Result<()>| result.map_err(|error| error.to_string());
+        let write = |result: io::Result<()>| result.map_err(|error| error.to_string());
+        let interactive = self.interactive;
+        let mut permit = |service: &str, access: Access| {
+            match guard(service, access) {
+            Decision::Allowed => Ok(()),
+            Decision::Denied(reason) => Err(format!(
+                "Policy forbids {} {}: {}",
+                access, service, reason
+            )),
+            Decision::Confirm if !interactive => Err(format!(
+                "Policy requires confirmation for {} {}, which a non-interactive shell cannot ask for",
+                access, service
+            )),
+            Decision::Confirm => {
+                write(write!(output, "Confirm {} {}? [y/N] ", access, service))?;
+                write(output.flush())?;
+                let mut answer = String::new();
+                write(input.read_line(&mut answer).map(drop))?;
+                if answer.trim().eq_ignore_ascii_case("y") {
+                    Ok(())
+                } else {
+                    Err("Not confirmed".to_string())
+                }
+            }
+        }
+        };
+        match command {
+            "keychain" => match argument {
+                "" => {
+                    let selected = self
+                        .keychain
+                        .as_ref()
+                        .map(|keychain| keychain.path().display().to_string())
+                        .unwrap_or_else(|| "default search list".to_string());
+                    write(writeln!(output, "{}", selected))?;
+                }
+                "default" => self.keychain = None,
+                name => {
+                    self.keychain = Some(Keychain::named(name).map_err(|error| error.to_string())?)
+                }
+            },
+            "use" => {
+                self.service = Some(argument.to_string()).filter(|service| !service.is_empty())
+            }
+            "ls" => {
+                let mut listed =
+                    items::list_items(self.keychain.as_ref()).map_err(|error| error.to_string())?;
+                ListOptions::default().sort(&mut listed);
+                for item in listed {
+                    match self.service {
+                        Some(ref service) if *service == item.service => {
+                            write(writeln!(output, "{}", item.account))?
+                        }
+                        Some(_) => (),
+                        None => write(writeln!(output, "{}\t{}", item.service, item.account))?,
+                    }
+                }
+            }
+            "get" | "set" | "rm" if argument.is_empty() => {
+                return Err(format!("Usage: {} ACCOUNT", command))
+            }
+            "get" => {
+                permit(self.service()?, Access::Read)?;
+                let password =
+                    items::find_password(self.keychain.as_ref(), self.service()?, argument)
+                        .map_err(|error| error.to_string())?;
+                write(writeln!(output, "{}", password))?;
+            }
+            "set" => {
+                let mut parts = argument.splitn(2, ' ');
+                let account = parts.next().unwrap_or("");
+                let password = parts
+                    .next()
+                    .ok_or_else(|| "Usage: set ACCOUNT PASSWORD".to_string())?;
+                permit(self.service()?, Access::Write)?;
+                show(items::set_password(
+                    self.keychain.as_ref(),
+                    self.service()?,
+                    account,
+                    password,
+                ))?;
+            }
+            "rm" => {
+                permit(self.service()?, Access::Delete)?;
+                show(items::delete_item(
+                    self.keychain.as_ref(),
+                    self.service()?,
+                    argument,
+                ))?
+            }
+            "help" => write(writeln!(output, "{}", HELP))?,
+            "exit" | "quit" => return Ok(false),
+            _ => return Err(format!("Unknown command: {}, see `help`", command)),
+        }
+        Ok(true)
+    }
+}
+
+/// Run a shell session on `input` and `output`.
+///
+/// Write a prompt before every command if `interactive` is true.  Check the
+/// service and access of every command which reads, writes or deletes
+/// secrets with `guard` first.  Errors of commands, and refusals of `guard`,
+/// are written to `output`, and do not end the session.
+///
+/// # Errors
+///
+/// Return an IO error if reading or writing fails.
+pub fn run<R: BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    interactive: bool,
+    guard: &mut dyn FnMut(&str, Access) -> Decision,
+) -> io::Result<()> {
+    let mut session = Session {
+        interactive,
+        ..Session::default()
+    };
+    let mut line = String::new();
+    loop {
+        if interactive {
+            write!(output, "{}", session.prompt())?;
+            output.flush()?;
+        }
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            break;
+        }
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line.splitn(2, char::is_whitespace);
+        let command = parts.next().unwrap_or("");
+        let argument = parts.next().unwrap_or("").trim();
+        match session.execute(command, argument, input, output, guard) {
+            Ok(true) => (),
+            Ok(false) => break,
+            Err(message) => writeln!(output, "error: {}", message)?,
+        }
+        output.flush()?;
+    }
+    Ok(())
+}
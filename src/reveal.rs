@@ -0,0 +1,102 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Reveal items in Keychain Access.
+//!
+//! Keychain Access has no URL scheme to select an item, so this module
+//! launches it and types the label of the item into its search field through
+//! System Events.  Typing requires accessibility permissions for the calling
+//! terminal; without them Keychain Access just opens.
+
+use std::io;
+use std::process::Command;
+
+use crate::keychain::items::{self, Item};
+use crate::keychain::keychains::Keychain;
+use crate::keychain::{KeychainError, Result};
+
+/// The bundle identifier of Keychain Access.
+const KEYCHAIN_ACCESS: &str = "com.apple.keychainaccess";
+
+/// Find the item to reveal.
+///
+/// Without `account` take the first item of `service`.
+fn find(keychain: Option<&Keychain>, service: &str, account: Option<&str>) -> Result<Item> {
+    match account {
+        Some(account) => items::find_item(keychain, service, account),
+        None => items::list_items_sorted(keychain, &Default::default())?
+            .into_iter()
+            .find(|item| item.service == service)
+            .ok_or_else(|| KeychainError::item_not_found(format!("No item for {}", service))),
+    }
+}
+
+/// Quote `s` as AppleScript string.
+fn applescript_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Type `text` into the search field of Keychain Access.
+fn search(text: &str) -> io::Result<bool> {
+    let script = format!(
+        r#"tell application id "{}" to activate
+tell application "System Events"
+    keystroke "f" using command down
+    keystroke {}
+end tell"#,
+        KEYCHAIN_ACCESS,
+        applescript_string(text)
+    );
+    Command::new("osascript")
+        .arg("-e")
+        .arg(script)
+        .status()
+        .map(|status| status.success())
+}
+
+/// How an item was revealed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Revealed {
+    /// Keychain Access shows the search for the item.
+    Searched(Item),
+    /// Keychain Access opened, but searching for the item failed.
+    Opened(Item),
+}
+
+/// Open Keychain Access and search for the item of `service` and `account`.
+///
+/// # Errors
+///
+/// Return `KeychainError` if the item does not exist or keychain access
+/// fails, and an IO error if Keychain Access cannot be launched.
+pub fn reveal(
+    keychain: Option<&Keychain>,
+    service: &str,
+    account: Option<&str>,
+) -> Result<Revealed> {
+    let item = find(keychain, service, account)?;
+    let launched = Command::new("open")
+        .arg("-b")
+        .arg(KEYCHAIN_ACCESS)
+        .status()?;
+    if !launched.success() {
+        return Err(io::Error::other("Failed to launch Keychain Access").into());
+    }
+    let text = item.label.clone().unwrap_or_else(|| item.service.clone());
+    if search(&text).unwrap_or(false) {
+        Ok(Revealed::Searched(item))
+    } else {
+        Ok(Revealed::Opened(item))
+    }
+}
@@ -0,0 +1,193 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Graceful shutdown of long-running modes.
+//!
+//! `install` catches SIGTERM and SIGINT, which launchd and Ctrl-C send.  The
+//! signal handler only records the signal; accept loops built on
+//! `serve_until_shutdown` notice it, stop accepting connections and return,
+//! and `exit` leaves with status 128 plus the signal number, like shells
+//! report processes killed by signals.  Before it leaves, `exit` runs the
+//! hooks registered with `on_shutdown`; caches of secrets register hooks
+//! which forget them, and the audit log one which flushes it:
+//!
+//! ```no_run
+//! use rust_ffi_demo::runtime;
+//! use std::os::unix::net::UnixListener;
+//!
+//! runtime::install();
+//! let listener = UnixListener::bind("/tmp/example.sock").unwrap();
+//! runtime::serve_until_shutdown(&listener, |stream| drop(stream)).unwrap();
+//! std::fs::remove_file("/tmp/example.sock").unwrap();
+//! runtime::exit();
+//! ```
+
+use std::io::{self, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::raw::c_int;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::process;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+const SIGINT: c_int = 2;
+const SIGTERM: c_int = 15;
+
+extern "C" {
+    fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+}
+
+/// The signal which requested shutdown, or 0.
+static SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+/// The hooks which `exit` runs, in order of registration.
+static SHUTDOWN_HOOKS: Mutex<Vec<Box<dyn FnOnce() + Send>>> = Mutex::new(Vec::new());
+
+/// How often accept loops check for shutdown.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Record `signum`, and nothing else, to stay async-signal-safe.
+extern "C" fn handle_signal(signum: c_int) {
+    SIGNAL.store(signum, Ordering::SeqCst);
+}
+
+/// Catch SIGTERM and SIGINT, to request shutdown.
+pub fn install() {
+    unsafe {
+        signal(SIGINT, handle_signal);
+        signal(SIGTERM, handle_signal);
+    }
+}
+
+/// The signal which requested shutdown, if any.
+pub fn shutdown_signal() -> Option<i32> {
+    match SIGNAL.load(Ordering::SeqCst) {
+        0 => None,
+        signum => Some(signum),
+    }
+}
+
+/// The exit status after shutdown on `signum`.
+///
+/// ```
+/// assert_eq!(rust_ffi_demo::runtime::exit_status(15), 143);
+/// ```
+pub fn exit_status(signum: i32) -> i32 {
+    128 + signum
+}
+
+/// Run `hook` when `exit` leaves the process.
+///
+/// `process::exit` runs no destructors, so whatever must happen before the
+/// process ends, eg, overwriting secrets in memory, goes into a hook.  Hooks
+/// run in the order of registration.
+pub fn on_shutdown<F: FnOnce() + Send + 'static>(hook: F) {
+    SHUTDOWN_HOOKS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(Box::new(hook));
+}
+
+/// Run the shutdown hooks, flush standard output, and exit after shutdown.
+///
+/// Exit with `exit_status` of the signal which requested shutdown, or with
+/// status 0 if no signal did.
+pub fn exit() -> ! {
+    let hooks = std::mem::take(
+        &mut *SHUTDOWN_HOOKS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner),
+    );
+    for hook in hooks {
+        hook();
+    }
+    let _ = io::stdout().flush();
+    match shutdown_signal() {
+        Some(signum) => {
+            eprintln!("Shutting down on signal {}", signum);
+            process::exit(exit_status(signum))
+        }
+        None => process::exit(0),
+    }
+}
+
+/// A listener which accepts connections.
+pub trait Listener {
+    /// The type of connections.
+    type Stream;
+
+    /// Make `accept` return `io::ErrorKind::WouldBlock` instead of waiting.
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+
+    /// Accept a connection, in blocking mode.
+    fn accept_blocking(&self) -> io::Result<Self::Stream>;
+}
+
+impl Listener for UnixListener {
+    type Stream = UnixStream;
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixListener::set_nonblocking(self, nonblocking)
+    }
+
+    fn accept_blocking(&self) -> io::Result<UnixStream> {
+        let (stream, _) = self.accept()?;
+        // Accepted sockets inherit non-blocking mode from the listener
+        stream.set_nonblocking(false)?;
+        Ok(stream)
+    }
+}
+
+impl Listener for TcpListener {
+    type Stream = TcpStream;
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpListener::set_nonblocking(self, nonblocking)
+    }
+
+    fn accept_blocking(&self) -> io::Result<TcpStream> {
+        let (stream, _) = self.accept()?;
+        stream.set_nonblocking(false)?;
+        Ok(stream)
+    }
+}
+
+/// Accept connections on `listener` and pass them to `handle` until shutdown
+/// is requested.
+///
+/// Connections already passed to `handle` are not interrupted.
+///
+/// # Errors
+///
+/// Return an IO error if accepting connections fails.
+pub fn serve_until_shutdown<L, F>(listener: &L, mut handle: F) -> io::Result<()>
+where
+    L: Listener,
+    F: FnMut(L::Stream),
+{
+    listener.set_nonblocking(true)?;
+    while shutdown_signal().is_none() {
+        match listener.accept_blocking() {
+            Ok(stream) => handle(stream),
+            Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(POLL_INTERVAL)
+            }
+            Err(ref error) if error.kind() == io::ErrorKind::Interrupted => (),
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,85 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Search generic passwords by service, account and label.
+//!
+//! The keychain only looks up items by exact attribute values, so searching
+//! lists the attributes of all items and filters them here.  Searching never
+//! reads passwords.
+
+use regex::Regex;
+
+use crate::keychain::items::{self, Item, ListOptions};
+use crate::keychain::keychains::Keychain;
+use crate::keychain::{KeychainError, Result};
+
+/// What to search for.
+#[derive(Debug, Clone)]
+pub enum Matcher {
+    /// A substring, ignoring case.
+    Substring(String),
+    /// A regular expression.
+    Regex(Regex),
+}
+
+impl Matcher {
+    /// Match `pattern` as substring, ignoring case.
+    pub fn substring(pattern: &str) -> Matcher {
+        Matcher::Substring(pattern.to_lowercase())
+    }
+
+    /// Match `pattern` as regular expression.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::InvalidData` if `pattern` is no valid
+    /// regular expression.
+    pub fn regex(pattern: &str) -> Result<Matcher> {
+        Regex::new(pattern)
+            .map(Matcher::Regex)
+            .map_err(|error| KeychainError::invalid_data(error.to_string()))
+    }
+
+    /// Whether `text` matches.
+    pub fn matches_text(&self, text: &str) -> bool {
+        match *self {
+            Matcher::Substring(ref pattern) => text.to_lowercase().contains(pattern.as_str()),
+            Matcher::Regex(ref regex) => regex.is_match(text),
+        }
+    }
+
+    /// Whether the service, account or label of `item` matches.
+    pub fn matches(&self, item: &Item) -> bool {
+        self.matches_text(&item.service)
+            || self.matches_text(&item.account)
+            || item
+                .label
+                .as_ref()
+                .is_some_and(|label| self.matches_text(label))
+    }
+}
+
+/// Find all generic passwords whose service, account or label matches.
+///
+/// If `keychain` is given only search this keychain.
+///
+/// # Errors
+///
+/// Return `KeychainError` if listing items fails.
+pub fn search(keychain: Option<&Keychain>, matcher: &Matcher) -> Result<Vec<Item>> {
+    let mut found = items::list_items(keychain)?;
+    found.retain(|item| matcher.matches(item));
+    ListOptions::default().sort(&mut found);
+    Ok(found)
+}
@@ -0,0 +1,254 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Shell code which exports secrets as environment variables.
+//!
+//! The `env` command prints a line like `export DATABASE_URL='…'` for every
+//! item with a service prefix, for `eval` or direnv's `.envrc`:
+//!
+//! ```sh
+//! eval "$(rust-ffi-demo env --service-prefix myapp.)"
+//! ```
+//!
+//! The variable of an item is the rest of its service after the prefix, in
+//! upper case, with all other characters than ASCII letters and digits
+//! replaced by `_`.
+//!
+//! `hook` prints a shell function for zsh, bash and fish which loads the
+//! secrets a project declares whenever the shell enters its directory, and
+//! unloads them again when it leaves, see `rust_ffi_demo::hook`:
+//!
+//! ```sh
+//! eval "$(rust-ffi-demo hook zsh)"
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+/// The dialect of shell code.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dialect {
+    /// POSIX shells, like sh, bash and zsh.
+    Posix,
+    /// The fish shell.
+    Fish,
+}
+
+impl FromStr for Dialect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Dialect, String> {
+        match s {
+            "posix" => Ok(Dialect::Posix),
+            "fish" => Ok(Dialect::Fish),
+            _ => Err(format!("Unknown shell dialect: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            Dialect::Posix => "posix",
+            Dialect::Fish => "fish",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Quote `value` as a single word in `dialect`.
+///
+/// ```
+/// use rust_ffi_demo::shell::{quote, Dialect};
+///
+/// assert_eq!(quote("it's $HOME", Dialect::Posix), r#"'it'\''s $HOME'"#);
+/// assert_eq!(quote(r"it's C:\", Dialect::Fish), r#"'it\'s C:\\'"#);
+/// ```
+pub fn quote(value: &str, dialect: Dialect) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('\'');
+    for c in value.chars() {
+        match (dialect, c) {
+            // POSIX shells have no escapes in single quotes, so close the
+            // quotes, add an escaped quote, and open the quotes again
+            (Dialect::Posix, '\'') => quoted.push_str(r"'\''"),
+            (Dialect::Fish, '\'') | (Dialect::Fish, '\\') => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
+/// A line of shell code which exports `name` with `value` in `dialect`.
+///
+/// ```
+/// use rust_ffi_demo::shell::{export, Dialect};
+///
+/// assert_eq!(export("TOKEN", "s3cr3t", Dialect::Posix), "export TOKEN='s3cr3t'");
+/// assert_eq!(export("TOKEN", "s3cr3t", Dialect::Fish), "set -gx TOKEN 's3cr3t'");
+/// ```
+pub fn export(name: &str, value: &str, dialect: Dialect) -> String {
+    match dialect {
+        Dialect::Posix => format!("export {}={}", name, quote(value, dialect)),
+        Dialect::Fish => format!("set -gx {} {}", name, quote(value, dialect)),
+    }
+}
+
+/// A line of shell code which removes the variable `name` in `dialect`.
+///
+/// ```
+/// use rust_ffi_demo::shell::{unset, Dialect};
+///
+/// assert_eq!(unset("TOKEN", Dialect::Posix), "unset TOKEN");
+/// assert_eq!(unset("TOKEN", Dialect::Fish), "set -e TOKEN");
+/// ```
+pub fn unset(name: &str, dialect: Dialect) -> String {
+    match dialect {
+        Dialect::Posix => format!("unset {}", name),
+        Dialect::Fish => format!("set -e {}", name),
+    }
+}
+
+/// An interactive shell to hook into.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shell {
+    /// zsh, which runs `chpwd_functions` on directory changes.
+    Zsh,
+    /// bash, which has no directory hook, so the hook runs before every
+    /// prompt.
+    Bash,
+    /// fish, which runs functions on changes of `PWD`.
+    Fish,
+}
+
+impl Shell {
+    /// The name of this shell.
+    pub fn name(self) -> &'static str {
+        match self {
+            Shell::Zsh => "zsh",
+            Shell::Bash => "bash",
+            Shell::Fish => "fish",
+        }
+    }
+
+    /// The dialect of the code this shell runs.
+    pub fn dialect(self) -> Dialect {
+        match self {
+            Shell::Zsh | Shell::Bash => Dialect::Posix,
+            Shell::Fish => Dialect::Fish,
+        }
+    }
+}
+
+impl FromStr for Shell {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Shell, String> {
+        match s {
+            "zsh" => Ok(Shell::Zsh),
+            "bash" => Ok(Shell::Bash),
+            "fish" => Ok(Shell::Fish),
+            _ => Err(format!("Unknown shell: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Shell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Shell code for `shell` which evaluates the output of `program hook-env`
+/// whenever the working directory changes, and once right away.
+///
+/// ```
+/// use rust_ffi_demo::shell::{hook, Shell};
+///
+/// let code = hook(Shell::Fish, "/usr/local/bin/rust-ffi-demo");
+/// assert!(code.contains("'/usr/local/bin/rust-ffi-demo' hook-env fish | source"));
+/// ```
+pub fn hook(shell: Shell, program: &str) -> String {
+    let command = format!("{} hook-env {}", quote(program, shell.dialect()), shell);
+    match shell {
+        Shell::Zsh => format!(
+            r#"_rust_ffi_demo_hook() {{
+  eval "$({})"
+}}
+typeset -ag chpwd_functions
+if (( ! ${{chpwd_functions[(I)_rust_ffi_demo_hook]}} )); then
+  chpwd_functions=(_rust_ffi_demo_hook $chpwd_functions)
+fi
+_rust_ffi_demo_hook
+"#,
+            command
+        ),
+        Shell::Bash => format!(
+            r#"_rust_ffi_demo_hook() {{
+  local status=$?
+  if [[ "$PWD" != "${{_rust_ffi_demo_pwd-}}" ]]; then
+    _rust_ffi_demo_pwd=$PWD
+    eval "$({})"
+  fi
+  return $status
+}}
+if [[ ";${{PROMPT_COMMAND:-}};" != *";_rust_ffi_demo_hook;"* ]]; then
+  PROMPT_COMMAND="_rust_ffi_demo_hook${{PROMPT_COMMAND:+;$PROMPT_COMMAND}}"
+fi
+"#,
+            command
+        ),
+        Shell::Fish => format!(
+            r#"function __rust_ffi_demo_hook --on-variable PWD
+    {} | source
+end
+__rust_ffi_demo_hook
+"#,
+            command
+        ),
+    }
+}
+
+/// The name of the variable of `service` after its prefix.
+///
+/// Return `None` if `service` is empty, or starts with a digit.
+///
+/// ```
+/// use rust_ffi_demo::shell::variable_name;
+///
+/// assert_eq!(variable_name("database-url").as_deref(), Some("DATABASE_URL"));
+/// assert_eq!(variable_name("aws.secret_key").as_deref(), Some("AWS_SECRET_KEY"));
+/// assert_eq!(variable_name("2fa"), None);
+/// ```
+pub fn variable_name(service: &str) -> Option<String> {
+    if service.is_empty() || service.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    Some(
+        service
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect(),
+    )
+}
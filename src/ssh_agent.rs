@@ -0,0 +1,382 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! An SSH agent serving private keys from the keychain.
+//!
+//! Implements the subset of the SSH agent protocol needed to list and use
+//! keys, see draft-miller-ssh-agent.  Keys stay in the keychain, or the Secure
+//! Enclave; the agent only ever sees public keys and signatures.  Requests to
+//! add, remove, or lock keys fail.
+
+use std::fs::{self, DirBuilder};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use crate::keychain::keys::{find_private_keys, KeyType, PrivateKey, SignatureAlgorithm};
+use crate::keychain::Result;
+use crate::runtime;
+
+const SSH_AGENT_FAILURE: u8 = 5;
+const SSH_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+const SSH_AGENT_IDENTITIES_ANSWER: u8 = 12;
+const SSH_AGENTC_SIGN_REQUEST: u8 = 13;
+const SSH_AGENT_SIGN_RESPONSE: u8 = 14;
+
+const SSH_AGENT_RSA_SHA2_256: u32 = 2;
+const SSH_AGENT_RSA_SHA2_512: u32 = 4;
+
+/// The maximum length of a request we accept.
+const MAX_REQUEST_LENGTH: usize = 256 * 1024;
+
+/// The maximum number of clients we serve at the same time.
+const MAX_CLIENTS: usize = 16;
+
+/// Counts a client as connected while it lives.
+struct Client(Arc<AtomicUsize>);
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Bind a Unix socket accessible to the current user only at `path`.
+///
+/// Refuse to replace anything but a socket at `path`.  Bind the socket in a
+/// new private directory next to `path` first, and move it to `path` only
+/// after restricting its permissions, so that other users never get to
+/// connect to it.
+fn bind_private(path: &Path) -> io::Result<UnixListener> {
+    match fs::symlink_metadata(path) {
+        Ok(ref metadata) if !metadata.file_type().is_socket() => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Not replacing {}, which is no socket", path.display()),
+            ))
+        }
+        Ok(_) => (),
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => (),
+        Err(error) => return Err(error),
+    }
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("No socket name in {}", path.display()),
+        )
+    })?;
+    let directory = path.with_file_name(format!(".{}.{}", name.to_string_lossy(), process::id()));
+    DirBuilder::new().mode(0o700).create(&directory)?;
+    let temporary = directory.join("socket");
+    let result = UnixListener::bind(&temporary).and_then(|listener| {
+        fs::set_permissions(&temporary, fs::Permissions::from_mode(0o600))?;
+        fs::rename(&temporary, path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_file(&temporary);
+    fs::remove_dir(&directory)?;
+    result
+}
+
+/// Serve the agent protocol on a Unix socket at `path`.
+///
+/// Replace any existing socket at `path`, and make the socket accessible to
+/// the current user only.  Handle every client on a separate thread, up to
+/// `MAX_CLIENTS` at a time, until shutdown is requested, see `runtime`; then
+/// remove the socket and return.  Close connections of further clients right
+/// away.
+///
+/// # Errors
+///
+/// Return an IO error if the socket cannot be created, if something other
+/// than a socket exists at `path`, or if accepting connections fails.
+pub fn serve(path: &Path) -> io::Result<()> {
+    let listener = bind_private(path)?;
+    let clients = Arc::new(AtomicUsize::new(0));
+    let result = runtime::serve_until_shutdown(&listener, |stream| {
+        if MAX_CLIENTS <= clients.fetch_add(1, Ordering::SeqCst) {
+            clients.fetch_sub(1, Ordering::SeqCst);
+            eprintln!("ssh-agent: too many clients, closing connection");
+            return;
+        }
+        let client = Client(clients.clone());
+        thread::spawn(move || {
+            let _client = client;
+            if let Err(error) = handle_client(stream) {
+                eprintln!("ssh-agent: client failed: {}", error);
+            }
+        });
+    });
+    // Don't leave a dead socket behind for clients to connect to
+    fs::remove_file(path)?;
+    result
+}
+
+/// Answer requests from `stream` until the client disconnects.
+fn handle_client(mut stream: UnixStream) -> io::Result<()> {
+    loop {
+        let mut length = [0; 4];
+        match stream.read_exact(&mut length) {
+            Err(ref error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            result => result?,
+        }
+        let length = u32::from_be_bytes(length) as usize;
+        if MAX_REQUEST_LENGTH < length {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Request too large: {} bytes", length),
+            ));
+        }
+        let mut request = vec![0; length];
+        stream.read_exact(&mut request)?;
+
+        let response = handle_request(&request);
+        let mut message = Vec::with_capacity(response.len() + 4);
+        put_u32(&mut message, response.len() as u32);
+        message.extend_from_slice(&response);
+        stream.write_all(&message)?;
+    }
+}
+
+/// Handle a single `request` and return the response message.
+fn handle_request(request: &[u8]) -> Vec<u8> {
+    let mut reader = Reader(request);
+    let response = match reader.read_u8() {
+        Some(SSH_AGENTC_REQUEST_IDENTITIES) => list_identities(),
+        Some(SSH_AGENTC_SIGN_REQUEST) => {
+            let blob = reader.read_string();
+            let data = reader.read_string();
+            let flags = reader.read_u32();
+            match (blob, data, flags) {
+                (Some(blob), Some(data), Some(flags)) => sign_request(blob, data, flags),
+                _ => Ok(None),
+            }
+        }
+        _ => Ok(None),
+    };
+    match response {
+        Ok(Some(response)) => response,
+        Ok(None) => vec![SSH_AGENT_FAILURE],
+        Err(error) => {
+            eprintln!("ssh-agent: {}", error);
+            vec![SSH_AGENT_FAILURE]
+        }
+    }
+}
+
+/// Answer an identities request with all supported keys.
+fn list_identities() -> Result<Option<Vec<u8>>> {
+    let mut identities = Vec::new();
+    let mut count = 0;
+    for key in find_private_keys()? {
+        if let Some(blob) = public_key_blob(&key)? {
+            put_string(&mut identities, &blob);
+            put_string(&mut identities, comment(&key).as_bytes());
+            count += 1;
+        }
+    }
+    let mut response = vec![SSH_AGENT_IDENTITIES_ANSWER];
+    put_u32(&mut response, count);
+    response.extend_from_slice(&identities);
+    Ok(Some(response))
+}
+
+/// Answer a sign request for the key with the public key `blob`.
+///
+/// Return `None` if no such key exists.
+fn sign_request(blob: &[u8], data: &[u8], flags: u32) -> Result<Option<Vec<u8>>> {
+    for key in find_private_keys()? {
+        if public_key_blob(&key)?.as_deref() != Some(blob) {
+            continue;
+        }
+        let signature = match sign(&key, data, flags)? {
+            Some(signature) => signature,
+            None => return Ok(None),
+        };
+        let mut response = vec![SSH_AGENT_SIGN_RESPONSE];
+        put_string(&mut response, &signature);
+        return Ok(Some(response));
+    }
+    Ok(None)
+}
+
+/// The comment of `key` in identity listings.
+fn comment(key: &PrivateKey) -> String {
+    let label = key.label.as_ref().map_or("keychain", String::as_str);
+    if key.secure_enclave {
+        format!("{} (Secure Enclave)", label)
+    } else {
+        label.to_string()
+    }
+}
+
+/// Get the SSH public key blob of `key`.
+///
+/// Return `None` if the type of `key` is not supported.
+fn public_key_blob(key: &PrivateKey) -> Result<Option<Vec<u8>>> {
+    let mut blob = Vec::new();
+    match key.key_type {
+        Some(KeyType::Rsa) => {
+            // A PKCS#1 RSAPublicKey is a sequence of modulus and exponent
+            let data = key.public_key_data()?;
+            let integers = der_sequence(&data).and_then(|s| {
+                let (n, rest) = der_integer(s)?;
+                let (e, _) = der_integer(rest)?;
+                Some((n, e))
+            });
+            let (n, e) = match integers {
+                Some(integers) => integers,
+                None => return Ok(None),
+            };
+            put_string(&mut blob, b"ssh-rsa");
+            // DER integers are minimal two's complement, just like mpints
+            put_string(&mut blob, e);
+            put_string(&mut blob, n);
+        }
+        Some(KeyType::EcdsaP256) => {
+            put_string(&mut blob, b"ecdsa-sha2-nistp256");
+            put_string(&mut blob, b"nistp256");
+            put_string(&mut blob, &key.public_key_data()?);
+        }
+        Some(KeyType::EcdsaP384) => {
+            put_string(&mut blob, b"ecdsa-sha2-nistp384");
+            put_string(&mut blob, b"nistp384");
+            put_string(&mut blob, &key.public_key_data()?);
+        }
+        None => return Ok(None),
+    }
+    Ok(Some(blob))
+}
+
+/// Sign `data` with `key`, and encode the signature for SSH.
+///
+/// Return `None` if the type of `key` is not supported.
+fn sign(key: &PrivateKey, data: &[u8], flags: u32) -> Result<Option<Vec<u8>>> {
+    let mut signature = Vec::new();
+    match key.key_type {
+        Some(KeyType::Rsa) => {
+            let (name, algorithm): (&[u8], _) = if flags & SSH_AGENT_RSA_SHA2_512 != 0 {
+                (b"rsa-sha2-512", SignatureAlgorithm::RsaPkcs1Sha512)
+            } else if flags & SSH_AGENT_RSA_SHA2_256 != 0 {
+                (b"rsa-sha2-256", SignatureAlgorithm::RsaPkcs1Sha256)
+            } else {
+                (b"ssh-rsa", SignatureAlgorithm::RsaPkcs1Sha1)
+            };
+            put_string(&mut signature, name);
+            put_string(&mut signature, &key.sign(algorithm, data)?);
+        }
+        Some(KeyType::EcdsaP256) | Some(KeyType::EcdsaP384) => {
+            let (name, algorithm): (&[u8], _) = if key.key_type == Some(KeyType::EcdsaP256) {
+                (b"ecdsa-sha2-nistp256", SignatureAlgorithm::EcdsaSha256)
+            } else {
+                (b"ecdsa-sha2-nistp384", SignatureAlgorithm::EcdsaSha384)
+            };
+            // An X9.62 signature is a DER sequence of r and s, whereas SSH
+            // wants both as mpints in a string.
+            let der = key.sign(algorithm, data)?;
+            let integers = der_sequence(&der).and_then(|s| {
+                let (r, rest) = der_integer(s)?;
+                let (s, _) = der_integer(rest)?;
+                Some((r, s))
+            });
+            let (r, s) = match integers {
+                Some(integers) => integers,
+                None => return Ok(None),
+            };
+            let mut rs = Vec::new();
+            put_string(&mut rs, r);
+            put_string(&mut rs, s);
+            put_string(&mut signature, name);
+            put_string(&mut signature, &rs);
+        }
+        None => return Ok(None),
+    }
+    Ok(Some(signature))
+}
+
+/// Read a DER element with `tag` from `input`.
+///
+/// Return the contents of the element and the remaining input.
+fn der_element(tag: u8, input: &[u8]) -> Option<(&[u8], &[u8])> {
+    if *input.first()? != tag {
+        return None;
+    }
+    let first = *input.get(1)?;
+    let (length, offset) = if first & 0x80 == 0 {
+        (first as usize, 2)
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || 4 < count {
+            return None;
+        }
+        let length = input
+            .get(2..2 + count)?
+            .iter()
+            .fold(0, |length, &b| (length << 8) | b as usize);
+        (length, 2 + count)
+    };
+    let contents = input.get(offset..offset + length)?;
+    Some((contents, &input[offset + length..]))
+}
+
+/// Read the contents of a DER sequence from `input`.
+fn der_sequence(input: &[u8]) -> Option<&[u8]> {
+    der_element(0x30, input).map(|(contents, _)| contents)
+}
+
+/// Read a DER integer from `input`.
+fn der_integer(input: &[u8]) -> Option<(&[u8], &[u8])> {
+    der_element(0x02, input)
+}
+
+/// Append a big endian `u32` to `buffer`.
+fn put_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_be_bytes());
+}
+
+/// Append an SSH string, ie, length-prefixed bytes, to `buffer`.
+fn put_string(buffer: &mut Vec<u8>, value: &[u8]) {
+    put_u32(buffer, value.len() as u32);
+    buffer.extend_from_slice(value);
+}
+
+/// A reader for SSH wire format.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn read_u8(&mut self) -> Option<u8> {
+        let (&value, rest) = self.0.split_first()?;
+        self.0 = rest;
+        Some(value)
+    }
+
+    fn read_u32(&mut self) -> Option<u32> {
+        let bytes = self.0.get(..4)?;
+        let value = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        self.0 = &self.0[4..];
+        Some(value)
+    }
+
+    fn read_string(&mut self) -> Option<&'a [u8]> {
+        let length = self.read_u32()? as usize;
+        let value = self.0.get(..length)?;
+        self.0 = &self.0[length..];
+        Some(value)
+    }
+}
@@ -0,0 +1,706 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Stores of secrets beyond a single keychain.
+//!
+//! A `SecretStore` holds secrets identified by service and account, with a
+//! label and a modification time.  Besides the keychain there’s a store in
+//! memory and a store in a JSON export file, so secrets can move between the
+//! keychain and other places through a single interface.
+//!
+//! A `CachedStore` in front of another store remembers secrets for a while,
+//! and forgets them as soon as the keychain reports changes:
+//!
+//! ```no_run
+//! use rust_ffi_demo::store::{CachedStore, KeychainStore, SecretStore};
+//! use std::time::Duration;
+//!
+//! let store = CachedStore::new(KeychainStore::new(None), Duration::from_secs(300));
+//! let subscription = store.subscribe().unwrap();
+//! // Only the first read goes to the keychain, and may prompt
+//! let secret = store.get("api.example.com", "jane").unwrap();
+//! assert_eq!(store.get("api.example.com", "jane").unwrap(), secret);
+//! // Deliver pending notifications, which invalidate changed items
+//! subscription.run_for(Duration::from_millis(10));
+//! ```
+//!
+//! A `RateLimitedStore` makes callers wait if they access the store too
+//! often, so a caller stuck in a loop cannot flood the user with prompts.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::export::{self, Record};
+use crate::keychain::events::{Event, EventKind, Subscription};
+use crate::keychain::items::{self, Item};
+use crate::keychain::keychains::Keychain;
+use crate::keychain::sensitive::Sensitive;
+use crate::keychain::{KeychainError, KeychainErrorCode, Result};
+use crate::runtime;
+
+/// An entry of a store, without its secret.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// The service of the entry.
+    pub service: String,
+    /// The account of the entry.
+    pub account: String,
+    /// The label of the entry.
+    pub label: Option<String>,
+    /// Application specific data of the entry, if the store keeps any.
+    ///
+    /// Export files do not keep generic data.
+    pub generic: Option<Vec<u8>>,
+    /// When the entry was last modified, if known.
+    pub modified: Option<SystemTime>,
+}
+
+impl From<Entry> for Item {
+    /// A generic password with the attributes of `entry`.
+    fn from(entry: Entry) -> Item {
+        Item {
+            label: entry.label,
+            generic: entry.generic,
+            modified: entry.modified,
+            ..Item::new(entry.service, entry.account)
+        }
+    }
+}
+
+/// A store of secrets.
+pub trait SecretStore {
+    /// List all entries of this store.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainError` if the store cannot be read.
+    fn entries(&self) -> Result<Vec<Entry>>;
+
+    /// Get the secret of `service` and `account`.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::ItemNotFound` if there is no such entry, and
+    /// `KeychainError` if the store cannot be read otherwise.
+    fn get(&self, service: &str, account: &str) -> Result<Vec<u8>>;
+
+    /// Get the entry of `service` and `account`, without its secret.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::ItemNotFound` if there is no such entry, and
+    /// `KeychainError` if the store cannot be read otherwise.
+    fn entry(&self, service: &str, account: &str) -> Result<Entry> {
+        self.entries()?
+            .into_iter()
+            .find(|entry| entry.service == service && entry.account == account)
+            .ok_or_else(|| not_found(service, account))
+    }
+
+    /// Set the `secret` of `entry`, adding the entry if needed.
+    ///
+    /// Stores which can keep modification times take the time from `entry`,
+    /// others set the current time.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainError` if the store cannot be written.
+    fn set(&mut self, entry: &Entry, secret: &[u8]) -> Result<()>;
+
+    /// Delete the entry of `service` and `account`.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::ItemNotFound` if there is no such entry, and
+    /// `KeychainError` if the store cannot be written otherwise.
+    fn delete(&mut self, service: &str, account: &str) -> Result<()>;
+}
+
+/// Generic passwords in the keychain.
+#[derive(Debug, Clone, Default)]
+pub struct KeychainStore {
+    keychain: Option<Keychain>,
+}
+
+impl KeychainStore {
+    /// A store of the generic passwords in `keychain`.
+    ///
+    /// If `keychain` is `None` read from all keychains in the search list, and
+    /// add new entries to the default keychain.
+    pub fn new(keychain: Option<Keychain>) -> KeychainStore {
+        KeychainStore { keychain }
+    }
+}
+
+impl SecretStore for KeychainStore {
+    fn entries(&self) -> Result<Vec<Entry>> {
+        let entries = items::list_items(self.keychain.as_ref())?
+            .into_iter()
+            .map(|item| Entry {
+                service: item.service,
+                account: item.account,
+                label: item.label,
+                generic: item.generic,
+                modified: item.modified,
+            })
+            .collect();
+        Ok(entries)
+    }
+
+    fn get(&self, service: &str, account: &str) -> Result<Vec<u8>> {
+        items::find_data(self.keychain.as_ref(), service, account).map(Sensitive::into_exposed)
+    }
+
+    fn entry(&self, service: &str, account: &str) -> Result<Entry> {
+        let item = items::find_item(self.keychain.as_ref(), service, account)?;
+        Ok(Entry {
+            service: item.service,
+            account: item.account,
+            label: item.label,
+            generic: item.generic,
+            modified: item.modified,
+        })
+    }
+
+    fn set(&mut self, entry: &Entry, secret: &[u8]) -> Result<()> {
+        let item = Item {
+            label: entry.label.clone(),
+            generic: entry.generic.clone(),
+            ..Item::new(entry.service.clone(), entry.account.clone())
+        };
+        match items::add_item(self.keychain.as_ref(), &item, secret) {
+            Err(ref error) if error.status == KeychainErrorCode::DuplicateItem => {
+                items::update_item(self.keychain.as_ref(), &item, secret)
+            }
+            result => result,
+        }
+    }
+
+    fn delete(&mut self, service: &str, account: &str) -> Result<()> {
+        items::delete_item(self.keychain.as_ref(), service, account)
+    }
+}
+
+/// The label, generic data, modification time and secret of an entry in
+/// memory.
+type MemoryEntry = (Option<String>, Option<Vec<u8>>, Option<SystemTime>, Vec<u8>);
+
+/// Secrets in memory.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
+    entries: BTreeMap<(String, String), MemoryEntry>,
+}
+
+impl MemoryStore {
+    /// An empty store.
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+}
+
+impl SecretStore for MemoryStore {
+    fn entries(&self) -> Result<Vec<Entry>> {
+        let entries = self
+            .entries
+            .iter()
+            .map(
+                |((service, account), (label, generic, modified, _))| Entry {
+                    service: service.clone(),
+                    account: account.clone(),
+                    label: label.clone(),
+                    generic: generic.clone(),
+                    modified: *modified,
+                },
+            )
+            .collect();
+        Ok(entries)
+    }
+
+    fn get(&self, service: &str, account: &str) -> Result<Vec<u8>> {
+        self.entries
+            .get(&(service.to_string(), account.to_string()))
+            .map(|(_, _, _, secret)| secret.clone())
+            .ok_or_else(|| not_found(service, account))
+    }
+
+    fn set(&mut self, entry: &Entry, secret: &[u8]) -> Result<()> {
+        let modified = entry.modified.unwrap_or_else(SystemTime::now);
+        self.entries.insert(
+            (entry.service.clone(), entry.account.clone()),
+            (
+                entry.label.clone(),
+                entry.generic.clone(),
+                Some(modified),
+                secret.to_vec(),
+            ),
+        );
+        Ok(())
+    }
+
+    fn delete(&mut self, service: &str, account: &str) -> Result<()> {
+        self.entries
+            .remove(&(service.to_string(), account.to_string()))
+            .map(|_| ())
+            .ok_or_else(|| not_found(service, account))
+    }
+}
+
+/// Secrets in a JSON export file.
+///
+/// The file holds secrets in plain text, see `export`.  Every change writes
+/// the whole file.
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    path: PathBuf,
+    records: Vec<Record>,
+}
+
+impl FileStore {
+    /// Open the export file at `path`.
+    ///
+    /// If the file does not exist the store is empty, and the first change
+    /// creates the file.
+    ///
+    /// # Errors
+    ///
+    /// Return `KeychainErrorCode::Io` if the file cannot be read or is no
+    /// valid export.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<FileStore> {
+        let path = path.as_ref().to_path_buf();
+        let records = match File::open(&path) {
+            Ok(file) => export::read_json(file)?,
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(error) => return Err(error.into()),
+        };
+        Ok(FileStore { path, records })
+    }
+
+    /// The path of the export file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write all records to the file.
+    fn save(&self) -> Result<()> {
+        // Write a private temporary file next to the export and move it over
+        // the export, to never leave a partial export behind
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&temporary)?;
+        export::write_json(file, &self.records)?;
+        fs::rename(&temporary, &self.path)?;
+        Ok(())
+    }
+
+    fn position(&self, service: &str, account: &str) -> Option<usize> {
+        self.records
+            .iter()
+            .position(|record| record.service == service && record.account == account)
+    }
+}
+
+impl SecretStore for FileStore {
+    fn entries(&self) -> Result<Vec<Entry>> {
+        let entries = self
+            .records
+            .iter()
+            .map(|record| Entry {
+                service: record.service.clone(),
+                account: record.account.clone(),
+                label: record.label.clone(),
+                generic: None,
+                modified: record
+                    .modified
+                    .map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds)),
+            })
+            .collect();
+        Ok(entries)
+    }
+
+    fn get(&self, service: &str, account: &str) -> Result<Vec<u8>> {
+        self.position(service, account)
+            .map(|index| self.records[index].password.expose().clone().into_bytes())
+            .ok_or_else(|| not_found(service, account))
+    }
+
+    fn set(&mut self, entry: &Entry, secret: &[u8]) -> Result<()> {
+        let password = String::from_utf8(secret.to_vec())
+            .map_err(|_| KeychainError::invalid_data("Export files only hold UTF-8 secrets"))?;
+        let modified = entry
+            .modified
+            .unwrap_or_else(SystemTime::now)
+            .duration_since(UNIX_EPOCH)
+            .map(|modified| modified.as_secs())
+            .ok();
+        let record = Record {
+            service: entry.service.clone(),
+            account: entry.account.clone(),
+            label: entry.label.clone(),
+            password: Sensitive::new(password),
+            modified,
+        };
+        match self.position(&entry.service, &entry.account) {
+            Some(index) => self.records[index] = record,
+            None => self.records.push(record),
+        }
+        self.save()
+    }
+
+    fn delete(&mut self, service: &str, account: &str) -> Result<()> {
+        let index = self
+            .position(service, account)
+            .ok_or_else(|| not_found(service, account))?;
+        self.records.remove(index);
+        self.save()
+    }
+}
+
+/// Overwrite `bytes` with zeros.
+fn wipe(bytes: &mut [u8]) {
+    for byte in bytes.iter_mut() {
+        // Volatile writes, so that the compiler doesn't elide them
+        unsafe { ptr::write_volatile(byte, 0) };
+    }
+}
+
+/// A secret a `CachedStore` remembers, with the time it read it.
+///
+/// Overwrite the secret with zeros when dropped.
+struct CachedSecret {
+    read: Instant,
+    secret: Vec<u8>,
+}
+
+impl fmt::Debug for CachedSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CachedSecret")
+            .field("read", &self.read)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for CachedSecret {
+    fn drop(&mut self) {
+        wipe(&mut self.secret);
+    }
+}
+
+/// What a `CachedStore` remembers, with the time it read it.
+///
+/// `generation` counts invalidations, so that a read which raced with an
+/// invalidation doesn't remember what it read.
+#[derive(Debug, Default)]
+struct Cache {
+    secrets: BTreeMap<(String, String), CachedSecret>,
+    entries: Option<(Instant, Vec<Entry>)>,
+    generation: u64,
+}
+
+impl Cache {
+    /// Forget the secret of `service` and `account`, and all entries.
+    fn invalidate(&mut self, service: &str, account: &str) {
+        self.secrets
+            .remove(&(service.to_string(), account.to_string()));
+        self.entries = None;
+        self.generation += 1;
+    }
+
+    /// Forget everything.
+    fn clear(&mut self) {
+        self.secrets.clear();
+        self.entries = None;
+        self.generation += 1;
+    }
+
+    /// Forget secrets and entries read `ttl` or longer ago.
+    fn evict_expired(&mut self, ttl: Duration) {
+        self.secrets.retain(|_, cached| cached.read.elapsed() < ttl);
+        if let Some((read, _)) = self.entries {
+            if read.elapsed() >= ttl {
+                self.entries = None;
+            }
+        }
+    }
+
+    /// Forget what `event` may have changed.
+    fn invalidate_for(&mut self, event: &Event) {
+        match (event.kind, &event.service, &event.account) {
+            (EventKind::Add, _, _) => {
+                self.entries = None;
+                self.generation += 1;
+            }
+            (EventKind::Update, Some(service), Some(account)) => self.invalidate(service, account),
+            // Deletions don't tell which item is gone, and locks should make
+            // secrets unreadable
+            _ => self.clear(),
+        }
+    }
+}
+
+/// A store which remembers the secrets and entries of another store.
+///
+/// Reads within the time to live of a previous read come from memory, so
+/// they neither prompt nor call into the keychain.  Changes through this
+/// store invalidate what it remembered about the entry, and with `subscribe`
+/// changes by other processes do so too.  Without subscription reads may
+/// return outdated secrets for up to the time to live.
+///
+/// The store keeps secrets in plain memory until they are invalidated, or
+/// until the first operation after they expire, and overwrites them with
+/// zeros when it forgets them.  Call `evict_expired` to forget expired
+/// secrets without another operation.
+#[derive(Debug)]
+pub struct CachedStore<S> {
+    store: S,
+    ttl: Duration,
+    cache: Arc<Mutex<Cache>>,
+}
+
+impl<S: SecretStore> CachedStore<S> {
+    /// Remember reads from `store` for `ttl`.
+    ///
+    /// Forget all remembered secrets on `runtime::exit`.
+    pub fn new(store: S, ttl: Duration) -> CachedStore<S> {
+        let cache = Arc::new(Mutex::new(Cache::default()));
+        let remembered = Arc::downgrade(&cache);
+        runtime::on_shutdown(move || {
+            if let Some(cache) = remembered.upgrade() {
+                cache.lock().unwrap_or_else(PoisonError::into_inner).clear();
+            }
+        });
+        CachedStore { store, ttl, cache }
+    }
+
+    /// Forget all remembered secrets and entries.
+    pub fn invalidate_all(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+
+    /// Forget all secrets and entries which outlived the time to live.
+    pub fn evict_expired(&self) {
+        self.cache.lock().unwrap().evict_expired(self.ttl);
+    }
+
+    /// Invalidate remembered secrets on keychain change notifications.
+    ///
+    /// Notifications arrive through the run loop of the current thread; see
+    /// `Subscription`.  Only one subscription may be active per process, and
+    /// the store only hears about changes while the subscription lives.
+    ///
+    /// # Errors
+    ///
+    /// See `Subscription::new`.
+    pub fn subscribe(&self) -> Result<Subscription> {
+        let cache = self.cache.clone();
+        Subscription::new(move |event| cache.lock().unwrap().invalidate_for(&event))
+    }
+
+    /// The store behind this cache.
+    pub fn into_inner(self) -> S {
+        self.store
+    }
+}
+
+impl<S: SecretStore> SecretStore for CachedStore<S> {
+    fn entries(&self) -> Result<Vec<Entry>> {
+        let generation = {
+            let mut cache = self.cache.lock().unwrap();
+            cache.evict_expired(self.ttl);
+            if let Some((_, ref entries)) = cache.entries {
+                return Ok(entries.clone());
+            }
+            cache.generation
+        };
+        let entries = self.store.entries()?;
+        let mut cache = self.cache.lock().unwrap();
+        // Entries may have changed while we read them
+        if cache.generation == generation {
+            cache.entries = Some((Instant::now(), entries.clone()));
+        }
+        Ok(entries)
+    }
+
+    fn get(&self, service: &str, account: &str) -> Result<Vec<u8>> {
+        let key = (service.to_string(), account.to_string());
+        let generation = {
+            let mut cache = self.cache.lock().unwrap();
+            cache.evict_expired(self.ttl);
+            if let Some(cached) = cache.secrets.get(&key) {
+                return Ok(cached.secret.clone());
+            }
+            cache.generation
+        };
+        // Don't hold the lock while the keychain prompts
+        let secret = self.store.get(service, account)?;
+        let mut cache = self.cache.lock().unwrap();
+        // The secret may have changed while we read it
+        if cache.generation == generation {
+            let cached = CachedSecret {
+                read: Instant::now(),
+                secret: secret.clone(),
+            };
+            cache.secrets.insert(key, cached);
+        }
+        Ok(secret)
+    }
+
+    fn entry(&self, service: &str, account: &str) -> Result<Entry> {
+        self.store.entry(service, account)
+    }
+
+    fn set(&mut self, entry: &Entry, secret: &[u8]) -> Result<()> {
+        let result = self.store.set(entry, secret);
+        self.cache
+            .lock()
+            .unwrap()
+            .invalidate(&entry.service, &entry.account);
+        result
+    }
+
+    fn delete(&mut self, service: &str, account: &str) -> Result<()> {
+        let result = self.store.delete(service, account);
+        self.cache.lock().unwrap().invalidate(service, account);
+        result
+    }
+}
+
+/// The tokens of a `RateLimitedStore`.
+#[derive(Debug)]
+struct Bucket {
+    /// Available tokens, negative for operations waiting in line.
+    tokens: f64,
+    /// When `tokens` was last refilled.
+    refilled: Instant,
+}
+
+/// A store which limits the rate of operations on another store.
+///
+/// Operations take a token from a bucket of `burst` tokens, which refills at
+/// `per_second` tokens per second.  Without tokens left an operation waits in
+/// line until its token arrives, so bursts pass quickly and a steady flood of
+/// operations slows down to `per_second`:
+///
+/// ```no_run
+/// use rust_ffi_demo::store::{CachedStore, KeychainStore, RateLimitedStore, SecretStore};
+/// use std::time::Duration;
+///
+/// // At most two keychain reads per second after a burst of ten, and
+/// // repeated reads of the same secret from memory
+/// let limited = RateLimitedStore::new(KeychainStore::new(None), 2.0, 10);
+/// let store = CachedStore::new(limited, Duration::from_secs(60));
+/// for _ in 0..1000 {
+///     store.get("api.example.com", "jane").unwrap();
+/// }
+/// ```
+///
+/// Put a `CachedStore` in front to debounce repeated reads of the same
+/// secret, because the limit applies to cached reads too otherwise.
+#[derive(Debug)]
+pub struct RateLimitedStore<S> {
+    store: S,
+    per_second: f64,
+    burst: f64,
+    bucket: Mutex<Bucket>,
+}
+
+impl<S: SecretStore> RateLimitedStore<S> {
+    /// Limit operations on `store` to `per_second` after a burst of `burst`.
+    ///
+    /// # Panics
+    ///
+    /// Panic if `per_second` is not positive or `burst` is zero.
+    pub fn new(store: S, per_second: f64, burst: u32) -> RateLimitedStore<S> {
+        assert!(per_second > 0.0, "Rate must be positive");
+        assert!(burst > 0, "Burst must be positive");
+        RateLimitedStore {
+            store,
+            per_second,
+            burst: f64::from(burst),
+            bucket: Mutex::new(Bucket {
+                tokens: f64::from(burst),
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take a token, and wait until it arrives.
+    fn acquire(&self) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.refilled).as_secs_f64() * self.per_second;
+            bucket.tokens = (bucket.tokens + refill).min(self.burst) - 1.0;
+            bucket.refilled = now;
+            if bucket.tokens < 0.0 {
+                Duration::from_secs_f64(-bucket.tokens / self.per_second)
+            } else {
+                Duration::ZERO
+            }
+        };
+        // Wait without the lock, so that later operations can line up
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+
+    /// The store behind this limit.
+    pub fn into_inner(self) -> S {
+        self.store
+    }
+}
+
+impl<S: SecretStore> SecretStore for RateLimitedStore<S> {
+    fn entries(&self) -> Result<Vec<Entry>> {
+        self.acquire();
+        self.store.entries()
+    }
+
+    fn get(&self, service: &str, account: &str) -> Result<Vec<u8>> {
+        self.acquire();
+        self.store.get(service, account)
+    }
+
+    fn entry(&self, service: &str, account: &str) -> Result<Entry> {
+        self.acquire();
+        self.store.entry(service, account)
+    }
+
+    fn set(&mut self, entry: &Entry, secret: &[u8]) -> Result<()> {
+        self.acquire();
+        self.store.set(entry, secret)
+    }
+
+    fn delete(&mut self, service: &str, account: &str) -> Result<()> {
+        self.acquire();
+        self.store.delete(service, account)
+    }
+}
+
+/// The error for a missing entry of `service` and `account`.
+fn not_found(service: &str, account: &str) -> KeychainError {
+    KeychainError::item_not_found(format!("No entry for {} and {}", service, account))
+}
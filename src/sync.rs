@@ -0,0 +1,221 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Two-way synchronization between stores.
+//!
+//! Entries only in one store are copied to the other.  Entries in both stores
+//! with different secrets or labels are conflicts, which a `Policy` resolves
+//! by picking the entry to keep.  There is no record of earlier syncs, so
+//! deletions are not synchronized; an entry deleted from one store comes back
+//! from the other.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::keychain::{correlation, KeychainError, Result};
+use crate::policy::Access;
+use crate::store::{Entry, SecretStore};
+
+/// One of the two stores of a sync.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Side {
+    /// The first store.
+    A,
+    /// The second store.
+    B,
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Side::A => write!(f, "a"),
+            Side::B => write!(f, "b"),
+        }
+    }
+}
+
+/// How to resolve conflicts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    /// Keep the entry modified last; keep the entry of `A` if both were
+    /// modified at the same time or times are unknown.
+    NewestWins,
+    /// Always keep the entry of `A`.
+    PreferA,
+    /// Ask which entry to keep.
+    Interactive,
+}
+
+impl FromStr for Policy {
+    type Err = KeychainError;
+
+    fn from_str(s: &str) -> Result<Policy> {
+        match s {
+            "newest-wins" => Ok(Policy::NewestWins),
+            "prefer-a" => Ok(Policy::PreferA),
+            "interactive" => Ok(Policy::Interactive),
+            _ => Err(KeychainError::invalid_data(format!(
+                "Unknown conflict policy: {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// A conflict between entries of both stores.
+#[derive(Debug)]
+pub struct Conflict<'a> {
+    /// The entry in store `A`.
+    pub a: &'a Entry,
+    /// The entry in store `B`.
+    pub b: &'a Entry,
+}
+
+/// An entry copied during a sync.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Copied {
+    /// The copied entry.
+    pub entry: Entry,
+    /// The store the entry was copied to.
+    pub to: Side,
+    /// Whether the copy resolved a conflict, rather than added a new entry.
+    pub conflict: bool,
+}
+
+impl fmt::Display for Copied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let from = if self.to == Side::A { Side::B } else { Side::A };
+        write!(
+            f,
+            "{} -> {}\t{}\t{}{}",
+            from,
+            self.to,
+            self.entry.service,
+            self.entry.account,
+            if self.conflict { "\t(conflict)" } else { "" }
+        )
+    }
+}
+
+/// Pick the side to keep for `conflict` according to `policy`.
+fn resolve(
+    policy: Policy,
+    conflict: &Conflict<'_>,
+    ask: &mut dyn FnMut(&Conflict<'_>) -> Side,
+) -> Side {
+    match policy {
+        Policy::NewestWins if conflict.b.modified > conflict.a.modified => Side::B,
+        Policy::NewestWins | Policy::PreferA => Side::A,
+        Policy::Interactive => ask(conflict),
+    }
+}
+
+/// Index `entries` by service and account.
+fn index(entries: Vec<Entry>) -> BTreeMap<(String, String), Entry> {
+    entries
+        .into_iter()
+        .map(|entry| ((entry.service.clone(), entry.account.clone()), entry))
+        .collect()
+}
+
+/// Synchronize the stores `a` and `b`.
+///
+/// Copy entries missing from one store from the other, and resolve conflicts
+/// with `policy`.  `ask` picks the side to keep for `Policy::Interactive`,
+/// and is not called for other policies.  `guard` checks the service of
+/// every secret before it's read or written, and stops the sync with its
+/// error if it refuses.
+///
+/// Return all copied entries, ordered by service and account.
+///
+/// # Errors
+///
+/// Return the error of `guard`, or `KeychainError` if reading or writing a
+/// store fails.  Entries copied before the failure stay copied.
+pub fn sync<E: From<KeychainError>>(
+    a: &mut dyn SecretStore,
+    b: &mut dyn SecretStore,
+    policy: Policy,
+    ask: &mut dyn FnMut(&Conflict<'_>) -> Side,
+    guard: &mut dyn FnMut(&str, Access) -> std::result::Result<(), E>,
+) -> std::result::Result<Vec<Copied>, E> {
+    correlation::scope(|_| {
+        let entries_a = index(a.entries()?);
+        let mut entries_b = index(b.entries()?);
+
+        let mut copied = Vec::new();
+        for (key, entry_a) in entries_a {
+            guard(&entry_a.service, Access::Read)?;
+            let secret_a = a.get(&entry_a.service, &entry_a.account)?;
+            let entry_b = match entries_b.remove(&key) {
+                Some(entry_b) => entry_b,
+                None => {
+                    guard(&entry_a.service, Access::Write)?;
+                    b.set(&entry_a, &secret_a)?;
+                    copied.push(Copied {
+                        entry: entry_a,
+                        to: Side::B,
+                        conflict: false,
+                    });
+                    continue;
+                }
+            };
+            let secret_b = b.get(&entry_b.service, &entry_b.account)?;
+            if secret_a == secret_b && entry_a.label == entry_b.label {
+                continue;
+            }
+            let conflict = Conflict {
+                a: &entry_a,
+                b: &entry_b,
+            };
+            match resolve(policy, &conflict, ask) {
+                Side::A => {
+                    guard(&entry_a.service, Access::Write)?;
+                    b.set(&entry_a, &secret_a)?;
+                    copied.push(Copied {
+                        entry: entry_a,
+                        to: Side::B,
+                        conflict: true,
+                    });
+                }
+                Side::B => {
+                    guard(&entry_b.service, Access::Write)?;
+                    a.set(&entry_b, &secret_b)?;
+                    copied.push(Copied {
+                        entry: entry_b,
+                        to: Side::A,
+                        conflict: true,
+                    });
+                }
+            }
+        }
+        // Whatever is left only exists in `b`
+        for entry_b in entries_b.into_values() {
+            guard(&entry_b.service, Access::Read)?;
+            let secret_b = b.get(&entry_b.service, &entry_b.account)?;
+            guard(&entry_b.service, Access::Write)?;
+            a.set(&entry_b, &secret_b)?;
+            copied.push(Copied {
+                entry: entry_b,
+                to: Side::A,
+                conflict: false,
+            });
+        }
+        copied.sort_by(|x, y| {
+            (&x.entry.service, &x.entry.account).cmp(&(&y.entry.service, &y.entry.account))
+        });
+        Ok(copied)
+    })
+}
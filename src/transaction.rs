@@ -0,0 +1,151 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Apply several changes to the keychain, all or nothing.
+//!
+//! The keychain has no transactions, so `apply` records the inverse of every
+//! change it made, and undoes them in reverse order if a later change fails.
+//! Rolling back is best effort: if an undo fails as well, the error says so.
+
+use crate::keychain::items::{self, Item};
+use crate::keychain::keychains::Keychain;
+use crate::keychain::{correlation, KeychainError, Result, SecretBytes};
+
+/// A change to a generic password.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// Add a new item with the given data.
+    Add(Item, SecretBytes),
+    /// Replace the data, and the label if any, of an existing item.
+    Update(Item, SecretBytes),
+    /// Delete the item with the given service and account.
+    Delete {
+        /// The service of the item.
+        service: String,
+        /// The account of the item.
+        account: String,
+    },
+}
+
+impl Change {
+    /// The service and account of the item this change affects.
+    fn target(&self) -> (&str, &str) {
+        match *self {
+            Change::Add(ref item, _) | Change::Update(ref item, _) => {
+                (&item.service, &item.account)
+            }
+            Change::Delete {
+                ref service,
+                ref account,
+            } => (service, account),
+        }
+    }
+}
+
+/// The result of a successful `apply`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Report {
+    /// How many changes were applied.
+    pub applied: usize,
+}
+
+/// Find the change which undoes `change`, before applying `change`.
+fn inverse(keychain: Option<&Keychain>, change: &Change) -> Result<Change> {
+    let (service, account) = change.target();
+    match *change {
+        Change::Add(..) => Ok(Change::Delete {
+            service: service.to_string(),
+            account: account.to_string(),
+        }),
+        Change::Update(..) | Change::Delete { .. } => {
+            let item = items::find_item(keychain, service, account)?;
+            let data = items::find_data(keychain, service, account)?.into_exposed();
+            if let Change::Update(..) = *change {
+                Ok(Change::Update(item, data))
+            } else {
+                Ok(Change::Add(item, data))
+            }
+        }
+    }
+}
+
+/// Apply a single `change`.
+fn apply_change(keychain: Option<&Keychain>, change: &Change) -> Result<()> {
+    match *change {
+        Change::Add(ref item, ref data) => items::add_item(keychain, item, data),
+        Change::Update(ref item, ref data) => items::update_item(keychain, item, data),
+        Change::Delete {
+            ref service,
+            ref account,
+        } => items::delete_item(keychain, service, account),
+    }
+}
+
+/// Apply all `changes` in order, or none of them.
+///
+/// Apply changes to `keychain` if given, or to the default keychain otherwise.
+/// Before each update or delete read the current data and attributes of the
+/// item, which may prompt for access.  Restoring an updated item restores
+/// its data and label, but cannot remove a label the update added.
+///
+/// # Errors
+///
+/// If a change fails, undo all changes applied so far, and return the
+/// `KeychainError` of the failed change, with a message which names the
+/// change and mentions any undo that failed as well.
+pub fn apply(keychain: Option<&Keychain>, changes: &[Change]) -> Result<Report> {
+    correlation::scope(|_| {
+        let mut undo = Vec::with_capacity(changes.len());
+        for (index, change) in changes.iter().enumerate() {
+            let result = inverse(keychain, change).and_then(|inverse| {
+                apply_change(keychain, change)?;
+                undo.push(inverse);
+                Ok(())
+            });
+            if let Err(error) = result {
+                let (service, account) = change.target();
+                let failed_undos: Vec<String> = undo
+                    .iter()
+                    .rev()
+                    .filter_map(|inverse| apply_change(keychain, inverse).err())
+                    .map(|error| error.message)
+                    .collect();
+                let rollback = if failed_undos.is_empty() {
+                    format!("rolled back {} changes", undo.len())
+                } else {
+                    format!(
+                        "rolling back {} changes failed: {}",
+                        undo.len(),
+                        failed_undos.join("; ")
+                    )
+                };
+                return Err(KeychainError {
+                    status: error.status,
+                    message: format!(
+                        "Change {} of {} {} failed: {}; {}",
+                        index + 1,
+                        service,
+                        account,
+                        error.message,
+                        rollback
+                    ),
+                    correlation_id: error.correlation_id,
+                });
+            }
+        }
+        Ok(Report {
+            applied: changes.len(),
+        })
+    })
+}
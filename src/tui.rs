@@ -0,0 +1,412 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! An interactive terminal browser for generic password items.
+//!
+//! Lists services and accounts with incremental search, shows the attributes
+//! of the selected item, and copies passwords, edits labels and deletes items
+//! through the keychain API.  Passwords are only read when copied, so browsing
+//! never prompts for keychain access.  A guard decides on every copy, edit and
+//! deletion first, and accesses which it wants confirmed ask for a yes.
+
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::time::SystemTime;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::Frame;
+
+use crate::keychain::find_generic_password;
+use crate::keychain::items::{self, Item, ListOptions};
+use crate::output::format_time;
+use crate::policy::{Access, Decision};
+use crate::search::Matcher;
+
+/// Help for the keys of the browser.
+const HELP: &str = "/ search  c copy password  e edit label  d delete  r reload  q quit";
+
+/// What keys currently do.
+#[derive(Debug, Clone, PartialEq)]
+enum Mode {
+    /// Navigate and act on items.
+    Browse,
+    /// Type the search query.
+    Search,
+    /// Type a new label for the selected item.
+    EditLabel(String),
+    /// Confirm an action on the selected item.
+    Confirm(Pending),
+}
+
+/// An action on the selected item which may need confirmation.
+#[derive(Debug, Clone, PartialEq)]
+enum Pending {
+    /// Copy the password.
+    Copy,
+    /// Set the label.
+    Label(String),
+    /// Delete the item.
+    Delete,
+}
+
+impl Pending {
+    /// The access this action needs.
+    fn access(&self) -> Access {
+        match self {
+            Pending::Copy => Access::Read,
+            Pending::Label(_) => Access::Write,
+            Pending::Delete => Access::Delete,
+        }
+    }
+}
+
+/// The state of the browser.
+struct Browser<'a> {
+    items: Vec<Item>,
+    query: String,
+    /// Indices of the items matching `query`.
+    visible: Vec<usize>,
+    list: ListState,
+    mode: Mode,
+    status: String,
+    /// Decides on every access to an item.
+    guard: &'a mut dyn FnMut(&str, Access) -> Decision,
+}
+
+/// Copy `text` to the clipboard with `pbcopy`.
+fn copy_to_clipboard(text: &str) -> io::Result<()> {
+    let mut child = Command::new("pbcopy").stdin(Stdio::piped()).spawn()?;
+    child
+        .stdin
+        .take()
+        .expect("Child has stdin")
+        .write_all(text.as_bytes())?;
+    let status = child.wait()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other("pbcopy failed"))
+    }
+}
+
+impl<'a> Browser<'a> {
+    fn new(guard: &'a mut dyn FnMut(&str, Access) -> Decision) -> Browser<'a> {
+        let mut browser = Browser {
+            items: Vec::new(),
+            query: String::new(),
+            visible: Vec::new(),
+            list: ListState::default(),
+            mode: Mode::Browse,
+            status: HELP.to_string(),
+            guard,
+        };
+        browser.reload();
+        browser
+    }
+
+    /// Load all items from the keychain again.
+    fn reload(&mut self) {
+        match items::list_items(None) {
+            Ok(mut items) => {
+                ListOptions::default().sort(&mut items);
+                self.items = items;
+            }
+            Err(error) => self.status = error.to_string(),
+        }
+        self.filter();
+    }
+
+    /// Update the visible items for the current query.
+    fn filter(&mut self) {
+        let matcher = Matcher::substring(&self.query);
+        self.visible = (0..self.items.len())
+            .filter(|&index| matcher.matches(&self.items[index]))
+            .collect();
+        let selected = match self.list.selected() {
+            _ if self.visible.is_empty() => None,
+            Some(index) => Some(index.min(self.visible.len() - 1)),
+            None => Some(0),
+        };
+        self.list.select(selected);
+    }
+
+    /// The selected item, if any.
+    fn selected(&self) -> Option<&Item> {
+        self.list
+            .selected()
+            .and_then(|index| self.visible.get(index))
+            .map(|&index| &self.items[index])
+    }
+
+    /// Move the selection by `offset` items.
+    fn move_selection(&mut self, offset: isize) {
+        if let Some(index) = self.list.selected() {
+            let last = self.visible.len().saturating_sub(1) as isize;
+            let index = (index as isize + offset).max(0).min(last);
+            self.list.select(Some(index as usize));
+        }
+    }
+
+    /// Copy the password of the selected item.
+    fn copy_password(&mut self) {
+        let result = match self.selected() {
+            Some(item) => find_generic_password(&item.service, &item.account)
+                .map_err(|error| error.to_string())
+                .and_then(|account| {
+                    copy_to_clipboard(account.password.expose()).map_err(|error| error.to_string())
+                }),
+            None => return,
+        };
+        self.status = match result {
+            Ok(()) => "Password copied to clipboard".to_string(),
+            Err(message) => message,
+        };
+    }
+
+    /// Set the label of the selected item.
+    fn set_label(&mut self, label: &str) {
+        let result = match self.selected() {
+            Some(item) => items::set_label(None, &item.service, &item.account, label),
+            None => return,
+        };
+        self.status = match result {
+            Ok(()) => "Label changed".to_string(),
+            Err(error) => error.to_string(),
+        };
+        self.reload();
+    }
+
+    /// Delete the selected item.
+    fn delete(&mut self) {
+        let result = match self.selected() {
+            Some(item) => items::delete_item(None, &item.service, &item.account),
+            None => return,
+        };
+        self.status = match result {
+            Ok(()) => "Item deleted".to_string(),
+            Err(error) => error.to_string(),
+        };
+        self.reload();
+    }
+
+    /// Ask the guard about `action` on the selected item.
+    ///
+    /// Perform allowed actions right away, except deletions, which always
+    /// need confirmation, and ask for confirmation if the guard wants it.
+    fn request(&mut self, action: Pending) {
+        let service = match self.selected() {
+            Some(item) => item.service.clone(),
+            None => return,
+        };
+        let access = action.access();
+        match (self.guard)(&service, access) {
+            Decision::Denied(reason) => {
+                self.status = format!("Policy forbids {} {}: {}", access, service, reason);
+            }
+            Decision::Allowed if action != Pending::Delete => self.perform(action),
+            Decision::Allowed => {
+                self.status = "Delete this item? (y/n)".to_string();
+                self.mode = Mode::Confirm(action);
+            }
+            Decision::Confirm => {
+                self.status = format!("Policy asks to confirm {} {} (y/n)", access, service);
+                self.mode = Mode::Confirm(action);
+            }
+        }
+    }
+
+    /// Perform `action` on the selected item.
+    fn perform(&mut self, action: Pending) {
+        match action {
+            Pending::Copy => self.copy_password(),
+            Pending::Label(label) => self.set_label(&label),
+            Pending::Delete => self.delete(),
+        }
+    }
+
+    /// Handle a `key` press.
+    ///
+    /// Return `false` to quit.
+    fn handle_key(&mut self, key: KeyEvent) -> bool {
+        match self.mode.clone() {
+            Mode::Browse => match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return false,
+                KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+                KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+                KeyCode::PageDown => self.move_selection(10),
+                KeyCode::PageUp => self.move_selection(-10),
+                KeyCode::Char('/') => self.mode = Mode::Search,
+                KeyCode::Char('c') => self.request(Pending::Copy),
+                KeyCode::Char('e') => {
+                    if let Some(item) = self.selected() {
+                        let label = item.label.clone().unwrap_or_default();
+                        self.mode = Mode::EditLabel(label);
+                    }
+                }
+                KeyCode::Char('d') => self.request(Pending::Delete),
+                KeyCode::Char('r') => {
+                    self.status = HELP.to_string();
+                    self.reload();
+                }
+                _ => (),
+            },
+            Mode::Search => match key.code {
+                KeyCode::Enter => self.mode = Mode::Browse,
+                KeyCode::Esc => {
+                    self.query.clear();
+                    self.mode = Mode::Browse;
+                    self.filter();
+                }
+                KeyCode::Backspace => {
+                    self.query.pop();
+                    self.filter();
+                }
+                KeyCode::Down => self.move_selection(1),
+                KeyCode::Up => self.move_selection(-1),
+                KeyCode::Char(c) => {
+                    self.query.push(c);
+                    self.filter();
+                }
+                _ => (),
+            },
+            Mode::EditLabel(mut label) => match key.code {
+                KeyCode::Enter => {
+                    self.mode = Mode::Browse;
+                    self.request(Pending::Label(label));
+                }
+                KeyCode::Esc => self.mode = Mode::Browse,
+                KeyCode::Backspace => {
+                    label.pop();
+                    self.mode = Mode::EditLabel(label);
+                }
+                KeyCode::Char(c) => {
+                    label.push(c);
+                    self.mode = Mode::EditLabel(label);
+                }
+                _ => (),
+            },
+            Mode::Confirm(action) => {
+                self.mode = Mode::Browse;
+                if key.code == KeyCode::Char('y') {
+                    self.perform(action);
+                } else {
+                    self.status = HELP.to_string();
+                }
+            }
+        }
+        true
+    }
+
+    /// The lines describing the selected item.
+    fn details(&self) -> Vec<Line<'static>> {
+        let item = match self.selected() {
+            Some(item) => item,
+            None => return vec![Line::from("No item selected")],
+        };
+        let optional = |value: &Option<String>| value.clone().unwrap_or_default();
+        let time = |value: &Option<SystemTime>| value.map(format_time).unwrap_or_default();
+        vec![
+            Line::from(format!("Service:     {}", item.service)),
+            Line::from(format!("Account:     {}", item.account)),
+            Line::from(format!("Label:       {}", optional(&item.label))),
+            Line::from(format!("Kind:        {}", optional(&item.description))),
+            Line::from(format!("Comment:     {}", optional(&item.comment))),
+            Line::from(format!("Created:     {}", time(&item.created))),
+            Line::from(format!("Modified:    {}", time(&item.modified))),
+        ]
+    }
+
+    fn draw(&mut self, frame: &mut Frame<'_>) {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(3),
+                Constraint::Min(0),
+                Constraint::Length(1),
+            ])
+            .split(frame.area());
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(rows[1]);
+
+        let search_title = if self.mode == Mode::Search {
+            "Search (Enter to finish, Esc to clear)"
+        } else {
+            "Search"
+        };
+        let search = Paragraph::new(self.query.as_str())
+            .block(Block::default().borders(Borders::ALL).title(search_title));
+        frame.render_widget(search, rows[0]);
+
+        let entries: Vec<ListItem<'_>> = self
+            .visible
+            .iter()
+            .map(|&index| {
+                let item = &self.items[index];
+                ListItem::new(format!("{} — {}", item.service, item.account))
+            })
+            .collect();
+        let title = format!("Items ({}/{})", self.visible.len(), self.items.len());
+        let list = List::new(entries)
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, columns[0], &mut self.list);
+
+        let details = Paragraph::new(self.details())
+            .block(Block::default().borders(Borders::ALL).title("Details"))
+            .wrap(Wrap { trim: false });
+        frame.render_widget(details, columns[1]);
+
+        let status = match self.mode {
+            Mode::EditLabel(ref label) => format!("New label: {}", label),
+            _ => self.status.clone(),
+        };
+        frame.render_widget(Paragraph::new(status), rows[2]);
+    }
+}
+
+/// Run the browser until the user quits.
+///
+/// Ask `guard` before every access to the password or the attributes of an
+/// item.
+///
+/// # Errors
+///
+/// Return an IO error if the terminal fails.  Keychain errors are shown in
+/// the browser instead.
+pub fn run(guard: &mut dyn FnMut(&str, Access) -> Decision) -> io::Result<()> {
+    let mut terminal = ratatui::init();
+    let mut browser = Browser::new(guard);
+    let result = loop {
+        if let Err(error) = terminal.draw(|frame| browser.draw(frame)) {
+            break Err(error);
+        }
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => {
+                if !browser.handle_key(key) {
+                    break Ok(());
+                }
+            }
+            Ok(_) => (),
+            Err(error) => break Err(error),
+        }
+    };
+    ratatui::restore();
+    result
+}
@@ -0,0 +1,92 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Tests of the tracing spans of correlation scopes.
+
+#![cfg(all(target_os = "macos", feature = "tracing"))]
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+use rust_ffi_demo::keychain::correlation;
+
+/// A subscriber which records the name and the `id` field of new spans.
+#[derive(Debug, Default, Clone)]
+struct Spans(Arc<Mutex<Vec<(String, String)>>>);
+
+/// Takes the `id` field of a span.
+struct IdField(String);
+
+impl Visit for IdField {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "id" {
+            self.0 = format!("{:?}", value);
+        }
+    }
+}
+
+impl Subscriber for Spans {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let mut id = IdField(String::new());
+        span.record(&mut id);
+        let mut spans = self.0.lock().unwrap();
+        spans.push((span.metadata().name().to_string(), id.0));
+        Id::from_u64(spans.len() as u64)
+    }
+
+    fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, _event: &Event<'_>) {}
+
+    fn enter(&self, _span: &Id) {}
+
+    fn exit(&self, _span: &Id) {}
+}
+
+#[test]
+fn scope_runs_in_span_with_id() {
+    let spans = Spans::default();
+    let id = tracing::subscriber::with_default(spans.clone(), || {
+        correlation::scope(|id| {
+            // Nested scopes belong to the same flow, and the same span
+            correlation::scope(|nested| assert_eq!(nested, id));
+            id
+        })
+    });
+    assert_eq!(
+        *spans.0.lock().unwrap(),
+        vec![("correlation".to_string(), id.to_string())]
+    );
+}
+
+#[test]
+fn with_id_runs_in_span_with_id() {
+    let spans = Spans::default();
+    let id = correlation::scope(|id| id);
+    tracing::subscriber::with_default(spans.clone(), || correlation::with_id(id, || ()));
+    assert_eq!(
+        *spans.0.lock().unwrap(),
+        vec![("correlation".to_string(), id.to_string())]
+    );
+}
@@ -0,0 +1,81 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Tests of items against a temporary keychain.
+//!
+//! Every test creates its own keychain file in the temporary directory, and
+//! deletes it afterwards, so no test touches the keychains of the user.
+
+#![cfg(target_os = "macos")]
+
+use std::env;
+use std::process;
+
+use rust_ffi_demo::keychain::items::{self, Item};
+use rust_ffi_demo::keychain::keychains::Keychain;
+
+/// A keychain which is deleted when dropped.
+struct TemporaryKeychain(Option<Keychain>);
+
+impl TemporaryKeychain {
+    /// Create a temporary keychain for the test `name`.
+    fn create(name: &str) -> TemporaryKeychain {
+        let path =
+            env::temp_dir().join(format!("rust-ffi-demo-{}-{}.keychain", name, process::id()));
+        TemporaryKeychain(Some(Keychain::create(path, b"t3st").unwrap()))
+    }
+
+    /// The temporary keychain.
+    fn keychain(&self) -> &Keychain {
+        self.0.as_ref().unwrap()
+    }
+}
+
+impl Drop for TemporaryKeychain {
+    fn drop(&mut self) {
+        if let Some(keychain) = self.0.take() {
+            if let Ok(path) = keychain.resolved_path() {
+                keychain.delete(&path).ok();
+            }
+        }
+    }
+}
+
+#[test]
+fn change_password_changes_only_the_data() {
+    let temporary = TemporaryKeychain::create("change-password");
+    let keychain = Some(temporary.keychain());
+    let item = Item {
+        label: Some("Example API".to_string()),
+        description: Some("API token".to_string()),
+        comment: Some("Rotated monthly".to_string()),
+        generic: Some(b"generic".to_vec()),
+        ..Item::new("api.example.com", "jane")
+    };
+    items::add_item(keychain, &item, b"0ld s3cret").unwrap();
+    let before = items::find_item(keychain, "api.example.com", "jane").unwrap();
+
+    items::change_password(keychain, "api.example.com", "jane", b"n3w s3cret").unwrap();
+
+    let data = items::find_data(keychain, "api.example.com", "jane").unwrap();
+    assert_eq!(data.expose(), b"n3w s3cret");
+    let after = items::find_item(keychain, "api.example.com", "jane").unwrap();
+    assert_eq!(after.label, before.label);
+    assert_eq!(after.description, before.description);
+    assert_eq!(after.comment, before.comment);
+    assert_eq!(after.generic, before.generic);
+    assert_eq!(after.created, before.created);
+    assert_eq!(after.protection, before.protection);
+    assert_eq!(after.access_group, before.access_group);
+}
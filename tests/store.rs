@@ -0,0 +1,117 @@
+// Copyright 2017 Sebastian Wiesner <sebastian@swsnr.de>
+
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+
+// 	http://www.apache.org/licenses/LICENSE-2.0
+
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Tests of caching stores in front of stores in memory.
+
+#![cfg(target_os = "macos")]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::Duration;
+
+use rust_ffi_demo::keychain::Result;
+use rust_ffi_demo::store::{CachedStore, Entry, MemoryStore, SecretStore};
+
+/// A store in memory which counts reads of secrets.
+///
+/// With a `gate`, the first read waits at the gate twice, so that a test can
+/// act while the read is under way.
+struct CountingStore {
+    store: MemoryStore,
+    reads: Arc<AtomicUsize>,
+    gate: Option<Arc<Barrier>>,
+}
+
+impl CountingStore {
+    /// A store with a secret for `api.example.com` and `jane`.
+    fn new(gate: Option<Arc<Barrier>>) -> (CountingStore, Arc<AtomicUsize>) {
+        let mut store = MemoryStore::new();
+        let entry = Entry {
+            service: "api.example.com".to_string(),
+            account: "jane".to_string(),
+            label: None,
+            generic: None,
+            modified: None,
+        };
+        store.set(&entry, b"hunter2").unwrap();
+        let reads = Arc::new(AtomicUsize::new(0));
+        let store = CountingStore {
+            store,
+            reads: reads.clone(),
+            gate,
+        };
+        (store, reads)
+    }
+}
+
+impl SecretStore for CountingStore {
+    fn entries(&self) -> Result<Vec<Entry>> {
+        self.store.entries()
+    }
+
+    fn get(&self, service: &str, account: &str) -> Result<Vec<u8>> {
+        if self.reads.fetch_add(1, Ordering::SeqCst) == 0 {
+            if let Some(ref gate) = self.gate {
+                gate.wait();
+                gate.wait();
+            }
+        }
+        self.store.get(service, account)
+    }
+
+    fn set(&mut self, entry: &Entry, secret: &[u8]) -> Result<()> {
+        self.store.set(entry, secret)
+    }
+
+    fn delete(&mut self, service: &str, account: &str) -> Result<()> {
+        self.store.delete(service, account)
+    }
+}
+
+#[test]
+fn reads_within_the_time_to_live_come_from_memory() {
+    let (store, reads) = CountingStore::new(None);
+    let store = CachedStore::new(store, Duration::from_secs(300));
+    assert_eq!(store.get("api.example.com", "jane").unwrap(), b"hunter2");
+    assert_eq!(store.get("api.example.com", "jane").unwrap(), b"hunter2");
+    assert_eq!(reads.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn expired_secrets_are_read_again() {
+    let (store, reads) = CountingStore::new(None);
+    let store = CachedStore::new(store, Duration::from_secs(0));
+    assert_eq!(store.get("api.example.com", "jane").unwrap(), b"hunter2");
+    store.evict_expired();
+    assert_eq!(store.get("api.example.com", "jane").unwrap(), b"hunter2");
+    assert_eq!(reads.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn reads_racing_with_invalidation_are_not_remembered() {
+    let gate = Arc::new(Barrier::new(2));
+    let (store, reads) = CountingStore::new(Some(gate.clone()));
+    let store = CachedStore::new(store, Duration::from_secs(300));
+    thread::scope(|scope| {
+        let read = scope.spawn(|| store.get("api.example.com", "jane").unwrap());
+        // Invalidate while the first read is under way
+        gate.wait();
+        store.invalidate_all();
+        gate.wait();
+        assert_eq!(read.join().unwrap(), b"hunter2");
+    });
+    assert_eq!(store.get("api.example.com", "jane").unwrap(), b"hunter2");
+    assert_eq!(reads.load(Ordering::SeqCst), 2);
+}